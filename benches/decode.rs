@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kc8587::td0::decode_td0;
+
+// a 512 byte sector's data block in each encoding, as TeleDisk would store the sectors that pick it
+fn blocks() -> [(&'static str, Vec<u8>); 3] {
+    let raw: Vec<u8> = (0..512).map(|i| (i * 37 % 251) as u8).collect();
+    // freshly formatted
+    let repeated = vec![1, 0, 1, 0xe5, 0xf6];
+    // a boot sector's worth of code, then zeros: runs and literals mixed
+    let mut rle = vec![2, 0, 255];
+    rle.extend((0..255).map(|i| (i * 13 % 256) as u8));
+    rle.extend_from_slice(&[0, 1, 0x55, 1, 128, 0, 0]);
+    rle.extend_from_slice(&[1, 0, 0, 0]);
+    [("raw", [&[0][..], &raw].concat()), ("repeated", repeated), ("rle", rle)]
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_td0");
+    group.throughput(Throughput::Bytes(512));
+    for (name, block) in blocks() {
+        let mut output = Vec::new();
+        decode_td0(block[0], &block[1..], 512, &mut output).unwrap();
+        group.bench_with_input(BenchmarkId::new("fresh", name), &block, |b, block| b.iter(|| {
+            let mut output = Vec::new();
+            decode_td0(block[0], &block[1..], 512, &mut output).unwrap();
+            output
+        }));
+        group.bench_with_input(BenchmarkId::new("reused", name), &block, |b, block| b.iter(|| {
+            decode_td0(block[0], &block[1..], 512, &mut output).unwrap();
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
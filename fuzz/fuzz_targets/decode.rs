@@ -0,0 +1,22 @@
+#![no_main]
+
+use kc8587::td0::{decode_td0, decode_td0_fitted};
+use libfuzzer_sys::fuzz_target;
+
+// a data block decoded for a sector of any size: it comes out that size or is an error, and
+// fitting it agrees
+fuzz_target!(|input: (u8, u16, &[u8])| {
+    let (method, sector_size, block) = input;
+    let mut data = Vec::new();
+    let decoded = decode_td0(method, block, sector_size, &mut data);
+    if decoded.is_ok() {
+        assert_eq!(data.len(), sector_size as usize);
+    }
+    // into a buffer holding the last sector's data, as when it's reused
+    let mut fitted = vec![0xe5; 300];
+    match decode_td0_fitted(method, block, sector_size, &mut fitted) {
+        Ok(None) => assert_eq!((decoded, &data), (Ok(()), &fitted)),
+        Ok(Some(_)) => assert_eq!(fitted.len(), sector_size as usize),
+        Err(e) => assert_eq!(decoded, Err(e)),
+    }
+});
@@ -0,0 +1,24 @@
+#![no_main]
+
+use kc8587::td0::{Td0Reader, TrackError};
+use libfuzzer_sys::fuzz_target;
+
+// any bytes as a TD0 image: reading it must stop at an error rather than panic, and every sector
+// read must decode or say why not
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut reader) = Td0Reader::new(data) else { return };
+    for track in reader.tracks() {
+        let track = match track {
+            Ok(track) => track,
+            Err(TrackError { partial: Some(track), .. }) => track,
+            Err(_) => return,
+        };
+        for sector in track.sectors() {
+            if let Ok((data, _)) = sector.fitted_data() {
+                assert_eq!(data.len(), sector.header.sector_size as usize);
+                sector.crc_ok(&data);
+            }
+            let _ = sector.data();
+        }
+    }
+});
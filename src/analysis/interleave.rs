@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use super::{Disk, TrackPosition};
+use crate::report::{tr, Language};
+
+// how the sectors were laid out around each track when the disk was formatted, from the order
+// TeleDisk found them in after the index hole
+#[derive(Debug, Default, serde::Serialize)]
+pub(crate) struct Interleave {
+    pub(crate) factor: Option<usize>,          // slots from one sector to the next numbered, on most tracks
+    #[serde(rename = "differing_tracks")]
+    pub(crate) differing: usize,               // tracks with some other factor
+    pub(crate) cylinder_skew: Option<usize>,   // slots the first sector moves from one cylinder to the next
+    pub(crate) head_skew: Option<usize>,       // and from one head to the other
+}
+
+impl Interleave {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        let mut text = format!("{} {}", t("interleave"), self.factor.map_or("?".to_string(), |f| f.to_string()));
+        if self.differing > 0 {
+            text += &format!(" ({} {})", self.differing, t("tracks differ"));
+        }
+        // single sided disks have no head skew, and single track ones no cylinder skew
+        for (skew, name) in [(self.cylinder_skew, t("cylinder skew")), (self.head_skew, t("head skew"))] {
+            if let Some(skew) = skew {
+                text += &format!(" - {} {}", name, skew);
+            }
+        }
+        text
+    }
+}
+
+impl Disk {
+    // the interleave of each track with two or more ordinary sectors, and the skews between the
+    // tracks with the same number of them
+    pub(crate) fn interleave(&self) -> Interleave {
+        // each track's sector numbers in the order they were recorded
+        let mut tracks: BTreeMap<TrackPosition, Vec<u8>> = BTreeMap::new();
+        for track in &self.tracks {
+            let numbers: Vec<u8> = track.sectors.iter().filter(|s| s.header.id_quirk().is_none()).map(|s| s.header.sector_number).collect();
+            if numbers.len() >= 2 {
+                tracks.insert((track.cylinder, track.header.side_number), numbers);
+            }
+        }
+        let factors: Vec<usize> = tracks.values().filter_map(|numbers| {
+            let mut by_number: Vec<(u8, usize)> = numbers.iter().enumerate().map(|(slot, &n)| (n, slot)).collect();
+            by_number.sort();
+            most_common(by_number.windows(2).map(|w| (w[1].1 + numbers.len() - w[0].1) % numbers.len()))
+        }).collect();
+        let factor = most_common(factors.iter().copied());
+
+        // the slot the lowest numbered sector is in, against the track it's compared with
+        let first_slot = |numbers: &Vec<u8>| numbers.iter().enumerate().min_by_key(|&(_, n)| n).map_or(0, |(slot, _)| slot);
+        let skew = |other: &dyn Fn(TrackPosition) -> TrackPosition| most_common(tracks.iter().filter_map(|(&position, numbers)| {
+            let next = tracks.get(&other(position)).filter(|next| next.len() == numbers.len())?;
+            Some((first_slot(next) + numbers.len() - first_slot(numbers)) % numbers.len())
+        }));
+        Interleave {
+            factor,
+            differing: factors.iter().filter(|&&f| Some(f) != factor).count(),
+            cylinder_skew: skew(&|(cylinder, head)| (cylinder + 1, head)),
+            head_skew: skew(&|(cylinder, head)| (cylinder, head + 1)),
+        }
+    }
+}
+
+// the value seen most often, the lowest on a tie
+fn most_common(values: impl Iterator<Item = usize>) -> Option<usize> {
+    let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    counts.into_iter().rev().max_by_key(|&(_, n)| n).map(|(value, _)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::flat_disk;
+
+    // a double sided disk of 9 sector tracks formatted with an interleave, each track's first
+    // sector moved round by the skews
+    fn formatted(cylinders: usize, factor: usize, cylinder_skew: usize, head_skew: usize) -> Disk {
+        let mut disk = flat_disk(&vec![0xe5; cylinders * 2 * 9 * 512], cylinders, 2, 9, 512);
+        for track in &mut disk.tracks {
+            let start = track.cylinder * cylinder_skew + track.header.side_number as usize * head_skew;
+            let mut slots = vec![0; 9];
+            let mut slot = start % 9;
+            for number in 1..=9 {
+                while slots[slot] != 0 { slot = (slot + 1) % 9; }
+                slots[slot] = number;
+                slot = (slot + factor) % 9;
+            }
+            for (sector, number) in track.sectors.iter_mut().zip(slots) {
+                sector.header.sector_number = number;
+            }
+        }
+        disk
+    }
+
+    #[test]
+    fn finds_the_factor_and_skews() {
+        let interleave = formatted(10, 2, 3, 1).interleave();
+        assert_eq!((interleave.factor, interleave.differing), (Some(2), 0));
+        assert_eq!((interleave.cylinder_skew, interleave.head_skew), (Some(3), Some(1)));
+        assert_eq!(interleave.describe(Language::En), "interleave 2 - cylinder skew 3 - head skew 1");
+    }
+
+    #[test]
+    fn counts_tracks_that_differ() {
+        let mut disk = formatted(10, 1, 0, 0);
+        let other = formatted(1, 3, 0, 0);
+        disk.tracks[5].sectors = other.tracks.into_iter().next().unwrap().sectors;
+        let interleave = disk.interleave();
+        assert_eq!((interleave.factor, interleave.differing), (Some(1), 1));
+        assert_eq!((interleave.cylinder_skew, interleave.head_skew), (Some(0), Some(0)));
+    }
+
+    #[test]
+    fn ignores_quirky_and_lone_sectors() {
+        let mut disk = formatted(4, 2, 0, 0);
+        for track in &mut disk.tracks {
+            track.sectors[4].header.sector_number = 0xff;
+        }
+        disk.tracks[1].sectors.truncate(1);
+        let interleave = disk.interleave();
+        assert_eq!(interleave.factor, Some(2));
+        assert_eq!(serde_json::to_value(&interleave).unwrap()["differing_tracks"], 0);
+
+        let single = flat_disk(&[0; 512], 1, 1, 1, 512).interleave();
+        assert_eq!((single.factor, single.cylinder_skew, single.head_skew), (None, None, None));
+        assert_eq!(single.describe(Language::En), "interleave ?");
+    }
+
+    #[test]
+    fn most_common_prefers_the_lowest() {
+        assert_eq!(most_common([3, 1, 3, 1, 2].into_iter()), Some(1));
+        assert_eq!(most_common(std::iter::empty()), None);
+    }
+}
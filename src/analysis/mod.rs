@@ -0,0 +1,1036 @@
+pub(crate) mod interleave;
+pub(crate) mod protection;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use kc8587::td0::{IdQuirk, SectorHeader, TrackHeader};
+
+use crate::{
+    fs::{
+        apple_dos_catalogue, apple_sector, cpm::AmstradFormat, fat::{first_cluster, BootSector, Bpb, ClusterEntry, Fat, FatUsage, FatVolume, Partition},
+        prodos_catalogue, AppleCatalogue, Codepage, DENT_SIZE, TRDOS_DENT_SIZE, TrDosSystem,
+    },
+    report::{tr, Finding, Language, Location, Severity},
+};
+
+// the checksums of a disk's contents, each in lower case hex
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub(crate) struct Checksums {
+    pub(crate) crc32: String,
+    pub(crate) md5: String,
+    pub(crate) sha1: String,
+}
+
+// how many sectors of each condition there are on a disk, good ones aside
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub(crate) struct SectorCounts {
+    pub(crate) crc_errors: usize,
+    pub(crate) deleted: usize,
+    pub(crate) missing: usize,
+    pub(crate) odd_size: usize,
+    pub(crate) no_id: usize,
+    pub(crate) high_number: usize,
+}
+
+impl SectorCounts {
+    // sectors with quirky IDs are rare, so only mentioned when there are some
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        let mut text = format!("{} {} {} {} {} {} {} {}", self.crc_errors, t("CRC errors"), self.deleted, t("deleted"),
+            self.missing, t("missing"), self.odd_size, t("odd size"));
+        if self.no_id > 0 { text += &format!(" {} {}", self.no_id, t("no ID")); }
+        if self.high_number > 0 { text += &format!(" {} {}", self.high_number, t("numbered 100 or more")); }
+        text
+    }
+}
+
+// what a sector holds, judged from its bytes alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SectorContent {
+    Blank(u8),      // every byte the same, as formatting leaves them
+    Text,
+    Binary,
+    HighEntropy,    // compressed or encrypted
+}
+
+// sectors this much printable, once any padding at the end is dropped, are text
+pub(crate) const TEXT_SHARE: f64 = 0.9;
+
+// and sectors this close to the most entropy their size allows are compressed or encrypted
+pub(crate) const HIGH_ENTROPY_SHARE: f64 = 0.85;
+
+impl SectorContent {
+    pub(crate) fn of(data: &[u8]) -> Self {
+        match data.first() {
+            None => return SectorContent::Blank(0),
+            Some(&first) if data.iter().all(|&b| b == first) => return SectorContent::Blank(first),
+            _ => {},
+        }
+        // text files end in zeros, ^Z or the format filler
+        let end = data.iter().rposition(|b| ![0x00, 0x1a, 0xe5].contains(b)).map_or(0, |i| i + 1);
+        let printable = data[..end].iter().filter(|&&b| (0x20..=0x7e).contains(&b) || b"\t\r\n".contains(&b)).count();
+        if printable as f64 >= end as f64 * TEXT_SHARE {
+            SectorContent::Text
+        } else if entropy(data) >= (data.len() as f64).log2().min(8.0) * HIGH_ENTROPY_SHARE {
+            SectorContent::HighEntropy
+        } else {
+            SectorContent::Binary
+        }
+    }
+}
+
+// Shannon entropy in bits per byte, 0 for a single repeated byte up to 8
+pub(crate) fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    counts.iter().filter(|&&n| n > 0).map(|&n| {
+        let p = n as f64 / data.len() as f64;
+        -p * p.log2()
+    }).sum()
+}
+
+// how many sectors of a disk hold each kind of content, and their average entropy
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub(crate) struct ContentCounts {
+    pub(crate) blank: usize,
+    pub(crate) text: usize,
+    pub(crate) binary: usize,
+    pub(crate) high_entropy: usize,
+    #[serde(serialize_with = "two_places")]
+    pub(crate) entropy: f64,       // bits per byte, the mean over every sector
+}
+
+// a mean is only worth recording to two decimal places
+fn two_places<S: serde::Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64((value * 100.0).round() / 100.0)
+}
+
+impl ContentCounts {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        format!("{} {} {} {} {} {} {} {} {} - {:.2} {}", t("content"), self.blank, t("blank"), self.text, t("text"),
+            self.binary, t("binary"), self.high_entropy, t("high entropy"), self.entropy, t("bits/byte"))
+    }
+}
+
+// tracks whose contents are the same as others on the disk, as formatted but unused ones are, and
+// pairs of tracks that differ in only a few bytes, which can be hidden data or copy protection
+#[derive(Debug, Default, serde::Serialize)]
+pub(crate) struct TrackRepeats {
+    pub(crate) repeated: usize,        // tracks the same as one before them
+    pub(crate) groups: Vec<RepeatedTracks>,
+    #[serde(rename = "near_identical")]
+    pub(crate) near: Vec<NearTracks>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct RepeatedTracks {
+    #[serde(rename = "tracks", serialize_with = "serialize_track_ranges")]
+    pub(crate) ranges: Vec<(TrackPosition, TrackPosition)>,    // runs of tracks next to each other, first and last
+    pub(crate) count: usize,
+    pub(crate) filler: Option<u8>,     // the byte every sector is filled with, if it's only one
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct NearTracks {
+    #[serde(serialize_with = "serialize_track")]
+    pub(crate) track: TrackPosition,
+    #[serde(serialize_with = "serialize_track")]
+    pub(crate) like: TrackPosition,
+    pub(crate) bytes: usize,           // how many bytes differ
+    pub(crate) first_offset: usize,    // of the first difference within the track
+}
+
+// a track by cylinder and head
+pub(crate) type TrackPosition = (usize, u8);
+
+// tracks differing in more bytes than this aren't reported as nearly the same
+pub(crate) const NEAR_TRACK_BYTES: usize = 16;
+
+impl TrackRepeats {
+    pub(crate) fn describe(&self, language: Language) -> Vec<String> {
+        let t = |english| tr(language, english);
+        let groups = self.groups.iter().map(|group| format!("{} {} ({}{})", t("identical tracks"), track_ranges(&group.ranges), group.count,
+            group.filler.map(|b| format!(", {} {:02x}", t("all"), b)).unwrap_or_default()));
+        let near = self.near.iter().map(|near| format!("{} {}/{} {}/{} - {} {} {:#x}", t("near identical tracks"),
+            near.track.0, near.track.1, near.like.0, near.like.1, near.bytes, t("bytes differ, the first at"), near.first_offset));
+        groups.chain(near).collect()
+    }
+}
+
+// runs of tracks as CYLINDER/HEAD, or FIRST-LAST for more than one
+pub(crate) fn track_ranges(ranges: &[(TrackPosition, TrackPosition)]) -> String {
+    ranges.iter().map(|&(first, last)| if first == last {
+        format!("{}/{}", first.0, first.1)
+    } else {
+        format!("{}/{}-{}/{}", first.0, first.1, last.0, last.1)
+    }).collect::<Vec<_>>().join(" ")
+}
+
+// the same in catalogue records
+pub(crate) fn serialize_track_ranges<S: serde::Serializer>(ranges: &[(TrackPosition, TrackPosition)], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&track_ranges(ranges))
+}
+
+fn serialize_track<S: serde::Serializer>(&(cylinder, head): &TrackPosition, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{}/{}", cylinder, head))
+}
+
+// the condition of one sector, as shown in sector maps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SectorStatus {
+    Good,
+    CrcError,   // read with a CRC error
+    Deleted,    // deleted-data address mark
+    Missing,    // no data recorded
+    OddSize,    // not the size most sectors on the disk are
+    NoId,       // recorded without an ID, see SectorHeader::id_quirk
+    HighNumber, // numbered 100 or more
+}
+
+impl SectorStatus {
+    pub(crate) const ALL: [SectorStatus; 7] = [SectorStatus::Good, SectorStatus::CrcError, SectorStatus::Deleted, SectorStatus::Missing,
+        SectorStatus::OddSize, SectorStatus::NoId, SectorStatus::HighNumber];
+
+    // a sector as its header describes it, on a disk whose sectors are mostly usual_size
+    pub(crate) fn of(header: &SectorHeader, usual_size: u16) -> Self {
+        if let Some(quirk) = header.id_quirk() {
+            match quirk {
+                IdQuirk::NoId => SectorStatus::NoId,
+                IdQuirk::HighNumber => SectorStatus::HighNumber,
+            }
+        } else if !header.has_data() {
+            SectorStatus::Missing
+        } else if header.flags & 0x02 != 0 {
+            SectorStatus::CrcError
+        } else if header.flags & 0x04 != 0 {
+            SectorStatus::Deleted
+        } else if header.sector_size != usual_size {
+            SectorStatus::OddSize
+        } else {
+            SectorStatus::Good
+        }
+    }
+
+    pub(crate) fn rgb(&self) -> [u8; 3] {
+        match self {
+            SectorStatus::Good => [0x30, 0xb0, 0x30],
+            SectorStatus::CrcError => [0xe0, 0x20, 0x20],
+            SectorStatus::Deleted => [0xe0, 0xa0, 0x00],
+            SectorStatus::Missing => [0x60, 0x60, 0x60],
+            SectorStatus::OddSize => [0x30, 0x60, 0xe0],
+            SectorStatus::NoId => [0xc0, 0x30, 0xc0],
+            SectorStatus::HighNumber => [0x20, 0xc0, 0xc0],
+        }
+    }
+
+    pub(crate) fn symbol(&self) -> char {
+        match self {
+            SectorStatus::Good => '.',
+            SectorStatus::CrcError => 'C',
+            SectorStatus::Deleted => 'D',
+            SectorStatus::Missing => '-',
+            SectorStatus::OddSize => 'S',
+            SectorStatus::NoId => 'N',
+            SectorStatus::HighNumber => 'H',
+        }
+    }
+
+    // as the sector map's legend has it
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            SectorStatus::Good => "good",
+            SectorStatus::CrcError => "CRC error",
+            SectorStatus::Deleted => "deleted",
+            SectorStatus::Missing => "no data",
+            SectorStatus::OddSize => "odd size",
+            SectorStatus::NoId => "no ID",
+            SectorStatus::HighNumber => "numbered 100+",
+        }
+    }
+}
+
+// a track as stored in the image, its sectors in the order they were recorded
+#[derive(Debug)]
+pub(crate) struct Track {
+    pub(crate) header: TrackHeader,
+    pub(crate) cylinder: usize,    // the track header only has a byte for this, see read_tracks
+    pub(crate) sectors: Vec<Sector>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Sector {
+    pub(crate) header: SectorHeader,
+    pub(crate) data: Vec<u8>,  // decoded sector contents
+}
+
+// all the decoded tracks of one image
+#[derive(Debug, Default)]
+pub(crate) struct Disk {
+    pub(crate) tracks: Vec<Track>,
+    pub(crate) stored: u64,                // bytes of the file the tracks were read from
+    pub(crate) encodings: EncodingCounts,  // how a TD0's sectors were stored
+    pub(crate) findings: Vec<Finding>,     // what was unusual about reading it
+}
+
+// how many sectors of a TD0 were stored each way, and how many of each failed validation: their
+// decoded data doesn't match the low byte of its CRC kept in the sector header
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub(crate) struct EncodingCounts {
+    pub(crate) raw: usize,
+    pub(crate) repeated: usize,    // a two byte pattern repeated
+    pub(crate) rle: usize,
+    pub(crate) no_data: usize,     // skipped by DOS allocation or with no data field, so no data block
+    pub(crate) raw_failed: usize,
+    pub(crate) repeated_failed: usize,
+    pub(crate) rle_failed: usize,
+    pub(crate) track_headers_failed: usize,    // whose CRC byte doesn't match the rest of the track header
+    pub(crate) undecodable: usize,             // data blocks that couldn't be decoded, left blank
+}
+
+impl EncodingCounts {
+    pub(crate) fn total(&self) -> usize {
+        self.raw + self.repeated + self.rle + self.no_data + self.undecodable
+    }
+
+    pub(crate) fn failed(&self) -> usize {
+        self.raw_failed + self.repeated_failed + self.rle_failed + self.track_headers_failed + self.undecodable
+    }
+
+    pub(crate) fn add(&mut self, other: &EncodingCounts) {
+        self.raw += other.raw;
+        self.repeated += other.repeated;
+        self.rle += other.rle;
+        self.no_data += other.no_data;
+        self.raw_failed += other.raw_failed;
+        self.repeated_failed += other.repeated_failed;
+        self.rle_failed += other.rle_failed;
+        self.track_headers_failed += other.track_headers_failed;
+        self.undecodable += other.undecodable;
+    }
+
+    // a line per encoding with how many sectors used it and how many of those failed
+    pub(crate) fn describe_methods(&self, language: Language) -> Vec<String> {
+        let t = |english| tr(language, english);
+        [(t("raw"), self.raw, self.raw_failed), (t("repeated"), self.repeated, self.repeated_failed), (t("RLE"), self.rle, self.rle_failed)]
+            .into_iter().map(|(method, sectors, failed)| format!("{} {} {}, {} {}", method, sectors, t("sectors"), failed, t("failed validation")))
+            .chain([format!("{} {} {}", t("track headers"), self.track_headers_failed, t("failed validation"))])
+            .chain((self.undecodable > 0).then(|| format!("{} {} {}", self.undecodable, t("sectors"), t("undecodable"))))
+            .collect()
+    }
+}
+
+// the size an image was stored in against the size of its sectors, and how its sectors were stored
+#[derive(Debug, Default, Clone, serde::Serialize)]
+pub(crate) struct Compression {
+    pub(crate) stored: u64,
+    pub(crate) decoded: u64,
+    #[serde(flatten)]
+    pub(crate) encodings: EncodingCounts,  // all zero for images that aren't TD0s
+}
+
+impl Compression {
+    // the encodings are only mentioned for images that have them
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        let mut text = format!("{} {} {} {} {} ({:.1}%)", t("compression"), self.stored, t("bytes stored"), self.decoded, t("decoded"),
+            if self.decoded > 0 { self.stored as f64 * 100.0 / self.decoded as f64 } else { 0.0 });
+        let e = &self.encodings;
+        if e.total() > 0 {
+            text += &format!(" - {} {} {} {} {} {} {} {}", e.raw, t("raw"), e.repeated, t("repeated"), e.rle, t("RLE"), e.no_data, t("without data"));
+        }
+        if e.failed() > 0 {
+            text += &format!(" - {} {}", e.failed(), t("failed validation"));
+        }
+        text
+    }
+
+    pub(crate) fn add(&mut self, other: &Compression) {
+        self.stored += other.stored;
+        self.decoded += other.decoded;
+        self.encodings.add(&other.encodings);
+    }
+}
+
+impl Disk {
+    // sectors in logical order: tracks by cylinder then head, each track's sectors by sector number.
+    // sectors with quirky IDs aren't part of the format the disk imitates, so they're left out
+    pub(crate) fn logical_sectors(&self) -> Vec<&Sector> {
+        self.logical_track_sectors().into_iter().map(|(_, sector)| sector).collect()
+    }
+
+    // the same, each with the track it's on
+    pub(crate) fn logical_track_sectors(&self) -> Vec<(&Track, &Sector)> {
+        let mut tracks: Vec<&Track> = self.tracks.iter().collect();
+        tracks.sort_by_key(|t| (t.cylinder, t.header.side_number));
+        tracks.into_iter().flat_map(|t| {
+            let mut sectors: Vec<&Sector> = t.sectors.iter().filter(|s| s.header.id_quirk().is_none()).collect();
+            sectors.sort_by_key(|s| s.header.sector_number);
+            sectors.into_iter().map(move |s| (t, s))
+        }).collect()
+    }
+
+    // every place a pattern starts in the sectors' data, with the track and sector and the offset
+    // within it, not overlapping. a match can run on into the next sector in logical order, as files
+    // do, but sectors with quirky IDs are searched on their own
+    pub(crate) fn find(&self, pattern: &[u8]) -> Vec<(&Track, &Sector, usize)> {
+        fn search<'d>(pattern: &[u8], sectors: &[(&'d Track, &'d Sector)], found: &mut Vec<(&'d Track, &'d Sector, usize)>) {
+            let mut starts = Vec::with_capacity(sectors.len());
+            let mut bytes = Vec::new();
+            for (_, sector) in sectors {
+                starts.push(bytes.len());
+                bytes.extend_from_slice(&sector.data);
+            }
+            let mut at = 0;
+            while let Some(offset) = bytes.get(at..).and_then(|rest| rest.windows(pattern.len()).position(|w| w == pattern)) {
+                let position = at + offset;
+                let i = starts.partition_point(|&start| start <= position) - 1;
+                found.push((sectors[i].0, sectors[i].1, position - starts[i]));
+                at = position + pattern.len();
+            }
+        }
+
+        let mut found = Vec::new();
+        if pattern.is_empty() { return found; }
+        search(pattern, &self.logical_track_sectors(), &mut found);
+        for track in &self.tracks {
+            for sector in track.sectors.iter().filter(|s| s.header.id_quirk().is_some()) {
+                search(pattern, &[(track, sector)], &mut found);
+            }
+        }
+        found
+    }
+
+    // the sectors logical_sectors leaves out, in the order they were recorded
+    pub(crate) fn quirk_sectors(&self) -> Vec<&Sector> {
+        self.tracks.iter().flat_map(|t| &t.sectors).filter(|s| s.header.id_quirk().is_some()).collect()
+    }
+
+    pub(crate) fn sector(&self, cylinder: usize, head: u8, sector_number: u8) -> Option<&Sector> {
+        self.tracks.iter()
+            .filter(|t| t.cylinder == cylinder && t.header.side_number == head)
+            .flat_map(|t| &t.sectors)
+            .find(|s| s.header.sector_number == sector_number)
+    }
+
+    pub(crate) fn geometry(&self) -> Geometry {
+        let mut cylinders = std::collections::BTreeSet::new();
+        let mut heads = std::collections::BTreeSet::new();
+        let mut sector_sizes = std::collections::BTreeSet::new();
+        for track in &self.tracks {
+            cylinders.insert(track.cylinder);
+            heads.insert(track.header.side_number);
+            sector_sizes.extend(track.sectors.iter().map(|s| s.header.sector_size));
+        }
+        let per_track = self.tracks.iter().map(|t| t.sectors.len());
+        Geometry {
+            cylinders: cylinders.len(),
+            heads: heads.len(),
+            min_sectors_per_track: per_track.clone().min().unwrap_or(0),
+            max_sectors_per_track: per_track.max().unwrap_or(0),
+            sector_sizes: sector_sizes.into_iter().collect(),
+            capacity: self.tracks.iter().flat_map(|t| &t.sectors).map(|s| s.header.sector_size as u64).sum(),
+        }
+    }
+
+    // tracks unlike the rest: with other than the number of sectors most have, or with sectors of more
+    // than one size. special formats and protection make them, and so do dumps that went wrong
+    pub(crate) fn irregular_tracks(&self) -> Vec<Finding> {
+        let mut per_track: BTreeMap<usize, usize> = BTreeMap::new();
+        for track in &self.tracks {
+            *per_track.entry(track.sectors.len()).or_default() += 1;
+        }
+        // the most tracks have, the larger on a tie
+        let usual = per_track.iter().max_by_key(|&(&sectors, &tracks)| (tracks, sectors)).map(|(&sectors, _)| sectors);
+        let mut findings = Vec::new();
+        for track in &self.tracks {
+            let location = || Location::Track { cylinder: track.cylinder, head: track.header.side_number };
+            if let Some(usual) = usual.filter(|&usual| usual != track.sectors.len()) {
+                findings.push(finding!(Severity::Info, "irregular-track", location(),
+                    "{} sectors where most tracks have {}", track.sectors.len(), usual));
+            }
+            let mut sizes: BTreeMap<u16, usize> = BTreeMap::new();
+            for sector in &track.sectors {
+                *sizes.entry(sector.header.sector_size).or_default() += 1;
+            }
+            if sizes.len() > 1 {
+                let sizes: Vec<String> = sizes.iter().map(|(size, count)| format!("{} × {}", count, size)).collect();
+                findings.push(finding!(Severity::Info, "mixed-sizes", location(), "sectors of more than one size: {} bytes", sizes.join(", ")));
+            }
+        }
+        findings
+    }
+
+    // the decoded contents of the whole disk, in logical order
+    pub(crate) fn logical_bytes(&self) -> Vec<u8> {
+        self.logical_sectors().iter().flat_map(|s| s.data.iter().copied()).collect()
+    }
+
+    // the sector size most of the disk uses, against which odd sizes are noticed
+    pub(crate) fn usual_sector_size(&self) -> u16 {
+        let mut counts: BTreeMap<u16, usize> = BTreeMap::new();
+        for sector in self.tracks.iter().flat_map(|t| &t.sectors) {
+            *counts.entry(sector.header.sector_size).or_default() += 1;
+        }
+        counts.into_iter().max_by_key(|&(_, n)| n).map_or(512, |(size, _)| size)
+    }
+
+    // tracks in logical order, each with the status of its sectors in sector number order
+    pub(crate) fn sector_map(&self) -> Vec<(&Track, Vec<SectorStatus>)> {
+        let usual_size = self.usual_sector_size();
+        let mut tracks: Vec<&Track> = self.tracks.iter().collect();
+        tracks.sort_by_key(|t| (t.cylinder, t.header.side_number));
+        tracks.into_iter().map(|t| {
+            let mut sectors: Vec<&Sector> = t.sectors.iter().collect();
+            sectors.sort_by_key(|s| s.header.sector_number);
+            (t, sectors.iter().map(|s| SectorStatus::of(&s.header, usual_size)).collect())
+        }).collect()
+    }
+
+    pub(crate) fn sector_counts(&self) -> SectorCounts {
+        let mut counts = SectorCounts::default();
+        for status in self.sector_map().into_iter().flat_map(|(_, sectors)| sectors) {
+            match status {
+                SectorStatus::Good => {},
+                SectorStatus::CrcError => counts.crc_errors += 1,
+                SectorStatus::Deleted => counts.deleted += 1,
+                SectorStatus::Missing => counts.missing += 1,
+                SectorStatus::OddSize => counts.odd_size += 1,
+                SectorStatus::NoId => counts.no_id += 1,
+                SectorStatus::HighNumber => counts.high_number += 1,
+            }
+        }
+        counts
+    }
+
+    pub(crate) fn content_counts(&self) -> ContentCounts {
+        let mut counts = ContentCounts::default();
+        let mut total = 0.0;
+        for sector in self.tracks.iter().flat_map(|t| &t.sectors) {
+            match SectorContent::of(&sector.data) {
+                SectorContent::Blank(_) => counts.blank += 1,
+                SectorContent::Text => counts.text += 1,
+                SectorContent::Binary => counts.binary += 1,
+                SectorContent::HighEntropy => counts.high_entropy += 1,
+            }
+            total += entropy(&sector.data);
+        }
+        let sectors = counts.blank + counts.text + counts.binary + counts.high_entropy;
+        if sectors > 0 {
+            counts.entropy = total / sectors as f64;
+        }
+        counts
+    }
+
+    // identifies the disk by what's on it, so the same disk matches whatever its TD0 comment or
+    // compression; None when there's nothing to hash. bytes in the excluded ranges of the logical
+    // image are hashed as zeros so volatile fields don't stop otherwise identical disks matching
+    pub(crate) fn content_hash(&self, exclude: &[Range<usize>]) -> Option<String> {
+        let sectors = self.logical_sectors();
+        if sectors.is_empty() { return None; }
+        let mut hasher = Sha1::new();
+        if exclude.is_empty() {
+            for sector in sectors {
+                hasher.update(&sector.data);
+            }
+        } else {
+            let mut bytes = self.logical_bytes();
+            for range in exclude {
+                let end = range.end.min(bytes.len());
+                if range.start < end {
+                    bytes[range.start..end].fill(0);
+                }
+            }
+            hasher.update(&bytes);
+        }
+        Some(hex::encode(hasher.finalize()))
+    }
+
+    // the CRC32, MD5 and SHA-1 of the decoded sectors in logical order, as preservation databases
+    // list disks by. None when there's nothing to hash
+    pub(crate) fn checksums(&self) -> Option<Checksums> {
+        let sectors = self.logical_sectors();
+        if sectors.is_empty() { return None; }
+        let mut crc32 = flate2::Crc::new();
+        let mut md5 = Md5::new();
+        let mut sha1 = Sha1::new();
+        for sector in sectors {
+            crc32.update(&sector.data);
+            md5.update(&sector.data);
+            sha1.update(&sector.data);
+        }
+        Some(Checksums { crc32: format!("{:08x}", crc32.sum()), md5: hex::encode(md5.finalize()), sha1: hex::encode(sha1.finalize()) })
+    }
+
+    // a SHA-1 of each track's sectors in sector number order, keyed by cylinder and head
+    pub(crate) fn track_hashes(&self) -> Vec<((usize, u8), [u8; 20])> {
+        let mut hashes: Vec<((usize, u8), [u8; 20])> = self.tracks.iter().map(|t| {
+            let mut sectors: Vec<&Sector> = t.sectors.iter().collect();
+            sectors.sort_by_key(|s| s.header.sector_number);
+            let mut hasher = Sha1::new();
+            for sector in sectors {
+                hasher.update(&sector.data);
+            }
+            ((t.cylinder, t.header.side_number), hasher.finalize().into())
+        }).collect();
+        hashes.sort();
+        hashes
+    }
+
+    // each track's contents in sector number order, the tracks in logical order
+    pub(crate) fn track_contents(&self) -> Vec<(TrackPosition, Vec<u8>)> {
+        let mut tracks: Vec<&Track> = self.tracks.iter().collect();
+        tracks.sort_by_key(|t| (t.cylinder, t.header.side_number));
+        tracks.into_iter().map(|t| {
+            let mut sectors: Vec<&Sector> = t.sectors.iter().collect();
+            sectors.sort_by_key(|s| s.header.sector_number);
+            ((t.cylinder, t.header.side_number), sectors.iter().flat_map(|s| s.data.iter().copied()).collect())
+        }).collect()
+    }
+
+    pub(crate) fn track_repeats(&self) -> TrackRepeats {
+        let tracks = self.track_contents();
+        // positions in logical order of the tracks with each contents, in order of first appearance
+        let mut by_contents: Vec<(&[u8], Vec<usize>)> = Vec::new();
+        let mut index: std::collections::HashMap<&[u8], usize> = std::collections::HashMap::new();
+        for (i, (_, data)) in tracks.iter().enumerate() {
+            match index.get(data.as_slice()) {
+                Some(&group) => by_contents[group].1.push(i),
+                None => {
+                    index.insert(data, by_contents.len());
+                    by_contents.push((data, vec![i]));
+                },
+            }
+        }
+
+        let groups: Vec<RepeatedTracks> = by_contents.iter().filter(|(_, members)| members.len() > 1).map(|(data, members)| {
+            let mut ranges: Vec<(TrackPosition, TrackPosition)> = Vec::new();
+            for (n, &i) in members.iter().enumerate() {
+                match ranges.last_mut() {
+                    Some(range) if members[n - 1] + 1 == i => range.1 = tracks[i].0,
+                    _ => ranges.push((tracks[i].0, tracks[i].0)),
+                }
+            }
+            let filler = data.first().copied().filter(|&b| data.iter().all(|&d| d == b));
+            RepeatedTracks { ranges, count: members.len(), filler }
+        }).collect();
+
+        // only one of each set of identical tracks need be compared, and a comparison can stop as
+        // soon as there are too many differences
+        let mut near = Vec::new();
+        for (a, (a_data, a_members)) in by_contents.iter().enumerate() {
+            for (b_data, b_members) in &by_contents[a + 1..] {
+                if a_data.len() != b_data.len() { continue; }
+                let mut differences = a_data.iter().zip(b_data.iter()).enumerate().filter(|(_, (x, y))| x != y).map(|(offset, _)| offset);
+                let Some(first_offset) = differences.next() else { continue };
+                let bytes = 1 + differences.take(NEAR_TRACK_BYTES).count();
+                if bytes <= NEAR_TRACK_BYTES {
+                    near.push(NearTracks { track: tracks[a_members[0]].0, like: tracks[b_members[0]].0, bytes, first_offset });
+                }
+            }
+        }
+        let repeated = groups.iter().map(|group| group.count - 1).sum();
+        TrackRepeats { repeated, groups, near }
+    }
+
+    pub(crate) fn boot_sector(&self) -> Option<BootSector> {
+        self.logical_sectors().first().map(|s| BootSector::from_bytes(&s.data))
+    }
+
+    // the BPB in the first logical sector, if there is one
+    pub(crate) fn bpb(&self) -> Option<Bpb> {
+        self.logical_sectors().first().and_then(|s| Bpb::from_boot_sector(&s.data))
+    }
+
+    // the primary partitions from the master boot record, followed by the logical partitions in
+    // the chain of extended boot records that an extended partition holds
+    pub(crate) fn partitions(&self) -> Vec<Partition> {
+        let Some(boot) = self.boot_sector() else { return Vec::new() };
+        let bytes = self.logical_bytes();
+        let mut partitions = boot.partitions.clone();
+
+        for extended in boot.partitions.iter().filter(|p| p.is_extended()) {
+            let mut ebr = extended.start;
+            // the limit stops a chain that loops back on itself
+            for index in 5..=64 {
+                let Some(sector) = bytes.get(ebr as usize * 512..ebr as usize * 512 + 512) else { break };
+                let entries = Partition::entries(sector, ebr, index);
+                let Some(logical) = entries.iter().find(|p| p.slot == 0 && !p.is_extended()) else { break };
+                partitions.push(logical.clone());
+                // the link to the next EBR counts from the start of the extended partition, not this EBR
+                match entries.iter().find(|p| p.slot == 1 && p.is_extended()) {
+                    Some(next) => ebr = extended.start + (next.start - ebr),
+                    None => break,
+                }
+            }
+        }
+        partitions
+    }
+
+    // the FAT filesystems on the disk: the whole disk if it starts with a BPB, otherwise any in the
+    // partitions of a hard disk's partition table
+    pub(crate) fn fat_volumes(&self) -> Vec<FatVolume> {
+        let bytes = self.logical_bytes();
+        if let Some(bpb) = self.bpb() {
+            return vec![FatVolume { partition: None, offset: 0, size: bytes.len(), bpb }];
+        }
+        // whatever the partition type says, it's the BPB that shows there's a FAT filesystem
+        self.partitions().iter().filter(|p| !p.is_extended()).filter_map(|p| {
+            // partition tables count in 512 byte sectors
+            let offset = p.start as usize * 512;
+            let bpb = Bpb::from_boot_sector(bytes.get(offset..)?)?;
+            Some(FatVolume { partition: Some(p.index), offset, size: p.sectors as usize * 512, bpb })
+        }).collect()
+    }
+
+    // the FAT root directory located by the BPB, cut short if the disk is
+    pub(crate) fn fat_root_dir(&self, volume: &FatVolume) -> Vec<u8> {
+        let bytes = self.logical_bytes();
+        let range = volume.bpb.root_dir_range();
+        let (start, end) = (volume.offset + range.start, volume.offset + range.end);
+        bytes[start.min(bytes.len())..end.min(bytes.len())].to_vec()
+    }
+
+    // whether the FAT filesystems agree with themselves: each BPB's size matches its volume and the
+    // copies of the FAT are the same. None when there's no BPB to check
+    pub(crate) fn fat_consistent(&self) -> Option<bool> {
+        let volumes = self.fat_volumes();
+        if volumes.is_empty() { return None; }
+        let bytes = self.logical_bytes();
+        Some(volumes.iter().all(|volume| {
+            let bpb = &volume.bpb;
+            let size_ok = bpb.total_sectors == 0
+                || bpb.total_sectors as usize * bpb.bytes_per_sector as usize == volume.size.min(bytes.len() - volume.offset);
+            let fats: Vec<&[u8]> = bpb.fat_ranges().into_iter()
+                .map(|r| (volume.offset + r.start).min(bytes.len())..(volume.offset + r.end).min(bytes.len()))
+                .map(|r| &bytes[r])
+                .collect();
+            size_ok && fats.windows(2).all(|pair| pair[0] == pair[1])
+        }))
+    }
+
+    // how the clusters of each FAT filesystem are used. every directory is followed from the root
+    // to find the clusters files have, and the rest the FAT says are in use are lost
+    pub(crate) fn fat_usage(&self) -> Vec<FatUsage> {
+        let bytes = self.logical_bytes();
+        self.fat_volumes().iter().map(|volume| {
+            let data = &bytes[volume.offset.min(bytes.len())..(volume.offset + volume.size).min(bytes.len())];
+            let bpb = &volume.bpb;
+            let fat = Fat::new(data, bpb);
+            let mut reached = vec![false; fat.clusters + 2];
+            let mut directories = vec![self.fat_root_dir(volume)];
+            while let Some(directory) = directories.pop() {
+                for dent in directory.chunks_exact(DENT_SIZE).take_while(|dent| dent[0] != 0x00) {
+                    // deleted entries, volume labels and long names, and a directory's entries for itself
+                    // and its parent
+                    if dent[0] == 0xe5 || dent[0] == b'.' || dent[0x0b] & 0x08 != 0 { continue; }
+                    let chain = fat.chain(first_cluster(dent));
+                    // a directory already followed, as one cross linked with another would be, isn't again
+                    let new = chain.iter().any(|&n| !reached[n]);
+                    for &n in &chain {
+                        reached[n] = true;
+                    }
+                    if dent[0x0b] & 0x10 != 0 && new {
+                        directories.push(chain.iter().flat_map(|&n| bpb.cluster(data, n)).copied().collect());
+                    }
+                }
+            }
+
+            let mut usage = FatUsage { partition: volume.partition, cluster_size: bpb.cluster_size(), clusters: fat.clusters, ..Default::default() };
+            let mut lost = Vec::new();
+            for (n, &reached) in reached.iter().enumerate().skip(2) {
+                match fat.entry(n) {
+                    Some(ClusterEntry::Free) => usage.free += 1,
+                    Some(ClusterEntry::Bad) => usage.bad += 1,
+                    Some(entry) => {
+                        usage.used += 1;
+                        if !reached { lost.push((n, entry)); }
+                    },
+                    None => {},
+                }
+            }
+            // a lost chain starts at each lost cluster no other lost cluster leads to, and lost
+            // clusters all leading on to each other are a chain looping back on itself
+            let continued: BTreeSet<usize> = lost.iter().filter_map(|&(_, entry)| match entry {
+                ClusterEntry::Next(next) => Some(next),
+                _ => None,
+            }).collect();
+            usage.lost_clusters = lost.len();
+            usage.lost_chains = lost.iter().filter(|(n, _)| !continued.contains(n)).count().max(usize::from(!lost.is_empty()));
+            usage
+        }).collect()
+    }
+
+    // the TR-DOS system sector, sector 9 of the first track, if it has the TR-DOS id and a disk type
+    pub(crate) fn trdos_system(&self, codepage: Codepage) -> Option<TrDosSystem> {
+        let data = &self.sector(0, 0, 9)?.data;
+        if data.len() != 256 || data[0xe7] != 0x10 || !(0x16..=0x19).contains(&data[0xe3]) || data[0xe4] > 128 {
+            return None;
+        }
+        Some(TrDosSystem {
+            label: codepage.decode(&data[0xf5..0xfd]).trim_end().to_string(),
+            disk_type: data[0xe3],
+            files: data[0xe4],
+            deleted: data[0xf4],
+            free_sectors: u16::from_le_bytes([data[0xe5], data[0xe6]]),
+        })
+    }
+
+    // the Amstrad CP/M format, from the disk specification block the PCW and Spectrum +3 put in the
+    // first sector, or else from how the first track's sectors are numbered. IBM format is the same
+    // shape as a PC disk, so it's only taken as Amstrad's when the directory is already CP/M
+    pub(crate) fn amstrad_format(&self, filesystem: Option<&str>) -> Option<AmstradFormat> {
+        let first_track: Vec<&Sector> = self.tracks.iter()
+            .filter(|t| t.cylinder == 0 && t.header.side_number == 0)
+            .flat_map(|t| &t.sectors)
+            .collect();
+        let first_id = first_track.iter().map(|s| s.header.sector_number).min()?;
+        let last_id = first_track.iter().map(|s| s.header.sector_number).max()?;
+        let data = &first_track.iter().find(|s| s.header.sector_number == first_id)?.data;
+
+        let spec_block = data.len() >= 16 && data[0] <= 3 && data[1] & 0x03 <= 2 && (35..=83).contains(&data[2])
+            && (8..=10).contains(&data[3]) && data[4] == 2 && data[6] <= 4;
+        let name = match (spec_block, first_id) {
+            (true, _) => ["PCW/+3", "CPC SYSTEM", "CPC DATA", "PCW double sided"][data[0] as usize],
+            (false, 0xc1) => "CPC DATA",
+            (false, 0x41) => "CPC SYSTEM",
+            (false, 0x01) if last_id == 8 && filesystem == Some("CP/M") => "IBM",
+            _ => return None,
+        };
+
+        // files are written a sector at a time so a header is always at the start of one
+        let (mut amsdos_headers, mut plus3dos_headers) = (0, 0);
+        for sector in self.logical_sectors().iter().filter(|s| s.data.len() >= 128) {
+            let d = &sector.data[..128];
+            if d.starts_with(b"PLUS3DOS\x1a") && d[127] == d[..127].iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) {
+                plus3dos_headers += 1;
+            } else {
+                let checksum = d[..67].iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+                if checksum != 0 && checksum == u16::from_le_bytes([d[67], d[68]]) && d[0] <= 15
+                        && d[1..12].iter().all(|&b| (0x20..=0x7e).contains(&(b & 0x7f))) {
+                    amsdos_headers += 1;
+                }
+            }
+        }
+        Some(AmstradFormat { name, spec_block, amsdos_headers, plus3dos_headers })
+    }
+
+    // an Apple II DOS 3.3 or ProDOS catalogue, for disks of 16 sector, 256 byte tracks. the ProDOS
+    // volume directory is tried with blocks both in DOS 3.3 sector order and straight through,
+    // as images are found both ways
+    pub(crate) fn apple_catalogue(&self) -> Option<AppleCatalogue> {
+        let sectors = self.logical_sectors();
+        if sectors.is_empty() || sectors.iter().any(|s| s.header.sector_size != 256) {
+            return None;
+        }
+        let bytes = self.logical_bytes();
+        let sector = |track: u8, sector: u8| apple_sector(&bytes, track, sector);
+        apple_dos_catalogue(&bytes).or_else(|| {
+            // the two DOS 3.3 sectors holding each of the eight blocks of a track
+            const HALVES: [(u8, u8); 8] = [(0, 14), (13, 12), (11, 10), (9, 8), (7, 6), (5, 4), (3, 2), (1, 15)];
+            let dos_order = |block: u16| {
+                let (track, (first, second)) = ((block / 8) as u8, HALVES[block as usize % 8]);
+                Some([sector(track, first)?, sector(track, second)?].concat())
+            };
+            let straight = |block: u16| bytes.get(block as usize * 512..).and_then(|rest| rest.get(..512)).map(<[u8]>::to_vec);
+            prodos_catalogue(&dos_order).or_else(|| prodos_catalogue(&straight))
+        })
+    }
+
+    // the live entries of the TR-DOS catalogue in sectors 1 to 8 of the first track
+    pub(crate) fn trdos_catalogue(&self) -> Vec<&[u8]> {
+        (1..=8).filter_map(|s| self.sector(0, 0, s))
+            .flat_map(|sector| sector.data.chunks_exact(TRDOS_DENT_SIZE))
+            .take_while(|dent| dent[0] != 0x00)
+            .filter(|dent| dent[0] != 0x01)
+            .collect()
+    }
+
+    // the FAT volume label, from the root directory entry with the volume attribute set
+    pub(crate) fn fat_volume_label(&self, volume: &FatVolume, codepage: Codepage) -> Option<String> {
+        let root_dir = self.fat_root_dir(volume);
+        root_dir.chunks_exact(DENT_SIZE)
+            .take_while(|dent| dent[0] != 0x00)
+            .find(|dent| dent[0] != 0xe5 && dent[0x0b] & 0x08 != 0 && dent[0x0b] != 0x0f) // 0x0f is a long name
+            .map(|dent| codepage.decode(&dent[..11]).trim_end().to_string())
+    }
+}
+
+// the shape of the disk as found in the image, rather than as the header claims
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Geometry {
+    pub(crate) cylinders: usize,
+    pub(crate) heads: usize,
+    pub(crate) min_sectors_per_track: usize,   // these differ on disks with odd tracks
+    pub(crate) max_sectors_per_track: usize,
+    pub(crate) sector_sizes: Vec<u16>,
+    pub(crate) capacity: u64,                  // bytes, counting every sector found
+}
+
+impl Geometry {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        let range = |min: usize, max: usize| if min == max { min.to_string() } else { format!("{}-{}", min, max) };
+        format!("{} {} {} {} {} {} {} {} {} - {} {} ({}K)",
+            t("geometry"), self.cylinders, t("cylinders"), self.heads, t("heads"),
+            range(self.min_sectors_per_track, self.max_sectors_per_track), t("sectors/track"),
+            self.sector_sizes.iter().map(u16::to_string).collect::<Vec<_>>().join("/"), t("bytes/sector"),
+            self.capacity, t("bytes"), self.capacity / 1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::flat_disk;
+
+    #[test]
+    fn checksums_of_the_logical_image() {
+        // two sectors, given out of order
+        let bytes: Vec<u8> = (0..=255).cycle().take(1024).collect();
+        let mut disk = flat_disk(&bytes, 1, 1, 2, 512);
+        disk.tracks[0].sectors.reverse();
+        let checksums = disk.checksums().unwrap();
+        assert_eq!(checksums, Checksums {
+            crc32: "b70b4c26".to_string(),
+            md5: "b2ea9f7fcea831a4a63b213f41a8855b".to_string(),
+            sha1: "5b00669c480d5cffbdfa8bdba99561160f2d1b77".to_string(),
+        });
+        assert_eq!(disk.content_hash(&[]), Some(checksums.sha1));
+        assert_eq!(Disk::default().checksums(), None);
+    }
+
+    #[test]
+    fn fat_usage_follows_directories() {
+        // a 160K floppy: a file in clusters 2 and 3, a subdirectory in 4 holding a file in 5, 6
+        // marked bad, and chains of 7 and 8, and of 9, that nothing leads to
+        let mut img = vec![0; 320 * 512];
+        img[..0x20].copy_from_slice(&[0xeb, 0x3c, 0x90, b'K', b'C', b'8', b'5', b'8', b'7', b' ', b' ',
+            0x00, 0x02, 1, 1, 0, 2, 64, 0, 0x40, 0x01, 0xfe, 1, 0, 8, 0, 1, 0, 0, 0, 0, 0]);
+        img[510..512].copy_from_slice(&[0x55, 0xaa]);
+        let entries: [u16; 10] = [0xffe, 0xfff, 3, 0xfff, 0xfff, 0xfff, 0xff7, 8, 0xfff, 0xfff];
+        for (n, pair) in entries.chunks(2).enumerate() {
+            let packed = pair[0] as u32 | (pair[1] as u32) << 12;
+            img[512 + n * 3..512 + n * 3 + 3].copy_from_slice(&packed.to_le_bytes()[..3]);
+        }
+        let dent = |img: &mut [u8], at: usize, name: &[u8; 11], attr: u8, cluster: u16| {
+            img[at..at + 11].copy_from_slice(name);
+            img[at + 0x0b] = attr;
+            img[at + 0x1a..at + 0x1c].copy_from_slice(&cluster.to_le_bytes());
+        };
+        dent(&mut img, 3 * 512, b"FILE    TXT", 0x20, 2);
+        dent(&mut img, 3 * 512 + 32, b"SUB        ", 0x10, 4);
+        dent(&mut img, 3 * 512 + 64, b"\xe5ONE    TXT", 0x20, 10);
+        // cluster 4 is sector 9
+        dent(&mut img, 9 * 512, b".          ", 0x10, 4);
+        dent(&mut img, 9 * 512 + 32, b"..         ", 0x10, 0);
+        dent(&mut img, 9 * 512 + 64, b"INNER   TXT", 0x20, 5);
+
+        let usage = flat_disk(&img, 40, 1, 8, 512).fat_usage();
+        assert_eq!(usage, [FatUsage { partition: None, cluster_size: 512, clusters: 313, used: 7, free: 305, bad: 1, lost_chains: 2, lost_clusters: 3 }]);
+        assert_eq!(usage[0].describe(Language::En), "FAT 313 clusters of 512 bytes - 7 used, 305 free, 1 marked bad, 2 lost chains of 3 clusters");
+    }
+
+    #[test]
+    fn amstrad_formats_and_file_headers() {
+        let amstrad = |img: &[u8], first_id: u8, filesystem| {
+            let mut disk = flat_disk(img, 40, 1, 9, 512);
+            for track in &mut disk.tracks {
+                for sector in &mut track.sectors {
+                    sector.header.sector_number += first_id - 1;
+                }
+            }
+            disk.amstrad_format(filesystem).map(|f| (f.name, f.spec_block, f.amsdos_headers, f.plus3dos_headers))
+        };
+        let mut img = vec![0xe5; 40 * 9 * 512];
+        assert_eq!(amstrad(&img, 0xc1, None), Some(("CPC DATA", false, 0, 0)));
+        assert_eq!(amstrad(&img, 0x41, None), Some(("CPC SYSTEM", false, 0, 0)));
+        // IBM format is only Amstrad's on a CP/M disk, and eight sectors a track
+        assert_eq!(amstrad(&img, 1, Some("CP/M")), None);
+
+        // a +3 disk specification block, a file with a +3DOS header and one with an AMSDOS header
+        img[..10].copy_from_slice(&[0, 0, 40, 9, 2, 1, 3, 2, 0x2a, 0x52]);
+        let plus3 = 9 * 512;
+        img[plus3..plus3 + 128].fill(0);
+        img[plus3..plus3 + 9].copy_from_slice(b"PLUS3DOS\x1a");
+        img[plus3 + 127] = img[plus3..plus3 + 127].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let amsdos = 10 * 512;
+        img[amsdos..amsdos + 128].fill(0);
+        img[amsdos + 1..amsdos + 12].copy_from_slice(b"GAME    BAS");
+        let checksum = img[amsdos..amsdos + 67].iter().fold(0u16, |sum, &b| sum + b as u16);
+        img[amsdos + 67..amsdos + 69].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(amstrad(&img, 1, None), Some(("PCW/+3", true, 1, 1)));
+    }
+
+    #[test]
+    fn sector_contents() {
+        assert!(matches!(SectorContent::of(&[0xe5; 512]), SectorContent::Blank(0xe5)));
+        let mut text = b"10 PRINT \"HELLO\"\r\n20 GOTO 10\r\n".to_vec();
+        text.resize(512, 0x1a);
+        assert!(matches!(SectorContent::of(&text), SectorContent::Text));
+        let all: Vec<u8> = (0..=255).chain(0..=255).collect();
+        assert!(matches!(SectorContent::of(&all), SectorContent::HighEntropy));
+        let mut code = vec![0; 512];
+        code[..8].copy_from_slice(&[0xc3, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        code[511] = 0xff;
+        assert!(matches!(SectorContent::of(&code), SectorContent::Binary));
+        assert_eq!(entropy(&[7; 64]), 0.0);
+        assert_eq!(entropy(&all), 8.0);
+    }
+
+    #[test]
+    fn finds_repeated_and_near_tracks() {
+        let mut img = vec![0xe5; 6 * 9 * 512];
+        img[2 * 9 * 512..3 * 9 * 512].fill(0x11);
+        img[3 * 9 * 512..5 * 9 * 512].fill(0x11);
+        img[4 * 9 * 512 + 0x300] = 0x12;
+        let repeats = flat_disk(&img, 6, 1, 9, 512).track_repeats();
+        let groups: Vec<_> = repeats.groups.iter().map(|g| (track_ranges(&g.ranges), g.count, g.filler)).collect();
+        assert_eq!(groups, [("0/0-1/0 5/0".to_string(), 3, Some(0xe5)), ("2/0-3/0".to_string(), 2, Some(0x11))]);
+        let near: Vec<_> = repeats.near.iter().map(|n| (n.track, n.like, n.bytes, n.first_offset)).collect();
+        assert_eq!(near, [((2, 0), (4, 0), 1, 0x300)]);
+    }
+
+    #[test]
+    fn irregular_tracks() {
+        let mut disk = flat_disk(&[0; 4 * 4 * 256], 4, 1, 4, 256);
+        assert!(disk.irregular_tracks().is_empty());
+        disk.tracks[1].sectors.pop();
+        disk.tracks[2].sectors[3].header.sector_size = 512;
+        let findings: Vec<_> = disk.irregular_tracks().iter().map(|f| f.describe(Language::En)).collect();
+        assert_eq!(findings, [
+            "info irregular-track - cylinder 1 head 0 - 3 sectors where most tracks have 4",
+            "info mixed-sizes - cylinder 2 head 0 - sectors of more than one size: 3 × 256, 1 × 512 bytes",
+        ]);
+    }
+
+    #[test]
+    fn geometry_and_logical_order() {
+        let img: Vec<u8> = (0..2 * 2 * 4).flat_map(|n| [n as u8; 256]).collect();
+        let mut disk = flat_disk(&img, 2, 2, 4, 256);
+        disk.tracks.swap(0, 3);
+        disk.tracks[1].sectors.reverse();
+        disk.tracks[2].sectors.pop();
+        let geometry = disk.geometry();
+        assert_eq!((geometry.cylinders, geometry.heads, geometry.min_sectors_per_track, geometry.max_sectors_per_track),
+            (2, 2, 3, 4));
+        assert_eq!(geometry.describe(Language::En), "geometry 2 cylinders 2 heads 3-4 sectors/track 256 bytes/sector - 3840 bytes (3K)");
+        let order: Vec<u8> = disk.logical_sectors().iter().map(|s| s.data[0]).collect();
+        assert_eq!(order, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 12, 13, 14, 15]);
+        assert_eq!(disk.sector(1, 1, 4).map(|s| s.data[0]), Some(15));
+        assert_eq!(disk.usual_sector_size(), 256);
+    }
+
+    #[test]
+    fn encoding_counts_add_up() {
+        let mut counts = EncodingCounts { raw: 3, repeated: 2, rle: 1, no_data: 4, raw_failed: 1, undecodable: 1, ..Default::default() };
+        counts.add(&EncodingCounts { rle: 1, rle_failed: 1, ..Default::default() });
+        assert_eq!((counts.total(), counts.failed()), (12, 3));
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{serialize_track_ranges, track_ranges, Disk, Track, TrackPosition, TrackRepeats};
+use crate::report::{tr, Language};
+
+// the signs of copy protection looked for: code, description, and how much each adds to the
+// likelihood. a disk shows most of them only if it was made to, though CRC errors are as often damage
+pub(crate) const PROTECTION_SIGNS: &[(&str, &str, usize)] = &[
+    ("duplicate-ids", "duplicate sector IDs", 30),
+    ("mixed-sizes", "mixed sector sizes", 25),
+    ("quirky-ids", "sectors without IDs or numbered 100 or more", 25),
+    ("beyond-79", "cylinders beyond 79", 20),
+    ("missing-numbers", "gaps in sector numbers", 15),
+    ("extra-sectors", "extra sectors", 15),
+    ("deleted-data", "deleted data", 15),
+    ("crc-errors", "CRC errors", 15),
+    ("near-identical", "near identical tracks", 10),
+];
+
+// CRC errors on more tracks than this look like a damaged disk rather than a protected one
+pub(crate) const PROTECTION_CRC_TRACKS: usize = 3;
+
+// floppy drives step a few cylinders past 79, where protection tracks hide. disks going further
+// than this are some bigger format
+pub(crate) const FLOPPY_LAST_CYLINDER: usize = 85;
+
+// the signs of copy protection found on a disk, each with the tracks they turned up on
+#[derive(Debug, Default, serde::Serialize)]
+pub(crate) struct Protection {
+    pub(crate) likelihood: u8,     // 0 to 100
+    pub(crate) signs: Vec<ProtectionSign>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ProtectionSign {
+    #[serde(serialize_with = "sign_code")]
+    pub(crate) sign: usize,        // index into PROTECTION_SIGNS
+    #[serde(serialize_with = "serialize_track_ranges")]
+    pub(crate) tracks: Vec<(TrackPosition, TrackPosition)>,    // runs of tracks
+}
+
+fn sign_code<S: serde::Serializer>(&sign: &usize, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(PROTECTION_SIGNS[sign].0)
+}
+
+impl Protection {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        format!("{} {}% {}{}", t("protection"), self.likelihood, t("likely"), self.signs.iter()
+            .map(|sign| format!(" - {} {}", t(PROTECTION_SIGNS[sign.sign].1), track_ranges(&sign.tracks))).collect::<String>())
+    }
+}
+
+impl Disk {
+    // which of PROTECTION_SIGNS the disk shows, and on which tracks
+    pub(crate) fn protection(&self, repeats: &TrackRepeats) -> Protection {
+        let mut tracks: Vec<&Track> = self.tracks.iter().collect();
+        tracks.sort_by_key(|t| (t.cylinder, t.header.side_number));
+        let mut counts: BTreeMap<usize, usize> = BTreeMap::new();
+        for track in &tracks {
+            *counts.entry(track.sectors.len()).or_default() += 1;
+        }
+        let usual_count = counts.into_iter().max_by_key(|&(_, n)| n).map_or(0, |(count, _)| count);
+        let floppy = tracks.last().is_some_and(|t| t.cylinder <= FLOPPY_LAST_CYLINDER);
+        let near: Vec<TrackPosition> = repeats.near.iter().flat_map(|near| [near.track, near.like]).collect();
+
+        // for each sign, the indexes into tracks it shows on
+        let mut found = vec![Vec::new(); PROTECTION_SIGNS.len()];
+        for (i, track) in tracks.iter().enumerate() {
+            let mut numbers: Vec<u8> = track.sectors.iter().filter(|s| s.header.id_quirk().is_none())
+                .map(|s| s.header.sector_number).collect();
+            numbers.sort();
+            let duplicates = numbers.windows(2).any(|w| w[0] == w[1]);
+            numbers.dedup();
+            let sizes: BTreeSet<u16> = track.sectors.iter().map(|s| s.header.sector_size).collect();
+            let shows = [
+                duplicates,
+                sizes.len() > 1,
+                track.sectors.iter().any(|s| s.header.id_quirk().is_some()),
+                track.cylinder > 79 && floppy,
+                numbers.windows(2).any(|w| w[1] - w[0] > 1),
+                track.sectors.len() > usual_count,
+                track.sectors.iter().any(|s| s.header.flags & 0x04 != 0),
+                track.sectors.iter().any(|s| s.header.flags & 0x02 != 0),
+                near.contains(&(track.cylinder, track.header.side_number)),
+            ];
+            for (sign, _) in shows.iter().enumerate().filter(|(_, &shows)| shows) {
+                found[sign].push(i);
+            }
+        }
+        let crc = PROTECTION_SIGNS.iter().position(|(code, ..)| *code == "crc-errors").unwrap();
+        if found[crc].len() > PROTECTION_CRC_TRACKS {
+            found[crc].clear();
+        }
+
+        let mut protection = Protection::default();
+        let mut likelihood = 0;
+        for (sign, indexes) in found.into_iter().enumerate().filter(|(_, indexes)| !indexes.is_empty()) {
+            // tracks next to each other in logical order make a run
+            let mut ranges: Vec<(TrackPosition, TrackPosition, usize)> = Vec::new();
+            for i in indexes {
+                let position = (tracks[i].cylinder, tracks[i].header.side_number);
+                match ranges.last_mut() {
+                    Some((_, last, at)) if *at + 1 == i => (*last, *at) = (position, i),
+                    _ => ranges.push((position, position, i)),
+                }
+            }
+            protection.signs.push(ProtectionSign { sign, tracks: ranges.into_iter().map(|(first, last, _)| (first, last)).collect() });
+            likelihood += PROTECTION_SIGNS[sign].2;
+        }
+        protection.likelihood = likelihood.min(100) as u8;
+        protection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kc8587::td0::SectorHeader;
+    use crate::{analysis::{NearTracks, Sector}, formats::flat_disk};
+
+    fn disk(cylinders: usize) -> Disk {
+        flat_disk(&vec![0xe5; cylinders * 9 * 512], cylinders, 1, 9, 512)
+    }
+
+    // each sign found by its code, with the tracks it was found on
+    fn signs(protection: &Protection) -> Vec<(&'static str, String)> {
+        protection.signs.iter().map(|sign| (PROTECTION_SIGNS[sign.sign].0, track_ranges(&sign.tracks))).collect()
+    }
+
+    #[test]
+    fn an_ordinary_disk_shows_nothing() {
+        let protection = disk(40).protection(&TrackRepeats::default());
+        assert!(protection.signs.is_empty());
+        assert_eq!(protection.likelihood, 0);
+    }
+
+    #[test]
+    fn finds_each_sign_on_its_tracks() {
+        let mut disk = disk(40);
+        disk.tracks[3].sectors[1].header.sector_number = 1;
+        disk.tracks[4].sectors[8].header.sector_size = 1024;
+        disk.tracks[5].sectors[0].header.sector_number = 0xff;
+        disk.tracks[6].sectors.remove(4);
+        let header = SectorHeader { sector_number: 10, ..disk.tracks[7].sectors[0].header };
+        disk.tracks[7].sectors.push(Sector { header, data: vec![0; 512] });
+        disk.tracks[8].sectors[2].header.flags = 0x04;
+        disk.tracks[11].sectors[2].header.flags = 0x02;
+        disk.tracks[12].sectors[2].header.flags = 0x02;
+        let repeats = TrackRepeats {
+            near: vec![NearTracks { track: (20, 0), like: (21, 0), bytes: 2, first_offset: 0x100 }],
+            ..Default::default()
+        };
+        let protection = disk.protection(&repeats);
+        assert_eq!(signs(&protection), [
+            ("duplicate-ids", "3/0".to_string()),
+            ("mixed-sizes", "4/0".to_string()),
+            ("quirky-ids", "5/0".to_string()),
+            ("missing-numbers", "3/0 6/0".to_string()),
+            ("extra-sectors", "7/0".to_string()),
+            ("deleted-data", "8/0".to_string()),
+            ("crc-errors", "11/0-12/0".to_string()),
+            ("near-identical", "20/0-21/0".to_string()),
+        ]);
+        assert_eq!(protection.likelihood, 100);
+        assert_eq!(serde_json::to_value(&protection).unwrap()["signs"][6], serde_json::json!({ "sign": "crc-errors", "tracks": "11/0-12/0" }));
+    }
+
+    #[test]
+    fn crc_errors_on_many_tracks_are_damage() {
+        let mut disk = disk(40);
+        for track in &mut disk.tracks[10..10 + PROTECTION_CRC_TRACKS + 1] {
+            track.sectors[0].header.flags = 0x02;
+        }
+        assert!(disk.protection(&TrackRepeats::default()).signs.is_empty());
+    }
+
+    #[test]
+    fn cylinders_beyond_79_only_on_floppies() {
+        let protection = disk(82).protection(&TrackRepeats::default());
+        assert_eq!(signs(&protection), [("beyond-79", "80/0-81/0".to_string())]);
+        assert_eq!(protection.likelihood, 20);
+        assert_eq!(protection.describe(Language::En), "protection 20% likely - cylinders beyond 79 80/0-81/0");
+        assert!(disk(FLOPPY_LAST_CYLINDER + 2).protection(&TrackRepeats::default()).signs.is_empty());
+    }
+}
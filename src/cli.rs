@@ -0,0 +1,1156 @@
+use std::{
+    io::{self, ErrorKind, Write},
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+};
+use chrono::NaiveDate;
+use clap::{CommandFactory, Parser, Subcommand};
+use serde_json::{json, Value};
+use kc8587::td0::{CommentHeader, ImageHeader, MAX_SIZE_CODE};
+
+use crate::{
+    fs::{Codepage, DetectorKind, FilesystemDetector, Thresholds},
+    report::{comment_timestamp, parse_only, Language, OnlyFilter, Scan},
+    flat_disk, Disk,
+};
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true, args_override_self = true)]
+#[command(after_help = "Exit status: 0 all images valid, 1 some images failed validation, \
+    3 no TD0 images found, 4 I/O error (2 is a command line error)")]
+pub(crate) struct Args {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Show every detail of each image. Diagnostics go to stderr: errors and warnings always, notes
+    /// on what was skipped and why with -v, each sector failing its CRC with -vv, every sector read
+    /// with -vvv. RUST_LOG overrides
+    #[clap(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub(crate) verbose: u8,
+
+    /// Print no per-image output; problems are still reported on stderr and in the exit status
+    #[clap(short, long)]
+    pub(crate) quiet: bool,
+
+    /// Only print the path of each TD0 whose header is good, container and member included, without
+    /// reading any further into it. Images that aren't still count in the exit status
+    #[clap(long)]
+    pub(crate) list: bool,
+
+    /// With --list, end each path with a nul rather than a newline, for xargs -0
+    #[clap(short = '0', long, requires = "list")]
+    pub(crate) null: bool,
+
+    /// Show full verbose detail for only every Nth image, and a one-line summary for the rest
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["verbose", "quiet"])]
+    sample_verbose: Option<u64>,
+
+    /// With --sample-verbose, show detail for the first N images in each directory instead
+    #[clap(long, requires = "sample_verbose")]
+    sample_per_dir: bool,
+
+    // set per image when sampling: print only the one-line summary
+    #[clap(skip)]
+    pub(crate) summary_only: bool,
+
+    // set by browse: keep each image's sectors in its report
+    #[cfg(feature = "browse")]
+    #[clap(skip)]
+    pub(crate) keep_disks: bool,
+
+    #[clap(short, long)]
+    pub(crate) disk_image_info: bool,
+
+    #[clap(short, long)]
+    pub(crate) track_info: bool,
+
+    #[clap(short, long)]
+    pub(crate) sector_info: bool,
+
+    #[clap(short, long)]
+    pub(crate) comment_info: bool,
+
+    /// The code page of file names and comments
+    #[clap(long, value_enum, default_value = "cp437")]
+    pub(crate) codepage: Codepage,
+
+    /// Only look for these filesystems: fat, cpm, trdos, apple. Repeatable
+    #[clap(long, value_enum, value_name = "NAME")]
+    pub(crate) detector: Vec<DetectorKind>,
+
+    /// Don't look for this filesystem. Repeatable
+    #[clap(long, value_enum, value_name = "NAME")]
+    no_detector: Vec<DetectorKind>,
+
+    /// List only the CP/M files in this user area, 0 to 15
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=15))]
+    pub(crate) cpm_user: Option<u8>,
+
+    /// The language of summaries and reports
+    #[clap(long, value_enum, default_value = "en")]
+    pub(crate) language: Language,
+
+    /// A regular expression whose named groups are recorded as fields of any comment it matches,
+    /// e.g. "Archived by (?P<archivist>\w+)". Tried before the built in patterns. Repeatable
+    #[clap(long, value_name = "REGEX")]
+    pub(crate) comment_pattern: Vec<regex::Regex>,
+
+    /// Analyse the boot sector: DOS BPB, boot signature and CP/M boot loaders
+    #[clap(short, long)]
+    pub(crate) analyse_first_tracks: bool,
+
+    /// Look for directory entries only in the first N tracks, in logical order, which is where FAT
+    /// and CP/M keep their directories, rather than the whole disk
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) tracks: Option<u64>,
+
+    /// Whether sector contents are analysed: off still reads and checks every sector, but looks for
+    /// no directories, filesystem or boot sector and doesn't classify the data, for header-only scans
+    #[clap(long, value_enum, value_name = "WHEN", default_value = "on")]
+    pub(crate) sector_analysis: SectorAnalysis,
+
+    /// Colour hex dumps, header and track lines, sector flags and errors: auto when output is to a
+    /// terminal and NO_COLOR isn't set, always or never. Just -u means always
+    #[clap(short = 'u', long = "colour", alias = "color", value_enum, value_name = "WHEN", default_value = "auto",
+        num_args = 0..=1, require_equals = true, default_missing_value = "always")]
+    pub(crate) colour: ColourChoice,
+
+    // whether stdout is coloured, from --colour
+    #[clap(skip)]
+    pub(crate) colours: bool,
+
+    /// Start every line printed about an image with a short ID made from a hash of its file, the
+    /// same on every run, so its lines can be picked out of the output. Also in the catalogue
+    #[clap(long)]
+    pub(crate) ids: bool,
+
+    // set per image with --ids
+    #[clap(skip)]
+    pub(crate) image_id: Option<String>,
+
+    /// Fit paths and hex dumps into N columns rather than the terminal's width, or 0 for no limit.
+    /// Output that isn't to a terminal isn't fitted unless this is given
+    #[clap(long, value_name = "N")]
+    pub(crate) width: Option<usize>,
+
+    /// Follow symbolic links while walking directories
+    #[clap(long)]
+    pub(crate) follow_symlinks: bool,
+
+    /// Descend at most N directory levels below the starting path
+    #[clap(long, value_name = "N")]
+    pub(crate) max_depth: Option<usize>,
+
+    /// Don't cross filesystem boundaries while walking directories
+    #[clap(long)]
+    pub(crate) one_file_system: bool,
+
+    /// Show image paths from the root rather than relative to the current directory
+    #[clap(long)]
+    pub(crate) absolute_paths: bool,
+
+    /// Walk directories and read archive members in order of name rather than as they're stored,
+    /// so scans of copies of a collection give the same output wherever they're run. A gzipped
+    /// tarball whose images aren't stored in order is read through more than once
+    #[clap(long)]
+    pub(crate) sort: bool,
+
+    /// Only look at files whose path below the starting directory matches GLOB, e.g. "*.td0" or
+    /// "disks/**/*.zip", ignoring case. Repeatable, any may match
+    #[clap(long, value_name = "GLOB")]
+    include: Vec<glob::Pattern>,
+
+    /// Skip files and directories whose path below the starting directory matches GLOB, ignoring
+    /// case. Repeatable
+    #[clap(long, value_name = "GLOB")]
+    pub(crate) exclude: Vec<glob::Pattern>,
+
+    /// Decrypt encrypted zip members with PASSWORD, ZipCrypto or AES. Without it they're reported as
+    /// unreadable
+    #[clap(long, value_name = "PASSWORD")]
+    pub(crate) zip_password: Option<String>,
+
+    /// After the scan, list images whose decoded contents are identical (whatever their names or containers)
+    #[clap(long)]
+    pub(crate) find_duplicates: bool,
+
+    /// After the scan, list the sets of disks found by their names and comments, such as
+    /// "Disk 1 of 3", with any disks they're missing
+    #[clap(long)]
+    pub(crate) find_sets: bool,
+
+    /// After the scan, list pairs of images that aren't identical but share at least PERCENT of their tracks
+    #[clap(long, value_name = "PERCENT", value_parser = clap::value_parser!(u64).range(1..=100))]
+    pub(crate) find_similar: Option<u64>,
+
+    /// Leave a region of the logical disk image out of duplicate matching: START-END or START+LENGTH
+    /// in bytes (decimal or 0x hex), or a preset: fat-oem, fat-dirty, fat-serial. Repeatable
+    #[clap(long, value_name = "REGION", value_parser = parse_hash_exclusion)]
+    pub(crate) hash_exclude: Vec<Range<usize>>,
+
+    /// Only report images matching FILTER: fs=NAME or fs!=NAME, health<N (or <=, =, >=, >),
+    /// status=STATUS, format=FORMAT, finding=CODE, has-comment, has-label or has-files. Repeatable, all must match
+    #[clap(long, value_name = "FILTER", value_parser = parse_only)]
+    pub(crate) only: Vec<OnlyFilter>,
+
+    /// Only look at TD0s whose header has drive type N: 1 360K, 2 1.2M, 3 720K, 4 1.44M, and so
+    /// on. Repeatable, any may match. Images that aren't TD0s are left out
+    #[clap(long, value_name = "N")]
+    pub(crate) drive_type: Vec<u8>,
+
+    /// Only look at TD0s recorded at KBPS, 250, 300 or 500, FM or MFM. Repeatable, any may match
+    #[clap(long, value_name = "KBPS", value_parser = parse_data_rate)]
+    pub(crate) data_rate: Vec<u8>,
+
+    /// Only look at TD0s whose header says the disk has N sides, 1 or 2
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=2))]
+    pub(crate) sides: Option<u8>,
+
+    /// Only look at TD0s made by the TeleDisk versions in RANGE, e.g. 2.1 or 1.0-2.0
+    #[clap(long, value_name = "RANGE", value_parser = parse_version_range)]
+    pub(crate) td0_version: Option<RangeInclusive<u8>>,
+
+    /// Only look at TD0s with a comment
+    #[clap(long)]
+    pub(crate) with_comment: bool,
+
+    /// Only look at TD0s whose comment is dated on or after DATE: YYYY-MM-DD, YYYY-MM or YYYY
+    #[clap(long, value_name = "DATE", value_parser = parse_date)]
+    pub(crate) after: Option<NaiveDate>,
+
+    /// Only look at TD0s whose comment is dated before DATE: YYYY-MM-DD, YYYY-MM or YYYY
+    #[clap(long, value_name = "DATE", value_parser = parse_date)]
+    pub(crate) before: Option<NaiveDate>,
+
+    /// Search the decoded sectors of every image for STRING, written in the --codepage, and print
+    /// where it's found, even with -q. Repeatable
+    #[clap(long, value_name = "STRING")]
+    pub(crate) grep: Vec<String>,
+
+    /// Search the decoded sectors of every image for BYTES in hex, e.g. "e5e5" or "de ad be ef". Repeatable
+    #[clap(long, value_name = "BYTES", value_parser = parse_hex_bytes)]
+    pub(crate) grep_hex: Vec<Box<[u8]>>,
+
+    /// Print a hex dump of the sector at cylinder, head and sector number C,H,S of each image. Repeatable
+    #[clap(long, value_name = "C,H,S", value_parser = parse_chs)]
+    pub(crate) dump: Vec<(usize, u8, u8)>,
+
+    /// Print a map of each image with a character per sector showing its status
+    #[clap(long)]
+    pub(crate) map: bool,
+
+    /// Write a PNG sector map of each image into DIR: a row per track, a pixel per sector
+    #[clap(long, value_name = "DIR")]
+    pub(crate) map_png: Option<String>,
+
+    /// Export each image into DIR as a flat IMG of its sectors in logical order, with a .map file
+    /// beside it recording the header and each sector's ID fields, flags and size
+    #[clap(long, value_name = "DIR")]
+    pub(crate) export_img: Option<String>,
+
+    /// Export each image into DIR as an HFE for Gotek and FlashFloppy drives, its tracks rebuilt as
+    /// MFM or FM at the bit rate the header's data rate and drive type describe
+    #[clap(long, value_name = "DIR")]
+    pub(crate) export_hfe: Option<String>,
+
+    /// Write each sector of each image with data into a directory of its own below DIR, named after
+    /// the image's path, as a file named by its cylinder, head and sector number: c00_h0_s01.bin
+    #[clap(long, value_name = "DIR")]
+    pub(crate) dump_sectors: Option<String>,
+
+    /// With --dump-sectors, write each track's sectors in the order they were recorded as one file
+    /// too: c00_h0.bin
+    #[clap(long, requires = "dump_sectors")]
+    pub(crate) whole_tracks: bool,
+
+    /// Write the files in the root directory of each FAT image into a directory of its own below DIR,
+    /// named after the image's path
+    #[clap(long, value_name = "DIR")]
+    pub(crate) extract: Option<String>,
+
+    /// With --extract, recover deleted files too, from the clusters following on from their first
+    #[clap(long, requires = "extract")]
+    pub(crate) undelete: bool,
+
+    /// Write a report of each analysed image into DIR, one file per image named after its path
+    #[clap(long, value_name = "DIR")]
+    pub(crate) out_dir: Option<String>,
+
+    /// The format of the reports written by --out-dir: its card, its catalogue record, or everything
+    /// printed about it, whatever options ask for, in its file rather than to stdout
+    #[clap(long, value_enum, value_name = "FORMAT", default_value = "text")]
+    pub(crate) out_format: OutFormat,
+
+    /// Write the comment of each image that has one into DIR, its date on the first line and the
+    /// text after, named after the image's path with .comment.txt
+    #[clap(long, value_name = "DIR")]
+    pub(crate) export_comments: Option<String>,
+
+    /// Write the comments of every analysed image to FILE as one JSON object keyed by image path
+    #[clap(long, value_name = "FILE")]
+    pub(crate) comment_report: Option<String>,
+
+    /// Write a standalone HTML report of every analysed image to FILE, for sharing: a table of
+    /// their headers and geometry that sorts by any column, and a section for each image with its
+    /// comment, findings, sector map and files
+    #[clap(long, value_name = "FILE")]
+    pub(crate) report_html: Option<String>,
+
+    /// Print totals over every analysed image once the scan is done: how many bytes they're
+    /// stored in against their decoded size, how their sectors were stored, and how many of each
+    /// encoding failed validation. Printed even with -q
+    #[clap(long)]
+    pub(crate) stats: bool,
+
+    /// The format --stats prints in
+    #[clap(long, value_enum, value_name = "FORMAT", default_value = "text", requires = "stats")]
+    pub(crate) stats_format: ReportFormat,
+
+    /// Print a line for each directory and archive once it's walked: the files in it, how many
+    /// were images and how many of those read cleanly. Printed even with -q, and listed under
+    /// `walk` in --stats JSON
+    #[clap(long)]
+    pub(crate) walk_summary: bool,
+
+    /// Write a catalogue of every analysed image to FILE, one JSON record per line
+    #[clap(long, value_name = "FILE")]
+    pub(crate) catalogue: Option<String>,
+
+    /// Record every analysed image in the SQLite database FILE, replacing rows from earlier scans
+    #[cfg(feature = "sqlite")]
+    #[clap(long, value_name = "FILE")]
+    pub(crate) db: Option<String>,
+
+    // set when the only argument is one image, see main
+    #[clap(skip)]
+    pub(crate) card: bool,
+
+    /// Read a single TD0 image from standard input (same as giving '-' as the path)
+    #[clap(long)]
+    pub(crate) stdin: bool,
+
+    /// Read default options and detector thresholds from FILE rather than the teledisk-analyser.toml
+    /// found in the XDG config directories
+    #[clap(long, value_name = "FILE")]
+    pub(crate) config: Option<String>,
+
+    /// Don't read a config file
+    #[clap(long, conflicts_with = "config")]
+    pub(crate) no_config: bool,
+
+    // set from the config file's [detectors] tables
+    #[clap(skip)]
+    pub(crate) thresholds: Thresholds,
+
+    /// Analyse .td0 files without a TeleDisk signature as what they turn out to be: a flat or
+    /// CPCEMU image, or a TeleDisk image whose signature alone is damaged
+    #[clap(long)]
+    pub(crate) ignore_signature: bool,
+
+    /// Print a JSON description of the formats, filesystems and outputs this build supports, and exit
+    #[clap(long, exclusive = true)]
+    pub(crate) capabilities: bool,
+
+    /// The path to the file or directory to process
+    #[clap(value_parser, required_unless_present_any = ["stdin", "capabilities"])]
+    pub(crate) path: Option<PathBuf>,
+}
+
+impl Args {
+    pub(crate) fn set_info_flags(&mut self, on: bool) {
+        self.disk_image_info = on;
+        self.track_info = on;
+        self.sector_info = on;
+        self.comment_info = on;
+        self.analyse_first_tracks = on;
+    }
+
+    // whether anything beyond the requested info lines should be printed for an image
+    pub(crate) fn shows_detail(&self) -> bool {
+        !self.quiet && !self.summary_only
+    }
+
+    // whether -d is all that's asked of an image, which its track and sector headers are enough
+    // for, so its data blocks are skipped rather than decoded and nothing looks at their contents
+    pub(crate) fn headers_only(&self) -> bool {
+        // browse keeps the sectors, and the database has a table of the files in them
+        #[cfg(feature = "browse")]
+        let keep_disks = self.keep_disks;
+        #[cfg(not(feature = "browse"))]
+        let keep_disks = false;
+        #[cfg(feature = "sqlite")]
+        let db = self.db.is_some();
+        #[cfg(not(feature = "sqlite"))]
+        let db = false;
+        self.disk_image_info && !self.analyse_first_tracks && !self.card && !keep_disks
+            && !self.find_duplicates && self.find_similar.is_none() && self.hash_exclude.is_empty()
+            && self.only.is_empty() && self.grep.is_empty() && self.grep_hex.is_empty() && self.dump.is_empty()
+            && !self.map && self.map_png.is_none() && self.export_img.is_none() && self.export_hfe.is_none()
+            && self.dump_sectors.is_none() && self.extract.is_none() && self.out_dir.is_none() && !self.stats && self.catalogue.is_none() && !db
+            && self.report_html.is_none()
+    }
+
+    // whether what's printed about each image goes to its file in --out-dir instead
+    pub(crate) fn writes_output(&self) -> bool {
+        self.out_dir.is_some() && matches!(self.out_format, OutFormat::Output)
+    }
+
+    // whether any of --drive-type, --data-rate, --sides, --td0-version, --with-comment, --after and
+    // --before is given
+    pub(crate) fn filters_headers(&self) -> bool {
+        !self.drive_type.is_empty() || !self.data_rate.is_empty() || self.sides.is_some() || self.td0_version.is_some() || self.with_comment
+            || self.after.is_some() || self.before.is_some()
+    }
+
+    // whether a TD0 with these headers is one those filters let through. one whose comment has no
+    // real date isn't in any period
+    pub(crate) fn header_wanted(&self, header: &ImageHeader, comment: Option<&CommentHeader>) -> bool {
+        let date = comment.and_then(comment_timestamp).map(|t| t.date());
+        (self.drive_type.is_empty() || self.drive_type.contains(&header.drive_type))
+            && (self.data_rate.is_empty() || self.data_rate.contains(&(header.data_rate & 0x7f)))
+            && self.sides.is_none_or(|sides| header.sides == sides)
+            && self.td0_version.as_ref().is_none_or(|versions| versions.contains(&header.version))
+            && (!self.with_comment || comment.is_some())
+            && self.after.is_none_or(|after| date.is_some_and(|date| date >= after))
+            && self.before.is_none_or(|before| date.is_some_and(|date| date < before))
+    }
+
+    // a fresh set of the filesystem detectors --detector and --no-detector leave in, in the order
+    // they're consulted
+    pub(crate) fn detectors(&self) -> Vec<Box<dyn FilesystemDetector>> {
+        DetectorKind::ALL.iter()
+            .filter(|kind| self.detector.is_empty() || self.detector.contains(kind))
+            .filter(|kind| !self.no_detector.contains(kind))
+            .map(|kind| kind.detector(&self.thresholds, self.cpm_user))
+            .collect()
+    }
+
+    // whether --include and --exclude let a file or directory found while walking be looked at
+    pub(crate) fn walks_into(&self, dirent: &walkdir::DirEntry, start_path: &Path) -> bool {
+        // the starting path itself is always walked
+        if dirent.depth() == 0 { return true; }
+        let path = dirent.path().strip_prefix(start_path).unwrap_or(dirent.path());
+        if self.exclude.iter().any(|glob| glob.matches_path_with(path, GLOB_OPTIONS)) { return false; }
+        !dirent.file_type().is_file() || self.include.is_empty()
+            || self.include.iter().any(|glob| glob.matches_path_with(path, GLOB_OPTIONS))
+    }
+
+    // the options to analyse the next image in dir with, which differ when --sample-verbose is in effect
+    pub(crate) fn for_image(&self, scan: &mut Scan, dir: &Path) -> std::borrow::Cow<'_, Args> {
+        match self.sample_verbose {
+            Some(n) => std::borrow::Cow::Owned(self.sampled(scan.sample_in_detail(n, self.sample_per_dir, dir))),
+            None => std::borrow::Cow::Borrowed(self),
+        }
+    }
+
+    // what lines about the image start with
+    pub(crate) fn id_prefix(&self) -> String {
+        self.image_id.as_ref().map_or_else(String::new, |id| format!("{} ", id))
+    }
+
+    // text in colour, if stdout is coloured
+    pub(crate) fn paint(&self, colour: &str, text: &str) -> String {
+        if self.colours { format!("{}{}{}", colour, text, COLOUR_OFF) } else { text.to_string() }
+    }
+
+    // line followed by path, the path cut from the left as much as it takes for the two to fit
+    pub(crate) fn with_path(&self, line: &str, path: &str) -> String {
+        match self.width {
+            Some(width) => format!("{}{}", line, shorten_path(path, width.saturating_sub(self.id_prefix().len() + visible_len(line)))),
+            None => format!("{}{}", line, path),
+        }
+    }
+
+    // as many bytes as fit a line of a hex dump, in eights
+    pub(crate) fn hex_bytes_per_line(&self) -> usize {
+        match self.width {
+            // an offset and two spaces, then three columns a byte in hex, the ASCII and two bars
+            Some(width) => (width.saturating_sub(8) / 4 / 8 * 8).clamp(8, 64),
+            None => 16,
+        }
+    }
+
+    fn sampled(&self, in_detail: bool) -> Args {
+        let mut args = self.clone();
+        args.verbose = in_detail as u8;
+        args.summary_only = !in_detail;
+        args.set_info_flags(in_detail);
+        args
+    }
+}
+
+// when --colour colours output
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColourChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColourChoice {
+    // whether output to a stream is coloured, given whether the stream is a terminal
+    pub(crate) fn enabled(self, terminal: bool) -> bool {
+        self.enabled_with(terminal, std::env::var_os("NO_COLOR").as_deref())
+    }
+
+    // the same given NO_COLOR's value, which turns auto off when it's set to anything
+    fn enabled_with(self, terminal: bool, no_color: Option<&std::ffi::OsStr>) -> bool {
+        match self {
+            ColourChoice::Always => true,
+            ColourChoice::Never => false,
+            // see https://no-color.org
+            ColourChoice::Auto => terminal && no_color.is_none_or(|value| value.is_empty()),
+        }
+    }
+}
+
+pub(crate) const HEADER_COLOUR: &str = "\x1b[1m";     // bold
+
+pub(crate) const TRACK_COLOUR: &str = "\x1b[36m";     // cyan
+
+pub(crate) const FLAG_COLOUR: &str = "\x1b[33m";      // yellow
+
+pub(crate) const ERROR_COLOUR: &str = "\x1b[31m";     // red
+
+const COLOUR_OFF: &str = "\x1b[0m";
+
+// the columns text takes on a terminal, leaving out colour escapes
+fn visible_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            len += 1;
+        }
+    }
+    len
+}
+
+// paths are never cut shorter than this, however little room the rest of a line leaves
+const MIN_PATH_COLUMNS: usize = 16;
+
+// a path cut from the left to fit width columns, keeping the end that names the image
+fn shorten_path(path: &str, width: usize) -> std::borrow::Cow<'_, str> {
+    let len = path.chars().count();
+    let width = width.max(MIN_PATH_COLUMNS);
+    if len <= width {
+        return path.into();
+    }
+    format!("…{}", path.chars().skip(len - (width - 1)).collect::<String>()).into()
+}
+
+// whether --sector-analysis looks at what's in the sectors
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SectorAnalysis {
+    On,
+    Off,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub(crate) enum ReportFormat {
+    Text,
+    Json,
+}
+
+// what --out-dir writes for each image
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub(crate) enum OutFormat {
+    Text,
+    Json,
+    Output,
+}
+
+// standard PC formats, for importing a flat image that has no .map
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub(crate) enum Profile {
+    Pc160k,
+    Pc180k,
+    Pc320k,
+    Pc360k,
+    Pc720k,
+    Pc1200k,
+    Pc1440k,
+}
+
+impl Profile {
+    // cylinders, heads, sectors per track, and the TD0 data rate and drive type
+    pub(crate) fn shape(&self) -> (usize, u8, u8, u8, u8) {
+        match self {
+            Profile::Pc160k => (40, 1, 8, 0, 1),
+            Profile::Pc180k => (40, 1, 9, 0, 1),
+            Profile::Pc320k => (40, 2, 8, 0, 1),
+            Profile::Pc360k => (40, 2, 9, 0, 1),
+            Profile::Pc720k => (80, 2, 9, 0, 3),
+            Profile::Pc1200k => (80, 2, 15, 2, 2),
+            Profile::Pc1440k => (80, 2, 18, 2, 4),
+        }
+    }
+
+    // the image cut into 512 byte sectors numbered from 1, cylinder by cylinder
+    pub(crate) fn layout(&self, img: &[u8]) -> io::Result<(ImageHeader, Disk)> {
+        let (cylinders, heads, sectors, data_rate, drive_type) = self.shape();
+        let size = cylinders * heads as usize * sectors as usize * 512;
+        if img.len() != size {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("image is {} bytes, not the {} of this format", img.len(), size)));
+        }
+        Ok((ImageHeader::synthetic(data_rate, drive_type, heads), flat_disk(img, cylinders, heads, sectors, 512)))
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum Command {
+    /// Report images added, removed or changed between two catalogues written by --catalogue
+    CompareRuns {
+        old: String,
+        new: String,
+    },
+    /// Rebuild a TD0 from a flat image and the .map --export-img wrote beside it
+    Import {
+        /// The flat image
+        image: String,
+        /// The TD0 file to write
+        #[clap(short, long, value_name = "FILE")]
+        output: String,
+        /// The sector map, if it isn't the image's name with .map in place of .img
+        #[clap(long, value_name = "FILE")]
+        map: Option<String>,
+        /// Lay the image out as a standard format instead of following a map
+        #[clap(long, value_enum, conflicts_with = "map")]
+        profile: Option<Profile>,
+        /// Lay the image out as C cylinders, H heads and S sectors a track, of 512 bytes unless
+        /// BYTES says otherwise
+        #[clap(long, value_name = "C,H,S[,BYTES]", value_parser = parse_geometry, conflicts_with_all = ["map", "profile"])]
+        geometry: Option<(usize, u8, u8, u16)>,
+        /// Lay the image out as its boot sector's BPB says, or as the standard format of its size
+        #[clap(long, conflicts_with_all = ["map", "profile", "geometry"])]
+        bpb: bool,
+        /// Store TEXT as the comment, dated when the image was last changed, in place of the map's
+        #[clap(long, value_name = "TEXT")]
+        comment: Option<String>,
+        /// The code page to write the comment in
+        #[clap(long, value_enum, default_value = "cp437")]
+        codepage: Codepage,
+    },
+    /// Rewrite a TD0 as a valid one: every CRC recomputed, a comment length that runs past the end
+    /// of the file cut to its text, a truncated image ended after what could be read, and sectors
+    /// that can't be decoded filled
+    Repair {
+        /// The TD0 image
+        image: String,
+        /// The TD0 file to write
+        #[clap(short, long, value_name = "FILE")]
+        output: String,
+        /// The byte to fill sectors with whose data is lost, in hex
+        #[clap(long, value_name = "XX", default_value = "e5", value_parser = parse_hex_byte)]
+        fill: u8,
+        /// Fill sectors that fail their CRC or decode to the wrong size too, instead of keeping
+        /// their data with a CRC that matches it
+        #[clap(long)]
+        fill_damaged: bool,
+    },
+    /// List the single byte fixes to the data rate, stepping or sides that would make a TD0
+    /// header's failing CRC match, and optionally write a copy with one applied
+    RepairHeader {
+        /// The TD0 image
+        image: String,
+        /// Write a copy with the fix applied to FILE
+        #[clap(short, long, value_name = "FILE")]
+        output: Option<String>,
+        /// The candidate to apply when there's more than one, numbered as listed
+        #[clap(long, value_name = "N", requires = "output")]
+        pick: Option<usize>,
+    },
+    /// Check that this build reads and writes images correctly, on synthetic images made in memory
+    Selftest,
+    /// Scan PATH and browse its images interactively: a tree of them, and for the selected one its
+    /// header, its sectors, a hex view of a sector and the files found in its directory
+    #[cfg(feature = "browse")]
+    Browse {
+        /// A directory, archive or image
+        path: String,
+        /// The code page of file names and comments
+        #[clap(long, value_enum, default_value = "cp437")]
+        codepage: Codepage,
+        /// The language of the header and tab titles
+        #[clap(long, value_enum, default_value = "en")]
+        language: Language,
+    },
+    /// Look things up in a database written by --db, without scanning again
+    #[cfg(feature = "sqlite")]
+    Query {
+        /// The database to query
+        #[clap(long, value_name = "FILE")]
+        db: String,
+        #[command(subcommand)]
+        table: QueryTable,
+    },
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Subcommand, Debug, Clone)]
+pub(crate) enum QueryTable {
+    /// List images, optionally only those matching an SQL condition on the images table's columns
+    Images {
+        /// e.g. "filesystem = 'CP/M' AND header_crc_ok = 0"
+        #[clap(long = "where", value_name = "CONDITION")]
+        condition: Option<String>,
+        /// Order by these columns instead of path, e.g. "health, path"
+        #[clap(long, value_name = "COLUMNS")]
+        sort: Option<String>,
+    },
+    /// List files found in the images' directories
+    Files {
+        /// Only files whose names match this pattern, with * and ? wildcards
+        #[clap(long, value_name = "PATTERN")]
+        name: Option<String>,
+        /// An SQL condition on the files table's columns: image_path, partition, name, size, directory
+        #[clap(long = "where", value_name = "CONDITION")]
+        condition: Option<String>,
+    },
+}
+
+pub(crate) const EXIT_INVALID_IMAGES: i32 = 1;
+
+pub(crate) const EXIT_COMMAND_LINE: i32 = 2;
+
+pub(crate) const EXIT_NO_TD0_FOUND: i32 = 3;
+
+pub(crate) const EXIT_IO_ERROR: i32 = 4;
+
+// the name an option value is given as on the command line
+pub(crate) fn value_name<T: clap::ValueEnum>(value: &T) -> String {
+    value.to_possible_value().map_or_else(String::new, |v| v.get_name().to_string())
+}
+
+const CONFIG_FILE_NAME: &str = "teledisk-analyser.toml";
+
+// options that make no sense as defaults
+const CONFIG_REFUSED: [&str; 4] = ["config", "no-config", "capabilities", "stdin"];
+
+// globs match paths whatever the case of their names, which varies across collections
+const GLOB_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+// the config file --config names, otherwise the first teledisk-analyser.toml in $XDG_CONFIG_HOME
+// (~/.config if it isn't set) or one of $XDG_CONFIG_DIRS (/etc/xdg)
+pub(crate) fn config_path(args: &Args) -> Option<PathBuf> {
+    if let Some(path) = &args.config {
+        return Some(PathBuf::from(path));
+    }
+    let home = std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    let dirs = std::env::var("XDG_CONFIG_DIRS").ok().filter(|dirs| !dirs.is_empty()).unwrap_or_else(|| "/etc/xdg".to_string());
+    home.into_iter().chain(dirs.split(':').map(PathBuf::from))
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+// what a config file sets: options to take as if they were on the command line, each with the
+// key it was set by, and the thresholds of the detectors
+pub(crate) struct Config {
+    pub(crate) options: Vec<(String, String)>,
+    pub(crate) thresholds: Thresholds,
+}
+
+impl Config {
+    // the options, leaving out those conflicting with one the command line gives, as it has the last word
+    pub(crate) fn options_allowed_by_command_line(&self, matches: &clap::ArgMatches) -> Vec<String> {
+        let command = Args::command();
+        let given: Vec<&clap::Arg> = command.get_arguments()
+            .filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(clap::parser::ValueSource::CommandLine))
+            .collect();
+        self.options.iter().filter(|(key, _)| {
+            let arg = command.get_arguments().find(|arg| arg.get_long() == Some(key.as_str())).unwrap();
+            !given.iter().any(|&other| conflicting(&command, arg, other))
+        }).map(|(_, option)| option.clone()).collect()
+    }
+
+    // whether an option would conflict with one of these, so a default for it shouldn't be taken
+    pub(crate) fn conflicts_with(&self, long: &str) -> bool {
+        let command = Args::command();
+        let find = |long: &str| command.get_arguments().find(|arg| arg.get_long() == Some(long));
+        let Some(arg) = find(long) else { return false };
+        self.options.iter().filter_map(|(key, _)| find(key)).any(|other| conflicting(&command, arg, other))
+    }
+}
+
+fn conflicting(command: &clap::Command, arg: &clap::Arg, other: &clap::Arg) -> bool {
+    command.get_arg_conflicts_with(other).contains(&arg) || command.get_arg_conflicts_with(arg).contains(&other)
+}
+
+// each key is a long option as written without its dashes, true for a flag and a list for an
+// option that can be repeated, except for the [detectors] tables
+pub(crate) fn read_config(path: &Path) -> Result<Config, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+    let command = Args::command();
+    let mut config = Config { options: Vec::new(), thresholds: Thresholds::default() };
+    for (key, value) in table {
+        if key == "detectors" {
+            let detectors = value.as_table().ok_or("detectors must be a table of tables")?;
+            for (detector, settings) in detectors {
+                let settings = settings.as_table().ok_or_else(|| format!("detectors.{} must be a table", detector))?;
+                for (setting, value) in settings {
+                    let value = value.as_integer().ok_or_else(|| format!("detectors.{}.{} must be a whole number", detector, setting))?;
+                    config.thresholds.set(detector, setting, value)?;
+                }
+            }
+            continue;
+        }
+        if CONFIG_REFUSED.contains(&key.as_str()) || !command.get_arguments().any(|arg| arg.get_long() == Some(key.as_str())) {
+            return Err(format!("{} isn't an option a config file can set", key));
+        }
+        let values = match value {
+            toml::Value::Boolean(true) => { config.options.push((key.clone(), format!("--{}", key))); continue; },
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(n) => n.to_string(),
+                toml::Value::Float(n) => n.to_string(),
+                _ => return Err(format!("{} must be a string, a number or a list of them", key)),
+            };
+            config.options.push((key.clone(), format!("--{}={}", key, value)));
+        }
+    }
+    Ok(config)
+}
+
+// what this build can read, recognise and write, for scripts and front ends driving it
+pub(crate) fn capabilities() -> Value {
+    fn names<T: clap::ValueEnum>() -> Vec<String> {
+        T::value_variants().iter().map(value_name).collect()
+    }
+    let mut outputs = vec!["catalogue", "out-dir", "export-img", "export-hfe", "dump-sectors", "extract", "export-comments", "comment-report", "report-html", "map", "map-png", "ids", "walk-summary", "list"];
+    if cfg!(feature = "sqlite") { outputs.push("db"); }
+    let mut commands = vec!["compare-runs", "import", "repair", "repair-header", "selftest"];
+    if cfg!(feature = "sqlite") { commands.push("query"); }
+    if cfg!(feature = "browse") { commands.push("browse"); }
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "features": { "sqlite": cfg!(feature = "sqlite"), "browse": cfg!(feature = "browse"), "serde": cfg!(feature = "serde") },
+        "input_formats": ["td0", "flat", "dsk", "edsk"],
+        "flat_extensions": ["img", "ima", "dsk"],
+        "compression": ["normal"],
+        "containers": ["zip", "tar.gz"],
+        "zip_compression": ["stored", "deflate", "bzip2", "zstd"],
+        "zip_encryption": ["zipcrypto", "aes"],
+        "filesystems": names::<DetectorKind>(),
+        "outputs": outputs,
+        "report_formats": names::<ReportFormat>(),
+        "only_filters": ["fs", "health", "status", "format", "finding", "has-comment", "has-label", "has-files"],
+        "header_filters": ["drive-type", "data-rate", "sides", "td0-version", "with-comment", "after", "before"],
+        "commands": commands,
+        "import_profiles": names::<Profile>(),
+        "hashes": ["sha1", "md5", "crc32"],
+        "codepages": names::<Codepage>(),
+        "languages": names::<Language>(),
+    })
+}
+
+// a --dump sector address
+fn parse_chs(spec: &str) -> Result<(usize, u8, u8), String> {
+    let parts: Vec<&str> = spec.split(',').map(str::trim).collect();
+    match parts[..] {
+        [c, h, s] => {
+            let n = |part: &str| part.parse::<u8>().map_err(|_| format!("'{}' isn't a number from 0 to 255", part));
+            let c = c.parse::<usize>().map_err(|_| format!("'{}' isn't a cylinder number", c))?;
+            Ok((c, n(h)?, n(s)?))
+        },
+        _ => Err("expected cylinder, head and sector number as C,H,S".to_string()),
+    }
+}
+
+// an import --geometry: cylinders, heads and sectors a track, and optionally a sector size the
+// TD0 format can hold
+fn parse_geometry(spec: &str) -> Result<(usize, u8, u8, u16), String> {
+    let parts: Vec<&str> = spec.split(',').map(str::trim).collect();
+    let n = |part: &str| part.parse::<u8>().ok().filter(|&n| n > 0).ok_or_else(|| format!("'{}' isn't a number from 1 to 255", part));
+    let (c, h, s, size) = match parts[..] {
+        [c, h, s] => (c, h, s, "512"),
+        [c, h, s, size] => (c, h, s, size),
+        _ => return Err("expected cylinders, heads and sectors a track as C,H,S, and optionally a sector size".to_string()),
+    };
+    let cylinders = c.parse::<usize>().ok().filter(|&c| c > 0).ok_or_else(|| format!("'{}' isn't a number of cylinders", c))?;
+    let size = size.parse::<u16>().ok().filter(|&size| size.is_power_of_two() && (128..=128 << MAX_SIZE_CODE).contains(&size))
+        .ok_or_else(|| format!("'{}' isn't a sector size from 128 to {} bytes", size, 128 << MAX_SIZE_CODE))?;
+    Ok((cylinders, n(h)?, n(s)?, size))
+}
+
+// a --hash-exclude region as a byte range of the logical image. the presets are the boot sector
+// fields DOS and Windows rewrite without the disk's contents really changing
+fn parse_hash_exclusion(spec: &str) -> Result<Range<usize>, String> {
+    match spec {
+        "fat-oem" => return Ok(0x03..0x0b),     // OEM name, stamped by Windows 9x
+        "fat-dirty" => return Ok(0x25..0x26),   // reserved byte, used as a dirty flag by NT
+        "fat-serial" => return Ok(0x27..0x2b),  // volume serial number, set when formatting
+        _ => {}
+    }
+    let number = |n: &str| {
+        let n = n.trim();
+        match n.strip_prefix("0x").or_else(|| n.strip_prefix("0X")) {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => n.parse(),
+        }.map_err(|_| format!("'{}' isn't a byte offset", n))
+    };
+    let range = if let Some((start, len)) = spec.split_once('+') {
+        let start = number(start)?;
+        start..start + number(len)?
+    } else if let Some((start, end)) = spec.split_once('-') {
+        number(start)?..number(end)?
+    } else {
+        return Err("expected START-END, START+LENGTH, fat-oem, fat-dirty or fat-serial".to_string());
+    };
+    if range.is_empty() {
+        return Err(format!("region {}..{} is empty", range.start, range.end));
+    }
+    Ok(range)
+}
+
+// a --data-rate in kbps as the header's data rate byte has it, without the FM bit
+fn parse_data_rate(spec: &str) -> Result<u8, String> {
+    match spec.trim().trim_end_matches("kbps").trim() {
+        "250" => Ok(0),
+        "300" => Ok(1),
+        "500" => Ok(2),
+        _ => Err("expected 250, 300 or 500".to_string()),
+    }
+}
+
+// an --after or --before date, to the day, month or year. a month or year is taken from its first day
+fn parse_date(spec: &str) -> Result<NaiveDate, String> {
+    let spec = spec.trim();
+    let mut parts = spec.splitn(3, '-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (parts.next().flatten(), parts.next().unwrap_or(Some(1)), parts.next().unwrap_or(Some(1)));
+    year.zip(month).zip(day).and_then(|((year, month), day)| NaiveDate::from_ymd_opt(year as i32, month, day))
+        .ok_or_else(|| format!("'{}' isn't a date such as 1989-06-30, 1989-06 or 1989", spec))
+}
+
+// a --td0-version range of TeleDisk releases, as the header's version byte has them: 2.1 is 21
+fn parse_version_range(spec: &str) -> Result<RangeInclusive<u8>, String> {
+    let version = |v: &str| {
+        let (major, minor) = v.trim().split_once('.').unwrap_or((v.trim(), "0"));
+        major.parse::<u8>().ok().zip(minor.parse::<u8>().ok()).filter(|&(major, minor)| major <= 25 && minor <= 9)
+            .map(|(major, minor)| major * 10 + minor)
+            .ok_or_else(|| format!("'{}' isn't a TeleDisk version such as 2.1", v.trim()))
+    };
+    let (low, high) = match spec.split_once('-') {
+        Some((low, high)) => (version(low)?, version(high)?),
+        None => (version(spec)?, version(spec)?),
+    };
+    if low > high {
+        return Err(format!("the range {} runs backwards", spec));
+    }
+    Ok(low..=high)
+}
+
+// a --grep-hex pattern: pairs of hex digits, which may be separated by spaces or colons
+fn parse_hex_bytes(spec: &str) -> Result<Box<[u8]>, String> {
+    let digits: String = spec.chars().filter(|c| !c.is_whitespace() && *c != ':').collect();
+    match hex::decode(&digits) {
+        Ok(bytes) if !bytes.is_empty() => Ok(bytes.into_boxed_slice()),
+        Ok(_) => Err("no bytes to search for".to_string()),
+        Err(e) => Err(format!("'{}' isn't hex bytes: {}", spec, e)),
+    }
+}
+
+// a --fill byte: two hex digits
+fn parse_hex_byte(spec: &str) -> Result<u8, String> {
+    u8::from_str_radix(spec, 16).map_err(|e| format!("'{}' isn't a hex byte: {}", spec, e))
+}
+
+// diagnostics go to stderr through the log crate, apart from the analysis on stdout, at the level
+// -v and -vv choose unless RUST_LOG says otherwise
+pub(crate) fn init_logging(verbosity: u8, colours: bool) {
+    env_logger::Builder::new()
+        .filter_level(log_level(verbosity))
+        .parse_default_env()
+        .format(move |out, record| writeln!(out, "{}", log_line(record.level(), &record.args().to_string(), colours)))
+        .init();
+}
+
+fn log_level(verbosity: u8) -> log::LevelFilter {
+    match verbosity {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
+// errors and warnings say which they are, in colour when it's on; notes are just their message
+fn log_line(level: log::Level, message: &str, colours: bool) -> String {
+    let (label, colour) = match level {
+        log::Level::Error => ("Error: ", ERROR_COLOUR),
+        log::Level::Warn => ("Warning: ", FLAG_COLOUR),
+        _ => return message.to_string(),
+    };
+    if colours {
+        format!("{}{}{}{}", colour, label, COLOUR_OFF, message)
+    } else {
+        format!("{}{}", label, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sector_addresses() {
+        assert_eq!(parse_chs("39, 1, 9"), Ok((39, 1, 9)));
+        assert!(parse_chs("39,1").is_err());
+        assert!(parse_chs("0,0,256").is_err());
+    }
+
+    #[test]
+    fn geometries() {
+        assert_eq!(parse_geometry("80,2,9"), Ok((80, 2, 9, 512)));
+        assert_eq!(parse_geometry("77, 2, 8, 1024"), Ok((77, 2, 8, 1024)));
+        assert!(parse_geometry("80,2").is_err());
+        assert!(parse_geometry("80,0,9").is_err());
+        assert!(parse_geometry("80,2,9,500").is_err());
+        assert!(parse_geometry("80,2,9,16384").is_err());
+    }
+
+    #[test]
+    fn hash_exclusions() {
+        assert_eq!(parse_hash_exclusion("fat-serial"), Ok(0x27..0x2b));
+        assert_eq!(parse_hash_exclusion("0x1fe+2"), Ok(0x1fe..0x200));
+        assert_eq!(parse_hash_exclusion("3-11"), Ok(3..11));
+        assert!(parse_hash_exclusion("11-3").is_err());
+        assert!(parse_hash_exclusion("boot").is_err());
+    }
+
+    #[test]
+    fn header_filters() {
+        assert_eq!(parse_version_range("2.1"), Ok(21..=21));
+        assert_eq!(parse_version_range("1.0-2.0"), Ok(10..=20));
+        assert!(parse_version_range("2.1-1.5").is_err());
+        assert!(parse_version_range("2.x").is_err());
+        assert_eq!(parse_data_rate("300"), Ok(1));
+        assert!(parse_data_rate("1000").is_err());
+
+        let args = Args::parse_from(["kc8587", "--drive-type", "3", "--drive-type", "4", "--data-rate", "250", "--sides", "2", "--td0-version", "2.0-2.1", "disks"]);
+        assert!(args.filters_headers());
+        assert!(args.header_wanted(&ImageHeader::synthetic(0x80, 0x03, 2), None));
+        assert!(!args.header_wanted(&ImageHeader::synthetic(0x02, 0x04, 2), None));
+        assert!(!args.header_wanted(&ImageHeader::synthetic(0x00, 0x01, 2), None));
+        assert!(!args.header_wanted(&ImageHeader::synthetic(0x00, 0x03, 1), None));
+        let args = Args::parse_from(["kc8587", "--with-comment", "disks"]);
+        let comment = CommentHeader { crc: 0, length: 0, year: 90, month: 0, day: 1, hour: 0, minute: 0, second: 0 };
+        assert!(args.header_wanted(&ImageHeader::synthetic(0x00, 0x01, 1), Some(&comment)));
+        assert!(!args.header_wanted(&ImageHeader::synthetic(0x00, 0x01, 1), None));
+        assert!(!Args::parse_from(["kc8587", "disks"]).filters_headers());
+    }
+
+    #[test]
+    fn comment_dates() {
+        assert_eq!(parse_date("1989-06-30"), Ok(NaiveDate::from_ymd_opt(1989, 6, 30).unwrap()));
+        assert_eq!(parse_date("1989"), Ok(NaiveDate::from_ymd_opt(1989, 1, 1).unwrap()));
+        assert!(parse_date("1989-13").is_err() && parse_date("June").is_err());
+
+        let comment = |year, month, day| CommentHeader { crc: 0, length: 0, year, month, day, hour: 0, minute: 0, second: 0 };
+        let args = Args::parse_from(["kc8587", "--after", "1990", "--before", "1991-07", "disks"]);
+        let header = ImageHeader::synthetic(0x00, 0x01, 1);
+        // months are counted from 0
+        assert!(args.header_wanted(&header, Some(&comment(90, 0, 1))));
+        assert!(args.header_wanted(&header, Some(&comment(91, 5, 30))));
+        assert!(!args.header_wanted(&header, Some(&comment(91, 6, 1))));
+        assert!(!args.header_wanted(&header, Some(&comment(89, 11, 31))));
+        assert!(!args.header_wanted(&header, Some(&comment(90, 0, 0))));
+        assert!(!args.header_wanted(&header, None));
+    }
+
+    #[test]
+    fn hex_patterns() {
+        assert_eq!(parse_hex_bytes("55 aa").unwrap()[..], [0x55, 0xaa]);
+        assert_eq!(parse_hex_bytes("e5:E5").unwrap()[..], [0xe5, 0xe5]);
+        assert!(parse_hex_bytes("").is_err());
+        assert!(parse_hex_bytes("5").is_err());
+    }
+
+    #[test]
+    fn command_line_wins_over_config() {
+        let option = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let config = Config {
+            options: vec![option("quiet", "--quiet"), option("codepage", "--codepage=cp850")],
+            thresholds: Thresholds::default(),
+        };
+        let matches = Args::command().get_matches_from(["kc8587", "--verbose", "disks"]);
+        assert_eq!(config.options_allowed_by_command_line(&matches), ["--codepage=cp850"]);
+        let matches = Args::command().get_matches_from(["kc8587", "disks"]);
+        assert_eq!(config.options_allowed_by_command_line(&matches), ["--quiet", "--codepage=cp850"]);
+    }
+
+    #[test]
+    fn config_options_win_over_defaults_they_conflict_with() {
+        let option = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let mut config = Config { options: vec![option("codepage", "--codepage=cp850")], thresholds: Thresholds::default() };
+        assert!(!config.conflicts_with("quiet"));
+        config.options.push(option("verbose", "--verbose"));
+        assert!(config.conflicts_with("quiet"));
+    }
+
+    #[test]
+    fn capabilities_name_real_options_and_commands() {
+        let capabilities = capabilities();
+        let command = Args::command();
+        let list = |key: &str| capabilities[key].as_array().unwrap().iter().map(|v| v.as_str().unwrap().to_string()).collect::<Vec<_>>();
+        for option in list("outputs").iter().chain(&list("header_filters")) {
+            assert!(command.get_arguments().any(|arg| arg.get_long() == Some(option)), "no --{}", option);
+        }
+        for subcommand in list("commands") {
+            assert!(command.find_subcommand(&subcommand).is_some(), "no {} command", subcommand);
+        }
+        assert_eq!(list("filesystems"), ["fat", "cpm", "trdos", "apple"]);
+        assert_eq!(list("codepages"), ["cp437", "cp850", "cp852", "cp866", "koi8-r", "koi8-u"]);
+        assert_eq!(capabilities["features"]["sqlite"], cfg!(feature = "sqlite"));
+    }
+
+    #[test]
+    fn each_v_lets_quieter_diagnostics_through() {
+        let levels: Vec<_> = [&[][..], &["-v"], &["-vv"], &["-v", "-v", "-v"]].iter()
+            .map(|flags| log_level(Args::parse_from(["kc8587", "--stdin"].iter().chain(*flags)).verbose))
+            .collect();
+        assert_eq!(levels, [log::LevelFilter::Warn, log::LevelFilter::Info, log::LevelFilter::Debug, log::LevelFilter::Trace]);
+        assert!(Args::try_parse_from(["kc8587", "-v", "--quiet", "--stdin"]).is_err());
+        assert_eq!(log_line(log::Level::Error, "Failed to read a.td0", false), "Error: Failed to read a.td0");
+        assert_eq!(log_line(log::Level::Warn, "no path", true), "\x1b[33mWarning: \x1b[0mno path");
+        assert_eq!(log_line(log::Level::Debug, "Track 3 of a.td0 fails its header CRC", true), "Track 3 of a.td0 fails its header CRC");
+    }
+
+    #[test]
+    fn colour_when_asked_or_on_a_terminal_without_no_color() {
+        let colour = |flags: &[&str]| Args::parse_from(["kc8587", "--stdin"].iter().chain(flags)).colour;
+        assert_eq!([colour(&[]), colour(&["-u"]), colour(&["--colour=never"]), colour(&["--color=always"])],
+            [ColourChoice::Auto, ColourChoice::Always, ColourChoice::Never, ColourChoice::Always]);
+        let set = Some(std::ffi::OsStr::new("1"));
+        let empty = Some(std::ffi::OsStr::new(""));
+        assert!(ColourChoice::Auto.enabled_with(true, None) && ColourChoice::Auto.enabled_with(true, empty));
+        assert!(!ColourChoice::Auto.enabled_with(false, None) && !ColourChoice::Auto.enabled_with(true, set));
+        assert!(ColourChoice::Always.enabled_with(false, set) && !ColourChoice::Never.enabled_with(true, None));
+        let mut args = Args::parse_from(["kc8587", "--stdin"]);
+        assert_eq!(args.paint(ERROR_COLOUR, "bad"), "bad");
+        args.colours = true;
+        assert_eq!(args.paint(ERROR_COLOUR, "bad"), "\x1b[31mbad\x1b[0m");
+    }
+
+    #[test]
+    fn paths_keep_their_ends() {
+        assert_eq!(visible_len("\x1b[31mred\x1b[0m"), 3);
+        let path = "a/very/long/path/to/some/disk/images/of/a/collection/disk01.td0";
+        assert_eq!(shorten_path(path, 200), path);
+        let short = shorten_path(path, MIN_PATH_COLUMNS);
+        assert_eq!(short.chars().count(), MIN_PATH_COLUMNS);
+        assert!(short.ends_with("disk01.td0"));
+    }
+}
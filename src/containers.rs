@@ -0,0 +1,813 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use flate2::read::GzDecoder;
+use pathdiff::diff_paths;
+use serde::Serialize;
+use tar::Archive;
+use walkdir::WalkDir;
+use zip::{
+    result::{InvalidPassword, ZipError},
+    CompressionMethod, ZipArchive,
+};
+use kc8587::td0::{crc16, ImageHeader};
+
+use crate::{
+    cli::Args,
+    report::{Comment, Scan, WalkSummary},
+    analyse_image, flat_geometry,
+};
+
+pub(crate) fn walk_path(args: &Args, scan: &mut Scan, start_path: &Path) {
+    // TODO validate start path exists
+    let mut walkdir = WalkDir::new(start_path)
+        .follow_links(args.follow_symlinks)
+        .same_file_system(args.one_file_system);
+    if let Some(max_depth) = args.max_depth {
+        walkdir = walkdir.max_depth(max_depth);
+    }
+    if args.sort {
+        walkdir = walkdir.sort_by_file_name();
+    }
+    let walkdir = walkdir.into_iter().filter_entry(|dirent| args.walks_into(dirent, start_path));
+    // the directories being walked, innermost last, with their depths. one is finished at the
+    // first entry after it that isn't below it
+    let mut open: Vec<(usize, WalkSummary)> = Vec::new();
+    for dirent in walkdir {
+        // iterate, filtering out directories
+        // with --follow-symlinks a link back up the tree is reported here rather than walked forever
+        let dirent = match dirent {
+            Ok(dirent) => dirent,
+            Err(e) => {
+                scan.io_error(&format!("Failed to read directory entry: {}", e));
+                continue;
+            }
+        };
+        while open.last().is_some_and(|(depth, _)| *depth >= dirent.depth()) {
+            let (_, summary) = open.pop().unwrap();
+            finish_summary(args, scan, summary);
+        }
+        if dirent.file_type().is_dir() {
+            let summary = WalkSummary { kind: "directory", path: shown_path(args, dirent.path()).to_string_lossy().to_string(), ..Default::default() };
+            open.push((dirent.depth(), summary));
+        }
+        if !dirent.file_type().is_file() { continue; }
+
+        let mark = scan.mark();
+        walk_file(args, scan, &dirent);
+        let found = scan.images_since(mark);
+        if let Some((_, summary)) = open.last_mut() {
+            summary.add(found);
+        }
+    }
+    while let Some((_, summary)) = open.pop() {
+        finish_summary(args, scan, summary);
+    }
+}
+
+// with --walk-summary, a line for each directory or archive that had files in it once it's done
+fn finish_summary(args: &Args, scan: &mut Scan, summary: WalkSummary) {
+    if !args.walk_summary || summary.files == 0 { return; }
+    println!("{}", summary.describe(args.language));
+    scan.walk_summaries.push(summary);
+}
+
+// a file found while walking, looked at as an archive or an image by its name and first bytes
+fn walk_file(args: &Args, scan: &mut Scan, dirent: &walkdir::DirEntry) {
+    // the directory's kept as it is, but a name that isn't UTF-8 can only be shown
+    let rel_parent_path = shown_path(args, dirent.path().parent().unwrap_or(Path::new("")));
+    let file_name = dirent.file_name().to_string_lossy();
+
+    // filename tests
+    let norm_file_name = file_name.to_lowercase();
+    let has_zip_ext = norm_file_name.ends_with("zip");
+    let has_gzip_ext = [".tgz", ".gz", ".gzip"].iter().any(|ext| norm_file_name.ends_with(ext));
+    // let has_tar_ext = norm_file_name.ends_with("tar");
+
+    let mut file = match File::open(dirent.path()) {
+        Ok(file) => file,
+        Err(e) => {
+            scan.io_error(&format!("Failed to open {}: {}", dirent.path().to_string_lossy(), e));
+            return;
+        }
+    };
+
+    let (file_length, modified) = match file.metadata() {
+        Ok(metadata) => (metadata.len(), metadata.modified().ok()),
+        Err(e) => {
+            scan.io_error(&format!("Failed to stat {}: {}", dirent.path().to_string_lossy(), e));
+            return;
+        }
+    };
+    if file_length < 4 {
+        log::info!("Skipping file {}: too short ({} bytes)", dirent.path().to_string_lossy(), file_length);
+        return;
+    }
+
+    // file content tests
+    let zip_magic = b"PK\x03\x04";
+    let gzip_magic = b"\x1f\x8b";
+    // tar doesn't have a magic number
+
+    let mut magic_bytes = [0; 4];
+    if let Err(e) = file.read_exact(&mut magic_bytes) {
+        scan.io_error(&format!("Failed to read {}: {}", dirent.path().to_string_lossy(), e));
+        return;
+    }
+
+    let has_zip_magic = &magic_bytes[..4] == zip_magic;
+    let has_gzip_magic = &magic_bytes[..2] == gzip_magic;
+
+    // since tar doesn't have a magic number, best check in rust seems to be to instantiate and try the iterator
+    // TODO we are currently specifically checking only for a tar inside a gzip!!
+    let contains_tar = {
+        let mut arc = Archive::new(GzDecoder::new(&file));
+        arc.entries().ok().and_then(|mut entries| entries.next()).is_some_and(|entry| entry.is_ok())
+    };
+
+    let file_type = if has_zip_ext || has_zip_magic {
+        "Zip"
+    } else if (has_gzip_ext || has_gzip_magic) || contains_tar {
+        "Tarball"
+    } else {
+        "File"
+    };
+
+    let provenance = Provenance {
+        host_file: Some(std::fs::canonicalize(dirent.path()).unwrap_or_else(|_| dirent.path().to_path_buf())),
+        host_modified: modified.map(|t| chrono::DateTime::<chrono::Local>::from(t).to_rfc3339()),
+        ..Provenance::default()
+    };
+
+    if file_type == "Zip" {
+        process_zip_archive(args, scan, file, &rel_parent_path, &file_name, &provenance);
+    } else if file_type == "Tarball" {
+        process_tarball(args, scan, file, &rel_parent_path, &file_name, &provenance);
+    } else if is_image_name(&file_name) {
+        if let Err(e) = file.seek(SeekFrom::Start(0)) {
+            scan.io_error(&format!("Failed to read {}: {}", dirent.path().to_string_lossy(), e));
+            return;
+        }
+        // images are read a few bytes at a time, which is slow unbuffered over a network share
+        analyse_image(args, scan, &mut BufReader::new(file), "F", &rel_parent_path, None, &file_name, &provenance);
+    }
+}
+
+// a directory as shown in image paths: relative to the current directory, as it's given when the
+// start path is already relative or can't be made so. with --absolute-paths, from the root
+fn shown_path(args: &Args, dir: &Path) -> PathBuf {
+    if args.absolute_paths {
+        return std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    }
+    let relative = match std::env::current_dir() {
+        Ok(current_dir) if dir.is_absolute() => diff_paths(dir, current_dir),
+        _ => None,
+    };
+    relative.unwrap_or_else(|| dir.to_path_buf())
+}
+
+// TD0 images, flat images of their sectors as other tools convert them to, and CPCEMU DSK images
+pub(crate) fn is_image_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".td0") || is_flat_image_name(&name)
+}
+
+pub(crate) fn is_flat_image_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    [".img", ".ima", ".dsk"].iter().any(|ext| name.ends_with(ext))
+}
+
+fn process_zip_archive(args : &Args, scan: &mut Scan, file: File, file_path: &Path, container_name: &str, provenance: &Provenance) {
+    let buf_reader = BufReader::new(file);
+    let mut summary = WalkSummary { kind: "zip", path: image_path(file_path, None, container_name), ..Default::default() };
+    match ZipArchive::new(buf_reader) {
+        Ok(mut archive) => {
+            let mut order: Vec<usize> = (0..archive.len()).collect();
+            // a member that can't be read sorts first, and is reported as it's come to
+            if args.sort {
+                order.sort_by_cached_key(|&i| archive.by_index_raw(i).map(|zip_file| zip_file.name().to_string()).ok());
+            }
+            for i in order {
+                let mark = scan.mark();
+                // look at the member without decompressing it first, so members that aren't images are
+                // never opened and those that can't be are named
+                let (zip_file_name, unsupported) = match archive.by_index_raw(i) {
+                    Ok(zip_file) if zip_file.is_dir() => continue,
+                    Ok(zip_file) => (zip_file.name().to_string(), unsupported_method(zip_file.compression())),
+                    Err(e) => {
+                        scan.io_error(&format!("Failed to read zip file {} of {}: {}", i, container_name, e));
+                        summary.add(scan.images_since(mark));
+                        continue;
+                    }
+                };
+                if is_image_name(&zip_file_name) {
+                    read_zip_member(args, scan, &mut archive, i, &zip_file_name, unsupported, file_path, container_name, provenance);
+                }
+                summary.add(scan.images_since(mark));
+            }
+        },
+        Err(e) => scan.io_error(&format!("Failed to read zip archive {}: {}", container_name, e))
+    }
+    finish_summary(args, scan, summary);
+}
+
+// an image in a zip, unless it's compressed in a way that can't be read or encrypted without --zip-password
+#[allow(clippy::too_many_arguments)]
+fn read_zip_member(args : &Args, scan: &mut Scan, archive: &mut ZipArchive<BufReader<File>>, i: usize, zip_file_name: &str,
+    unsupported: Option<String>, file_path: &Path, container_name: &str, provenance: &Provenance) {
+    let member_path = image_path(file_path, Some(container_name), zip_file_name);
+    if let Some(method) = unsupported {
+        scan.io_error(&format!("Can't read {}: it's compressed with {}, which isn't supported", member_path, method));
+        return;
+    }
+    let opened = match &args.zip_password {
+        Some(password) => archive.by_index_decrypt(i, password.as_bytes()),
+        None => archive.by_index(i).map(Ok),
+    };
+    match opened {
+        Ok(Ok(mut zip_file)) => {
+            // zip times are local and have no zone
+            let modified = zip_file.last_modified();
+            let modified = NaiveDate::from_ymd_opt(modified.year() as i32, modified.month() as u32, modified.day() as u32)
+                .and_then(|d| d.and_hms_opt(modified.hour() as u32, modified.minute() as u32, modified.second() as u32))
+                .map(|t| t.format("%Y-%m-%dT%H:%M:%S").to_string());
+            let size = zip_file.size();
+            let provenance = provenance.within("zip", zip_file_name, modified, size);
+            // never read past the member, whatever the image inside claims
+            let mut member = (&mut zip_file).take(size);
+            analyse_image(args, scan, &mut member, "Z", file_path, Some(container_name), zip_file_name, &provenance);
+        },
+        Ok(Err(InvalidPassword)) => scan.io_error(&format!("Can't read {}: --zip-password isn't its password", member_path)),
+        Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)) =>
+            scan.io_error(&format!("Can't read {}: it's encrypted, give its password with --zip-password", member_path)),
+        Err(e) => scan.io_error(&format!("Failed to read zip file {} of {}: {}", i, container_name, e)),
+    }
+}
+
+// the name of a zip member's compression method when it isn't one that can be decompressed: PKZIP
+// 1's shrink, reduce and implode turn up in old archives
+#[allow(deprecated)]  // the zip crate names methods it can't decompress by constants over this variant
+fn unsupported_method(method: CompressionMethod) -> Option<String> {
+    let CompressionMethod::Unsupported(number) = method else { return None };
+    Some(match number {
+        1 => "shrink".to_string(),
+        2..=5 => format!("reduce (factor {})", number - 1),
+        6 => "implode".to_string(),
+        9 => "deflate64".to_string(),
+        10 => "PKWARE DCL implode".to_string(),
+        14 => "LZMA".to_string(),
+        95 => "XZ".to_string(),
+        98 => "PPMd".to_string(),
+        _ => format!("method {}", number),
+    })
+}
+
+fn process_tarball(args : &Args, scan: &mut Scan, mut file: File, file_path: &Path, container_name: &str, provenance: &Provenance) {
+    let mut summary = WalkSummary { kind: "tar.gz", path: image_path(file_path, None, container_name), ..Default::default() };
+    if !args.sort {
+        tarball_pass(args, scan, &mut file, file_path, container_name, provenance, &mut summary, &mut TarPass::All);
+        finish_summary(args, scan, summary);
+        return;
+    }
+    // a gzipped tarball can only be read from the start, so to take its images in order of name
+    // they're listed first, then read in as many passes as it takes to come to each in turn.
+    // that's one more pass when they're stored in order already
+    let mut images = Vec::new();
+    if tarball_pass(args, scan, &mut file, file_path, container_name, provenance, &mut summary, &mut TarPass::List(&mut images)) {
+        images.sort();
+        let mut order: VecDeque<usize> = images.into_iter().map(|(_, i)| i).collect();
+        while !order.is_empty() {
+            let left = order.len();
+            let read = tarball_pass(args, scan, &mut file, file_path, container_name, provenance, &mut summary, &mut TarPass::Only(&mut order));
+            if !read || order.len() == left { break; }
+        }
+    }
+    finish_summary(args, scan, summary);
+}
+
+// what a pass through a tarball does with its members
+enum TarPass<'a> {
+    All,                                    // reads each image as it comes
+    List(&'a mut Vec<(String, usize)>),     // only notes the images' names and places
+    Only(&'a mut VecDeque<usize>),          // reads the images at these places, for as long as they're in order
+}
+
+// false when the tarball couldn't be read from the start. what's wrong with its members is only
+// reported on the first pass through it
+#[allow(clippy::too_many_arguments)]
+fn tarball_pass(args : &Args, scan: &mut Scan, file: &mut File, file_path: &Path, container_name: &str, provenance: &Provenance,
+        summary: &mut WalkSummary, pass: &mut TarPass) -> bool {
+    if let Err(e) = file.seek(SeekFrom::Start(0)) {
+        scan.io_error(&format!("Failed to read tarball {}: {}", summary.path, e));
+        return false;
+    }
+    let mut archive = Archive::new(GzDecoder::new(file));
+    let entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            scan.io_error(&format!("Failed to read tarball {}: {}", summary.path, e));
+            return false;
+        }
+    };
+    let first = !matches!(pass, TarPass::Only(_));
+    for (i, entry) in entries.enumerate() {
+        if let TarPass::Only(order) = pass {
+            // the next to read is further on, or back where this pass can't go
+            match order.front() {
+                Some(&next) if next > i => continue,
+                Some(&next) if next == i => {},
+                _ => break,
+            }
+        }
+        let mark = scan.mark();
+        match entry {
+            Ok(entry) if entry.header().entry_type().is_dir() => continue,
+            Ok(mut entry) => {
+                // a member's name is shown as well as it can be, whatever bytes it's made of
+                let tar_file_name = match entry.path() {
+                    Ok(path) => path.to_string_lossy().to_string(),
+                    Err(err) => {
+                        if first {
+                            scan.io_error(&format!("Failed to read tar entry: {} at {}: {}", container_name, i, err));
+                        }
+                        continue;
+                    }
+                };
+                if is_image_name(&tar_file_name) {
+                    match pass {
+                        // counted in the summary once it's been read
+                        TarPass::List(images) => {
+                            images.push((tar_file_name, i));
+                            continue;
+                        },
+                        TarPass::Only(order) => { order.pop_front(); },
+                        TarPass::All => {},
+                    }
+                    let modified = entry.header().mtime().ok()
+                        .and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+                        .map(|t| t.to_rfc3339());
+                    let size = entry.size();
+                    let provenance = provenance.within("tar.gz", &tar_file_name, modified, size);
+                    let mut member = (&mut entry).take(size);
+                    analyse_image(args, scan, &mut member, "T", file_path, Some(container_name), &tar_file_name, &provenance);
+                }
+            },
+            Err(err) => if first {
+                scan.io_error(&format!("Failed to read tar entry: {} at {}: {}", container_name, i, err));
+            }
+        }
+        summary.add(scan.images_since(mark));
+    }
+    true
+}
+
+// where an image was found, for chain of custody records
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct Provenance {
+    #[serde(serialize_with = "lossy_path")]
+    pub(crate) host_file: Option<PathBuf>,     // the file actually read, None for stdin
+    host_modified: Option<String>,
+    pub(crate) containers: Vec<ContainerMember>, // outermost first
+    // the rest are filled in by scanned once the image has been read
+    comment_date: Option<NaiveDateTime>,
+    tool_version: &'static str,
+    scanned_at: String,
+}
+
+// a path that isn't UTF-8 is still recorded, as near as it can be
+fn lossy_path<S: serde::Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    path.as_ref().map(|path| path.to_string_lossy()).serialize(serializer)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ContainerMember {
+    pub(crate) kind: &'static str,
+    pub(crate) member: String,                 // the path within the container
+    modified: Option<String>,       // the member's own timestamp, as the container records it
+    pub(crate) size: u64,                      // uncompressed, as the container declares it
+}
+
+impl Provenance {
+    pub(crate) fn within(&self, kind: &'static str, member: &str, modified: Option<String>, size: u64) -> Provenance {
+        let mut provenance = self.clone();
+        provenance.containers.push(ContainerMember { kind, member: member.to_string(), modified, size });
+        provenance
+    }
+
+    // the provenance of an image read now, with the date its comment gives
+    pub(crate) fn scanned(&self, comment: Option<&Comment>) -> Provenance {
+        Provenance {
+            comment_date: comment.and_then(|c| c.timestamp),
+            tool_version: env!("CARGO_PKG_VERSION"),
+            scanned_at: chrono::Local::now().to_rfc3339(),
+            ..self.clone()
+        }
+    }
+}
+
+// what a .td0 file that isn't a TeleDisk image most likely is instead, from its first bytes and size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sniffed {
+    AdvancedTd0,
+    DamagedTd0, // only the signature is wrong, as the header CRC shows
+    Zip,
+    Gzip,
+    Imd,
+    Dsk,
+    Hfe,
+    Flat,       // the size of a flat image of a format known, or the BPB of one
+    Blank,      // all the same byte
+    Text,
+    Unknown,
+}
+
+impl Sniffed {
+    pub(crate) fn of(bytes: &[u8]) -> Self {
+        let header = bytes.first_chunk::<12>().map(|header| ImageHeader::from_bytes(header));
+        if bytes.starts_with(b"td") {
+            Sniffed::AdvancedTd0
+        } else if header.is_some_and(|header| crc16(&[&b"TD"[..], &header.crc_bytes()[2..]].concat()) == header.crc) {
+            Sniffed::DamagedTd0
+        } else if bytes.starts_with(b"PK\x03\x04") {
+            Sniffed::Zip
+        } else if bytes.starts_with(b"\x1f\x8b") {
+            Sniffed::Gzip
+        } else if bytes.starts_with(b"IMD ") {
+            Sniffed::Imd
+        } else if bytes.starts_with(b"MV - CPC") || bytes.starts_with(b"EXTENDED CPC DSK") {
+            Sniffed::Dsk
+        } else if bytes.starts_with(b"HXCPICFE") || bytes.starts_with(b"HXCHFEV3") {
+            Sniffed::Hfe
+        } else if flat_geometry(bytes).is_some() {
+            Sniffed::Flat
+        } else if bytes.iter().all(|&b| b == bytes[0]) {
+            Sniffed::Blank
+        } else if bytes.iter().all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace() || b == 0x1a) {
+            Sniffed::Text
+        } else {
+            Sniffed::Unknown
+        }
+    }
+
+    pub(crate) fn describe(&self) -> &'static str {
+        match self {
+            Sniffed::AdvancedTd0 => "which is a TeleDisk image with advanced compression",
+            Sniffed::DamagedTd0 => "which looks like a TeleDisk image whose signature alone is damaged",
+            Sniffed::Zip => "which is a zip archive",
+            Sniffed::Gzip => "which is a gzip file",
+            Sniffed::Imd => "which is an ImageDisk image",
+            Sniffed::Dsk => "which is a CPCEMU disk image",
+            Sniffed::Hfe => "which is an HFE image",
+            Sniffed::Flat => "and it's the size of a flat image of a disk's sectors",
+            Sniffed::Blank => "and every byte is the same",
+            Sniffed::Text => "and it's all text",
+            Sniffed::Unknown => "which isn't a format known",
+        }
+    }
+
+    // what --ignore-signature can analyse
+    pub(crate) fn readable(&self) -> bool {
+        matches!(self, Sniffed::DamagedTd0 | Sniffed::Dsk | Sniffed::Flat)
+    }
+
+    // what to do about it, given whether --ignore-signature is analysing it anyway
+    pub(crate) fn suggestion(&self, ignoring_signature: bool) -> &'static str {
+        match self {
+            _ if ignoring_signature && self.readable() => "Analysing it as one anyway",
+            Sniffed::AdvancedTd0 => "Saving it again from TeleDisk with normal compression makes it readable",
+            Sniffed::DamagedTd0 => "--ignore-signature reads it as it is",
+            Sniffed::Zip => "Renaming it to .zip has its images analysed",
+            Sniffed::Gzip => "Renaming it to .gz has its images analysed",
+            Sniffed::Imd => "ImageDisk's IMDU can convert it to a flat image, which can be analysed",
+            Sniffed::Dsk => "Renaming it to .dsk, or --ignore-signature, has it analysed",
+            Sniffed::Flat => "Renaming it to .img, or --ignore-signature, has it analysed",
+            Sniffed::Hfe | Sniffed::Blank | Sniffed::Text | Sniffed::Unknown => "It was skipped",
+        }
+    }
+}
+
+// the first bytes of a file in hex and as ASCII
+pub(crate) fn first_bytes(bytes: &[u8]) -> String {
+    let bytes = &bytes[..bytes.len().min(8)];
+    format!("{} '{}'", bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" "), bytes.iter().map(|&b| if (0x20..=0x7e).contains(&b) { b as char } else { '.' }).collect::<String>())
+}
+
+// counts the bytes read through it, for the size a TD0 was stored in
+pub(crate) struct CountingReader<'a> {
+    pub(crate) inner: &'a mut dyn Read,
+    pub(crate) count: u64,
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+// the full path from file_path, container name if there's a container, and file_name, as it's
+// shown. the directory's separators are the system's, but members' paths are left as the
+// archive has them
+pub(crate) fn image_path(file_path: &Path, container_name: Option<&str>, file_name: &str) -> String {
+    let mut path = file_path.to_path_buf();
+    if let Some(container) = container_name {
+        path.push(without_root(container));
+    }
+    if !file_name.is_empty() {
+        path.push(without_root(file_name));
+    }
+    path.to_string_lossy().to_string()
+}
+
+// a name as it's pushed onto the path before it. a member stored as /A.TD0 or C:\A.TD0 would
+// otherwise take the place of the path it's in
+fn without_root(name: &str) -> &Path {
+    let mut components = Path::new(name).components();
+    while let Some(Component::Prefix(_) | Component::RootDir) = components.clone().next() {
+        components.next();
+    }
+    components.as_path()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_dir;
+
+    // a one sector TD0 that reads cleanly
+    fn good_td0() -> Vec<u8> {
+        let mut td0 = Vec::new();
+        let disk = crate::formats::flat_disk(&[0xe5; 512], 1, 1, 1, 512);
+        crate::import::write_td0(&mut td0, &ImageHeader::synthetic(0x02, 0x01, 1), None, &disk, crate::fs::Codepage::Cp437).unwrap();
+        td0
+    }
+
+    fn walk(dir: &Path, options: &[&str]) -> Scan {
+        let args = <Args as clap::Parser>::parse_from(["kc8587".as_ref(), "-q".as_ref()].into_iter()
+            .chain(options.iter().map(|o| o.as_ref())).chain([dir.as_os_str()]));
+        let mut scan = Scan::default();
+        walk_path(&args, &mut scan, dir);
+        scan
+    }
+
+    fn zip_of(members: &[(&str, &[u8])], options: zip::write::FileOptions) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, data) in members {
+            zip.start_file(*name, options).unwrap();
+            io::Write::write_all(&mut zip, data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn tgz_of(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()));
+        for (name, data) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, name, *data).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn image_names() {
+        assert!(is_image_name("GAMES.TD0"));
+        assert!(is_image_name("disk.ima"));
+        assert!(!is_image_name("readme.txt"));
+        assert!(is_flat_image_name("Disk.DSK"));
+        assert!(!is_flat_image_name("disk.td0"));
+    }
+
+    #[test]
+    fn shown_directories() {
+        let args = <Args as clap::Parser>::parse_from(["kc8587", "disks"]);
+        assert_eq!(shown_path(&args, Path::new("")), Path::new(""));
+        assert_eq!(shown_path(&args, Path::new("disks/games")), Path::new("disks/games"));
+        let current_dir = std::env::current_dir().unwrap();
+        assert_eq!(shown_path(&args, &current_dir.join("disks")), Path::new("disks"));
+        let args = <Args as clap::Parser>::parse_from(["kc8587", "--absolute-paths", "disks"]);
+        assert_eq!(shown_path(&args, Path::new("disks/games")), current_dir.join("disks/games"));
+        assert_eq!(shown_path(&args, Path::new("/")), Path::new("/"));
+    }
+
+    #[test]
+    fn summarises_each_directory() {
+        let dir = temp_dir("walk");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("good.td0"), good_td0()).unwrap();
+        std::fs::write(dir.join("readme.txt"), "a disk").unwrap();
+        std::fs::write(dir.join("sub/bad.td0"), "not a disk image").unwrap();
+        let scan = walk(&dir, &["--walk-summary"]);
+        let summaries: Vec<_> = scan.walk_summaries.iter().map(|s| (s.kind, s.path.ends_with("sub"), s.files, s.images, s.clean)).collect();
+        assert_eq!(summaries, [("directory", true, 1, 1, 0), ("directory", false, 2, 1, 1)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // a name that isn't UTF-8 is shown as well as it can be, but the file is still found by its own
+    #[cfg(unix)]
+    #[test]
+    fn walks_names_that_arent_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let dir = temp_dir("names");
+        let name = OsStr::from_bytes(b"disk\xe9.td0");
+        std::fs::write(dir.join(name), good_td0()).unwrap();
+        let scan = walk(&dir, &[]);
+        let report = &scan.reports[0];
+        assert!(report.path.ends_with("disk\u{fffd}.td0"));
+        assert_eq!(report.provenance.host_file.as_deref().and_then(Path::file_name), Some(name));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sorts_files_and_members_by_name() {
+        let dir = temp_dir("sort");
+        let td0 = good_td0();
+        for name in ["b.td0", "a.td0"] {
+            std::fs::write(dir.join(name), &td0).unwrap();
+        }
+        std::fs::write(dir.join("set.zip"), zip_of(&[("d.td0", &td0), ("c.td0", &td0)], zip::write::FileOptions::default())).unwrap();
+        std::fs::write(dir.join("set.tgz"), tgz_of(&[("f.td0", &td0), ("e.td0", &td0)])).unwrap();
+        let scan = walk(&dir, &["--sort"]);
+        let names: Vec<_> = scan.reports.iter().map(|r| r.name.as_str()).collect();
+        // set.tgz comes before set.zip, and each one's members are in order of name too
+        assert_eq!(names, ["a.td0", "b.td0", "e.td0", "f.td0", "c.td0", "d.td0"]);
+        // a member's directory is its archive, joined on with the system's separator
+        assert_eq!(scan.reports[2].directory, Path::new(&scan.reports[0].directory).join("set.tgz").to_string_lossy());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_where_each_image_came_from() {
+        let dir = temp_dir("provenance");
+        let td0 = good_td0();
+        std::fs::write(dir.join("set.zip"), zip_of(&[("disks/GOOD.TD0", &td0)], zip::write::FileOptions::default())).unwrap();
+        let scan = walk(&dir, &[]);
+        let record = serde_json::to_value(&scan.reports[0].provenance).unwrap();
+        assert!(record["host_file"].as_str().unwrap().ends_with("set.zip"));
+        assert!(record["host_modified"].is_string());
+        assert_eq!(record["containers"], serde_json::json!([{"kind": "zip", "member": "disks/GOOD.TD0", "modified": record["containers"][0]["modified"], "size": td0.len()}]));
+        assert!(record["containers"][0]["modified"].is_string());
+        assert_eq!((&record["comment_date"], &record["tool_version"]), (&serde_json::Value::Null, &serde_json::json!(env!("CARGO_PKG_VERSION"))));
+        assert!(record["scanned_at"].is_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // a scan of a zip with these options, written to a temporary file named after the test
+    fn scan_zip(test: &str, zip: &[u8], options: &[&str]) -> Scan {
+        let dir = temp_dir(test);
+        let path = dir.join("set.zip");
+        std::fs::write(&path, zip).unwrap();
+        let args = <Args as clap::Parser>::parse_from(["kc8587", "-q"].iter().chain(options).chain(&["set.zip"]));
+        let mut scan = Scan::default();
+        process_zip_archive(&args, &mut scan, File::open(&path).unwrap(), Path::new(""), "set.zip", &Provenance::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        scan
+    }
+
+    #[test]
+    fn reads_zip64_members() {
+        let options = zip::write::FileOptions::default().large_file(true);
+        let scan = scan_zip("zip64", &zip_of(&[("GOOD.TD0", &good_td0())], options), &[]);
+        assert_eq!(scan.shown().count(), 1);
+        assert_eq!(scan.exit_code(), 0);
+    }
+
+    #[test]
+    fn reports_members_it_cant_decompress() {
+        let options = zip::write::FileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut zip = zip_of(&[("BAD.TD0", b"not a disk image")], options);
+        // make it imploded, in the local header and the central directory
+        zip[8] = 6;
+        let central = zip.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+        zip[central + 10] = 6;
+        assert_eq!(unsupported_method(CompressionMethod::IMPLODE).as_deref(), Some("implode"));
+        let scan = scan_zip("implode", &zip, &[]);
+        assert_eq!((scan.invalid_images, scan.exit_code()), (0, crate::cli::EXIT_IO_ERROR));
+    }
+
+    #[test]
+    fn decrypts_with_the_zip_password() {
+        // BAD.TD0 holding "not a disk image", stored with ZipCrypto and the password swordfish
+        let zip = hex::decode(concat!(
+            "504b03040a0009000000da024f5df18509111c0000001000000007001c006261642e7464305554090003dc1cd06a",
+            "dc1cd06a75780b000104000000000400000000340a8f1b7d893a7219cd7241a3256ab42d85bfb899dbc18efad1bc",
+            "42504b0708f18509111c00000010000000504b01021e030a0009000000da024f5df18509111c0000001000000007",
+            "0018000000000000000000a481000000006261642e7464305554050003dc1cd06a75780b00010400000000040000",
+            "0000504b050600000000010001004d0000006d0000000000")).unwrap();
+        let scan = scan_zip("encrypted", &zip, &[]);
+        assert_eq!((scan.invalid_images, scan.exit_code()), (0, crate::cli::EXIT_IO_ERROR));
+        let scan = scan_zip("encrypted", &zip, &["--zip-password", "marlin"]);
+        assert_eq!((scan.invalid_images, scan.exit_code()), (0, crate::cli::EXIT_IO_ERROR));
+        // read, and found not to be an image
+        let scan = scan_zip("encrypted", &zip, &["--zip-password=swordfish"]);
+        assert_eq!((scan.invalid_images, scan.exit_code()), (1, crate::cli::EXIT_INVALID_IMAGES));
+    }
+
+    #[test]
+    fn tells_what_a_td0_file_is_instead() {
+        let mut damaged = good_td0();
+        damaged[..2].copy_from_slice(b"XY");
+        let mut flat = vec![0xf6; 160 * 1024];
+        flat[0] = 0xeb;
+        assert_eq!(Sniffed::of(&damaged), Sniffed::DamagedTd0);
+        assert_eq!(Sniffed::of(&flat), Sniffed::Flat);
+        for (bytes, sniffed) in [(&b"td\x00\x00\x15"[..], Sniffed::AdvancedTd0), (b"PK\x03\x04\x14\x00", Sniffed::Zip),
+                (b"\x1f\x8b\x08\x00", Sniffed::Gzip), (b"IMD 1.18: 01/02/2003", Sniffed::Imd), (b"EXTENDED CPC DSK File", Sniffed::Dsk),
+                (b"HXCPICFE\x00", Sniffed::Hfe), (&[0xe5; 300], Sniffed::Blank), (b"Disk 1 of 3\r\n\x1a", Sniffed::Text),
+                (b"\x00\x01\x02\x03", Sniffed::Unknown)] {
+            assert_eq!(Sniffed::of(bytes), sniffed, "{}", first_bytes(bytes));
+        }
+        assert_eq!(first_bytes(b"IMD 1.18: 01/02/2003"), "49 4d 44 20 31 2e 31 38 'IMD 1.18'");
+        assert_eq!(Sniffed::Flat.suggestion(false), "Renaming it to .img, or --ignore-signature, has it analysed");
+        assert_eq!(Sniffed::Flat.suggestion(true), "Analysing it as one anyway");
+        assert_eq!(Sniffed::Zip.suggestion(true), "Renaming it to .zip has its images analysed");
+
+        // skipped, unless --ignore-signature reads what it can
+        let dir = temp_dir("sniffed");
+        std::fs::write(dir.join("damaged.td0"), &damaged).unwrap();
+        std::fs::write(dir.join("flat.td0"), &flat).unwrap();
+        std::fs::write(dir.join("notes.td0"), b"Disk 1 of 3\r\n").unwrap();
+        let scan = walk(&dir, &[]);
+        assert_eq!((scan.invalid_images, scan.reports.len()), (3, 0));
+        let mut scan = walk(&dir, &["--ignore-signature"]);
+        assert_eq!(scan.invalid_images, 3);
+        scan.reports.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(scan.reports.iter().map(|r| (r.name.as_str(), r.format.code())).collect::<Vec<_>>(), [("damaged.td0", "td0"), ("flat.td0", "flat")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // counts the reads that reach the file, each a system call and over a network share a round trip
+    struct CountingReads<'a> {
+        file: &'a File,
+        reads: &'a std::cell::Cell<usize>,
+    }
+
+    impl Read for CountingReads<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads.set(self.reads.get() + 1);
+            self.file.read(buf)
+        }
+    }
+
+    // the difference BufReader makes to reading images a few bytes at a time, on a corpus of 720K
+    // disks of mixed contents: cargo test --release -- --ignored buffering --nocapture
+    #[test]
+    #[ignore]
+    fn buffering() {
+        let dir = temp_dir("buffering");
+        let images = 200;
+        for n in 0..images {
+            // a boot sector's worth of code, text, then formatted but unused sectors
+            let img: Vec<u8> = (0..80 * 2 * 9 * 512).map(|i: usize| match i % (9 * 512) {
+                i if i < 512 => (i * 37 + n) as u8,
+                i if i < 2048 => b"The quick brown fox jumps over the lazy dog. "[i % 45],
+                _ => 0xf6,
+            }).collect();
+            let mut td0 = Vec::new();
+            crate::import::write_td0(&mut td0, &ImageHeader::synthetic(0x02, 0x03, 2), None,
+                &crate::formats::flat_disk(&img, 80, 2, 9, 512), crate::fs::Codepage::Cp437).unwrap();
+            std::fs::write(dir.join(format!("disk{:03}.td0", n)), td0).unwrap();
+        }
+        let args = <Args as clap::Parser>::parse_from(["kc8587", "-q", "--stdin"]);
+        let time = |buffered: bool| {
+            let mut scan = Scan::default();
+            let reads = std::cell::Cell::new(0);
+            let start = std::time::Instant::now();
+            for n in 0..images {
+                let name = format!("disk{:03}.td0", n);
+                let file = File::open(dir.join(&name)).unwrap();
+                let mut file = CountingReads { file: &file, reads: &reads };
+                if buffered {
+                    analyse_image(&args, &mut scan, &mut BufReader::new(file), "F", &dir, None, &name, &Provenance::default());
+                } else {
+                    analyse_image(&args, &mut scan, &mut file, "F", &dir, None, &name, &Provenance::default());
+                }
+            }
+            assert_eq!(scan.reports.len(), images);
+            (start.elapsed(), reads.get() / images)
+        };
+        // the best of three, once the files are in the page cache
+        let best = |buffered| (0..3).map(|_| time(buffered)).min().unwrap();
+        time(false);
+        let ((file, file_reads), (buffered, buffered_reads)) = (best(false), best(true));
+        println!("{} images: File {:?} with {} reads an image, BufReader {:?} with {}", images, file, file_reads, buffered, buffered_reads);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn paths_through_containers() {
+        assert_eq!(image_path(Path::new(""), None, "a.td0"), "a.td0");
+        assert_eq!(image_path(Path::new("images"), None, "a.td0"), Path::new("images").join("a.td0").to_string_lossy());
+        assert_eq!(image_path(Path::new("images"), Some("set.zip"), "sub/a.td0"),
+            Path::new("images").join("set.zip").join("sub/a.td0").to_string_lossy());
+        // a member named from the root stays in its container
+        assert_eq!(image_path(Path::new("images"), Some("set.zip"), "/A.TD0"), Path::new("images").join("set.zip").join("A.TD0").to_string_lossy());
+        assert_eq!(image_path(Path::new("images"), Some("/set.tgz"), "a.td0"), Path::new("images").join("set.tgz").join("a.td0").to_string_lossy());
+    }
+}
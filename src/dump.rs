@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{analysis::{Disk, SectorContent, SectorStatus}, cli::Args};
+
+// a --dump of a blank sector is one line rather than screens of the same byte
+pub(crate) fn sector_dump(data: &[u8], colours: (&str, &str, &str), per_line: usize) -> Vec<String> {
+    match SectorContent::of(data) {
+        SectorContent::Blank(filler) => vec![format!("blank, all {:02x}", filler)],
+        _ => hex_dump(data, colours, per_line),
+    }
+}
+
+pub(crate) fn hex_colours(args: &Args) -> (&'static str, &'static str, &'static str) {
+    if args.colours {
+        ("\x1b[32m", "\x1b[34m", "\x1b[0m")
+    } else {
+        ("", "", "")
+    }
+}
+
+pub(crate) fn print_hex_and_ascii(args: &Args, line_number: usize, data: &[u8], hexonly: bool) {
+    let (grn, blu, off) = hex_colours(args);
+    // a whole entry a line unless that's wider than there's room for, after the line number
+    let chunklen = args.width.map_or(0x1c + 4, |width| (width.saturating_sub(11) / 3).clamp(8, 0x1c + 4));
+    // Include additional bytes
+    for i in (0..data.len()).step_by(chunklen) {
+        let end = (i + chunklen).min(data.len()); // Prevent overflow
+        let s: String = data[i..end]
+            .iter()
+            .map(|&b| {
+                if !hexonly && (0x20..=0x7e).contains(&b) {
+                    format!("{} {} {}", grn, b as char, off)
+                } else {
+                    format!("{}{:02x} {}", blu, b, off)
+                }
+            })
+            .collect();
+    
+        image_println!(args, "--- {:2}     {}", line_number, s);
+    }
+}
+
+// the canonical layout: offset, 16 bytes in hex, then the same bytes as ASCII
+pub(crate) fn print_hex_dump(args: &Args, data: &[u8]) {
+    for line in sector_dump(data, hex_colours(args), args.hex_bytes_per_line()) {
+        image_println!(args, "{}", line);
+    }
+}
+
+// per_line bytes a line with their offset and printable ASCII, coloured with the escapes given
+pub(crate) fn hex_dump(data: &[u8], (grn, blu, off): (&str, &str, &str), per_line: usize) -> Vec<String> {
+    data.chunks(per_line).enumerate().map(|(n, line)| {
+        let hex: String = (0..per_line).map(|i| match line.get(i) {
+            Some(b) => format!("{}{:02x}{} ", blu, b, off),
+            None => "   ".to_string(),
+        }).collect();
+        let ascii: String = line.iter().map(|&b| {
+            if (0x20..=0x7e).contains(&b) { format!("{}{}{}", grn, b as char, off) } else { ".".to_string() }
+        }).collect();
+        format!("{:04x}  {} |{}|", n * per_line, hex, ascii)
+    }).collect()
+}
+
+pub(crate) fn print_sector_map(args: &Args, disk: &Disk, td0_path: &str) {
+    for line in sector_map_lines(disk, td0_path) {
+        image_println!(args, "{}", line);
+    }
+}
+
+// a line per track, cylinder and head then a character per sector in sector number order
+fn sector_map_lines(disk: &Disk, td0_path: &str) -> Vec<String> {
+    let map = disk.sector_map();
+    let width = map.iter().map(|(_, sectors)| sectors.len()).max().unwrap_or(0);
+
+    let legend = SectorStatus::ALL.iter().map(|s| format!("{} {}", s.symbol(), s.name())).collect::<Vec<_>>().join(", ");
+    let mut lines = vec![format!("Sector map of '{}' ({}):", td0_path, legend)];
+    // sector positions, numbered by their last digit
+    lines.push(format!("   C  H  {}", (1..=width).map(|n| char::from(b'0' + (n % 10) as u8)).collect::<String>()));
+    for (track, sectors) in map {
+        lines.push(format!("  {:2} {:2}  {}", track.cylinder, track.header.side_number, sectors.iter().map(SectorStatus::symbol).collect::<String>()));
+    }
+    lines
+}
+
+// one row per track in logical order, one pixel per sector coloured by its status; tracks with
+// fewer sectors than the widest are padded with black
+pub(crate) fn write_map_png(disk: &Disk, path: &Path) -> io::Result<()> {
+    let map = disk.sector_map();
+    let width = map.iter().map(|(_, sectors)| sectors.len()).max().unwrap_or(0).max(1);
+    let height = map.len().max(1);
+
+    let mut pixels = vec![0u8; width * height * 3];
+    for (y, (_, sectors)) in map.iter().enumerate() {
+        for (x, status) in sectors.iter().enumerate() {
+            let i = (y * width + x) * 3;
+            pixels[i..i + 3].copy_from_slice(&status.rgb());
+        }
+    }
+
+    std::fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&pixels).map_err(io::Error::other)
+}
+
+// each sector recorded with data as a file of its own in dir, named by the track's cylinder and
+// head and the sector's number as --dump takes them. a number the track repeats gets ~2, ~3 and so
+// on after it, as --extract does. with tracks, each track's sectors are also written one after
+// another as they were recorded. returns how many sector files were written
+pub(crate) fn write_sector_files(disk: &Disk, dir: &Path, tracks: bool) -> io::Result<usize> {
+    std::fs::create_dir_all(dir)?;
+    let mut written = 0;
+    for track in &disk.tracks {
+        let name = format!("c{:02}_h{}", track.cylinder, track.header.side_number);
+        let mut seen: HashMap<u8, usize> = HashMap::new();
+        let mut whole = Vec::new();
+        for sector in track.sectors.iter().filter(|s| !s.data.is_empty()) {
+            let times = seen.entry(sector.header.sector_number).or_default();
+            *times += 1;
+            let suffix = if *times > 1 { format!("~{}", times) } else { String::new() };
+            let path = dir.join(format!("{}_s{:02}{}.bin", name, sector.header.sector_number, suffix));
+            File::create(path)?.write_all(&sector.data)?;
+            whole.extend_from_slice(&sector.data);
+            written += 1;
+        }
+        if tracks && !whole.is_empty() {
+            File::create(dir.join(format!("{}.bin", name)))?.write_all(&whole)?;
+        }
+    }
+    Ok(written)
+}
+
+// a line for each place a --grep or --grep-hex pattern is found, by the track's cylinder and head
+// and the sector's number as --dump takes them, with the offset in the sector
+pub(crate) fn grep_disk(args: &Args, disk: &Disk) -> Vec<String> {
+    let patterns = args.grep.iter().map(|text| (format!("'{}'", text), args.codepage.encode(text)))
+        .chain(args.grep_hex.iter().map(|bytes| (hex::encode(bytes), bytes.to_vec())));
+    let mut lines = Vec::new();
+    for (name, pattern) in patterns {
+        for (track, sector, offset) in disk.find(&pattern) {
+            lines.push(format!("cylinder {} head {} sector {} offset {} (0x{:x}) {}", track.cylinder, track.header.side_number,
+                sector.header.sector_number, offset, offset, name));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dumps_hex_and_ascii() {
+        let lines = hex_dump(b"TD\x00\x15hello, world", ("", "", ""), 8);
+        assert_eq!(lines, [
+            "0000  54 44 00 15 68 65 6c 6c  |TD..hell|",
+            "0008  6f 2c 20 77 6f 72 6c 64  |o, world|",
+        ]);
+        assert_eq!(hex_dump(b"A", ("<", "[", "]"), 2), ["0000  [41]     |<A]|"]);
+        assert_eq!(sector_dump(&[0xe5; 128], ("", "", ""), 16), ["blank, all e5"]);
+    }
+
+    #[test]
+    fn sector_map_has_a_line_per_track() {
+        let mut disk = crate::formats::flat_disk(&[0; 24 * 128], 2, 1, 12, 128);
+        disk.tracks[0].sectors[2].header.flags |= 0x04;
+        disk.tracks[1].sectors[0].header.flags |= 0x20;
+        disk.tracks[1].sectors[11].header.sector_size = 256;
+        let lines = sector_map_lines(&disk, "a.td0");
+        assert!(lines[0].starts_with("Sector map of 'a.td0' (. good, C CRC error, D deleted"));
+        assert_eq!(lines[1..], ["   C  H  123456789012", "   0  0  ..D.........", "   1  0  -..........S"]);
+    }
+
+    #[test]
+    fn map_png_has_a_pixel_per_sector() {
+        let path = crate::tests::temp_dir("map").join("map.png");
+        let mut disk = crate::formats::flat_disk(&[0; 4 * 128], 2, 1, 2, 128);
+        disk.tracks[0].sectors[1].header.flags |= 0x02;
+        disk.tracks[1].sectors.pop();
+        write_map_png(&disk, &path).unwrap();
+        let mut reader = png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        // a row per track, the shorter one padded with black
+        let [good, crc] = [SectorStatus::Good, SectorStatus::CrcError].map(|s| s.rgb());
+        assert_eq!(pixels[..info.buffer_size()], [good, crc, good, [0; 3]].concat());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn writes_a_file_per_sector() {
+        let dir = crate::tests::temp_dir("sectors");
+        let img: Vec<u8> = (0..4).flat_map(|n| [n; 128]).collect();
+        let mut disk = crate::formats::flat_disk(&img, 2, 1, 2, 128);
+        disk.tracks[1].sectors[1].header.sector_number = 1;
+        disk.tracks[1].sectors[0].data.clear();
+        assert_eq!(write_sector_files(&disk, &dir, true).unwrap(), 3);
+        assert_eq!(std::fs::read(dir.join("c00_h0_s02.bin")).unwrap(), [1; 128]);
+        assert_eq!(std::fs::read(dir.join("c00_h0.bin")).unwrap(), img[..256]);
+        assert_eq!(std::fs::read(dir.join("c01_h0_s01.bin")).unwrap(), [3; 128]);
+        assert!(!dir.join("c01_h0_s01~2.bin").exists());
+
+        disk.tracks[1].sectors[0].data = vec![2; 128];
+        write_sector_files(&disk, &dir, false).unwrap();
+        assert_eq!(std::fs::read(dir.join("c01_h0_s01.bin")).unwrap(), [2; 128]);
+        assert_eq!(std::fs::read(dir.join("c01_h0_s01~2.bin")).unwrap(), [3; 128]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
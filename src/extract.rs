@@ -0,0 +1,141 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::Path,
+};
+
+use crate::{
+    analysis::Disk,
+    fs::{
+        fat::{entry_name, file_size, first_cluster, ClusterEntry, Fat},
+        Codepage, DENT_SIZE,
+    },
+};
+
+// what was written of a FAT disk's root directory
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Extracted {
+    pub(crate) files: usize,
+    pub(crate) undeleted: usize,
+    pub(crate) reused: Vec<String>,    // deleted files some of whose clusters now belong to another
+}
+
+// write the files in the root directory of each FAT volume into dir, a directory per partition on
+// a hard disk. a file's clusters are followed through the FAT. a deleted file has lost its chain,
+// so with undelete its size is read from the clusters that follow on from its first, which on a
+// disk that wasn't written to much since is the file as it was. names that can't be files anywhere
+// have their ? and the like made _
+pub(crate) fn extract_fat_files(disk: &Disk, dir: &Path, undelete: bool, codepage: Codepage) -> io::Result<Extracted> {
+    let bytes = disk.logical_bytes();
+    let mut extracted = Extracted::default();
+    for volume in disk.fat_volumes() {
+        let data = &bytes[volume.offset.min(bytes.len())..(volume.offset + volume.size).min(bytes.len())];
+        let bpb = &volume.bpb;
+        let fat = Fat::new(data, bpb);
+        let volume_dir = match volume.partition {
+            Some(partition) => dir.join(format!("partition{}", partition)),
+            None => dir.to_path_buf(),
+        };
+        let mut names = HashSet::new();
+        for dent in disk.fat_root_dir(&volume).chunks_exact(DENT_SIZE).take_while(|dent| dent[0] != 0x00) {
+            // directories, volume labels and long names
+            if dent[0x0b] & 0x18 != 0 { continue; }
+            let deleted = dent[0] == 0xe5;
+            if deleted && !undelete { continue; }
+
+            let size = file_size(dent);
+            let clusters = size.div_ceil(bpb.cluster_size());
+            let first = first_cluster(dent);
+            let chain: Vec<usize> = match deleted {
+                _ if size == 0 => Vec::new(),
+                false => fat.chain(first),
+                true => (first..first + clusters).take_while(|&n| fat.entry(n).is_some()).collect(),
+            };
+            let mut contents: Vec<u8> = chain.iter().flat_map(|&n| bpb.cluster(data, n)).copied().collect();
+            contents.truncate(size);
+
+            let name = entry_name(dent, codepage);
+            if deleted && chain.iter().any(|&n| fat.entry(n) != Some(ClusterEntry::Free)) {
+                extracted.reused.push(name.clone());
+            }
+            let base: String = name.chars()
+                .map(|c| if c.is_alphanumeric() || "!#$%&'()-@^_`{}~.".contains(c) { c } else { '_' })
+                .collect();
+            // two deleted files can have had names differing only in the letter they've lost
+            let mut file_name = base.clone();
+            let mut n = 1;
+            while !names.insert(file_name.to_uppercase()) {
+                n += 1;
+                file_name = format!("{}~{}", base, n);
+            }
+            std::fs::create_dir_all(&volume_dir)?;
+            std::fs::write(volume_dir.join(&file_name), &contents)?;
+            if deleted { extracted.undeleted += 1 } else { extracted.files += 1 }
+        }
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::flat_disk;
+
+    fn set_fat12(img: &mut [u8], n: usize, value: u16) {
+        for fat in [512, 1024] {
+            let at = fat + n * 3 / 2;
+            let pair = u16::from_le_bytes([img[at], img[at + 1]]);
+            let pair = if n.is_multiple_of(2) { pair & 0xf000 | value } else { pair & 0x000f | value << 4 };
+            img[at..at + 2].copy_from_slice(&pair.to_le_bytes());
+        }
+    }
+
+    fn dent(img: &mut [u8], i: usize, name: &[u8; 11], attr: u8, cluster: u16, size: u32) {
+        let at = 3 * 512 + i * DENT_SIZE;
+        img[at..at + 11].copy_from_slice(name);
+        img[at + 0x0b] = attr;
+        img[at + 0x1a..at + 0x1c].copy_from_slice(&cluster.to_le_bytes());
+        img[at + 0x1c..at + 0x20].copy_from_slice(&size.to_le_bytes());
+    }
+
+    // a 160K DOS floppy: a label, a file of two clusters, and two deleted files, the first still
+    // in clusters nothing else has taken and the second in one that has been
+    fn floppy() -> Disk {
+        let mut img = vec![0; 320 * 512];
+        img[..0x20].copy_from_slice(&[0xeb, 0x3c, 0x90, b'K', b'C', b'8', b'5', b'8', b'7', b' ', b' ',
+            0x00, 0x02, 1, 1, 0, 2, 64, 0, 0x40, 0x01, 0xfe, 1, 0, 8, 0, 1, 0, 0, 0, 0, 0]);
+        img[510..512].copy_from_slice(&[0x55, 0xaa]);
+        set_fat12(&mut img, 0, 0xffe);
+        set_fat12(&mut img, 1, 0xfff);
+        set_fat12(&mut img, 2, 3);
+        set_fat12(&mut img, 3, 0xfff);
+        set_fat12(&mut img, 6, 0xfff);
+        dent(&mut img, 0, b"SYSTEM     ", 0x08, 0, 0);
+        dent(&mut img, 1, b"LIVE    TXT", 0x20, 2, 700);
+        dent(&mut img, 2, b"\xe5ONE    TXT", 0x20, 4, 600);
+        dent(&mut img, 3, b"\xe5ONE    TXT", 0x20, 6, 10);
+        // cluster n is sector n + 5, after the boot sector, two FATs and the root directory
+        img[7 * 512..8 * 512].fill(b'a');
+        img[8 * 512..9 * 512].fill(b'b');
+        img[9 * 512..11 * 512].fill(b'c');
+        img[11 * 512..12 * 512].fill(b'd');
+        flat_disk(&img, 40, 1, 8, 512)
+    }
+
+    #[test]
+    fn extracts_and_undeletes() {
+        let dir = crate::tests::temp_dir("extract");
+        let disk = floppy();
+        let extracted = extract_fat_files(&disk, &dir, false, Codepage::Cp437).unwrap();
+        assert_eq!(extracted, Extracted { files: 1, undeleted: 0, reused: Vec::new() });
+        let live = std::fs::read(dir.join("LIVE.TXT")).unwrap();
+        assert_eq!(live, [vec![b'a'; 512], vec![b'b'; 188]].concat());
+        assert!(!dir.join("_ONE.TXT").exists());
+
+        let extracted = extract_fat_files(&disk, &dir, true, Codepage::Cp437).unwrap();
+        assert_eq!(extracted, Extracted { files: 1, undeleted: 2, reused: vec!["?ONE.TXT".to_string()] });
+        assert_eq!(std::fs::read(dir.join("_ONE.TXT")).unwrap(), vec![b'c'; 600]);
+        assert_eq!(std::fs::read(dir.join("_ONE.TXT~2")).unwrap(), vec![b'd'; 10]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
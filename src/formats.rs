@@ -0,0 +1,196 @@
+use kc8587::td0::{SectorHeader, TrackHeader};
+
+use crate::{
+    analysis::{Disk, Sector, Track},
+    cli::Profile,
+    fs::fat::Bpb,
+    report::{ImageFormat, ImageStatus},
+};
+
+// flat images of these sizes that aren't one of the PC profiles: bytes, then cylinders, heads,
+// sectors per track and sector size
+pub(crate) const FLAT_FORMATS: &[(usize, usize, u8, u8, u16)] = &[
+    (143360, 35, 1, 16, 256),   // Apple II, DOS 3.3 or ProDOS order
+    (655360, 80, 2, 16, 256),   // TR-DOS
+];
+
+// the cylinders, heads, sectors per track and sector size a flat image seems to have: what its BPB
+// says if it has one that fits the image's size, otherwise a standard format of that size
+pub(crate) fn flat_geometry(img: &[u8]) -> Option<(usize, u8, u8, u16)> {
+    if let (Some(bpb), Some(shape)) = (Bpb::from_boot_sector(img), img.get(0x18..0x1c)) {
+        let sectors = u16::from_le_bytes([shape[0], shape[1]]) as usize;
+        let heads = u16::from_le_bytes([shape[2], shape[3]]) as usize;
+        let cylinder = bpb.bytes_per_sector as usize * sectors * heads;
+        if (1..=255).contains(&sectors) && (1..=255).contains(&heads) && img.len() >= cylinder && img.len().is_multiple_of(cylinder) {
+            return Some((img.len() / cylinder, heads as u8, sectors as u8, bpb.bytes_per_sector));
+        }
+    }
+    let profiles = <Profile as clap::ValueEnum>::value_variants().iter().map(|profile| {
+        let (cylinders, heads, sectors, _, _) = profile.shape();
+        (cylinders * heads as usize * sectors as usize * 512, cylinders, heads, sectors, 512)
+    });
+    profiles.chain(FLAT_FORMATS.iter().copied())
+        .find(|&(size, ..)| size == img.len())
+        .map(|(_, cylinders, heads, sectors, sector_size)| (cylinders, heads, sectors, sector_size))
+}
+
+// a flat image cut into sectors numbered from 1, cylinder by cylinder
+pub(crate) fn flat_disk(img: &[u8], cylinders: usize, heads: u8, sectors: u8, sector_size: u16) -> Disk {
+    let mut chunks = img.chunks_exact(sector_size as usize);
+    let mut disk = Disk::default();
+    for cylinder in 0..cylinders {
+        for head in 0..heads {
+            disk.tracks.push(Track {
+                header: TrackHeader { number_of_sectors: sectors, cylinder_number: cylinder as u8, side_number: head },
+                cylinder,
+                sectors: (1..=sectors).map(|sector_number| Sector {
+                    header: SectorHeader { cylinder_number: cylinder as u8, side_number: head, sector_number, sector_size, flags: 0 },
+                    data: chunks.next().unwrap().to_vec(),
+                }).collect(),
+            });
+        }
+    }
+    disk
+}
+
+// a 256 byte disk information block, then for each track a 256 byte track information block
+// listing its sectors' ID fields and FDC status, followed by their data. the standard format has
+// one size for every track and sector, the extended one a size per track and per sector, and 0 for
+// a track that wasn't formatted
+pub(crate) fn read_dsk(bytes: &[u8]) -> (ImageFormat, Disk, ImageStatus) {
+    let extended = bytes.starts_with(b"EXTENDED");
+    let creator = bytes.get(0x22..0x30).map_or_else(String::new,
+        |name| String::from_utf8_lossy(name).trim_end_matches(['\0', ' ']).to_string());
+    let format = if extended { ImageFormat::Edsk { creator } } else { ImageFormat::Dsk { creator } };
+    let mut disk = Disk::default();
+    let Some(info) = bytes.get(..0x100) else {
+        return (format, disk, ImageStatus::EmptyDump);
+    };
+    let track_count = info[0x30] as usize * info[0x31] as usize;
+    let standard_size = u16::from_le_bytes([info[0x32], info[0x33]]) as usize;
+
+    let mut offset = 0x100;
+    for t in 0..track_count {
+        let size = if extended { info.get(0x34 + t).copied().unwrap_or(0) as usize * 256 } else { standard_size };
+        if size == 0 { continue; }
+        let Some(block) = bytes.get(offset..offset + size) else {
+            let status = if disk.tracks.is_empty() { ImageStatus::EmptyDump } else { ImageStatus::Truncated };
+            return (format, disk, status);
+        };
+        offset += size;
+        if !block.starts_with(b"Track-Info") || size < 0x100 {
+            return (format, disk, ImageStatus::ReadError);
+        }
+
+        let (cylinder, side, sector_count) = (block[0x10], block[0x11], block[0x15].min(29));
+        let standard_length = 128usize << block[0x14].min(6);
+        let mut data_offset = 0x100;
+        let sectors = block[0x18..0x18 + sector_count as usize * 8].chunks_exact(8).map(|id| {
+            let sector_size = 128u16 << id[3].min(6);
+            let length = if extended { u16::from_le_bytes([id[6], id[7]]) as usize } else { standard_length };
+            // weak sectors are stored as several copies one after the other, of which the first is kept
+            let mut data = block.get(data_offset..).unwrap_or_default().iter().take(length.min(sector_size as usize)).copied().collect::<Vec<_>>();
+            data.resize(sector_size as usize, 0);
+            data_offset += length;
+            let (st1, st2) = (id[4], id[5]);
+            let mut flags = 0;
+            if st1 & 0x20 != 0 || st2 & 0x20 != 0 { flags |= 0x02; }    // data error
+            if st2 & 0x40 != 0 { flags |= 0x04; }                      // deleted data address mark
+            if st1 & 0x01 != 0 || st2 & 0x01 != 0 { flags |= 0x20; }    // missing address mark
+            Sector { header: SectorHeader { cylinder_number: id[0], side_number: id[1], sector_number: id[2], sector_size, flags }, data }
+        }).collect();
+        disk.tracks.push(Track {
+            header: TrackHeader { number_of_sectors: sector_count, cylinder_number: cylinder, side_number: side },
+            cylinder: cylinder as usize,
+            sectors,
+        });
+    }
+    let status = if disk.tracks.is_empty() { ImageStatus::EmptyDump } else { ImageStatus::Ok };
+    (format, disk, status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a Disk-Info block for tracks of one side, with the standard track size or each track's size
+    fn disk_info(signature: &[u8], tracks: u8, standard_size: u16, sizes: &[u8]) -> Vec<u8> {
+        let mut info = vec![0; 0x100];
+        info[..signature.len()].copy_from_slice(signature);
+        info[0x22..0x2a].copy_from_slice(b"kc8587  ");
+        info[0x30] = tracks;
+        info[0x31] = 1;
+        info[0x32..0x34].copy_from_slice(&standard_size.to_le_bytes());
+        info[0x34..0x34 + sizes.len()].copy_from_slice(sizes);
+        info
+    }
+
+    // a Track-Info block: ID, size code, FDC status registers and stored length of each sector
+    // then their data
+    fn track_info(cylinder: u8, size_code: u8, ids: &[(u8, u8, u8, u8, u16)], data: &[u8]) -> Vec<u8> {
+        let mut block = vec![0; 0x100];
+        block[..12].copy_from_slice(b"Track-Info\r\n");
+        block[0x10] = cylinder;
+        block[0x14] = size_code;
+        block[0x15] = ids.len() as u8;
+        for (i, &(sector, size, st1, st2, length)) in ids.iter().enumerate() {
+            block[0x18 + i * 8..0x20 + i * 8].copy_from_slice(&[cylinder, 0, sector, size, st1, st2, length as u8, (length >> 8) as u8]);
+        }
+        block.extend_from_slice(data);
+        block
+    }
+
+    fn sector_bytes(disk: &Disk, track: usize) -> Vec<Vec<u8>> {
+        disk.tracks[track].sectors.iter().map(|s| s.data.clone()).collect()
+    }
+
+    #[test]
+    fn reads_standard_tracks() {
+        let mut dsk = disk_info(b"MV - CPCEMU Disk-File\r\nDisk-Info\r\n", 2, 0x100 + 2 * 512, &[]);
+        for cylinder in 0..2 {
+            let data: Vec<u8> = [0xe5; 512].into_iter().chain([cylinder; 512]).collect();
+            dsk.extend(track_info(cylinder, 2, &[(0xc1, 2, 0, 0, 0), (0xc2, 2, 0, 0x40, 0)], &data));
+        }
+        let (format, disk, status) = read_dsk(&dsk);
+        assert!(matches!(format, ImageFormat::Dsk { ref creator } if creator == "kc8587"));
+        assert_eq!(status, ImageStatus::Ok);
+        assert_eq!(disk.tracks.len(), 2);
+        assert_eq!(disk.tracks[1].cylinder, 1);
+        assert_eq!(disk.tracks[1].sectors.iter().map(|s| s.header.sector_number).collect::<Vec<_>>(), [0xc1, 0xc2]);
+        assert_eq!(sector_bytes(&disk, 1), [vec![0xe5; 512], vec![1; 512]]);
+        assert_eq!(disk.tracks[0].sectors[1].header.flags, 0x04);
+    }
+
+    #[test]
+    fn reads_extended_track_sizes() {
+        // an unformatted track between two, the second with a weak sector, a short one and one
+        // with a data error
+        let mut dsk = disk_info(b"EXTENDED CPC DSK File\r\nDisk-Info\r\n", 3, 0, &[3, 0, 5]);
+        dsk.extend(track_info(0, 2, &[(1, 2, 0, 0, 512)], &[0x11; 512]));
+        let data: Vec<u8> = [1; 256].into_iter().chain([2; 256]).chain([3; 128]).chain([4; 256]).chain([0; 128]).collect();
+        dsk.extend(track_info(2, 1, &[(1, 1, 0x20, 0x20, 512), (2, 1, 0, 0, 128), (3, 1, 0x01, 0, 256)], &data));
+        let (format, disk, status) = read_dsk(&dsk);
+        assert!(matches!(format, ImageFormat::Edsk { .. }));
+        assert_eq!(status, ImageStatus::Ok);
+        assert_eq!(disk.tracks.iter().map(|t| t.cylinder).collect::<Vec<_>>(), [0, 2]);
+        let short: Vec<u8> = [3; 128].into_iter().chain([0; 128]).collect();
+        assert_eq!(sector_bytes(&disk, 1), [vec![1; 256], short, vec![4; 256]]);
+        assert_eq!(disk.tracks[1].sectors.iter().map(|s| s.header.flags).collect::<Vec<_>>(), [0x02, 0, 0x20]);
+    }
+
+    #[test]
+    fn reports_truncated_files() {
+        let mut dsk = disk_info(b"MV - CPCEMU Disk-File\r\nDisk-Info\r\n", 2, 0x100 + 512, &[]);
+        dsk.extend(track_info(0, 2, &[(1, 2, 0, 0, 0)], &[0xe5; 512]));
+        dsk.extend(track_info(1, 2, &[(1, 2, 0, 0, 0)], &[0xe5; 512]));
+
+        assert_eq!(read_dsk(&dsk[..0x80]).2, ImageStatus::EmptyDump);
+        assert_eq!(read_dsk(&dsk[..0x100 + 0x200]).2, ImageStatus::EmptyDump);
+        let (_, disk, status) = read_dsk(&dsk[..dsk.len() - 1]);
+        assert_eq!((disk.tracks.len(), status), (1, ImageStatus::Truncated));
+
+        // a track whose block isn't where the sizes say it is
+        dsk[0x32..0x34].copy_from_slice(&0x200u16.to_le_bytes());
+        assert_eq!(read_dsk(&dsk).2, ImageStatus::ReadError);
+    }
+}
@@ -0,0 +1,105 @@
+// boot loaders known by bytes their code or messages always have. the messages and the names of
+// the files a loader looks for change from one release to the next far more than the code does,
+// and unlike a hash of the whole sector they survive the BPB and serial number differing
+
+// a loader whose boot sector has every one of patterns somewhere in its first 512 bytes, or the
+// first of them at offset when that's given
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Fingerprint {
+    pub(crate) name: &'static str,
+    pub(crate) virus: bool,
+    #[serde(skip)]
+    offset: Option<usize>,
+    #[serde(skip)]
+    patterns: &'static [&'static [u8]],
+}
+
+const fn loader(name: &'static str, patterns: &'static [&'static [u8]]) -> Fingerprint {
+    Fingerprint { name, virus: false, offset: None, patterns }
+}
+
+const fn virus(name: &'static str, offset: Option<usize>, patterns: &'static [&'static [u8]]) -> Fingerprint {
+    Fingerprint { name, virus: true, offset, patterns }
+}
+
+// the first that matches is taken, so viruses, which keep the boot sector they replace elsewhere,
+// come first and a release comes before the family it's one of
+pub(crate) const FINGERPRINTS: &[Fingerprint] = &[
+    // Stoned and the viruses built on it, such as Michelangelo, jump far to 07c0:0005 to run at
+    // the same offsets wherever the BIOS loaded them
+    virus("Stoned", Some(0), &[b"\xea\x05\x00\xc0\x07"]),
+    virus("Stoned", None, &[b"Your PC is now Stoned!"]),
+    virus("Brain", None, &[b"Welcome to the Dungeon"]),
+    // Form carries a greeting it never shows
+    virus("Form", None, &[b"The FORM-Virus sends greetings"]),
+    loader("Windows NT", &[b"NTLDR"]),
+    loader("MS-DOS 7 (Windows 9x)", &[b"IO      SYS", b"WINBOOT SYS"]),
+    loader("MS-DOS 2-3", &[b"IO      SYS", b"MSDOS   SYS", b"strike any key"]),
+    loader("MS-DOS 4-6", &[b"IO      SYS", b"MSDOS   SYS", b"press any key"]),
+    loader("MS-DOS", &[b"IO      SYS", b"MSDOS   SYS"]),
+    loader("PC-DOS 1", &[b"ibmbio  com", b"ibmdos  com"]),
+    // DR-DOS loads the same files as PC-DOS, but says something else when it can't
+    loader("DR-DOS", &[b"IBMBIO  COM", b"Cannot load DOS"]),
+    loader("PC-DOS 2-3", &[b"IBMBIO  COM", b"IBMDOS  COM", b"strike any key"]),
+    loader("PC-DOS 4-7", &[b"IBMBIO  COM", b"IBMDOS  COM", b"press any key"]),
+    loader("PC-DOS", &[b"IBMBIO  COM", b"IBMDOS  COM"]),
+    loader("FreeDOS", &[b"KERNEL  SYS"]),
+    loader("OS/2", &[b"OS2BOOT"]),
+    loader("SYSLINUX", &[b"SYSLINUX"]),
+    loader("LILO", &[b"LILO"]),
+    loader("Linux kernel", &[b"Direct floppy boot is not supported"]),
+    loader("Linux kernel", &[b"Direct booting from floppy is no longer supported"]),
+    loader("CP/M-86", &[b"CP/M-86"]),
+    loader("CP/M Plus", &[b"CP/M Plus"]),
+    loader("CP/M Plus", &[b"CP/M 3"]),
+    loader("CP/M 2.2", &[b"CP/M", b"2.2"]),
+];
+
+impl Fingerprint {
+    fn matches(&self, code: &[u8]) -> bool {
+        let found = |pattern: &[u8]| code.windows(pattern.len()).any(|w| w == pattern);
+        match self.offset {
+            Some(offset) => code.get(offset..).is_some_and(|rest| rest.starts_with(self.patterns[0]))
+                && self.patterns[1..].iter().all(|p| found(p)),
+            None => self.patterns.iter().all(|p| found(p)),
+        }
+    }
+}
+
+pub(crate) fn identify(boot_sector: &[u8]) -> Option<&'static Fingerprint> {
+    let code = &boot_sector[..boot_sector.len().min(512)];
+    FINGERPRINTS.iter().find(|f| f.matches(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sector(parts: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut bytes = vec![0; 512];
+        for (offset, part) in parts {
+            bytes[*offset..*offset + part.len()].copy_from_slice(part);
+        }
+        bytes
+    }
+
+    #[test]
+    fn identifies_loaders_and_viruses() {
+        let name = |parts: &[(usize, &[u8])]| identify(&sector(parts)).map(|f| (f.name, f.virus));
+        let dos5 = [(0x1a0, &b"Non-System disk or disk error\r\nReplace and press any key when ready\r\n"[..]), (0x1e6, b"IO      SYSMSDOS   SYS")];
+        assert_eq!(name(&dos5), Some(("MS-DOS 4-6", false)));
+        assert_eq!(name(&[(0x1e6, b"IBMBIO  COMIBMDOS  COM"), (0x180, b"Cannot load DOS press key to retry")]), Some(("DR-DOS", false)));
+        assert_eq!(name(&[(0x1e6, b"ibmbio  comibmdos  com")]), Some(("PC-DOS 1", false)));
+        assert_eq!(name(&[(0, b"\xea\x05\x00\xc0\x07"), (0x1a0, b"IO      SYSMSDOS   SYS")]), Some(("Stoned", true)));
+        assert_eq!(name(&[(0x100, b"The FORM-Virus sends greetings to everyone who's reading this text."), (0x1e6, b"IO      SYSMSDOS   SYS")]),
+            Some(("Form", true)));
+        // the jump has to be where the BIOS starts running it
+        assert_eq!(name(&[(2, b"\xea\x05\x00\xc0\x07")]), None);
+        assert_eq!(name(&[(0x80, b"CP/M Plus")]), Some(("CP/M Plus", false)));
+        assert_eq!(name(&[]), None);
+        // only the sector the BIOS loads is looked at
+        let mut longer = sector(&[]);
+        longer.extend_from_slice(b"NTLDR");
+        assert!(identify(&longer).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::BTreeMap,
+    ops::ControlFlow,
+};
+
+use super::{dent_name, Codepage, Detection, FileEntry, FilesystemDetector, DENT_SIZE};
+use crate::{
+    analysis::Disk,
+    report::{tr, Language},
+};
+
+// CP/M has nothing but its directory to go on
+pub(crate) struct CpmDetector {
+    pub(crate) max_records: u8,
+    pub(crate) min_entries: usize,
+    pub(crate) user: Option<u8>,    // the one user area to list the files of
+}
+
+impl FilesystemDetector for CpmDetector {
+    fn filesystem(&self) -> &'static str {
+        "CP/M"
+    }
+
+    fn min_entries(&self) -> usize {
+        self.min_entries
+    }
+
+    fn probe_entry(&mut self, data: &[u8], i: usize, _codepage: Codepage) -> Vec<String> {
+        iscpm(data, i, self.max_records).continue_value().into_iter().collect()
+    }
+
+    fn read_entries(&self, disk: &Disk, codepage: Codepage) -> Detection {
+        let bytes = disk.logical_bytes();
+        let files = cpm_files(&bytes[..bytes.len() - bytes.len() % DENT_SIZE], self.max_records, codepage).into_iter()
+            .filter(|file| self.user.is_none_or(|user| file.user == user))
+            .map(|file| FileEntry { partition: None, name: file.name, size: file.records * 128, directory: false, deleted: false, user: Some(file.user) })
+            .collect();
+        Detection { filesystem: "CP/M", label: None, files }
+    }
+}
+
+// a file made up of all the extents with its user number and name
+#[derive(Debug, PartialEq)]
+pub(crate) struct CpmFile {
+    pub(crate) user: u8,
+    pub(crate) name: String,
+    pub(crate) extents: usize,
+    pub(crate) records: u64,    // 128 bytes each, so the size is to the record
+}
+
+// the files of the directory entries in data, each entry an extent of up to 16K of a file, by user
+// area then name. the KC 85 only uses user 0, but other machines use all 16
+pub(crate) fn cpm_files(data: &[u8], max_records: u8, codepage: Codepage) -> Vec<CpmFile> {
+    let mut files: BTreeMap<(u8, String), CpmFile> = BTreeMap::new();
+    for dent in data.chunks_exact(DENT_SIZE) {
+        if dent[0] > 15 || !valid_extent(dent, max_records) { continue; }
+        let name: Vec<u8> = dent[1..12].iter().map(|b| b & 0x7f).collect();
+        let name = dent_name(&name[..8], &name[8..], codepage);
+        let file = files.entry((dent[0], name.clone()))
+            .or_insert(CpmFile { user: dent[0], name, extents: 0, records: 0 });
+        file.extents += 1;
+        file.records += dent[15] as u64;
+    }
+    files.into_values().collect()
+}
+
+// on a match, continues with the line describing the entry
+fn iscpm(data: &[u8], i: usize, max_records: u8) -> ControlFlow<(), String> {
+    let status = data[i];
+    let cpm_name_and_ext = &data[i + 1..i + 12];
+    let ex = data[i + 12];
+    let s1 = data[i + 13];
+    let s2 = data[i + 14];
+    let rc = data[i + 15];
+    let al = &data[i + 16..i + 32];
+
+    // KC 85 / Robotron allow only 0x00, 0xe5, or 0x80
+    if status != 0x00 && status != 0xe5 && status != 0x80 { return ControlFlow::Break(()); }
+
+    let (name_and_ext, flags): ([char; 11], [bool; 11]) = cpm_name_and_ext.iter().enumerate().fold(
+        ([0 as char; 11], [false; 11]),
+        |(mut n, mut f), (i, b)| {
+            n[i] = (b & 0x7f) as char;
+            f[i] = b & 0x80 != 0;
+            (n, f)
+        }
+    );
+    
+    // check for false positive when status is 0xe5 *and* so is every byte of the filename and extension
+    if status == 0xe5 && cpm_name_and_ext.iter().all(|&b| b == 0xe5) { return ControlFlow::Break(()); }
+
+    if !valid_extent(&data[i..i + DENT_SIZE], max_records) { return ControlFlow::Break(()); }
+    
+    let (name, ext) = name_and_ext.split_at(8);
+
+    ControlFlow::Continue(format!("CPM {:2} St: {:02x} {}.{} {} ExS1S2Rc: {:3?} AL: {:3?}",
+        i/32, status,
+        name.iter().collect::<String>(), ext.iter().collect::<String>(),
+        flags.iter().map(|b| if *b { "1" } else { "0" }).collect::<String>(),
+        (ex, s1, s2, rc), al))
+}
+
+// whether everything in an entry but its status could be an extent
+fn valid_extent(dent: &[u8], max_records: u8) -> bool {
+    if dent[1..12].iter().any(|b| !(0x20..=0x7e).contains(&(b & 0x7f))) { return false; }
+
+    // KC 85 / Robotron -specific checks: S1 and S2 must be 0x00, s3 must be <= 128
+    if dent[13] != 0x00 || dent[14] != 0x00 || dent[15] > max_records { return false; }
+
+    // AL must match: zero or more nonzero pairs, followed by zero or more zero pairs
+    let pairs: Vec<&[u8]> = dent[16..32].chunks_exact(2).collect();
+    !pairs.windows(2).any(|window| window[1] != [0, 0] && window[0] == [0, 0])
+}
+
+// a CP/M disk as formatted by an Amstrad CPC, PCW or Spectrum +3
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct AmstradFormat {
+    #[serde(rename = "format")]
+    pub(crate) name: &'static str,     // CPC DATA, CPC SYSTEM, IBM, or one of the PCW formats
+    pub(crate) spec_block: bool,       // whether the first sector describes the format
+    pub(crate) amsdos_headers: usize,  // the 128 byte headers AMSDOS and +3DOS put before binary and BASIC files
+    pub(crate) plus3dos_headers: usize,
+}
+
+impl AmstradFormat {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        format!("Amstrad {} {}{} - {} {} {} {}", self.name, t("format"),
+            if self.spec_block { format!(" {}", t("with disk specification block")) } else { String::new() },
+            self.amsdos_headers, t("AMSDOS headers"), self.plus3dos_headers, t("+3DOS headers"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dent(status: u8, name: &[u8; 11], rc: u8, al: &[u8]) -> [u8; 32] {
+        let mut dent = [0; 32];
+        dent[0] = status;
+        dent[1..12].copy_from_slice(name);
+        dent[15] = rc;
+        dent[16..16 + al.len()].copy_from_slice(al);
+        dent
+    }
+
+    #[test]
+    fn directory_entries() {
+        assert!(iscpm(&dent(0, b"HELLO   COM", 8, &[2, 0, 3, 0]), 0, 128).is_continue());
+        // KC 85 user numbers stop at 0x00, 0x80 and deleted entries
+        assert!(iscpm(&dent(5, b"HELLO   COM", 8, &[2, 0]), 0, 128).is_break());
+        assert!(iscpm(&dent(0, b"HELLO   COM", 129, &[2, 0]), 0, 128).is_break());
+        // a gap in the allocation followed by more blocks
+        assert!(iscpm(&dent(0, b"HELLO   COM", 8, &[0, 0, 3, 0]), 0, 128).is_break());
+        assert!(iscpm(&[0xe5; 32], 0, 128).is_break());
+    }
+
+    #[test]
+    fn extents_make_files() {
+        let mut data = Vec::new();
+        // a file of three extents, the last one partly full, one of the same name in user 3, and a
+        // deleted one
+        for (ex, rc) in [(0, 128), (1, 128), (2, 17)] {
+            let mut extent = dent(0, b"BIGFILE DAT", rc, &[2 + ex, 0]);
+            extent[12] = ex;
+            data.extend(extent);
+        }
+        data.extend(dent(3, b"BIGFILE DAT", 4, &[9, 0]));
+        data.extend(dent(0xe5, b"GONE    TXT", 1, &[10, 0]));
+        data.extend([0xe5; 32]);
+        assert_eq!(cpm_files(&data, 128, Codepage::Cp437), [
+            CpmFile { user: 0, name: "BIGFILE.DAT".to_string(), extents: 3, records: 273 },
+            CpmFile { user: 3, name: "BIGFILE.DAT".to_string(), extents: 1, records: 4 },
+        ]);
+    }
+}
@@ -0,0 +1,661 @@
+use std::ops::{ControlFlow, Range};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+
+use super::{bootcode::{self, Fingerprint}, dent_name, Codepage, Detection, FileEntry, FilesystemDetector, DENT_SIZE};
+use crate::{
+    analysis::Disk,
+    report::{tr, Language},
+};
+
+// FAT is certain when there's a BPB, and the root directory of each volume is then listed
+pub(crate) struct FatDetector {
+    pub(crate) long_name: LongName,    // being gathered from the entries before an 8.3 entry
+    pub(crate) reserved_set: usize,
+    pub(crate) min_entries: usize,
+}
+
+impl FilesystemDetector for FatDetector {
+    fn filesystem(&self) -> &'static str {
+        "FAT"
+    }
+
+    fn min_entries(&self) -> usize {
+        self.min_entries
+    }
+
+    fn probe_entry(&mut self, data: &[u8], i: usize, codepage: Codepage) -> Vec<String> {
+        if let ControlFlow::Continue(line) = islfn(data, i) {
+            self.long_name.add(&data[i..i + DENT_SIZE]);
+            return vec![line];
+        }
+        let long_name = self.long_name.take(&data[i..i + DENT_SIZE]);
+        match isfat(data, i, self.reserved_set, codepage) {
+            ControlFlow::Continue(line) => vec![match long_name {
+                Some(long_name) => format!("{} Long: {}", line, long_name),
+                None => line,
+            }],
+            ControlFlow::Break(()) => Vec::new(),
+        }
+    }
+
+    fn probe_disk(&self, disk: &Disk, codepage: Codepage) -> Option<Detection> {
+        let volumes = disk.fat_volumes();
+        if volumes.is_empty() { return None; }
+        let files = volumes.iter().flat_map(|volume| {
+            disk.fat_root_dir(volume).chunks_exact(DENT_SIZE)
+                .take_while(|dent| dent[0] != 0x00)
+                .filter(|dent| dent[0] != b'.' && dent[0x0b] & 0x08 == 0) // also skips long names
+                .map(|dent| FileEntry {
+                    partition: volume.partition,
+                    name: entry_name(dent, codepage),
+                    size: file_size(dent) as u64,
+                    directory: dent[0x0b] & 0x10 != 0,
+                    deleted: dent[0] == 0xe5,
+                    user: None,
+                })
+                .collect::<Vec<_>>()
+        }).collect();
+        // the volume label of the first FAT filesystem
+        Some(Detection { filesystem: "FAT", label: disk.fat_volume_label(&volumes[0], codepage), files })
+    }
+}
+
+// NAME.EXT of a directory entry, starting with ? when it's deleted and its first letter lost
+pub(crate) fn entry_name(dent: &[u8], codepage: Codepage) -> String {
+    let mut name = [0; 8];
+    name.copy_from_slice(&dent[..8]);
+    name[0] = match name[0] {
+        0x05 => 0xe5,   // a name really starting with 0xe5
+        0xe5 => b'?',
+        b => b,
+    };
+    dent_name(&name, &dent[8..11], codepage)
+}
+
+pub(crate) fn file_size(dent: &[u8]) -> usize {
+    u32::from_le_bytes([dent[0x1c], dent[0x1d], dent[0x1e], dent[0x1f]]) as usize
+}
+
+pub(crate) fn first_cluster(dent: &[u8]) -> usize {
+    u16::from_le_bytes([dent[0x1a], dent[0x1b]]) as usize
+}
+
+// on a match, continues with the line describing the entry
+fn isfat(data: &[u8], i: usize, reserved_set: usize, codepage: Codepage) -> ControlFlow<(), String> {
+    let name_and_ext = &data[i..i+11];
+    let attr = data[i+0x0b];
+    let zeros = &data[i+0x0c..i+0x16]; // zeroes in my CM1910DC.TD0
+    let time = &data[i+0x16..i+0x18]; // time
+    let date = &data[i+0x18..i+0x1a]; // date
+    let cluster1 = &data[i+0x1a..i+0x1c]; // first cluster
+    let file_size = &data[i+0x1c..i+0x20]; // file size
+
+    // filename[0] can also be: 0x00, 0x05, 0x2E, 0xE5
+    let status: Option<&str> = match name_and_ext[0] {
+        0x00 => Some("00"), // Null character
+        0x05 => Some("05"), // Special value
+        0x2E => Some("2E"), // Special value
+        0xE5 => Some("E5"), // Special value
+        b if (0x20..=0x7E).contains(&b) || b >= 0x80 => Some("--"), // Printable characters
+        _ => None, // Any other value indicates an error
+    };
+
+    if status.is_none() { return ControlFlow::Break(()); }
+    let status = status.unwrap();
+    
+    // bytes from 0x80 up are letters in the disk's code page
+    for &b in name_and_ext {
+        if !(0x20..=0x7e).contains(&b) && b < 0x80 { return ControlFlow::Break(()); }
+    }
+
+    // count how many bytes in 'rest1' are non-zero - in my CM1910DC.TD0 they seem to be all zero
+    let nonzero_count = zeros.iter().fold(0, |acc, &b| if b != 0 { acc + 1 } else { acc });
+    if nonzero_count > reserved_set { return ControlFlow::Break(()); }
+
+    let first_letter = match name_and_ext[0] {
+        0x05 => codepage.decode(&[0xe5]),   // a name really starting with 0xe5
+        0xe5 => "?".to_string(),            // deleted, the first letter is lost
+        b if b >= 0x20 => codepage.decode(&[b]),
+        _ => "?".to_string(),
+    };
+
+    let att = match attr {
+        b if b & !0x3f != 0 => format!("  {:02x}  ", b),
+        _ => format!("{}{}{}{}{}{}",
+            if attr & 0x20 != 0 { "a" } else { "-" },   // archive
+            if attr & 0x10 != 0 { "d" } else { "-" },   // subdir
+            if attr & 0x08 != 0 { "v" } else { "-" },   // volume
+            if attr & 0x04 != 0 { "s" } else { "-" },   // system
+            if attr & 0x02 != 0 { "h" } else { "-" },   // hidden
+            if attr & 0x01 != 0 { "r" } else { "-" }    // readonly
+        ),
+    };
+
+    let time = u16::from_le_bytes([time[0], time[1]]);
+    let date = u16::from_le_bytes([date[0], date[1]]);
+    let modified = match dos_timestamp(time, date) {
+        _ if time == 0 && date == 0 => "no date".to_string(),  // left unset by some formatters and tools
+        Some(datetime) => datetime.to_string(),
+        None => format!("invalid {:04x} {:04x}", date, time),
+    };
+
+    ControlFlow::Continue(format!("FAT {:2} St: {} {}{}.{} Attr: {} Rest: {:02x?} {} {:04x?} {:08x?}",
+        i/32, status,
+        first_letter, codepage.decode(&name_and_ext[1..8]),
+        codepage.decode(&name_and_ext[8..11]),
+        att, zeros,
+        modified,
+        cluster1.iter().rev().fold(0, |acc, &b| (acc << 8) | b as usize),   // 16 bit little endian
+        file_size.iter().rev().fold(0, |acc, &b| (acc << 8) | b as usize),  // 32 bit little endian
+    ))
+}
+
+// on a match, continues with the line describing the entry
+fn islfn(data: &[u8], i: usize) -> ControlFlow<(), String> {
+    let sequence = data[i];
+    let attr = data[i + 0x0b];
+    let kind = data[i + 0x0c];
+    let cluster1 = &data[i + 0x1a..i + 0x1c]; // always zero in long name entries
+
+    if attr != 0x0f || kind != 0 || cluster1 != [0, 0] { return ControlFlow::Break(()); }
+    // up to 20 fragments, 0x40 marks the last (stored first), 0xe5 a deleted entry
+    if sequence != 0xe5 && (sequence & 0xa0 != 0 || !(1..=20).contains(&(sequence & 0x1f))) {
+        return ControlFlow::Break(());
+    }
+
+    ControlFlow::Continue(format!("LFN {:2} St: {:02x} '{}' Chk: {:02x}",
+        i/32, sequence, lfn_fragment(&data[i..i + DENT_SIZE]), data[i + 0x0d]))
+}
+
+// a VFAT long name, gathered from the entries just before the 8.3 entry it belongs to
+#[derive(Debug, Default)]
+pub(crate) struct LongName {
+    fragments: Vec<String>, // in the order they're stored, which is the end of the name first
+    pub(crate) next: u8,               // the sequence number the next fragment should have
+    pub(crate) checksum: u8,           // of the 8.3 name, as recorded in every fragment
+}
+
+impl LongName {
+    pub(crate) fn add(&mut self, dent: &[u8]) {
+        let sequence = dent[0];
+        if sequence & 0x40 != 0 {
+            self.fragments.clear();
+            self.next = sequence & 0x1f;
+            self.checksum = dent[0x0d];
+        }
+        if sequence == 0xe5 || sequence & 0x1f != self.next || dent[0x0d] != self.checksum {
+            // deleted, or not following on from the fragments so far
+            self.fragments.clear();
+            self.next = 0;
+            return;
+        }
+        self.fragments.push(lfn_fragment(dent));
+        self.next -= 1;
+    }
+
+    // the long name, if the fragments so far are all of one that belongs to this 8.3 entry
+    pub(crate) fn take(&mut self, dent: &[u8]) -> Option<String> {
+        let complete = !self.fragments.is_empty() && self.next == 0;
+        let fragments = std::mem::take(&mut self.fragments);
+        self.next = 0;
+
+        let checksum = dent[..11].iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+        (complete && checksum == self.checksum).then(|| fragments.into_iter().rev().collect())
+    }
+}
+
+// the 13 UCS-2 characters of a long name entry, up to the terminating 0
+fn lfn_fragment(dent: &[u8]) -> String {
+    let units: Vec<u16> = [1..11, 14..26, 28..32].into_iter()
+        .flat_map(|r| dent[r].chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect::<Vec<_>>())
+        .take_while(|&u| u != 0x0000)
+        .collect();
+    char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
+
+// the packed date and time of a directory entry: years from 1980, and seconds in steps of two
+fn dos_timestamp(time: u16, date: u16) -> Option<NaiveDateTime> {
+    let date = NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, ((date >> 5) & 0x0f) as u32, (date & 0x1f) as u32)?;
+    let time = NaiveTime::from_hms_opt((time >> 11) as u32, ((time >> 5) & 0x3f) as u32, (time & 0x1f) as u32 * 2)?;
+    Some(NaiveDateTime::new(date, time))
+}
+
+// what the first sector of the disk shows about how it boots
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct BootSector {
+    pub(crate) jump: Option<&'static str>,  // the x86 jump DOS boot sectors start with
+    oem_name: Option<String>,    // only read when there's a BPB
+    pub(crate) bpb: Option<Bpb>,
+    pub(crate) signature: bool,             // 0x55 0xaa at offsets 510 and 511
+    cpm_loader: bool,
+    #[serde(skip)]
+    pub(crate) partitions: Vec<Partition>,  // for a hard disk's master boot record, recorded apart
+    pub(crate) machine: Option<&'static str>,  // the kind of computer that formatted the disk, when it shows
+    pub(crate) serial: Option<u32>,         // the 24 bit serial number Atari ST boot sectors have before the BPB
+    pub(crate) loader: Option<&'static Fingerprint>,  // whose boot code it is, see bootcode
+}
+
+impl BootSector {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let jump = match bytes {
+            [0xeb, _, 0x90, ..] => Some("short"),
+            [0xe9, ..] => Some("near"),
+            _ => None,
+        };
+        let bpb = Bpb::from_boot_sector(bytes);
+        let signature = bytes.len() >= 512 && bytes[510] == 0x55 && bytes[511] == 0xaa;
+
+        // without a DOS jump and BPB, Z80 code usually starts by disabling interrupts, setting up
+        // the stack or jumping over some data. loaders that name CP/M are recognised whatever they start with
+        let mentions_cpm = bytes.windows(4).any(|w| w.eq_ignore_ascii_case(b"CP/M"));
+        let z80_start = matches!(bytes.first(), Some(0xf3 | 0x31 | 0xc3 | 0x18));
+        let cpm_loader = mentions_cpm || (jump.is_none() && bpb.is_none() && z80_start);
+
+        // a floppy's boot sector has the signature too, but a BPB instead of a partition table
+        let partitions = if bpb.is_none() { Partition::table(bytes) } else { Vec::new() };
+
+        // MSX boot code at 0x1e starts with RET NC, as the BIOS calls it twice and only the second
+        // time with carry set. the Atari ST has the same BPB but no x86 jump or 0x55 0xaa, and its
+        // boot sector is executable when its big endian words add up to 0x1234
+        let msx = bpb.is_some() && (bytes.get(0x1e) == Some(&0xd0) || bytes.get(3..6) == Some(b"MSX"));
+        let st_executable = bytes.len() >= 512
+            && bytes[..512].chunks_exact(2).fold(0u16, |sum, w| sum.wrapping_add(u16::from_be_bytes([w[0], w[1]]))) == 0x1234;
+        let atari = !msx && bpb.is_some() && (bytes[0] == 0x60 || st_executable || (jump.is_none() && !signature));
+        let machine = if msx {
+            Some("MSX")
+        } else if atari {
+            Some("Atari ST")
+        } else if jump.is_some() && (bpb.is_some() || signature) || !partitions.is_empty() {
+            Some("PC")
+        } else {
+            None
+        };
+
+        // an Atari ST's loader name is the six bytes after the branch, and the serial follows it
+        let oem_name = bpb.as_ref().map(|_| if atari { &bytes[2..8] } else { &bytes[3..11] });
+        BootSector {
+            jump,
+            oem_name: oem_name.map(|name| String::from_utf8_lossy(name).trim_end().to_string()),
+            bpb,
+            signature,
+            cpm_loader,
+            partitions,
+            machine,
+            serial: atari.then(|| u32::from_be_bytes([0, bytes[8], bytes[9], bytes[10]])),
+            loader: bootcode::identify(bytes),
+        }
+    }
+}
+
+impl BootSector {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        let mut parts = Vec::new();
+        match self.jump {
+            Some("short") => parts.push(t("short jump").to_string()),
+            Some(_) => parts.push(t("near jump").to_string()),
+            None => {},
+        }
+        if let Some(oem_name) = &self.oem_name { parts.push(format!("OEM '{}'", oem_name)); }
+        if let Some(bpb) = &self.bpb {
+            parts.push(format!("{} {} {} {} {} {:02x}", bpb.bytes_per_sector, t("bytes/sector"), bpb.sectors_per_cluster,
+                t("sectors/cluster"), t("media"), bpb.media_descriptor));
+        }
+        if self.signature { parts.push(format!("{} 55aa", t("signature"))); }
+        if self.cpm_loader { parts.push(t("CP/M boot loader").to_string()); }
+        if let Some(serial) = self.serial { parts.push(format!("{} {:06x}", t("serial"), serial)); }
+        if let Some(loader) = self.loader { parts.push(format!("{} {}", t(if loader.virus { "virus" } else { "loader" }), loader.name)); }
+        if !self.partitions.is_empty() { parts.push(format!("{} {} {}", t("partition table with"), self.partitions.len(), t("partitions"))); }
+        if parts.is_empty() { parts.push(t("nothing recognised").to_string()); }
+        if let Some(machine) = self.machine { parts.push(format!("- {}", machine)); }
+        format!("{} {}", t("boot sector"), parts.join(" "))
+    }
+}
+
+// a FAT filesystem and where it is in the logical disk
+#[derive(Debug)]
+pub(crate) struct FatVolume {
+    pub(crate) partition: Option<u8>,  // None for a floppy, or a hard disk without a partition table
+    pub(crate) offset: usize,
+    pub(crate) size: usize,
+    pub(crate) bpb: Bpb,
+}
+
+// an entry in a hard disk's partition table
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Partition {
+    pub(crate) index: u8,          // 1 to 4 for primary partitions, logical partitions from 5
+    #[serde(skip)]
+    pub(crate) slot: usize,        // which of the four entries in its table
+    pub(crate) bootable: bool,
+    #[serde(rename = "type")]
+    pub(crate) kind: u8,
+    #[serde(rename = "type_name")]
+    pub(crate) kind_name: &'static str,
+    #[serde(rename = "first_chs")]
+    pub(crate) first: (u16, u8, u8),  // cylinder, head and sector, as recorded for BIOSes that need them
+    #[serde(rename = "last_chs")]
+    pub(crate) last: (u16, u8, u8),
+    pub(crate) start: u32,         // in sectors from the start of the disk
+    pub(crate) sectors: u32,
+}
+
+impl Partition {
+    pub(crate) fn is_extended(&self) -> bool {
+        [0x05, 0x0f, 0x85].contains(&self.kind)
+    }
+
+    // the usual use of the partition type, there being no authority on them
+    fn kind_name(kind: u8) -> &'static str {
+        match kind {
+            0x01 => "FAT12",
+            0x04 => "FAT16 <32M",
+            0x05 | 0x0f | 0x85 => "extended",
+            0x06 => "FAT16",
+            0x07 => "HPFS/NTFS",
+            0x0b | 0x0c => "FAT32",
+            0x0e => "FAT16 LBA",
+            0x52 | 0xdb => "CP/M",
+            0x63 => "Unix",
+            0x80 | 0x81 => "Minix",
+            0x82 => "Linux swap",
+            0x83 => "Linux",
+            _ => "unknown",
+        }
+    }
+
+    // the four entries at 0x1be of a master boot record, if they look like a partition table
+    pub(crate) fn table(bytes: &[u8]) -> Vec<Partition> {
+        Partition::entries(bytes, 0, 1)
+    }
+
+    // the entries of the partition table in an MBR or EBR at sector 'base' of the disk. an MBR's are
+    // numbered by slot, an EBR's by 'index' as only its first entry is a partition
+    pub(crate) fn entries(bytes: &[u8], base: u32, index: u8) -> Vec<Partition> {
+        if bytes.len() < 512 || bytes[510] != 0x55 || bytes[511] != 0xaa { return Vec::new(); }
+        let entries: Vec<&[u8]> = bytes[0x1be..0x1fe].chunks_exact(16).collect();
+        if entries.iter().any(|e| e[0] != 0x00 && e[0] != 0x80) { return Vec::new(); }
+
+        // the top two bits of the sector byte are bits 8 and 9 of the cylinder
+        let chs = |b: &[u8]| (((b[1] as u16 & 0xc0) << 2) | b[2] as u16, b[0], b[1] & 0x3f);
+        entries.iter().enumerate()
+            .filter(|(_, e)| e[4] != 0)
+            .map(|(slot, e)| Partition {
+                index: if base == 0 { slot as u8 + 1 } else { index },
+                slot,
+                bootable: e[0] == 0x80,
+                kind: e[4],
+                kind_name: Partition::kind_name(e[4]),
+                first: chs(&e[1..4]),
+                last: chs(&e[5..8]),
+                start: base.saturating_add(u32::from_le_bytes([e[8], e[9], e[10], e[11]])),
+                sectors: u32::from_le_bytes([e[12], e[13], e[14], e[15]]),
+            })
+            .filter(|p| p.sectors != 0)
+            .collect()
+    }
+}
+
+// the parts of a DOS BIOS parameter block needed to locate the FAT root directory
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Bpb {
+    pub(crate) bytes_per_sector: u16,
+    sectors_per_cluster: u8,
+    reserved_sectors: u16,
+    number_of_fats: u8,
+    root_entries: u16,
+    pub(crate) total_sectors: u16,     // 0 on disks too big to count them here
+    media_descriptor: u8,
+    sectors_per_fat: u16,
+}
+
+impl Bpb {
+    // returns None unless the fields add up to a plausible BPB
+    pub(crate) fn from_boot_sector(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 0x18 { return None; }
+
+        let bytes_per_sector = u16::from_le_bytes([bytes[0x0b], bytes[0x0c]]);
+        let sectors_per_cluster = bytes[0x0d];
+        let reserved_sectors = u16::from_le_bytes([bytes[0x0e], bytes[0x0f]]);
+        let number_of_fats = bytes[0x10];
+        let root_entries = u16::from_le_bytes([bytes[0x11], bytes[0x12]]);
+        let total_sectors = u16::from_le_bytes([bytes[0x13], bytes[0x14]]);
+        let media_descriptor = bytes[0x15];
+        let sectors_per_fat = u16::from_le_bytes([bytes[0x16], bytes[0x17]]);
+
+        let plausible = [128, 256, 512, 1024, 2048, 4096].contains(&bytes_per_sector)
+            && sectors_per_cluster.is_power_of_two()
+            && reserved_sectors != 0
+            && (1..=2).contains(&number_of_fats)
+            && root_entries != 0
+            && sectors_per_fat != 0;
+
+        plausible.then_some(Bpb {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            number_of_fats,
+            root_entries,
+            total_sectors,
+            media_descriptor,
+            sectors_per_fat,
+        })
+    }
+
+    // byte ranges of each copy of the FAT within the volume
+    pub(crate) fn fat_ranges(&self) -> Vec<Range<usize>> {
+        let bps = self.bytes_per_sector as usize;
+        let size = self.sectors_per_fat as usize * bps;
+        (0..self.number_of_fats as usize)
+            .map(|n| self.reserved_sectors as usize * bps + n * size)
+            .map(|start| start..start + size)
+            .collect()
+    }
+
+    // byte range of the root directory within the volume
+    pub(crate) fn root_dir_range(&self) -> Range<usize> {
+        let start = (self.reserved_sectors as usize + self.number_of_fats as usize * self.sectors_per_fat as usize)
+            * self.bytes_per_sector as usize;
+        start..start + self.root_entries as usize * DENT_SIZE
+    }
+
+    pub(crate) fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * self.bytes_per_sector as usize
+    }
+
+    // how many clusters fit in the data area after the root directory, numbered from 2. the BPB's
+    // count of sectors says how big the volume is when it has one
+    pub(crate) fn clusters(&self, volume_size: usize) -> usize {
+        let size = match self.total_sectors {
+            0 => volume_size,
+            sectors => sectors as usize * self.bytes_per_sector as usize,
+        };
+        size.saturating_sub(self.root_dir_range().end) / self.cluster_size()
+    }
+
+    // cluster n of the volume, as much of it as there is
+    pub(crate) fn cluster<'a>(&self, volume: &'a [u8], n: usize) -> &'a [u8] {
+        let start = (self.root_dir_range().end + (n.saturating_sub(2)) * self.cluster_size()).min(volume.len());
+        &volume[start..(start + self.cluster_size()).min(volume.len())]
+    }
+}
+
+// how a FAT volume's clusters are taken up: by files and directories, free, marked bad, or in
+// chains no directory entry leads to, which DOS leaves when a file is only half written
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub(crate) struct FatUsage {
+    pub(crate) partition: Option<u8>,
+    pub(crate) cluster_size: usize,
+    pub(crate) clusters: usize,
+    pub(crate) used: usize,            // including the lost clusters
+    pub(crate) free: usize,
+    pub(crate) bad: usize,
+    pub(crate) lost_chains: usize,
+    pub(crate) lost_clusters: usize,
+}
+
+impl FatUsage {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        format!("{}FAT {} {} {} {} - {} {}, {} {}, {} {}, {} {} {} {}",
+            self.partition.map(|p| format!("{} {}: ", t("partition"), p)).unwrap_or_default(),
+            self.clusters, t("clusters of"), self.cluster_size, t("bytes"), self.used, t("used"), self.free, t("free"),
+            self.bad, t("marked bad"), self.lost_chains, t("lost chains of"), self.lost_clusters, t("clusters"))
+    }
+}
+
+// what a FAT says of a cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ClusterEntry {
+    Free,
+    Next(usize),    // the file goes on in this cluster
+    Bad,
+    Last,           // of its file, which includes the reserved values
+}
+
+// the first copy of a volume's FAT, with 12 bit entries unless there are too many clusters to count
+// with them
+pub(crate) struct Fat<'a> {
+    table: &'a [u8],
+    fat12: bool,
+    pub(crate) clusters: usize,
+}
+
+impl<'a> Fat<'a> {
+    pub(crate) fn new(volume: &'a [u8], bpb: &Bpb) -> Self {
+        let range = bpb.fat_ranges()[0].clone();
+        let table = &volume[range.start.min(volume.len())..range.end.min(volume.len())];
+        let clusters = bpb.clusters(volume.len());
+        Fat { table, fat12: clusters < 4085, clusters }
+    }
+
+    // None for a cluster that isn't on the volume or past the end of what's left of the table
+    pub(crate) fn entry(&self, n: usize) -> Option<ClusterEntry> {
+        if !(2..self.clusters + 2).contains(&n) { return None; }
+        let (value, bad) = if self.fat12 {
+            let pair = self.table.get(n * 3 / 2..n * 3 / 2 + 2)?;
+            let pair = u16::from_le_bytes([pair[0], pair[1]]);
+            (if n.is_multiple_of(2) { pair & 0xfff } else { pair >> 4 }, 0xff7)
+        } else {
+            let entry = self.table.get(n * 2..n * 2 + 2)?;
+            (u16::from_le_bytes([entry[0], entry[1]]), 0xfff7)
+        };
+        Some(match value as usize {
+            0 => ClusterEntry::Free,
+            v if v == bad => ClusterEntry::Bad,
+            v if (2..self.clusters + 2).contains(&v) => ClusterEntry::Next(v),
+            _ => ClusterEntry::Last,
+        })
+    }
+
+    // the clusters of a file from its first, as far as the chain can be followed: to its end, a
+    // cluster that isn't a file's, or one seen before
+    pub(crate) fn chain(&self, first: usize) -> Vec<usize> {
+        let mut chain = Vec::new();
+        let mut seen = vec![false; self.clusters + 2];
+        let mut n = first;
+        while let Some(entry) = self.entry(n).filter(|_| !seen[n]) {
+            if matches!(entry, ClusterEntry::Free | ClusterEntry::Bad) { break; }
+            seen[n] = true;
+            chain.push(n);
+            match entry {
+                ClusterEntry::Next(next) => n = next,
+                _ => break,
+            }
+        }
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dent(name: &[u8; 11], attr: u8) -> [u8; 32] {
+        let mut dent = [0; 32];
+        dent[..11].copy_from_slice(name);
+        dent[0x0b] = attr;
+        dent[0x1c..0x20].copy_from_slice(&1234u32.to_le_bytes());
+        dent
+    }
+
+    #[test]
+    fn timestamps() {
+        // 1990-06-15 13:45:30
+        let date = (10 << 9) | (6 << 5) | 15;
+        let time = (13 << 11) | (45 << 5) | 15;
+        assert_eq!(dos_timestamp(time, date).unwrap().to_string(), "1990-06-15 13:45:30");
+        assert_eq!(dos_timestamp(0, 0), None);
+    }
+
+    #[test]
+    fn fat_chains() {
+        // a 12 bit FAT on a floppy: 2 -> 3 -> 5, 4 bad, 6 -> 7 -> 6 looping, 8 free
+        let mut volume = vec![0; 720 * 512];
+        volume[..0x18].copy_from_slice(&[0xeb, 0x3c, 0x90, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x00, 0x02, 2, 1, 0, 2, 112, 0, 0xd0, 0x02, 0xfd, 2, 0]);
+        volume[512..512 + 12].copy_from_slice(&[0xfd, 0xff, 0xff, 0x03, 0x50, 0x00, 0xf7, 0xff, 0xff, 0x07, 0x60, 0x00]);
+        let bpb = Bpb::from_boot_sector(&volume).unwrap();
+        let fat = Fat::new(&volume, &bpb);
+        assert_eq!((fat.fat12, fat.clusters, bpb.cluster_size()), (true, 354, 1024));
+        assert_eq!(fat.entry(2), Some(ClusterEntry::Next(3)));
+        assert_eq!(fat.entry(3), Some(ClusterEntry::Next(5)));
+        assert_eq!(fat.entry(4), Some(ClusterEntry::Bad));
+        assert_eq!(fat.entry(5), Some(ClusterEntry::Last));
+        assert_eq!(fat.entry(8), Some(ClusterEntry::Free));
+        assert_eq!((fat.entry(1), fat.entry(356)), (None, None));
+        assert_eq!(fat.chain(2), [2, 3, 5]);
+        assert_eq!(fat.chain(6), [6, 7]);
+        assert!(fat.chain(4).is_empty());
+
+        // too many clusters for 12 bits: a 20M partition with 16 bit entries
+        let mut volume = vec![0; 40960 * 512];
+        volume[..0x18].copy_from_slice(&[0xeb, 0x3c, 0x90, 0, 0, 0, 0, 0, 0, 0, 0,
+            0x00, 0x02, 4, 1, 0, 2, 0, 2, 0x00, 0xa0, 0xf8, 40, 0]);
+        volume[512..512 + 8].copy_from_slice(&[0xf8, 0xff, 0xff, 0xff, 0x03, 0x00, 0xff, 0xff]);
+        let bpb = Bpb::from_boot_sector(&volume).unwrap();
+        let fat = Fat::new(&volume, &bpb);
+        assert!(!fat.fat12);
+        assert_eq!(fat.chain(2), [2, 3]);
+    }
+
+    #[test]
+    fn machines_from_boot_sectors() {
+        // a 720K BPB behind each machine's start
+        let boot = |start: &[u8], signature: bool| {
+            let mut bytes = vec![0; 512];
+            bytes[..start.len()].copy_from_slice(start);
+            bytes[0x0b..0x18].copy_from_slice(&[0x00, 0x02, 2, 1, 0, 2, 112, 0, 0xa0, 0x05, 0xf9, 3, 0]);
+            if signature { bytes[510..].copy_from_slice(&[0x55, 0xaa]); }
+            BootSector::from_bytes(&bytes)
+        };
+        let pc = boot(b"\xeb\x3c\x90MSDOS5.0", true);
+        assert_eq!((pc.machine, pc.oem_name.as_deref(), pc.serial), (Some("PC"), Some("MSDOS5.0"), None));
+        // the ST's loader name and serial follow a 68000 branch
+        let atari = boot(b"\x60\x1cLoader\x12\x34\x56", false);
+        assert_eq!((atari.machine, atari.oem_name.as_deref(), atari.serial), (Some("Atari ST"), Some("Loader"), Some(0x123456)));
+        // no branch either, but no PC signature
+        assert_eq!(boot(b"\x00\x00", false).machine, Some("Atari ST"));
+        let mut msx = vec![0; 0x1f];
+        msx[..3].copy_from_slice(b"\xeb\xfe\x90");
+        msx[0x1e] = 0xd0;
+        assert_eq!(boot(&msx, false).machine, Some("MSX"));
+        assert_eq!(boot(b"\xeb\xfe\x90MSX_03  ", false).machine, Some("MSX"));
+    }
+
+    #[test]
+    fn deleted_entries_are_named() {
+        assert_eq!(entry_name(&dent(b"\xe5EADME  TXT", 0x20), Codepage::Cp437), "?EADME.TXT");
+        assert_eq!(entry_name(&dent(b"\x05TUDE   TXT", 0x20), Codepage::Cp437), "\u{3c3}TUDE.TXT");
+    }
+
+    #[test]
+    fn directory_entries() {
+        assert!(isfat(&dent(b"COMMAND COM", 0x20), 0, 0, Codepage::Cp437).is_continue());
+        assert!(isfat(&dent(b"COMMAND\x01COM", 0x20), 0, 0, Codepage::Cp437).is_break());
+        let mut reserved = dent(b"COMMAND COM", 0x20);
+        reserved[0x0c] = 1;
+        assert!(isfat(&reserved, 0, 0, Codepage::Cp437).is_break());
+        assert!(isfat(&reserved, 0, 1, Codepage::Cp437).is_continue());
+    }
+}
@@ -0,0 +1,560 @@
+pub(crate) mod bootcode;
+pub(crate) mod cpm;
+pub(crate) mod fat;
+
+use std::ops::ControlFlow;
+
+use cpm::CpmDetector;
+use fat::{FatDetector, LongName};
+use crate::{
+    cli::Args,
+    report::{tr, Language},
+    analysis::Disk,
+    dump::print_hex_and_ascii,
+};
+
+// the code pages text on these disks was most often written in
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub(crate) enum Codepage {
+    Cp437,  // the original IBM PC
+    Cp850,  // DOS Western European
+    Cp852,  // DOS Central European
+    Cp866,  // DOS Cyrillic
+    Koi8R,
+    Koi8U,
+}
+
+impl Codepage {
+    // the characters of bytes 0x80 to 0xff, for the DOS code pages
+    fn oem_table(&self) -> Option<&'static [char; 128]> {
+        match self {
+            Codepage::Cp437 => Some(&oem_cp::code_table::DECODING_TABLE_CP437),
+            Codepage::Cp850 => Some(&oem_cp::code_table::DECODING_TABLE_CP850),
+            Codepage::Cp852 => Some(&oem_cp::code_table::DECODING_TABLE_CP852),
+            Codepage::Cp866 => Some(&oem_cp::code_table::DECODING_TABLE_CP866),
+            Codepage::Koi8R | Codepage::Koi8U => None,
+        }
+    }
+
+    pub(crate) fn encoding(&self) -> &'static encoding_rs::Encoding {
+        match self {
+            Codepage::Koi8U => encoding_rs::KOI8_U,
+            _ => encoding_rs::KOI8_R,
+        }
+    }
+
+    pub(crate) fn decode(&self, bytes: &[u8]) -> String {
+        match self.oem_table() {
+            Some(oem_table) => bytes.iter().map(|&b| if b < 0x80 { b as char } else { oem_table[b as usize - 0x80] }).collect(),
+            None => self.encoding().decode_without_bom_handling(bytes).0.into_owned(),
+        }
+    }
+
+    // characters the code page doesn't have become '?'
+    pub(crate) fn encode(&self, text: &str) -> Vec<u8> {
+        match self.oem_table() {
+            Some(oem_table) => text.chars().map(|c| if c.is_ascii() {
+                c as u8
+            } else {
+                oem_table.iter().position(|&t| t == c).map_or(b'?', |i| 0x80 + i as u8)
+            }).collect(),
+            None => self.encoding().encode(text).0.into_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FileEntry {
+    pub(crate) partition: Option<u8>,
+    pub(crate) name: String,
+    pub(crate) size: u64,
+    pub(crate) directory: bool,
+    pub(crate) deleted: bool,   // still in the directory, though its space may since have been reused
+    pub(crate) user: Option<u8>,    // the CP/M user area it's in
+}
+
+impl FileEntry {
+    // the name and size columns of a listing, and after them a mark for a deleted file and the
+    // user area of a CP/M file outside user 0
+    pub(crate) fn listing(&self, language: Language) -> String {
+        format!("{:12} {:>8}{}{}", self.name, if self.directory { "<DIR>".to_string() } else { self.size.to_string() },
+            if self.deleted { format!(" {}", tr(language, "deleted")) } else { String::new() },
+            self.user.filter(|&user| user != 0).map(|user| format!(" {} {}", tr(language, "user"), user)).unwrap_or_default())
+    }
+}
+
+// NAME.EXT from the space padded fields of a directory entry
+pub(crate) fn dent_name(name: &[u8], ext: &[u8], codepage: Codepage) -> String {
+    let name = codepage.decode(name).trim_end().to_string();
+    let ext = codepage.decode(ext).trim_end().to_string();
+    if ext.is_empty() { name } else { format!("{}.{}", name, ext) }
+}
+
+pub(crate) const DENT_SIZE: usize = 32;
+
+pub(crate) const TRDOS_DENT_SIZE: usize = 16;
+
+// run the directory entry heuristics over the region of the disk most likely to hold a directory,
+// so entries split across sectors are seen in the order the filesystem wrote them: the FAT root
+// directory when a BPB says where it is, otherwise every sector in logical order
+pub(crate) fn analyse_directories(args: &Args, disk: &Disk, detectors: &mut [Box<dyn FilesystemDetector>]) -> Vec<usize> {
+    let mut dent_scanner = DentScanner::new(args.shows_detail(), detectors);
+
+    let volumes = disk.fat_volumes();
+    if volumes.is_empty() {
+        // with --tracks, only as far as the first tracks go
+        let mut tracks = 0;
+        let mut last = None;
+        for (track, sector) in disk.logical_track_sectors() {
+            let position = (track.cylinder, track.header.side_number);
+            if last != Some(position) {
+                tracks += 1;
+                last = Some(position);
+            }
+            if args.tracks.is_some_and(|n| tracks > n) { break; }
+            dent_scanner.scan(args, &sector.data);
+        }
+    }
+    for volume in &volumes {
+        for chunk in disk.fat_root_dir(volume).chunks(volume.bpb.bytes_per_sector as usize) {
+            dent_scanner.scan(args, chunk);
+        }
+        dent_scanner.finish(args);
+    }
+    dent_scanner.finish(args);
+    dent_scanner.counts
+}
+
+// the first detector to find its filesystem's structures in the disk decides, otherwise the one
+// whose directory entries turned up most often, of those with enough, the earlier one on a tie.
+// counts are per detector
+pub(crate) fn detect_filesystem(disk: &Disk, detectors: &[Box<dyn FilesystemDetector>], counts: &[usize], codepage: Codepage) -> Option<Detection> {
+    detectors.iter().find_map(|detector| detector.probe_disk(disk, codepage)).or_else(|| {
+        let (best, _) = counts.iter().enumerate().rev()
+            .filter(|&(i, &count)| count >= detectors[i].min_entries())
+            .max_by_key(|&(_, count)| count)?;
+        Some(detectors[best].read_entries(disk, codepage))
+    })
+}
+
+// what a detector could tell about a disk it recognised
+#[derive(Debug)]
+pub(crate) struct Detection {
+    pub(crate) filesystem: &'static str,
+    pub(crate) label: Option<String>,
+    pub(crate) files: Vec<FileEntry>,
+}
+
+// one way of recognising a filesystem: entry by entry in the stream of sectors the directory
+// heuristics are run over, by structures it looks for in the disk as a whole, or both
+pub(crate) trait FilesystemDetector {
+    fn filesystem(&self) -> &'static str;
+
+    // lines describing any of this filesystem's directory entries in the DENT_SIZE bytes at offset i
+    // of data, which holds whole entries
+    fn probe_entry(&mut self, _data: &[u8], _i: usize, _codepage: Codepage) -> Vec<String> {
+        Vec::new()
+    }
+
+    // the filesystem, if the disk has the structures that show it's this one
+    fn probe_disk(&self, _disk: &Disk, _codepage: Codepage) -> Option<Detection> {
+        None
+    }
+
+    // how many directory entries it takes to recognise the filesystem by them alone
+    fn min_entries(&self) -> usize {
+        1
+    }
+
+    // what can be read of the filesystem when it was only recognised by its directory entries
+    fn read_entries(&self, _disk: &Disk, _codepage: Codepage) -> Detection {
+        Detection { filesystem: self.filesystem(), label: None, files: Vec::new() }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DetectorKind {
+    Fat,
+    Cpm,
+    Trdos,
+    Apple,
+}
+
+impl DetectorKind {
+    // in the order they're consulted. FAT comes before CP/M so a tie goes to FAT
+    pub(crate) const ALL: [DetectorKind; 4] = [DetectorKind::Fat, DetectorKind::Cpm, DetectorKind::Trdos, DetectorKind::Apple];
+
+    pub(crate) fn detector(&self, thresholds: &Thresholds, cpm_user: Option<u8>) -> Box<dyn FilesystemDetector> {
+        let min_entries = thresholds.min_entries[*self as usize];
+        match self {
+            DetectorKind::Fat => Box::new(FatDetector { long_name: LongName::default(), reserved_set: thresholds.fat_reserved_set, min_entries }),
+            DetectorKind::Cpm => Box::new(CpmDetector { max_records: thresholds.cpm_max_records, min_entries, user: cpm_user }),
+            DetectorKind::Trdos => Box::new(TrDosDetector { max_first_sector: thresholds.trdos_max_first_sector, min_entries }),
+            DetectorKind::Apple => Box::new(AppleDetector),
+        }
+    }
+}
+
+// how much the directory entry heuristics put up with before deciding bytes aren't an entry, and
+// how many entries it takes to recognise a filesystem by them. the config file's [detectors]
+// tables can change them
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Thresholds {
+    fat_reserved_set: usize,        // bytes 0x0c to 0x15 of a FAT entry, reserved but not always zero
+    cpm_max_records: u8,            // 128 byte records in a CP/M extent
+    trdos_max_first_sector: u8,
+    pub(crate) min_entries: [usize; 4],        // indexed by DetectorKind
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds { fat_reserved_set: 2, cpm_max_records: 128, trdos_max_first_sector: 15, min_entries: [1; 4] }
+    }
+}
+
+impl Thresholds {
+    // a setting from the table for detector in the config file
+    pub(crate) fn set(&mut self, detector: &str, key: &str, value: i64) -> Result<(), String> {
+        let kind: DetectorKind = clap::ValueEnum::from_str(detector, true).map_err(|_| format!("there's no {} detector", detector))?;
+        let out_of_range = || format!("detectors.{}.{} can't be {}", detector, key, value);
+        match (kind, key) {
+            (DetectorKind::Fat, "reserved-set") => self.fat_reserved_set = usize::try_from(value).ok().filter(|&n| n <= 10).ok_or_else(out_of_range)?,
+            (DetectorKind::Cpm, "max-records") => self.cpm_max_records = u8::try_from(value).map_err(|_| out_of_range())?,
+            (DetectorKind::Trdos, "max-first-sector") => self.trdos_max_first_sector = u8::try_from(value).map_err(|_| out_of_range())?,
+            (DetectorKind::Fat | DetectorKind::Cpm | DetectorKind::Trdos, "min-entries") =>
+                self.min_entries[kind as usize] = usize::try_from(value).ok().filter(|&n| n > 0).ok_or_else(out_of_range)?,
+            _ => return Err(format!("the {} detector has no setting {}", detector, key)),
+        }
+        Ok(())
+    }
+}
+
+// TR-DOS is certain when the system sector is there, but its catalogue entries are distinctive too
+struct TrDosDetector {
+    max_first_sector: u8,
+    pub(crate) min_entries: usize,
+}
+
+impl FilesystemDetector for TrDosDetector {
+    fn filesystem(&self) -> &'static str {
+        "TR-DOS"
+    }
+
+    fn min_entries(&self) -> usize {
+        self.min_entries
+    }
+
+    // TR-DOS entries are half the size, so there are two to look at
+    fn probe_entry(&mut self, data: &[u8], i: usize, codepage: Codepage) -> Vec<String> {
+        [i, i + TRDOS_DENT_SIZE].into_iter().filter_map(|half| istrdos(data, half, self.max_first_sector, codepage).continue_value()).collect()
+    }
+
+    fn probe_disk(&self, disk: &Disk, codepage: Codepage) -> Option<Detection> {
+        let system = disk.trdos_system(codepage)?;
+        Some(Detection { label: Some(system.label).filter(|label| !label.is_empty()), ..self.read_entries(disk, codepage) })
+    }
+
+    fn read_entries(&self, disk: &Disk, codepage: Codepage) -> Detection {
+        let files = disk.trdos_catalogue().into_iter().map(|dent| FileEntry {
+            partition: None,
+            name: format!("{}.{}", codepage.decode(&dent[..8]).trim_end(), codepage.decode(&dent[8..9])),
+            size: dent[13] as u64 * 256,    // sectors taken, the length field not always being bytes
+            directory: false,
+            deleted: false,
+            user: None,
+        }).collect();
+        Detection { filesystem: "TR-DOS", label: None, files }
+    }
+}
+
+// a TR-DOS catalogue entry: name, type letter, start address or line, length, sector count, then
+// where the file starts. the catalogue itself fills track 0 so no file can start there
+fn istrdos(data: &[u8], i: usize, max_first_sector: u8, codepage: Codepage) -> ControlFlow<(), String> {
+    let name = &data[i..i + 8];
+    let kind = data[i + 8];
+    let start = u16::from_le_bytes([data[i + 9], data[i + 10]]);
+    let length = u16::from_le_bytes([data[i + 11], data[i + 12]]);
+    let sectors = data[i + 13];
+    let first_sector = data[i + 14];
+    let first_track = data[i + 15];
+
+    // 0x01 marks a deleted file
+    if name[0] != 0x01 && !(0x20..=0x7e).contains(&name[0]) { return ControlFlow::Break(()); }
+    if name[1..].iter().any(|b| !(0x20..=0x7e).contains(b)) { return ControlFlow::Break(()); }
+    if !b"BCD#".contains(&kind) || sectors == 0 || first_sector > max_first_sector || first_track == 0 {
+        return ControlFlow::Break(());
+    }
+
+    let first_letter = if name[0] == 0x01 { "?".to_string() } else { codepage.decode(&name[..1]) };
+
+    ControlFlow::Continue(format!("TRD {:2} St: {:02x} {}{}.{} Start: {:5} Len: {:5} Sec: {:3} At: {}/{}",
+        i / TRDOS_DENT_SIZE, name[0], first_letter, codepage.decode(&name[1..]),
+        kind as char, start, length, sectors, first_track, first_sector))
+}
+
+// the ZX Spectrum Beta Disk interface's description of a disk, kept after its catalogue
+#[derive(Debug)]
+pub(crate) struct TrDosSystem {
+    pub(crate) label: String,
+    pub(crate) disk_type: u8,      // 0x16 to 0x19 for 80 or 40 tracks, double or single sided
+    pub(crate) files: u8,          // including deleted ones
+    pub(crate) deleted: u8,
+    pub(crate) free_sectors: u16,
+}
+
+impl TrDosSystem {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        let (tracks, sides) = match self.disk_type {
+            0x16 => (80, 2),
+            0x17 => (40, 2),
+            0x18 => (80, 1),
+            _ => (40, 1),
+        };
+        format!("TR-DOS '{}' {} {} {} {} - {} {} {} {} {} {}", self.label, tracks, t("tracks"), sides, t("sides"),
+            self.files, t("files"), self.deleted, t("deleted"), self.free_sectors, t("sectors free"))
+    }
+}
+
+// DOS 3.3 and ProDOS are followed from where their catalogues start, see Disk::apple_catalogue
+struct AppleDetector;
+
+impl FilesystemDetector for AppleDetector {
+    fn filesystem(&self) -> &'static str {
+        "Apple II"
+    }
+
+    fn probe_disk(&self, disk: &Disk, _codepage: Codepage) -> Option<Detection> {
+        let catalogue = disk.apple_catalogue()?;
+        Some(Detection {
+            filesystem: catalogue.system,
+            label: (catalogue.system == "ProDOS").then_some(catalogue.volume),
+            files: catalogue.files,
+        })
+    }
+}
+
+// an Apple II disk's catalogue, found by following it from the DOS 3.3 VTOC or ProDOS volume directory
+#[derive(Debug)]
+pub(crate) struct AppleCatalogue {
+    pub(crate) system: &'static str,   // DOS 3.3 or ProDOS
+    pub(crate) volume: String,         // ProDOS volume names, or the DOS 3.3 volume number
+    pub(crate) files: Vec<FileEntry>,
+}
+
+impl AppleCatalogue {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        format!("Apple {} {} {} - {} {}", self.system, tr(language, "volume"), self.volume, self.files.len(), tr(language, "files"))
+    }
+}
+
+// the VTOC at track 17 sector 0 says where the catalogue sectors start, each then naming the next.
+// file names are 30 characters with the high bit set
+pub(crate) fn apple_dos_catalogue(bytes: &[u8]) -> Option<AppleCatalogue> {
+    let sector = |track: u8, sector: u8| apple_sector(bytes, track, sector);
+    let vtoc = sector(17, 0)?;
+    if vtoc[0x27] != 122 || vtoc[0x35] != 16 || u16::from_le_bytes([vtoc[0x36], vtoc[0x37]]) != 256 || vtoc[1] == 0 {
+        return None;
+    }
+    let mut files = Vec::new();
+    let (mut track, mut number) = (vtoc[1], vtoc[2]);
+    // stop after a track's worth, in case the chain loops
+    for _ in 0..32 {
+        if track == 0 { break; }
+        let data = sector(track, number)?;
+        for entry in data[0x0b..].chunks_exact(35) {
+            if entry[0] == 0x00 || entry[0] == 0xff { continue; }   // unused, or deleted
+            let name: String = entry[3..33].iter().map(|&b| (b & 0x7f) as char).collect();
+            files.push(FileEntry {
+                partition: None,
+                name: name.trim_end().to_string(),
+                size: u16::from_le_bytes([entry[33], entry[34]]) as u64 * 256,
+                directory: false,
+                deleted: false,
+                user: None,
+            });
+        }
+        (track, number) = (data[1], data[2]);
+    }
+    Some(AppleCatalogue { system: "DOS 3.3", volume: vtoc[6].to_string(), files })
+}
+
+pub(crate) fn apple_sector(bytes: &[u8], track: u8, sector: u8) -> Option<&[u8]> {
+    bytes.get((track as usize * 16 + sector as usize) * 256..).and_then(|rest| rest.get(..256))
+}
+
+// the volume directory starts at block 2 with a header entry naming the volume, then file entries
+// of 39 bytes, 13 to a block, and each block links to the next
+pub(crate) fn prodos_catalogue(block: &dyn Fn(u16) -> Option<Vec<u8>>) -> Option<AppleCatalogue> {
+    let key = block(2)?;
+    let header = &key[4..];
+    if header[0] >> 4 != 0x0f || header[0] & 0x0f == 0 || header[0x1f] != 0x27 || header[0x20] != 0x0d || key[0..2] != [0, 0] {
+        return None;
+    }
+    let volume = String::from_utf8_lossy(&header[1..1 + (header[0] & 0x0f) as usize]).to_string();
+
+    let mut files = Vec::new();
+    let mut data = key;
+    for _ in 0..16 {
+        for entry in data[4..4 + 13 * 0x27].chunks_exact(0x27) {
+            let storage_type = entry[0] >> 4;
+            if storage_type == 0 || storage_type >= 0x0e { continue; }  // deleted, or a directory header
+            files.push(FileEntry {
+                partition: None,
+                name: String::from_utf8_lossy(&entry[1..1 + (entry[0] & 0x0f) as usize]).to_string(),
+                size: u32::from_le_bytes([entry[0x15], entry[0x16], entry[0x17], 0]) as u64,
+                directory: storage_type == 0x0d,
+                deleted: false,
+                user: None,
+            });
+        }
+        let next = u16::from_le_bytes([data[2], data[3]]);
+        if next == 0 { break; }
+        data = block(next)?;
+    }
+    Some(AppleCatalogue { system: "ProDOS", volume, files })
+}
+
+// feeds decoded sectors to the directory entry heuristics as one continuous stream, so sectors
+// that aren't a multiple of the entry size, or entries straddling two sectors, are still seen whole
+struct DentScanner<'a> {
+    pub(crate) carry: Vec<u8>, // bytes of a directory entry begun in the previous sector
+    pub(crate) print: bool,
+    pub(crate) detectors: &'a mut [Box<dyn FilesystemDetector>],
+    pub(crate) counts: Vec<usize>, // entries each detector accepted
+}
+
+impl<'a> DentScanner<'a> {
+    pub(crate) fn new(print: bool, detectors: &'a mut [Box<dyn FilesystemDetector>]) -> Self {
+        let counts = vec![0; detectors.len()];
+        DentScanner { carry: Vec::new(), print, detectors, counts }
+    }
+
+    pub(crate) fn scan(&mut self, args: &Args, data: &[u8]) {
+        let mut buf = std::mem::take(&mut self.carry);
+        buf.extend_from_slice(data);
+        let whole = buf.len() - buf.len() % DENT_SIZE;
+        analyse_raw_sector(args, &buf[..whole], self.print, self.detectors, &mut self.counts);
+        self.carry = buf.split_off(whole);
+    }
+
+    // whatever is left over at the end of the image can't be a complete entry
+    pub(crate) fn finish(&mut self, args: &Args) {
+        if !self.carry.is_empty() && self.print {
+            print_hex_and_ascii(args, 0, &self.carry, true);
+        }
+        self.carry.clear();
+    }
+}
+
+// data must hold whole directory entries, see DentScanner
+fn analyse_raw_sector(args: &Args, data: &[u8], print: bool, detectors: &mut [Box<dyn FilesystemDetector>], counts: &mut [usize]) {
+    let dent_size = DENT_SIZE;
+
+    for i in (0..data.len()).step_by(dent_size) {
+        let mut clocked = 0;
+        for (detector, count) in detectors.iter_mut().zip(counts.iter_mut()) {
+            let lines = detector.probe_entry(data, i, args.codepage);
+            if lines.is_empty() { continue; }
+            clocked += 1;
+            *count += lines.len();
+            if print {
+                for line in lines { image_println!(args, "{}", line); }
+            }
+        }
+
+        if clocked != 1 && print {
+            print_hex_and_ascii(args, i/32, &data[i..i+dent_size], clocked != 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two tracks of sixteen 256 byte sectors with a TR-DOS catalogue of a file and a deleted one
+    fn trdos_disk(system: bool) -> Disk {
+        let mut img = vec![0; 2 * 16 * 256];
+        img[..16].copy_from_slice(b"GAME    B\x00\x60\x00\x1b\x1c\x00\x01");
+        img[16..32].copy_from_slice(b"\x01LD     C\x00\x80\x00\x02\x02\x0c\x02");
+        if system {
+            let sector = &mut img[8 * 256..9 * 256];
+            sector[0xe3..0xe8].copy_from_slice(&[0x16, 2, 0x10, 0x09, 0x10]);
+            sector[0xf4] = 1;
+            sector[0xf5..0xfd].copy_from_slice(b"SPECCY  ");
+        }
+        crate::formats::flat_disk(&img, 2, 1, 16, 256)
+    }
+
+    #[test]
+    fn trdos_catalogues() {
+        let detector = DetectorKind::Trdos.detector(&Thresholds::default(), None);
+        let detection = detector.probe_disk(&trdos_disk(true), Codepage::Cp437).unwrap();
+        assert_eq!((detection.filesystem, detection.label.as_deref()), ("TR-DOS", Some("SPECCY")));
+        // the deleted file is left out
+        let files: Vec<_> = detection.files.iter().map(|f| (f.name.as_str(), f.size)).collect();
+        assert_eq!(files, [("GAME.B", 0x1c * 256)]);
+        assert!(detector.probe_disk(&trdos_disk(false), Codepage::Cp437).is_none());
+
+        let disk = trdos_disk(false);
+        let catalogue = &disk.sector(0, 0, 1).unwrap().data;
+        let mut detector = DetectorKind::Trdos.detector(&Thresholds::default(), None);
+        let lines = detector.probe_entry(catalogue, 0, Codepage::Cp437);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("GAME    .B") && lines[1].contains("?LD     .C"), "{:?}", lines);
+        // a file can't start on the catalogue's own track
+        assert!(istrdos(&[b"GAME    B\x00\x60\x00\x1b\x1c\x00\x00".as_slice(), &[0; 16]].concat(), 0, 15, Codepage::Cp437).is_break());
+    }
+
+    #[test]
+    fn apple_catalogues() {
+        let probe = |img: &[u8]| DetectorKind::Apple.detector(&Thresholds::default(), None)
+            .probe_disk(&crate::formats::flat_disk(img, 35, 1, 16, 256), Codepage::Cp437)
+            .map(|d| (d.filesystem, d.label, d.files.iter().map(|f| (f.name.clone(), f.size, f.directory)).collect::<Vec<_>>()));
+
+        // DOS 3.3: the VTOC at track 17 sector 0 leads to a catalogue sector with a file and a deleted one
+        let mut img = vec![0; 35 * 16 * 256];
+        let vtoc = 17 * 16 * 256;
+        img[vtoc + 1..vtoc + 3].copy_from_slice(&[17, 15]);
+        img[vtoc + 6] = 254;
+        img[vtoc + 0x27] = 122;
+        img[vtoc + 0x35..vtoc + 0x38].copy_from_slice(&[16, 0, 1]);
+        let catalogue = vtoc + 15 * 256 + 0x0b;
+        img[catalogue..catalogue + 3].copy_from_slice(&[18, 0, 0x02]);
+        img[catalogue + 3..catalogue + 33].copy_from_slice(&[b"HELLO".map(|b| b | 0x80).as_slice(), &[0xa0; 25]].concat());
+        img[catalogue + 33] = 2;
+        img[catalogue + 35] = 0xff;
+        assert_eq!(probe(&img), Some(("DOS 3.3", None, vec![("HELLO".to_string(), 512, false)])));
+
+        // ProDOS, its blocks straight through: the volume directory in block 2 names the volume,
+        // then has a file and a subdirectory
+        let mut img = vec![0; 35 * 16 * 256];
+        let key = 2 * 512 + 4;
+        img[key..key + 6].copy_from_slice(b"\xf5DISKS");
+        img[key + 0x1f..key + 0x21].copy_from_slice(&[0x27, 0x0d]);
+        img[key + 0x27..key + 0x2c].copy_from_slice(b"\x24GAME");
+        img[key + 0x27 + 0x15..key + 0x27 + 0x18].copy_from_slice(&[0xe8, 0x03, 0]);
+        img[key + 2 * 0x27..key + 2 * 0x27 + 6].copy_from_slice(b"\xd5SAVES");
+        assert_eq!(probe(&img), Some(("ProDOS", Some("DISKS".to_string()), vec![("GAME".to_string(), 1000, false), ("SAVES".to_string(), 0, true)])));
+
+        assert_eq!(probe(&vec![0; 35 * 16 * 256]), None);
+    }
+
+    #[test]
+    fn code_pages_decode_and_encode() {
+        // "Größe" in code page 437 and 850, and "Диск" in 866 and KOI8-R
+        assert_eq!(Codepage::Cp437.decode(b"Gr\x94\xe1e"), "Größe");
+        assert_eq!(Codepage::Cp850.decode(b"Gr\x94\xe1e"), "Größe");
+        assert_eq!(Codepage::Cp866.decode(b"\x84\xa8\xe1\xaa"), "Диск");
+        assert_eq!(Codepage::Koi8R.decode(b"\xe4\xc9\xd3\xcb"), "Диск");
+        // the same byte is a different letter in another code page
+        assert_eq!(Codepage::Cp437.decode(b"\x9b"), "¢");
+        assert_eq!(Codepage::Cp850.decode(b"\x9b"), "ø");
+        for codepage in [Codepage::Cp437, Codepage::Cp850, Codepage::Cp852, Codepage::Cp866, Codepage::Koi8R, Codepage::Koi8U] {
+            let bytes: Vec<u8> = (0x20..=0xff).collect();
+            assert_eq!(codepage.encode(&codepage.decode(&bytes)), bytes, "{:?}", codepage);
+        }
+        assert_eq!(Codepage::Cp437.encode("Диск"), b"????");
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, ErrorKind, Write},
+    path::Path,
+};
+use kc8587::td0::{IdQuirk, ImageHeader};
+
+use crate::analysis::{Disk, Track};
+
+// how an HFE track is laid down: bit rate in kbit/s of MFM data, so FM at the same cell rate is half
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct HfeTiming {
+    pub(crate) fm: bool,
+    pub(crate) bit_rate: u16,
+    pub(crate) rpm: u16,
+}
+
+impl HfeTiming {
+    // from the TD0 header: rate 0 is 250 kbps, 1 is 300 kbps (a DD disk in a 1.2M drive spinning at
+    // 360 rpm, the same track as 250 kbps at 300 rpm), 2 is 500 kbps, and bit 7 is FM. a 1.2M drive
+    // spins at 360 rpm for its own HD disks. flat images have no header so their size decides
+    pub(crate) fn new(disk: &Disk, header: Option<&ImageHeader>) -> Self {
+        let (data_rate, drive_type) = match header {
+            Some(h) => (h.data_rate, h.drive_type),
+            None => {
+                let busiest = disk.tracks.iter()
+                    .map(|t| t.sectors.iter().map(|s| s.header.sector_size as usize).sum::<usize>()).max().unwrap_or(0);
+                if busiest > 6000 { (2, 4) } else { (0, 3) }
+            },
+        };
+        let fm = data_rate & 0x80 != 0;
+        let (kbps, rpm) = match data_rate & 0x03 {
+            2 if drive_type == 2 => (500, 360),
+            2 => (500, 300),
+            _ => (250, 300),
+        };
+        HfeTiming { fm, bit_rate: if fm { kbps / 2 } else { kbps }, rpm }
+    }
+
+    // bytes of data that fit in one revolution, each written as 16 cells
+    pub(crate) fn track_bytes(&self) -> usize {
+        self.bit_rate as usize * 1000 * 60 / self.rpm as usize / 8
+    }
+}
+
+// cells in the order they pass the head, packed least significant bit first as HFE stores them
+pub(crate) struct CellWriter {
+    pub(crate) fm: bool,
+    pub(crate) cells: Vec<u8>,
+    pub(crate) count: usize,
+    pub(crate) last: bool,
+}
+
+impl CellWriter {
+    pub(crate) fn new(fm: bool) -> Self {
+        CellWriter { fm, cells: Vec::new(), count: 0, last: false }
+    }
+
+    pub(crate) fn cell(&mut self, on: bool) {
+        if self.count.is_multiple_of(8) { self.cells.push(0); }
+        if on { *self.cells.last_mut().unwrap() |= 1 << (self.count % 8); }
+        self.count += 1;
+    }
+
+    // a byte with its clock cells, all set in FM and set between two zeros in MFM
+    pub(crate) fn byte(&mut self, byte: u8) {
+        let clock = if self.fm { 0xff } else {
+            let previous = (byte >> 1) | if self.last { 0x80 } else { 0 };
+            !(byte | previous)
+        };
+        self.clocked(byte, clock);
+    }
+
+    pub(crate) fn clocked(&mut self, byte: u8, clock: u8) {
+        for i in (0..8).rev() {
+            self.cell(clock >> i & 1 != 0);
+            self.cell(byte >> i & 1 != 0);
+        }
+        self.last = byte & 1 != 0;
+    }
+
+    pub(crate) fn bytes(&mut self, byte: u8, count: usize) {
+        (0..count).for_each(|_| self.byte(byte));
+    }
+
+    // gap bytes up to a length in bytes of data
+    pub(crate) fn fill(&mut self, len: usize) {
+        let gap = if self.fm { 0xff } else { 0x4e };
+        self.bytes(gap, len.saturating_sub(self.count / 16));
+    }
+
+    // an address mark: A1 sync bytes with a clock missing then the mark in MFM, the mark with its
+    // special clock in FM
+    pub(crate) fn mark(&mut self, mark: u8) -> u16 {
+        if self.fm {
+            self.clocked(mark, if mark == 0xfc { 0xd7 } else { 0xc7 });
+            crc16_ccitt(0xffff, &[mark])
+        } else {
+            let (sync, clock) = if mark == 0xfc { (0xc2, 0x14) } else { (0xa1, 0x0a) };
+            (0..3).for_each(|_| self.clocked(sync, clock));
+            self.byte(mark);
+            crc16_ccitt(0xffff, &[sync, sync, sync, mark])
+        }
+    }
+
+    // a field after its mark, followed by its CRC, spoiled when the sector was read with a CRC error
+    pub(crate) fn field(&mut self, crc: u16, data: &[u8], bad_crc: bool) {
+        data.iter().for_each(|&b| self.byte(b));
+        let crc = crc16_ccitt(crc, data) ^ if bad_crc { 0xffff } else { 0 };
+        self.byte((crc >> 8) as u8);
+        self.byte(crc as u8);
+    }
+}
+
+// the CRC floppy controllers put after ID and data fields
+pub(crate) fn crc16_ccitt(mut crc: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// one side of a track as IBM System 34 lays it out, its sectors in the order they were recorded,
+// and with gap 3 shrunk so they fit a revolution where they can
+pub(crate) fn hfe_side(track: Option<&Track>, timing: HfeTiming) -> CellWriter {
+    let mut w = CellWriter::new(timing.fm);
+    let (gap, sync, gap4a, gap1, gap2) = if timing.fm { (0xff, 6, 40, 26, 11) } else { (0x4e, 12, 80, 50, 22) };
+    let marks = if timing.fm { 1 } else { 4 };
+    let Some(track) = track.filter(|t| !t.sectors.is_empty()) else {
+        w.fill(timing.track_bytes());
+        return w;
+    };
+    let sectors = &track.sectors;
+    let used = gap4a + sync + marks + gap1 + sectors.iter()
+        .map(|s| 2 * (sync + marks + 2) + 4 + gap2 + s.data.len()).sum::<usize>();
+    let gap3 = (timing.track_bytes().saturating_sub(used) / sectors.len()).clamp(4, if timing.fm { 27 } else { 84 });
+
+    w.bytes(gap, gap4a);
+    w.bytes(0x00, sync);
+    w.mark(0xfc);
+    w.bytes(gap, gap1);
+    for sector in sectors {
+        let h = &sector.header;
+        let size_code = (h.sector_size.max(128) / 128).trailing_zeros() as u8;
+        // a sector recorded without an ID gets its data field alone
+        if h.id_quirk() != Some(IdQuirk::NoId) {
+            w.bytes(0x00, sync);
+            let crc = w.mark(0xfe);
+            w.field(crc, &[h.cylinder_number, h.side_number, h.sector_number, size_code], false);
+        }
+        w.bytes(gap, gap2);
+        if h.flags & 0x30 == 0 {
+            w.bytes(0x00, sync);
+            let crc = w.mark(if h.flags & 0x04 != 0 { 0xf8 } else { 0xfb });
+            w.field(crc, &sector.data, h.flags & 0x02 != 0);
+        }
+        w.bytes(gap, gap3);
+    }
+    w.fill(timing.track_bytes());
+    w
+}
+
+// an HFE v1 image: a 512 byte header, a list of where each cylinder's track is and how long, then
+// the tracks in 512 byte blocks each holding 256 bytes of side 0 then 256 bytes of side 1
+pub(crate) fn write_hfe(disk: &Disk, header: Option<&ImageHeader>, hfe_path: &Path) -> io::Result<()> {
+    let timing = HfeTiming::new(disk, header);
+    let cylinders = disk.tracks.iter().map(|t| t.cylinder + 1).max().unwrap_or(0);
+    let sides = if disk.tracks.iter().any(|t| t.header.side_number & 1 != 0) { 2 } else { 1 };
+    if cylinders > 255 {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{} cylinders is more than HFE holds", cylinders)));
+    }
+
+    let mut tracks: Vec<[Option<&Track>; 2]> = vec![[None, None]; cylinders];
+    for track in &disk.tracks {
+        tracks[track.cylinder][(track.header.side_number & 1) as usize].get_or_insert(track);
+    }
+    let mut list = Vec::new();
+    let mut data = Vec::new();
+    let list_blocks = (cylinders * 4).div_ceil(512).max(1);
+    for [side0, side1] in tracks {
+        let mut sides = [hfe_side(side0, timing), hfe_side(side1, timing)];
+        let len = sides.iter().map(|w| w.cells.len()).max().unwrap_or(0);
+        if len * 2 > u16::MAX as usize {
+            return Err(io::Error::new(ErrorKind::InvalidData, "a track is too long for HFE"));
+        }
+        // both sides the same length, and whole blocks though only len of each is played
+        for w in &mut sides {
+            w.fill(len / 2);
+            w.cells.resize(len.div_ceil(256) * 256, 0);
+        }
+        let [side0, side1] = sides.map(|w| w.cells);
+        let block = 1 + list_blocks + data.len() / 512;
+        list.extend_from_slice(&(block as u16).to_le_bytes());
+        list.extend_from_slice(&((len * 2) as u16).to_le_bytes());
+        for (a, b) in side0.chunks(256).zip(side1.chunks(256)) {
+            data.extend_from_slice(a);
+            data.extend_from_slice(b);
+        }
+    }
+    list.resize(list_blocks * 512, 0xff);
+
+    let mut head = Vec::with_capacity(512);
+    head.extend_from_slice(b"HXCPICFE");
+    // revision, tracks, sides, encoding (ISO IBM MFM or FM)
+    head.extend_from_slice(&[0, cylinders as u8, sides, if timing.fm { 2 } else { 0 }]);
+    head.extend_from_slice(&timing.bit_rate.to_le_bytes());
+    head.extend_from_slice(&timing.rpm.to_le_bytes());
+    // interface mode (IBM PC DD or HD), unused, the track list's block
+    head.extend_from_slice(&[if timing.bit_rate > 300 { 1 } else { 0 }, 1]);
+    head.extend_from_slice(&1u16.to_le_bytes());
+    // write allowed, single step, and no other encoding on track 0
+    head.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    head.resize(512, 0xff);
+
+    std::fs::create_dir_all(hfe_path.parent().unwrap_or(Path::new(".")))?;
+    let mut out = BufWriter::new(File::create(hfe_path)?);
+    out.write_all(&head)?;
+    out.write_all(&list)?;
+    out.write_all(&data)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::flat_disk;
+
+    // the cells of each byte written, as the 16 bit words they're usually quoted as
+    fn words(w: &CellWriter) -> Vec<u16> {
+        (0..w.count / 16)
+            .map(|i| (0..16).fold(0, |word, bit| {
+                let cell = i * 16 + bit;
+                word << 1 | (w.cells[cell / 8] >> (cell % 8) & 1) as u16
+            }))
+            .collect()
+    }
+
+    // the data cells of each byte written, dropping the clocks
+    fn data(w: &CellWriter) -> Vec<u8> {
+        words(w).iter().map(|word| (0..8).fold(0, |byte, bit| byte << 1 | (word >> (14 - 2 * bit) & 1) as u8)).collect()
+    }
+
+    #[test]
+    fn crc_is_ccitt() {
+        assert_eq!(crc16_ccitt(0xffff, b"123456789"), 0x29b1);
+        // the CRC of an ID field's mark, ID and CRC comes back to nothing
+        let mut field = vec![0xa1, 0xa1, 0xa1, 0xfe, 0, 0, 1, 2];
+        let crc = crc16_ccitt(0xffff, &field);
+        field.extend_from_slice(&crc.to_be_bytes());
+        assert_eq!(crc16_ccitt(0xffff, &field), 0);
+    }
+
+    #[test]
+    fn cells_are_encoded_and_packed() {
+        let mut mfm = CellWriter::new(false);
+        mfm.byte(0x4e);
+        mfm.mark(0xfe);
+        mfm.byte(0x00);
+        assert_eq!(words(&mfm), [0x9254, 0x4489, 0x4489, 0x4489, 0x5554, 0xaaaa]);
+        // the first cell is the lowest bit of the first byte
+        assert_eq!(mfm.cells[..2], [0x49, 0x2a]);
+
+        let mut fm = CellWriter::new(true);
+        fm.mark(0xfc);
+        fm.mark(0xfe);
+        fm.byte(0x00);
+        assert_eq!(words(&fm), [0xf77a, 0xf57e, 0xaaaa]);
+    }
+
+    #[test]
+    fn a_track_is_laid_out_in_one_revolution() {
+        let mut disk = flat_disk(&[0xe5; 9 * 512], 1, 1, 9, 512);
+        disk.tracks[0].sectors.swap(1, 5);
+        disk.tracks[0].sectors[2].header.flags = 0x02;
+        let timing = HfeTiming::new(&disk, None);
+        assert_eq!(timing, HfeTiming { fm: false, bit_rate: 250, rpm: 300 });
+        assert_eq!(timing.track_bytes(), 6250);
+
+        let w = hfe_side(Some(&disk.tracks[0]), timing);
+        assert_eq!(w.count, 6250 * 16);
+        let bytes = data(&w);
+        assert_eq!(bytes[80 + 12..80 + 16], [0xc2, 0xc2, 0xc2, 0xfc]);
+        let ids: Vec<usize> = bytes.windows(4).enumerate()
+            .filter(|(_, window)| window == &[0xa1, 0xa1, 0xa1, 0xfe]).map(|(i, _)| i).collect();
+        // in the order they were recorded, each ID followed by its data field
+        assert_eq!(ids.iter().map(|&i| bytes[i + 6]).collect::<Vec<_>>(), [1, 6, 3, 4, 5, 2, 7, 8, 9]);
+        for (n, &i) in ids.iter().enumerate() {
+            assert_eq!(bytes[i + 4..i + 8], [0, 0, bytes[i + 6], 2]);
+            assert_eq!(crc16_ccitt(0xffff, &bytes[i..i + 10]), 0);
+            let data = i + 10 + 22 + 12;
+            assert_eq!(bytes[data..data + 4], [0xa1, 0xa1, 0xa1, 0xfb]);
+            assert!(bytes[data + 4..data + 516].iter().all(|&b| b == 0xe5));
+            // the sector read with a CRC error gets a CRC that doesn't check
+            assert_eq!(crc16_ccitt(0xffff, &bytes[data..data + 518]) == 0, n != 2);
+        }
+    }
+
+    #[test]
+    fn an_empty_track_is_all_gap() {
+        let timing = HfeTiming { fm: true, bit_rate: 125, rpm: 300 };
+        let w = hfe_side(None, timing);
+        assert_eq!(w.count, timing.track_bytes() * 16);
+        assert!(data(&w).iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn writes_a_header_and_track_list() {
+        let dir = crate::tests::temp_dir("hfe");
+        let path = dir.join("disk.hfe");
+        write_hfe(&flat_disk(&[0xe5; 2 * 9 * 512], 2, 1, 9, 512), None, &path).unwrap();
+        let hfe = std::fs::read(&path).unwrap();
+        assert_eq!(hfe[..8], *b"HXCPICFE");
+        // two cylinders, one side, MFM at 250 kbit/s and 300 rpm, the track list in block 1
+        assert_eq!(hfe[8..20], [0, 2, 1, 0, 250, 0, 44, 1, 0, 1, 1, 0]);
+        // each track 6250 bytes of data a side, so 12500 bytes of cells, in blocks of 256 bytes of each side
+        let tracks = 2 * 6250 * 2;
+        let blocks = (6250usize * 2).div_ceil(256);
+        assert_eq!(hfe[512..520], [2, 0, tracks as u8, (tracks >> 8) as u8, 2 + blocks as u8, 0, tracks as u8, (tracks >> 8) as u8]);
+        assert!(hfe[520..1024].iter().all(|&b| b == 0xff));
+        assert_eq!(hfe.len(), 1024 + 2 * blocks * 512);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::{
+    analysis::SectorStatus,
+    report::{tr, ImageReport, Language, Scan},
+};
+
+// clicking a column's heading sorts the table by it, numerically where both cells are numbers,
+// and clicking it again reverses the order
+const SCRIPT: &str = r#"
+for (const th of document.querySelectorAll('#images th')) {
+  th.addEventListener('click', () => {
+    const table = th.closest('table'), body = table.tBodies[0], column = th.cellIndex;
+    const ascending = th.dataset.order !== 'ascending';
+    for (const other of table.querySelectorAll('th')) delete other.dataset.order;
+    th.dataset.order = ascending ? 'ascending' : 'descending';
+    const key = row => row.cells[column].dataset.sort ?? row.cells[column].textContent;
+    const rows = [...body.rows].sort((a, b) => {
+      const [x, y] = [key(a), key(b)];
+      const order = x !== '' && y !== '' && !isNaN(x) && !isNaN(y) ? x - y : x.localeCompare(y);
+      return ascending ? order : -order;
+    });
+    body.append(...rows);
+  });
+}
+"#;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 1em 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }
+th { background: #eee; cursor: pointer; white-space: nowrap; }
+th[data-order=ascending]::after { content: ' \25b2'; }
+th[data-order=descending]::after { content: ' \25bc'; }
+td.number { text-align: right; }
+details { margin: 0.5em 0; }
+summary { cursor: pointer; font-weight: bold; }
+pre { background: #f8f8f8; padding: 0.5em; overflow-x: auto; }
+.map span { display: inline-block; width: 0.8em; }
+img.map { image-rendering: pixelated; border: 1px solid #ccc; }
+"#;
+
+// for putting a --map-png file inside the page as a data: URL, so the page still stands alone
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            encoded.push(if i <= chunk.len() { ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char } else { '=' });
+        }
+    }
+    encoded
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// one file with its style and script inside it, so it can be mailed or put on a web site as it
+// is: a row per image shown, then a section per image that opens to show the rest
+pub(crate) fn write_html_report(scan: &Scan, path: &str, language: Language) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let t = |english| tr(language, english);
+    writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>kc8587 {}</title>\n<style>{}</style>\n</head>\n<body>",
+        env!("CARGO_PKG_VERSION"), STYLE)?;
+    let reports: Vec<&ImageReport> = scan.shown().collect();
+    writeln!(out, "<h1>{} {}</h1>", reports.len(), t("images"))?;
+
+    let columns = [t("image"), t("format"), t("status"), t("health"), t("version"), t("data rate"), t("drive type"), t("sides"),
+        t("cylinders"), t("heads"), t("sectors per track"), t("capacity"), t("filesystem"), t("label"), t("comment date")];
+    writeln!(out, "<table id=\"images\">\n<thead><tr>{}</tr></thead>\n<tbody>",
+        columns.iter().map(|c| format!("<th>{}</th>", escape(c))).collect::<String>())?;
+    for (n, report) in reports.iter().enumerate() {
+        writeln!(out, "<tr>{}</tr>", row(n, report, language))?;
+    }
+    writeln!(out, "</tbody>\n</table>")?;
+
+    for (n, report) in reports.iter().enumerate() {
+        section(&mut out, n, report, language)?;
+    }
+    writeln!(out, "<script>{}</script>\n</body>\n</html>", SCRIPT)?;
+    out.flush()
+}
+
+fn row(n: usize, report: &ImageReport, language: Language) -> String {
+    let text = |s: &str| format!("<td>{}</td>", escape(s));
+    let number = |value: Option<u64>| value.map_or_else(|| "<td></td>".to_string(), |v| format!("<td class=\"number\">{}</td>", v));
+    let header = report.header.as_ref();
+    let geometry = &report.geometry;
+    let sectors = if geometry.min_sectors_per_track == geometry.max_sectors_per_track {
+        geometry.min_sectors_per_track.to_string()
+    } else {
+        format!("{}-{}", geometry.min_sectors_per_track, geometry.max_sectors_per_track)
+    };
+    [
+        format!("<td><a href=\"#image-{}\">{}</a></td>", n, escape(&report.path)),
+        text(report.format.code()),
+        text(tr(language, &report.status.to_string())),
+        number(Some(report.health as u64)),
+        header.map_or_else(|| text(""), |h| format!("<td data-sort=\"{}\">{}.{}</td>", h.version, h.version / 10, h.version % 10)),
+        text(&header.map(|h| format!("{:02x}", h.data_rate)).unwrap_or_default()),
+        text(&header.map(|h| format!("{:02x}", h.drive_type)).unwrap_or_default()),
+        number(header.map(|h| h.sides as u64)),
+        number(Some(geometry.cylinders as u64)),
+        number(Some(geometry.heads as u64)),
+        format!("<td class=\"number\" data-sort=\"{}\">{}</td>", geometry.max_sectors_per_track, sectors),
+        number(Some(geometry.capacity)),
+        text(report.filesystem.unwrap_or("")),
+        text(report.label.as_deref().unwrap_or("")),
+        text(&report.comment.as_ref().map(|c| c.date()).unwrap_or_default()),
+    ].concat()
+}
+
+// the card's lines, then what's worth seeing laid out rather than described: the comment as it
+// reads, the sector map in its colours, and the files
+fn section(out: &mut impl Write, n: usize, report: &ImageReport, language: Language) -> io::Result<()> {
+    let t = |english| tr(language, english);
+    writeln!(out, "<details id=\"image-{}\">\n<summary>{}</summary>", n, escape(&report.path))?;
+    // the first line is the path, and the files come last and get their own listing
+    let card = report.card(language);
+    let lines: Vec<&str> = card.lines().skip(1).take_while(|line| !line.starts_with("        ")).collect();
+    writeln!(out, "<pre>{}</pre>", escape(&lines.iter().map(|line| line.trim_start()).collect::<Vec<_>>().join("\n")))?;
+    if let Some(comment) = report.comment.as_ref().filter(|c| !c.text.is_empty()) {
+        writeln!(out, "<h3>{}</h3>\n<pre>{}</pre>", escape(t("comment")), escape(&comment.text.replace('\0', "\n")))?;
+    }
+    if !report.sector_map.is_empty() {
+        let legend = SectorStatus::ALL.iter().map(|s| format!("{} {}", status_span(*s), escape(t(s.name())))).collect::<Vec<_>>().join(", ");
+        writeln!(out, "<h3>{}</h3>\n<p class=\"map\">{}</p>\n<pre class=\"map\">", escape(t("sector map")), legend)?;
+        for ((cylinder, head), statuses) in &report.sector_map {
+            writeln!(out, "{:3} {:2}  {}", cylinder, head, statuses.iter().map(|s| status_span(*s)).collect::<String>())?;
+        }
+        writeln!(out, "</pre>")?;
+        // the PNG drawn four pixels to a sector, when --map-png wrote one and it can still be read
+        if let Some(png) = report.map_png.as_ref().and_then(|path| std::fs::read(path).ok()) {
+            let (width, height) = (report.sector_map.iter().map(|(_, sectors)| sectors.len()).max().unwrap_or(0).max(1), report.sector_map.len());
+            writeln!(out, "<p><img class=\"map\" src=\"data:image/png;base64,{}\" width=\"{}\" height=\"{}\" alt=\"{}\"></p>",
+                base64(&png), width * 4, height * 4, escape(t("sector map")))?;
+        }
+    }
+    if !report.files.is_empty() {
+        let files = report.files.iter()
+            .map(|file| format!("{}{}", file.partition.map(|p| format!("{}: ", p)).unwrap_or_default(), file.listing(language)))
+            .collect::<Vec<_>>().join("\n");
+        writeln!(out, "<h3>{}</h3>\n<pre>{}</pre>", escape(t("files")), escape(&files))?;
+    }
+    writeln!(out, "</details>")
+}
+
+fn status_span(status: SectorStatus) -> String {
+    let [r, g, b] = status.rgb();
+    format!("<span style=\"background:#{:02x}{:02x}{:02x}\" title=\"{}\">{}</span>", r, g, b, escape(status.name()), escape(&status.symbol().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_to_whole_groups() {
+        assert_eq!(["", "f", "fo", "foo", "foob"].map(|s| base64(s.as_bytes())), ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg=="]);
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(escape("<a href=\"x\">Tom & Jerry's</a>"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+    }
+}
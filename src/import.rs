@@ -0,0 +1,380 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, ErrorKind, Write},
+    path::Path,
+};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use serde_json::Value;
+use kc8587::td0::{crc16, encode_td0, ImageHeader, SectorHeader, TrackHeader};
+
+use crate::{
+    analysis::{Disk, Sector, Track},
+    cli::Profile,
+    formats::{flat_disk, flat_geometry},
+    fs::Codepage,
+    report::Comment,
+};
+
+// the sectors in logical order as a flat image, and a .map file beside it with what the flat image
+// loses: the header, the comment, and every track's sectors in the order they were recorded, each
+// with its ID fields, size, flags and where its data is in the image. sectors with quirky IDs go
+// after the logical ones so they don't shift the layout the disk's format expects
+pub(crate) fn write_img_export(disk: &Disk, header: Option<&ImageHeader>, comment: Option<&Comment>, td0_path: &str, img_path: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(img_path.parent().unwrap_or(Path::new(".")))?;
+    let mut img = BufWriter::new(File::create(img_path)?);
+    let mut offsets = std::collections::HashMap::new();
+    let mut offset = 0;
+    for sector in disk.logical_sectors().into_iter().chain(disk.quirk_sectors()) {
+        img.write_all(&sector.data)?;
+        offsets.insert(sector as *const Sector, offset);
+        offset += sector.data.len();
+    }
+    img.flush()?;
+
+    let mut map = BufWriter::new(File::create(img_path.with_extension("map"))?);
+    writeln!(map, "# sectors of '{}', tracks and sectors in recorded order", td0_path)?;
+    writeln!(map, "# track CYLINDER HEAD SECTORS, then ID_CYLINDER ID_HEAD ID_SECTOR SIZE FLAGS OFFSET per sector")?;
+    let geometry = disk.geometry();
+    let header = header.cloned().unwrap_or_else(|| ImageHeader::synthetic(0, 0, geometry.heads as u8));
+    writeln!(map, "header {} seq {:02x} ver {:02x} rate {:02x} type {:02x} step {:02x} dos {:02x} sides {:02x}",
+        String::from_utf8_lossy(&header.signature), header.sequence, header.version, header.data_rate,
+        header.drive_type, header.stepping & 0x7f, header.dos_flag, header.sides)?;
+    if let Some(comment) = comment {
+        let timestamp = comment.timestamp.map_or_else(|| "-".to_string(), |t| t.format("%Y-%m-%dT%H:%M:%S").to_string());
+        writeln!(map, "comment {} {}", timestamp, Value::from(comment.text.as_str()))?;
+    }
+    for track in &disk.tracks {
+        writeln!(map, "track {} {} {}", track.cylinder, track.header.side_number, track.sectors.len())?;
+        for sector in &track.sectors {
+            let h = &sector.header;
+            write!(map, "  {} {} {} {} {:02x} {}", h.cylinder_number, h.side_number, h.sector_number, h.sector_size, h.flags,
+                offsets[&(sector as *const Sector)])?;
+            match h.id_quirk() {
+                Some(quirk) => writeln!(map, " {}", quirk.code())?,
+                None => writeln!(map)?,
+            }
+        }
+    }
+    map.flush()
+}
+
+// how import cuts a flat image into tracks and sectors
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Layout<'a> {
+    Map(Option<&'a str>),           // as a .map says, by default the one beside the image
+    Profile(Profile),
+    Geometry(usize, u8, u8, u16),   // cylinders, heads, sectors per track and their size
+    Bpb,                            // as the boot sector's BPB says, or the standard format of the image's size
+}
+
+// rebuild a TD0 from a flat image, laid out by the .map --export-img wrote, a standard format or a
+// geometry, with a comment if one is given, dated when the image was last changed
+pub(crate) fn import_img(image: &str, layout: Layout, output: &str, codepage: Codepage, comment: Option<&str>) -> io::Result<()> {
+    let img = std::fs::read(image)?;
+    let (header, map_comment, disk) = match layout {
+        Layout::Profile(profile) => {
+            let (header, disk) = profile.layout(&img)?;
+            (header, None, disk)
+        },
+        Layout::Geometry(cylinders, heads, sectors, sector_size) => {
+            let size = cylinders * heads as usize * sectors as usize * sector_size as usize;
+            if img.len() != size {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("image is {} bytes, not the {} of that geometry", img.len(), size)));
+            }
+            (geometry_header(cylinders, heads, sectors, sector_size), None, flat_disk(&img, cylinders, heads, sectors, sector_size))
+        },
+        Layout::Bpb => {
+            let (cylinders, heads, sectors, sector_size) = flat_geometry(&img).ok_or_else(|| io::Error::new(ErrorKind::InvalidData,
+                format!("image is {} bytes, with no BPB to say how it's laid out and not the size of a format known", img.len())))?;
+            (geometry_header(cylinders, heads, sectors, sector_size), None, flat_disk(&img, cylinders, heads, sectors, sector_size))
+        },
+        Layout::Map(map) => {
+            let map_path = map.map_or_else(|| Path::new(image).with_extension("map"), std::path::PathBuf::from);
+            let text = std::fs::read_to_string(&map_path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", map_path.to_string_lossy(), e)))?;
+            read_sector_map(&text, &img)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", map_path.to_string_lossy(), e)))?
+        },
+    };
+    let comment = match comment {
+        Some(text) => {
+            let modified = std::fs::metadata(image)?.modified()?;
+            Some(Comment {
+                timestamp: Some(chrono::DateTime::<chrono::Local>::from(modified).naive_local()),
+                text: text.to_string(),
+                crc_ok: true,
+                truncated: false,
+                fields: BTreeMap::new(),
+            })
+        },
+        None => map_comment,
+    };
+
+    let mut out = BufWriter::new(File::create(output)?);
+    write_td0(&mut out, &header, comment.as_ref(), &disk, codepage)?;
+    out.flush()
+}
+
+// the data rate and drive type of the standard format with the same shape, otherwise double
+// density unless the tracks hold more than a double density track can, in a 40 or 80 track drive
+fn geometry_header(cylinders: usize, heads: u8, sectors: u8, sector_size: u16) -> ImageHeader {
+    let standard = <Profile as clap::ValueEnum>::value_variants().iter().map(Profile::shape)
+        .find(|&(c, h, s, _, _)| (c, h, s, 512) == (cylinders, heads, sectors, sector_size));
+    let (data_rate, drive_type) = match standard {
+        Some((_, _, _, data_rate, drive_type)) => (data_rate, drive_type),
+        None if sectors as usize * sector_size as usize > 6250 => (2, 2),
+        None if cylinders > 42 => (0, 3),
+        None => (0, 1),
+    };
+    ImageHeader::synthetic(data_rate, drive_type, heads)
+}
+
+// the header, comment and tracks of a .map, with each sector's data taken from the flat image
+pub(crate) fn read_sector_map(text: &str, img: &[u8]) -> Result<(ImageHeader, Option<Comment>, Disk), String> {
+    let mut header = None;
+    let mut comment = None;
+    let mut disk = Disk::default();
+    for (n, line) in text.lines().enumerate() {
+        let error = |e: &str| format!("line {}: {}", n + 1, e);
+        let number = |field: &str| field.parse::<usize>().map_err(|_| error(&format!("'{}' isn't a number", field)));
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] => {},
+            [first, ..] if first.starts_with('#') => {},
+            ["header", _signature, pairs @ ..] => {
+                let mut values = BTreeMap::new();
+                for pair in pairs.chunks_exact(2) {
+                    values.insert(pair[0], u8::from_str_radix(pair[1], 16).map_err(|_| error(&format!("bad {} value", pair[0])))?);
+                }
+                let value = |key| values.get(key).copied().ok_or_else(|| error(&format!("no {} in header", key)));
+                header = Some(ImageHeader {
+                    signature: *b"TD", sequence: value("seq")?, check_sequence: 0, version: value("ver")?,
+                    data_rate: value("rate")?, drive_type: value("type")?, stepping: value("step")?,
+                    dos_flag: value("dos")?, sides: value("sides")?, crc: 0,
+                });
+            },
+            ["comment", timestamp, ..] => {
+                let json = line.trim_start()["comment".len()..].trim_start()[timestamp.len()..].trim_start();
+                comment = Some(Comment {
+                    timestamp: match *timestamp {
+                        "-" => None,
+                        _ => Some(NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S").map_err(|_| error("bad comment timestamp"))?),
+                    },
+                    text: serde_json::from_str(json).map_err(|_| error("bad comment text"))?,
+                    crc_ok: true,
+                    truncated: false,
+                    fields: BTreeMap::new(),
+                });
+            },
+            ["track", cylinder, head, sectors] => {
+                let cylinder = number(cylinder)?;
+                disk.tracks.push(Track {
+                    header: TrackHeader { number_of_sectors: number(sectors)? as u8, cylinder_number: cylinder as u8, side_number: number(head)? as u8 },
+                    cylinder,
+                    sectors: Vec::new(),
+                });
+            },
+            // a quirky ID is noted after the offset, but the ID fields say it all
+            [cylinder, head, sector_number, size, flags, offset] | [cylinder, head, sector_number, size, flags, offset, "no-id" | "high-id"] => {
+                let track = disk.tracks.last_mut().ok_or_else(|| error("sector before any track"))?;
+                let (size, offset) = (number(size)?, number(offset)?);
+                if !(128..=8192).contains(&size) || !size.is_power_of_two() {
+                    return Err(error("sector size isn't 128 to 8192 bytes"));
+                }
+                let data = img.get(offset..offset + size).ok_or_else(|| error("sector is beyond the end of the image"))?;
+                track.sectors.push(Sector {
+                    header: SectorHeader {
+                        cylinder_number: number(cylinder)? as u8,
+                        side_number: number(head)? as u8,
+                        sector_number: number(sector_number)? as u8,
+                        sector_size: size as u16,
+                        flags: u8::from_str_radix(flags, 16).map_err(|_| error("bad sector flags"))?,
+                    },
+                    data: data.to_vec(),
+                });
+            },
+            _ => return Err(error("not a header, comment, track or sector line")),
+        }
+    }
+    if let Some(track) = disk.tracks.iter().find(|t| t.sectors.len() != t.header.number_of_sectors as usize) {
+        return Err(format!("track {} head {} should have {} sectors but has {}",
+            track.cylinder, track.header.side_number, track.header.number_of_sectors, track.sectors.len()));
+    }
+    Ok((header.ok_or("no header line")?, comment, disk))
+}
+
+// an image in the plain TD0 format, without advanced compression, each sector stored the
+// shortest way encode_td0 finds
+pub(crate) fn write_td0(out: &mut dyn Write, header: &ImageHeader, comment: Option<&Comment>, disk: &Disk, codepage: Codepage) -> io::Result<()> {
+    let mut header_bytes = header.crc_bytes();
+    header_bytes[7] = header.stepping & 0x7f | if comment.is_some() { 0x80 } else { 0 };
+    out.write_all(&header_bytes)?;
+    out.write_all(&crc16(&header_bytes).to_le_bytes())?;
+
+    if let Some(comment) = comment {
+        let mut text = codepage.encode(&comment.text);
+        text.push(0);
+        // a comment whose date couldn't be read gets the earliest TeleDisk can record
+        let t = comment.timestamp.unwrap_or_default();
+        let mut bytes = (text.len() as u16).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[(t.year() - 1900) as u8, t.month0() as u8, t.day() as u8, t.hour() as u8, t.minute() as u8, t.second() as u8]);
+        bytes.extend_from_slice(&text);
+        out.write_all(&crc16(&bytes).to_le_bytes())?;
+        out.write_all(&bytes)?;
+    }
+
+    for track in &disk.tracks {
+        let track_header = [track.sectors.len() as u8, track.cylinder as u8, track.header.side_number];
+        out.write_all(&track_header)?;
+        out.write_all(&[crc16(&track_header) as u8])?;
+        for sector in &track.sectors {
+            let h = &sector.header;
+            let size_code = (h.sector_size / 128).trailing_zeros() as u8;
+            out.write_all(&[h.cylinder_number, h.side_number, h.sector_number, size_code, h.flags, crc16(&sector.data) as u8])?;
+            if h.has_data() {
+                let block = encode_td0(&sector.data);
+                out.write_all(&(block.len() as u16).to_le_bytes())?;
+                out.write_all(&block)?;
+            }
+        }
+    }
+    out.write_all(&[255, 0, 0, 0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kc8587::td0::Td0Reader;
+
+    const MAP: &str = "# a map as --export-img writes it
+header TD seq 00 ver 15 rate 02 type 03 step 01 dos 00 sides 01
+comment 1994-03-05T12:34:56 \"backup of \\\"disk 1\\\"\"
+track 0 0 3
+  0 0 1 512 00 0
+  0 0 2 512 02 512
+  0 0 3 256 00 1024
+track 1 0 2
+  1 0 1 512 04 1280
+  0 0 100 512 10 0 high-id
+";
+
+    fn image() -> Vec<u8> {
+        let mut img: Vec<u8> = (0..512).map(|i| i as u8).collect();
+        img.extend_from_slice(&[0xe5; 512]);
+        img.extend([0xde, 0xad].repeat(128));
+        img.extend((0..512).map(|i| (i * 7) as u8));
+        img
+    }
+
+    #[test]
+    fn reads_a_sector_map() {
+        let (header, comment, disk) = read_sector_map(MAP, &image()).unwrap();
+        assert_eq!((header.version, header.data_rate, header.drive_type, header.sides), (0x15, 0x02, 0x03, 0x01));
+        let comment = comment.unwrap();
+        assert_eq!(comment.text, "backup of \"disk 1\"");
+        assert_eq!(comment.timestamp.unwrap().to_string(), "1994-03-05 12:34:56");
+        assert_eq!(disk.tracks.iter().map(|t| t.sectors.len()).collect::<Vec<_>>(), [3, 2]);
+        assert_eq!(disk.tracks[0].sectors[2].data, [0xde, 0xad].repeat(128));
+        assert_eq!(disk.tracks[1].sectors[1].header.sector_number, 100);
+
+        assert!(read_sector_map("track 0 0 1\n  0 0 1 512 00 0\n", &image()).unwrap_err().contains("no header"));
+        assert!(read_sector_map(&MAP.replace("04 1280", "04 1700"), &image()).unwrap_err().starts_with("line 9:"));
+        assert!(read_sector_map(&MAP.replace("track 1 0 2", "track 1 0 3"), &image()).unwrap_err().contains("should have 3"));
+    }
+
+    #[test]
+    fn written_td0s_read_back() {
+        let (header, comment, disk) = read_sector_map(MAP, &image()).unwrap();
+        let mut td0 = Vec::new();
+        write_td0(&mut td0, &header, comment.as_ref(), &disk, Codepage::Cp437).unwrap();
+
+        let mut reader = Td0Reader::new(&td0[..]).unwrap();
+        assert!(reader.headers.image_header.crc_ok());
+        assert!(reader.headers.image_header.has_comment_header());
+        let comment_header = reader.headers.comment_header.as_ref().unwrap();
+        let text = reader.comment.as_deref().unwrap();
+        assert!(comment_header.crc_ok(text));
+        assert_eq!(text, b"backup of \"disk 1\"\0");
+        assert_eq!((comment_header.year, comment_header.month, comment_header.day), (94, 2, 5));
+
+        let tracks: Vec<_> = reader.tracks().collect::<Result<_, _>>().unwrap();
+        assert_eq!(tracks.len(), disk.tracks.len());
+        for (read, written) in tracks.iter().zip(&disk.tracks) {
+            assert!(read.crc_ok);
+            assert_eq!(read.header.cylinder_number as usize, written.cylinder);
+            for (sector, expected) in read.sectors().zip(&written.sectors) {
+                let (h, e) = (&sector.header, &expected.header);
+                assert_eq!((h.cylinder_number, h.side_number, h.sector_number, h.sector_size, h.flags),
+                    (e.cylinder_number, e.side_number, e.sector_number, e.sector_size, e.flags));
+                let data = sector.data().unwrap();
+                // a sector without data comes back blank, with the CRC of what was written
+                if expected.header.has_data() {
+                    assert_eq!(data, expected.data);
+                } else {
+                    assert_eq!(data, vec![0; 512]);
+                }
+                assert!(sector.crc_ok(&expected.data));
+            }
+        }
+        // the blank and the repeated pattern sectors are stored as their pattern
+        let encodings: Vec<_> = tracks.iter().flat_map(|t| t.sectors().map(|s| s.encoding())).collect();
+        assert_eq!(encodings, [Some(0), Some(1), Some(1), Some(0), None]);
+        assert!(reader.into_inner().is_empty());
+    }
+
+    #[test]
+    fn imports_by_geometry_and_bpb_with_a_comment() {
+        let dir = crate::tests::temp_dir("import");
+        let (image, output) = (dir.join("disk.img"), dir.join("disk.td0"));
+        // 8 sectors a track, 1K sectors on 77 cylinders: no standard format, so it needs a geometry
+        let img: Vec<u8> = (0..77 * 2 * 8 * 1024).map(|i| if i % 3000 < 1000 { (i * 7) as u8 } else { 0xe5 }).collect();
+        std::fs::write(&image, &img).unwrap();
+        let (image, output) = (image.to_str().unwrap(), output.to_str().unwrap());
+        assert!(import_img(image, Layout::Bpb, output, Codepage::Cp437, None).is_err());
+        assert!(import_img(image, Layout::Geometry(80, 2, 8, 1024), output, Codepage::Cp437, None).is_err());
+        import_img(image, Layout::Geometry(77, 2, 8, 1024), output, Codepage::Cp437, Some("made by hand")).unwrap();
+
+        let td0 = std::fs::read(output).unwrap();
+        let mut reader = Td0Reader::new(&td0[..]).unwrap();
+        let header = &reader.headers.image_header;
+        assert_eq!((header.data_rate, header.drive_type, header.sides), (2, 2, 2));
+        assert_eq!(reader.comment.as_deref(), Some(&b"made by hand\0"[..]));
+        let mut read = Vec::new();
+        let mut encodings = BTreeMap::new();
+        for track in reader.tracks() {
+            for sector in track.unwrap().sectors() {
+                assert_eq!(sector.header.sector_size, 1024);
+                *encodings.entry(sector.encoding()).or_insert(0) += 1;
+                read.extend(sector.data().unwrap());
+            }
+        }
+        assert_eq!(read, img);
+        // the long runs of filler are stored as RLE
+        assert!(encodings.contains_key(&Some(2)), "{:?}", encodings);
+        assert!(td0.len() < img.len() / 2);
+
+        // an 800K FAT image, 10 sectors a track, says how it's laid out
+        let mut img = vec![0xf6; 819200];
+        img[0x0b..0x0d].copy_from_slice(&512u16.to_le_bytes());
+        img[0x0d] = 2;
+        img[0x0e..0x10].copy_from_slice(&1u16.to_le_bytes());
+        img[0x10] = 2;
+        img[0x11..0x13].copy_from_slice(&112u16.to_le_bytes());
+        img[0x13..0x15].copy_from_slice(&1600u16.to_le_bytes());
+        img[0x15] = 0xf9;
+        img[0x16..0x18].copy_from_slice(&3u16.to_le_bytes());
+        img[0x18..0x1a].copy_from_slice(&10u16.to_le_bytes());
+        img[0x1a..0x1c].copy_from_slice(&2u16.to_le_bytes());
+        img[0x1fe..0x200].copy_from_slice(&[0x55, 0xaa]);
+        std::fs::write(image, &img).unwrap();
+        import_img(image, Layout::Bpb, output, Codepage::Cp437, None).unwrap();
+        let td0 = std::fs::read(output).unwrap();
+        let mut reader = Td0Reader::new(&td0[..]).unwrap();
+        assert_eq!((reader.headers.image_header.data_rate, reader.headers.image_header.drive_type), (0, 3));
+        assert!(reader.comment.is_none());
+        let tracks: Vec<_> = reader.tracks().collect::<Result<_, _>>().unwrap();
+        assert_eq!((tracks.len(), tracks[0].sectors().len()), (160, 10));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,6 @@
+// the TeleDisk format on its own, for anything that wants to read TD0 images without the rest of
+// the analyser, which is the kc8587 binary built on this
+pub mod td0;
+// td0::summarise exported for javascript, for a browser front end
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,657 +1,1809 @@
 use std::{
-    fs::File,
-    io::{BufReader, Read, Seek, SeekFrom},
-    ops::ControlFlow,
-    path::Path
+    collections::BTreeMap,
+    fmt,
+    io::{self, ErrorKind, IsTerminal, Read, Write},
+    path::Path,
 };
-use flate2::read::GzDecoder;
-use tar::Archive;
-use walkdir::WalkDir;
-use zip::ZipArchive;
-use chrono::NaiveDate;
-use chrono::NaiveDateTime;
-use chrono::NaiveTime;
-use clap::Parser;
-use pathdiff::diff_paths;
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    #[clap(short, long)]
-    verbose: bool,
-
-    #[clap(short, long)]
-    disk_image_info: bool,
-
-    #[clap(short, long)]
-    track_info: bool,
-
-    #[clap(short, long)]
-    sector_info: bool,
-
-    #[clap(short, long)]
-    comment_info: bool,
-
-    #[clap(short, long)]
-    analyse_first_tracks: bool,
-
-    #[clap(short = 'u', long = "colour", alias = "color")]
-    colour: bool,
-
-    /// The path to the file or directory to process
-    #[clap(value_parser)]
-    path: String,
+use clap::{CommandFactory, FromArgMatches, Parser};
+use sha1::{Digest, Sha1};
+use kc8587::td0::{self, crc16, header_repairs, DecodeError, ImageHeader, TeleDiskHeaders};
+
+use crate::{
+    analysis::{Compression, ContentCounts, Disk, Sector, Track, TrackRepeats},
+    cli::{
+        capabilities, config_path, init_logging, read_config, Args, Command,
+        ERROR_COLOUR, EXIT_COMMAND_LINE, EXIT_INVALID_IMAGES, EXIT_IO_ERROR, FLAG_COLOUR,
+        HEADER_COLOUR, OutFormat, SectorAnalysis, TRACK_COLOUR,
+    },
+    containers::{
+        first_bytes, image_path, is_flat_image_name, is_image_name, walk_path, CountingReader,
+        Provenance, Sniffed,
+    },
+    extract::extract_fat_files,
+    dump::{grep_disk, print_hex_dump, print_sector_map, write_map_png, write_sector_files},
+    formats::{flat_disk, flat_geometry, read_dsk},
+    fs::{analyse_directories, detect_filesystem, fat::Partition, FileEntry},
+    hfe::write_hfe,
+    html::write_html_report,
+    import::{import_img, write_img_export, Layout},
+    repair::{repair, Filler},
+    report::{
+        check_volume_sets, comment_timestamp, compare_runs, header_findings, health_score, print_duplicates,
+        print_similar, print_stats, raw_comment_date, tr, write_catalogue, write_comment_report, Comment, Finding,
+        ImageFormat, ImageReport, ImageStatus, Location, Scan, Severity, SortKeys, Toolchain,
+    },
+    selftest::selftest,
+    sets::{group_disk_sets, print_disk_sets},
+};
+#[cfg(feature = "sqlite")]
+use crate::report::{query_database, write_database};
+#[cfg(feature = "browse")]
+use crate::tui::browse;
+
+// println! for a line about the image being analysed, which starts with its ID with --ids and
+// goes to its file with --out-format output
+macro_rules! image_println {
+    ($args:expr, $($arg:tt)*) => {
+        $crate::print_image_line(format_args!("{}{}", $args.id_prefix(), format_args!($($arg)*)))
+    };
+}
+
+// a finding with its message as a template, filled in with each of what follows as a string, so
+// the template can be looked up in another language
+macro_rules! finding {
+    ($severity:expr, $code:expr, $location:expr, $template:literal $(, $arg:expr)* $(,)?) => {
+        $crate::report::Finding::new($severity, $code, $location, $template, vec![$($arg.to_string()),*])
+    };
+}
+
+// declared after image_println and finding so they can use them
+mod analysis;
+mod cli;
+mod containers;
+mod dump;
+mod extract;
+mod formats;
+mod fs;
+mod hfe;
+mod html;
+mod import;
+mod repair;
+mod report;
+mod selftest;
+mod sets;
+#[cfg(feature = "browse")]
+mod tui;
+
+thread_local! {
+    // with --out-format output, the lines about the image being analysed, gathered for its file
+    // in --out-dir rather than printed
+    static IMAGE_OUTPUT: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+    // lines held back until what goes before them is known, as the first of several images
+    // joined in one file only knows it's #1 when its end is reached
+    static HELD_LINES: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
+
+fn print_image_line(line: fmt::Arguments) {
+    if HELD_LINES.with_borrow_mut(|held| held.as_mut().map(|lines| lines.push(line.to_string())).is_some()) {
+        return;
+    }
+    IMAGE_OUTPUT.with_borrow_mut(|output| match output {
+        Some(lines) => lines.push(line.to_string()),
+        None => println!("{}", line),
+    })
 }
 
+fn hold_image_lines() {
+    HELD_LINES.set(Some(Vec::new()));
+}
+
+// prints the lines held back, after the line that goes before them
+fn release_image_lines(first: Option<String>) {
+    let held = HELD_LINES.take().unwrap_or_default();
+    for line in first.iter().chain(&held) {
+        print_image_line(format_args!("{}", line));
+    }
+}
+
+// the conventions comments most often follow, with the fields they give: who dumped the disk and
+// when, what it was dumped from, and any notes
+const COMMENT_PATTERNS: &[&str] = &[
+    r"(?im)dumped\s+by\s+(?P<dumper>.+?)(?:\s+on\s+(?P<dumped_on>[\w/.-]+))?(?:\s+from\s+(?:the\s+)?(?:original\s+)?(?P<source>.+?))?\s*$",
+    r"(?im)^\s*(?:label|source|original)\s*[:=]\s*(?P<source>.+?)\s*$",
+    r"(?im)^\s*(?:notes?|remarks?)\s*[:=]\s*(?P<notes>.+?)\s*$",
+];
+
 fn main() {
-    let mut args = Args::parse(); 
-    if args.verbose { 
-        args.disk_image_info = true;
-        args.track_info = true; 
-        args.sector_info = true; 
-        args.comment_info = true; 
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // given just one image and no options, show what's known about it instead of the sector by
+    // sector heuristics meant for digging into whole collections. that's only a default, so a config
+    // file's options still win over it, as the command line's win over the config file's
+    let card = std::env::args_os().len() == 2
+        && args.path.as_deref().is_some_and(|p| is_image_name(&p.to_string_lossy()) && p.is_file());
+    let mut card_quiet = card;
+    // a config file's options are taken as if given before the command line's, which win over them.
+    // subcommands have their own options and aren't affected
+    if args.command.is_none() && !args.capabilities && !args.no_config {
+        if let Some(path) = config_path(&args) {
+            match read_config(&path) {
+                Ok(config) => {
+                    let options = config.options_allowed_by_command_line(&matches);
+                    if !options.is_empty() {
+                        let mut argv = std::env::args_os();
+                        args = Args::parse_from(argv.next().into_iter().chain(options.into_iter().map(Into::into)).chain(argv));
+                    }
+                    card_quiet &= !config.conflicts_with("quiet");
+                    args.thresholds = config.thresholds;
+                },
+                Err(e) => {
+                    init_logging(args.verbose, args.colour.enabled(io::stderr().is_terminal()));
+                    log::error!("Failed to read config file {}: {}", path.display(), e);
+                    std::process::exit(EXIT_COMMAND_LINE);
+                },
+            }
+        }
+    }
+    init_logging(args.verbose, args.colour.enabled(io::stderr().is_terminal()));
+    // output written into files isn't coloured or fitted to the terminal
+    let terminal = io::stdout().is_terminal() && !args.writes_output();
+    args.colours = args.colour.enabled(terminal);
+    // from here on width is what output is fitted to, if anything
+    args.width = match args.width {
+        Some(0) => None,
+        Some(width) => Some(width),
+        None if terminal => terminal_size::terminal_size().map(|(width, _)| width.0 as usize),
+        None => None,
+    };
+    if args.verbose > 0 { 
+        args.set_info_flags(true);
     } 
+    if args.quiet {
+        args.set_info_flags(false);
+    }
+    args.comment_pattern.extend(COMMENT_PATTERNS.iter().map(|p| regex::Regex::new(p).unwrap()));
+    if card {
+        args.quiet |= card_quiet;
+        args.card = true;
+    }
     let args = args;
 
-    let start_path = &args.path;
-    // TODO validate start path exists
-    let walkdir = WalkDir::new(start_path).into_iter();
-    for dirent in walkdir {
-        // iterate, filtering out directories
-        let dirent = dirent.expect("Failed to read directory entry");
-        if !dirent.file_type().is_file() { continue; }
-
-        let abs_parent_path = dirent.path().parent().unwrap().to_string_lossy();
-        let current_dir = std::env::current_dir().unwrap();
-        let rello = diff_paths(Path::new(abs_parent_path.as_ref()), Path::new(&current_dir)).expect("Failed to get relative path");
-        let rel_parent_path = rello.to_string_lossy();
-
-        let file_name = dirent.file_name().to_string_lossy();
-
-        // filename tests
-        let norm_file_name = file_name.to_lowercase();
-        let has_zip_ext = norm_file_name.ends_with("zip");
-        let has_gzip_ext = [".tgz", ".gz", ".gzip"].iter().any(|ext| norm_file_name.ends_with(ext));
-        // let has_tar_ext = norm_file_name.ends_with("tar");
-
-        let mut file = File::open(dirent.path()).expect("Failed to open file");
-
-        let file_length = file.metadata().unwrap().len();
-        if file_length < 4 {
-            if args.verbose {
-                println!("Skipping file {}: too short ({} bytes)", dirent.path().to_string_lossy(), file_length);
-            }
-            continue; // Skip to the next file
+    if args.capabilities {
+        println!("{:#}", capabilities());
+        return;
+    }
+
+    if let Some(command) = &args.command {
+        match command {
+            Command::CompareRuns { old, new } => if let Err(e) = compare_runs(old, new) {
+                log::error!("{}", e);
+                std::process::exit(EXIT_IO_ERROR);
+            },
+            Command::Import { image, output, map, profile, geometry, bpb, codepage, comment } => {
+                let layout = match (profile, geometry) {
+                    (Some(profile), _) => Layout::Profile(*profile),
+                    (_, Some((cylinders, heads, sectors, size))) => Layout::Geometry(*cylinders, *heads, *sectors, *size),
+                    _ if *bpb => Layout::Bpb,
+                    _ => Layout::Map(map.as_deref()),
+                };
+                if let Err(e) = import_img(image, layout, output, *codepage, comment.as_deref()) {
+                    log::error!("Failed to import {}: {}", image, e);
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            },
+            Command::Repair { image, output, fill, fill_damaged } => {
+                if let Err(e) = repair(image, output, Filler { byte: *fill, damaged: *fill_damaged }) {
+                    log::error!("Failed to repair {}: {}", image, e);
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            },
+            Command::RepairHeader { image, output, pick } => match repair_header(image, output.as_deref(), *pick) {
+                Ok(true) => {},
+                Ok(false) => std::process::exit(EXIT_INVALID_IMAGES),
+                Err(e) => {
+                    log::error!("Failed to repair {}: {}", image, e);
+                    std::process::exit(EXIT_IO_ERROR);
+                },
+            },
+            Command::Selftest => if !selftest() {
+                std::process::exit(EXIT_INVALID_IMAGES);
+            },
+            #[cfg(feature = "browse")]
+            Command::Browse { path, codepage, language } => if let Err(e) = browse(path, *codepage, *language) {
+                log::error!("Failed to browse {}: {}", path, e);
+                std::process::exit(EXIT_IO_ERROR);
+            },
+            #[cfg(feature = "sqlite")]
+            Command::Query { db, table } => if let Err(e) = query_database(db, table) {
+                log::error!("Failed to query database {}: {}", db, e);
+                std::process::exit(EXIT_IO_ERROR);
+            },
         }
+        return;
+    }
 
-        // file content tests
-        let zip_magic = b"PK\x03\x04";
-        let gzip_magic = b"\x1f\x8b";
-        // tar doesn't have a magic number
+    let mut scan = Scan::default();
 
-        let mut magic_bytes = [0; 4];
-        file.read_exact(&mut magic_bytes).expect("Failed to read file magic");
+    if args.stdin || args.path.as_deref() == Some(Path::new("-")) {
+        let stdin = std::io::stdin();
+        analyse_image(&args, &mut scan, &mut stdin.lock(), "S", Path::new(""), None, "<stdin>", &Provenance::default());
+    } else {
+        walk_path(&args, &mut scan, args.path.as_deref().unwrap());
+    }
 
-        let has_zip_magic = &magic_bytes[..4] == zip_magic;
-        let has_gzip_magic = &magic_bytes[..2] == gzip_magic;
+    // everything written after the walk is in order of path, whatever order the walk took
+    scan.reports.sort_by(|a, b| a.path.cmp(&b.path));
+    check_volume_sets(&mut scan);
+    group_disk_sets(&mut scan);
 
-        // since tar doesn't have a magic number, best check in rust seems to be to instantiate and try the iterator
-        // TODO we are currently specifically checking only for a tar inside a gzip!!
-        let contains_tar = {
-            let mut arc = Archive::new(GzDecoder::new(&file));
-            arc.entries().unwrap().next().unwrap().is_ok()
-        };
+    if args.find_duplicates {
+        print_duplicates(&scan);
+    }
 
-        let file_type = if has_zip_ext || has_zip_magic {
-            "Zip"
-        } else if (has_gzip_ext || has_gzip_magic) || contains_tar {
-            "Tarball"
-        } else {
-            "File"
-        };
+    if args.find_sets {
+        print_disk_sets(&scan);
+    }
+
+    if let Some(percent) = args.find_similar {
+        print_similar(&scan, percent);
+    }
+
+    if args.stats {
+        print_stats(&scan, args.stats_format, args.language);
+    }
+
+    if let Some(catalogue) = &args.catalogue {
+        if let Err(e) = write_catalogue(&scan, catalogue) {
+            scan.io_error(&format!("Failed to write catalogue {}: {}", catalogue, e));
+        }
+    }
+
+    if let Some(path) = &args.comment_report {
+        if let Err(e) = write_comment_report(&scan, path) {
+            scan.io_error(&format!("Failed to write {}: {}", path, e));
+        }
+    }
+
+    if let Some(path) = &args.report_html {
+        if let Err(e) = write_html_report(&scan, path, args.language) {
+            scan.io_error(&format!("Failed to write {}: {}", path, e));
+        }
+    }
 
-        if file_type == "Zip" {
-            process_zip_archive(&args, file, &rel_parent_path, &file_name);
-        } else if file_type == "Tarball" {
-            process_tarball(&args, file, &rel_parent_path, &file_name);
-        } else if file_name.to_lowercase().ends_with(".td0") {
-            file.seek(SeekFrom::Start(0)).expect("Failed to seek to start of file");
-            analyze_teledisk_image_format_from_stream(
-                &args, &mut file, "F", &rel_parent_path, None, &file_name);
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = &args.db {
+        if let Err(e) = write_database(&scan, db) {
+            scan.io_error(&format!("Failed to update database {}: {}", db, e));
         }
     }
+
+    std::process::exit(scan.exit_code());
 }
 
-fn process_zip_archive(args : &Args, file: File, file_path: &str, container_name: &str) {
-    let buf_reader = BufReader::new(file);
-    match ZipArchive::new(buf_reader) {
-        Ok(mut archive) => {
-            for i in 0..archive.len() {
-                match archive.by_index(i) {
-                    Ok(mut zip_file) => {
-                        if zip_file.name().to_lowercase().ends_with(".td0") {
-                            let zip_file_name = zip_file.name().to_string();
-                            analyze_teledisk_image_format_from_stream(
-                                args, &mut zip_file, "Z", file_path, Some(container_name), &zip_file_name);
-                        }
-                    },
-                    Err(e) => verbose_error(args, &format!("Failed to read zip file {}: {}", i, e))
-                }
-            }
+#[allow(clippy::too_many_arguments)]
+fn analyse_image(args : &Args, scan: &mut Scan, file: &mut dyn Read,
+        typ: &str, file_path: &Path, container_name: Option<&str>, file_name: &str, provenance: &Provenance) {
+    if args.list {
+        list_image(args, scan, file, file_path, container_name, file_name);
+        return;
+    }
+    // each image found in the file takes the lines about it for its own file. what's left is
+    // about the file, such as why it isn't an image
+    if args.writes_output() && IMAGE_OUTPUT.with_borrow(Option::is_none) {
+        IMAGE_OUTPUT.set(Some(Vec::new()));
+        analyse_image(args, scan, file, typ, file_path, container_name, file_name, provenance);
+        let lines = IMAGE_OUTPUT.take().unwrap_or_default();
+        if let Some(dir) = args.out_dir.as_ref().filter(|_| !lines.is_empty()) {
+            write_image_output(scan, dir, &image_path(file_path, container_name, file_name), &lines);
+        }
+        return;
+    }
+    // with --ids the whole file is read first for the hash its ID comes from
+    if args.ids && args.image_id.is_none() {
+        let mut bytes = Vec::new();
+        if let Err(e) = file.read_to_end(&mut bytes) {
+            scan.io_error(&format!("Failed to read {}: {}", file_name, e));
+            return;
+        }
+        let mut args = args.clone();
+        args.image_id = Some(image_id(&bytes));
+        analyse_image(&args, scan, &mut io::Cursor::new(bytes), typ, file_path, container_name, file_name, provenance);
+        return;
+    }
+    // only TD0s have the header fields filtered on
+    if is_flat_image_name(file_name) && args.filters_headers() {
+        scan.passed_over += 1;
+        return;
+    }
+    if is_flat_image_name(file_name) {
+        let mut bytes = Vec::new();
+        if let Err(e) = file.read_to_end(&mut bytes) {
+            scan.io_error(&format!("Failed to read {}: {}", file_name, e));
+        } else if bytes.starts_with(b"MV - CPC") || bytes.starts_with(b"EXTENDED CPC DSK") {
+            analyse_dsk_image(args, scan, &bytes, typ, file_path, container_name, file_name, provenance);
+        } else {
+            analyse_flat_image(args, scan, &bytes, typ, file_path, container_name, file_name, provenance);
+        }
+    } else {
+        analyze_teledisk_image_format_from_stream(args, scan, file, typ, file_path, container_name, file_name, provenance);
+    }
+}
+
+// with --list, the path of a TD0 whose header is good, and nothing more read of it
+fn list_image(args: &Args, scan: &mut Scan, file: &mut dyn Read, file_path: &Path, container_name: Option<&str>, file_name: &str) {
+    if is_flat_image_name(file_name) { return; }
+    let headers = match TeleDiskHeaders::from_stream(file) {
+        Ok(headers) => headers,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+            scan.invalid_images += 1;
+            log::info!("{} is too short for a TeleDisk header", file_name);
+            return;
+        },
+        Err(e) => {
+            scan.io_error(&format!("Failed to read {}: {}", file_name, e));
+            return;
         },
-        Err(e) => verbose_error(args, &format!("Failed to read zip archive: {}", e))
+    };
+    let header = &headers.image_header;
+    if !header.is_valid() || !header.crc_ok() {
+        scan.invalid_images += 1;
+        log::info!("{} isn't listed: its TeleDisk header isn't good", image_path(file_path, container_name, file_name));
+        return;
+    }
+    if !args.header_wanted(header, headers.comment_header.as_ref()) {
+        scan.passed_over += 1;
+        return;
+    }
+    scan.listed += 1;
+    let end = if args.null { '\0' } else { '\n' };
+    if let Err(e) = write!(io::stdout().lock(), "{}{}", image_path(file_path, container_name, file_name), end) {
+        scan.io_error(&format!("Failed to write the list: {}", e));
     }
 }
 
-fn process_tarball(args : &Args, mut file: File, file_path: &str, container_name: &str) {
-    file.seek(SeekFrom::Start(0)).expect("Failed to seek to start of file");
-    let mut archive = Archive::new(GzDecoder::new(file));
-    let entries = archive.entries().expect("Failed to read tarball");
-    for (i, entry) in entries.enumerate() {
-        match entry {
-            Ok(mut entry) => {
-                if entry.path().unwrap().to_str().unwrap().to_lowercase().ends_with(".td0") {
-                    let tar_file_name = entry.path().unwrap().to_string_lossy().to_string();
-                    analyze_teledisk_image_format_from_stream(
-                        args, &mut entry, "T", file_path, Some(container_name), &tar_file_name);
-                }
-            },
-            Err(err) => verbose_error(args, &format!("Failed to read tar entry: {} at {}: {}", container_name, i, err))
+// Crockford's base32 of the first 40 bits of the SHA-1 of an image file: eight characters, none of
+// them letters that read as digits
+fn image_id(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+    let bits = Sha1::digest(bytes)[..5].iter().fold(0u64, |bits, &b| bits << 8 | b as u64);
+    (0..8).rev().map(|i| ALPHABET[(bits >> (i * 5)) as usize & 0x1f] as char).collect()
+}
+
+// lists the fixes for an image's header, and writes a copy with one applied if asked to: the only
+// one, or the one picked when there are several. false if there's nothing that could be written
+fn repair_header(image: &str, output: Option<&str>, pick: Option<usize>) -> io::Result<bool> {
+    let mut bytes = std::fs::read(image)?;
+    let Some(header) = bytes.first_chunk::<12>().filter(|h| h[..2] == *b"TD" || h[..2] == *b"td") else {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a TeleDisk image"));
+    };
+    if ImageHeader::from_bytes(header).crc_ok() {
+        println!("{}: header CRC ok, nothing to repair", image);
+        return Ok(true);
+    }
+    let repairs = header_repairs(header);
+    if repairs.is_empty() {
+        println!("{}: header CRC bad, and no single byte change to the data rate, stepping or sides makes it match", image);
+        return Ok(false);
+    }
+    println!("{}: header CRC bad, {} candidate fix{}:", image, repairs.len(), if repairs.len() == 1 { "" } else { "es" });
+    for (n, repair) in repairs.iter().enumerate() {
+        println!("    {} {} {:02x} -> {:02x}", n + 1, repair.field, repair.from, repair.to);
+    }
+    let Some(output) = output else { return Ok(true) };
+    let repair = match (pick, repairs.len()) {
+        (Some(n), _) => repairs.get(n.wrapping_sub(1))
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("there's no candidate {}", n)))?,
+        (None, 1) => &repairs[0],
+        (None, _) => return Err(io::Error::new(ErrorKind::InvalidInput, "several candidates, choose one with --pick")),
+    };
+    repair.apply(&mut bytes);
+    std::fs::write(output, &bytes)?;
+    println!("Wrote {} with {} {:02x} -> {:02x}", output, repair.field, repair.from, repair.to);
+    Ok(true)
+}
+
+// where the text of a comment that ran out of file ends and the tracks begin: the first place
+// before anything but text where a track header's CRC checks out, or the end of image marker
+pub(crate) fn comment_end(raw: &[u8]) -> usize {
+    let text = |b: u8| (0x20..=0x7e).contains(&b) || b"\0\t\r\n".contains(&b);
+    for (i, &b) in raw.iter().enumerate() {
+        let header = &raw[i..raw.len().min(i + 4)];
+        if b == 255 || (header.len() == 4 && header[0] > 0 && crc16(&header[..3]) as u8 == header[3]) {
+            return i;
         }
+        if !text(b) { break; }
     }
+    raw.len()
 }
 
-#[derive(Debug)]
-struct TeleDiskHeaders {
-    image_header: ImageHeader,              // Standard header
-    comment_header: Option<CommentHeader>,  // Optional comment header
+// the named groups of each pattern that matches, the first pattern to give a field winning
+fn comment_fields(text: &str, patterns: &[regex::Regex]) -> BTreeMap<String, String> {
+    let mut fields = BTreeMap::new();
+    for pattern in patterns {
+        let Some(captures) = pattern.captures(text) else { continue };
+        for name in pattern.capture_names().flatten() {
+            if let Some(value) = captures.name(name) {
+                fields.entry(name.to_string()).or_insert_with(|| value.as_str().trim().to_string());
+            }
+        }
+    }
+    fields
 }
 
-impl TeleDiskHeaders {
-    fn from_stream(file: &mut dyn Read) -> Self {
-        let mut header_bytes = [0; 12];
-        file.read_exact(&mut header_bytes).expect("Failed to read image header");
-        let image_header = ImageHeader::from_bytes(&header_bytes);
+// a flat image of sectors with no header, as converted from a TD0 or read straight from a drive,
+// analysed as the format its BPB or size suggests
+#[allow(clippy::too_many_arguments)]
+fn analyse_flat_image(args : &Args, scan: &mut Scan, img: &[u8],
+        typ: &str, file_path: &Path, container_name: Option<&str>, file_name: &str, provenance: &Provenance) {
+    let Some((cylinders, heads, sectors, sector_size)) = flat_geometry(img) else {
+        scan.invalid_images += 1;
+        log::info!("{} is {} bytes, which isn't the size of a disk format known", file_name, img.len());
+        if args.card {
+            image_println!(args, "{} is not a disk image of a known format", file_name);
+        }
+        return;
+    };
 
-        let mut comment_header = None;
+    let args = &*args.for_image(scan, file_path);
+    let td0_path = image_path(file_path, container_name, file_name);
+    if args.disk_image_info {
+        image_println!(args, "{}", args.paint(HEADER_COLOUR, &args.with_path(&format!("{} : flat image {} bytes, {} cylinders {} heads {} sectors of {} - ",
+            typ, img.len(), cylinders, heads, sectors, sector_size), &td0_path)));
+    }
+    let mut disk = flat_disk(img, cylinders, heads, sectors, sector_size);
+    disk.stored = img.len() as u64;
+    analyse_disk(args, scan, typ, file_path, td0_path, container_name, file_name, provenance,
+        ImageFormat::Flat, None, None, disk, ImageStatus::Ok);
+}
 
-        if image_header.has_comment_header() {
-            let mut comment_bytes = [0; 10];
-            file.read_exact(&mut comment_bytes).expect("Failed to read comment header");
-            comment_header = Some(CommentHeader::from_bytes(&comment_bytes));
-        }
+// a CPCEMU disk image, as Amstrad and Spectrum emulators use, standard or extended
+#[allow(clippy::too_many_arguments)]
+fn analyse_dsk_image(args : &Args, scan: &mut Scan, bytes: &[u8],
+        typ: &str, file_path: &Path, container_name: Option<&str>, file_name: &str, provenance: &Provenance) {
+    let args = &*args.for_image(scan, file_path);
+    let td0_path = image_path(file_path, container_name, file_name);
+    let (format, mut disk, status) = read_dsk(bytes);
+    disk.stored = bytes.len() as u64;
+    if args.disk_image_info {
+        let (kind, creator) = match &format {
+            ImageFormat::Edsk { creator } => ("EXTENDED CPC DSK", creator.as_str()),
+            ImageFormat::Dsk { creator } => ("MV - CPCEMU", creator.as_str()),
+            _ => ("", ""),
+        };
+        image_println!(args, "{}", args.paint(HEADER_COLOUR, &args.with_path(&format!("{} : {} '{}' {} tracks {} sides - ", typ, kind,
+            creator, bytes.get(0x30).unwrap_or(&0), bytes.get(0x31).unwrap_or(&0)), &td0_path)));
+    }
+    analyse_disk(args, scan, typ, file_path, td0_path, container_name, file_name, provenance, format, None, None, disk, status);
+}
 
-        TeleDiskHeaders {
-            image_header,
-            comment_header,
+// images joined one after another in the same file are read in turn, each labelled with its place
+// in the file. what was read of the file past one image's end is read first for the next
+#[allow(clippy::too_many_arguments)]
+fn analyze_teledisk_image_format_from_stream(
+        args : &Args, scan: &mut Scan, file: &mut dyn Read,
+        typ: &str, file_path: &Path, container_name: Option<&str>, file_name: &str, provenance: &Provenance) {
+    let mut carried = Vec::new();
+    for number in 1.. {
+        let mut image = io::Cursor::new(carried).chain(&mut *file);
+        match analyse_td0_image(args, scan, &mut image, typ, file_path, container_name, file_name, provenance, number) {
+            Some(next) => carried = next,
+            None => break,
         }
     }
 }
 
-#[derive(Debug)]
-struct ImageHeader {
-    signature: [u8; 2], // Signature to identify the file format
-    sequence: u8,       // Sequence number
-    _check_sequence: u8, // Check sequence
-    version: u8,        // Version of the disk image format
-    data_rate: u8,      // Data rate of the disk image
-    drive_type: u8,     // Type of the drive
-    stepping: u8,       // Stepping field
-    dos_flag: u8,       // DOS allocation flag
-    sides: u8,          // Number of sides
-    _crc: u16,          // CRC of the header
+// one of the images in a file, the first unless it follows another's end-of-image marker. when
+// another follows this one, the bytes of it already read are returned
+#[allow(clippy::too_many_arguments)]
+fn analyse_td0_image(args : &Args, scan: &mut Scan, file: &mut dyn Read,
+        typ: &str, file_path: &Path, container_name: Option<&str>, base_name: &str, provenance: &Provenance, number: usize) -> Option<Vec<u8>> {
+    let file = &mut CountingReader { inner: file, count: 0 };
+    let labelled = |number: usize| format!("{} #{}", base_name, number);
+    let name = if number > 1 { labelled(number) } else { base_name.to_string() };
+    let file_name = name.as_str();
+    let headers = match TeleDiskHeaders::from_stream(file) {
+        Ok(headers) => headers,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+            scan.invalid_images += 1;
+            log::info!("{} is too short for a TeleDisk header", file_name);
+            return None;
+        },
+        Err(e) => {
+            scan.io_error(&format!("Failed to read {}: {}", file_name, e));
+            return None;
+        },
+    };
+
+    if !headers.image_header.is_valid() {
+        scan.invalid_images += 1;
+        // the rest is read to tell what the file is instead
+        let header = &headers.image_header;
+        let mut bytes = [&header.crc_bytes()[..], &header.crc.to_le_bytes()].concat();
+        if let Err(e) = file.read_to_end(&mut bytes) {
+            scan.io_error(&format!("Failed to read {}: {}", file_name, e));
+            return None;
+        }
+        let sniffed = Sniffed::of(&bytes);
+        let diagnosis = format!("it starts {}, {}. {}", first_bytes(&bytes), sniffed.describe(), sniffed.suggestion(args.ignore_signature));
+        if args.card {
+            image_println!(args, "{} is not a TeleDisk image: {}", file_name, diagnosis);
+        } else {
+            log::warn!("{} is not a TeleDisk image: {}", image_path(file_path, container_name, file_name), diagnosis);
+        }
+        if args.ignore_signature {
+            match sniffed {
+                Sniffed::DamagedTd0 => {
+                    bytes[..2].copy_from_slice(b"TD");
+                    analyze_teledisk_image_format_from_stream(args, scan, &mut io::Cursor::new(bytes), typ, file_path, container_name, file_name, provenance);
+                },
+                Sniffed::Dsk => analyse_dsk_image(args, scan, &bytes, typ, file_path, container_name, file_name, provenance),
+                Sniffed::Flat => analyse_flat_image(args, scan, &bytes, typ, file_path, container_name, file_name, provenance),
+                _ => {},
+            }
+        }
+        None
+    } else if !args.header_wanted(&headers.image_header, headers.comment_header.as_ref()) {
+        log::debug!("Passing over {}, its header doesn't match", image_path(file_path, container_name, file_name));
+        scan.passed_over += 1;
+        None
+    } else {
+        let args = &*args.for_image(scan, file_path);
+        let mut td0_path = image_path(file_path, container_name, file_name);
+
+        let header_text = format!("{} : {}{} seq {:02x} ver {:02x} rate {:02x} type {:02x} oh {} step {:02x} dos {:02x} sides {:02x} - ",
+            typ, headers.image_header.signature[0] as char, headers.image_header.signature[1] as char,
+            headers.image_header.sequence, headers.image_header.version, headers.image_header.data_rate, headers.image_header.drive_type,
+            if headers.comment_header.is_some() { "O" } else { "-" },
+            headers.image_header.stepping & 0x7f, headers.image_header.dos_flag, headers.image_header.sides);
+        let header_line = |td0_path: &str| args.paint(HEADER_COLOUR, &args.with_path(&header_text, td0_path));
+        // the first image's header line waits, with what's printed after it, until it's known
+        // whether there are more
+        let held = args.disk_image_info && number == 1;
+        if held {
+            hold_image_lines();
+        } else if args.disk_image_info {
+            image_println!(args, "{}", header_line(&td0_path));
+        }
+
+        let mut comment = None;
+        let mut recovered = None;
+        let mut findings = header_findings(&headers.image_header);
+        if let Some(comment_header) = headers.comment_header {
+            let datetime = comment_timestamp(&comment_header);
+            if datetime.is_none() {
+                findings.push(finding!(Severity::Warning, "comment-date", Location::Comment,
+                    "the comment header's date {} can't be right", raw_comment_date(&comment_header)));
+            }
+
+            // now we read 'length' bytes which we will convert to an ascii string (it's padded with zeros)
+            // no room is made ahead for a length that may be bogus, only for the bytes really there
+            let mut raw = Vec::new();
+            if let Err(e) = (&mut *file).take(comment_header.length as u64).read_to_end(&mut raw) {
+                if held {
+                    release_image_lines(Some(format!("{}{}", args.id_prefix(), header_line(&td0_path))));
+                }
+                scan.io_error(&format!("Failed to read comment of {}: {}", td0_path, e));
+                return None;
+            }
+            // a length running past the end of the file is corrupt or forged. the tracks are most
+            // likely where the text stops, so they're read on from there
+            let truncated = raw.len() < comment_header.length as usize;
+            if truncated {
+                let start = comment_end(&raw);
+                findings.push(finding!(Severity::Warning, "comment-truncated", Location::Comment,
+                    "a {} byte comment but only {} bytes after the header, tracks read from byte {} of it",
+                    comment_header.length, raw.len(), start));
+                recovered = Some(io::Cursor::new(raw.split_off(start)));
+            }
+            let data = args.codepage.decode(&raw);
+            if args.comment_info {
+                image_println!(args, "    {} : {}", datetime.map_or_else(|| raw_comment_date(&comment_header), |t| t.to_string()), data);
+            }
+            let text = data.trim_end_matches('\0').to_string();
+            comment = Some(Comment {
+                timestamp: datetime,
+                fields: comment_fields(&text, &args.comment_pattern),
+                text,
+                crc_ok: !truncated && comment_header.crc_ok(&raw),
+                truncated,
+            });
+        }
+        if args.disk_image_info {
+            image_println!(args, "    {}", Toolchain::identify(&headers.image_header, comment.as_ref()).describe(args.language));
+        }
+        let member_size = provenance.containers.last().map(|m| m.size);
+        let tracks: &mut dyn Read = match &mut recovered {
+            Some(rest) => rest,
+            None => file,
+        };
+        let (mut disk, status, trailing, mut next) = analyse_track_and_sector_data(args, tracks, typ, &headers.image_header, &td0_path, member_size);
+        // a comment cut short was read to the end of the file, and the next image is in what's left of it
+        if let Some((next, rest)) = next.as_mut().zip(recovered.as_ref()) {
+            next.extend_from_slice(&rest.get_ref()[rest.position() as usize..]);
+        }
+        let next_len = next.as_ref().map_or(trailing, Vec::len);
+        disk.stored = file.count - next_len as u64;
+        findings.append(&mut disk.findings);
+        disk.findings = findings;
+        // the first of several is only known to be once the next is found
+        let name = if next.is_some() && number == 1 {
+            td0_path = image_path(file_path, container_name, &labelled(1));
+            labelled(1)
+        } else {
+            name.clone()
+        };
+        if held {
+            release_image_lines(Some(format!("{}{}", args.id_prefix(), header_line(&td0_path))));
+        }
+        analyse_disk(args, scan, typ, file_path, td0_path, container_name, &name, provenance,
+            ImageFormat::Td0, Some(headers.image_header), comment, disk, status);
+        next
+    }
 }
 
-impl ImageHeader {
-    fn from_bytes(bytes: &[u8]) -> Self {
-        assert!(bytes.len() == 12, "ImageHeader must be 12 bytes long");
+// everything after reading an image's sectors, whatever format they came in: directories,
+// filesystems and boot sectors, per image outputs, and the report
+#[allow(clippy::too_many_arguments)]
+fn analyse_disk(args: &Args, scan: &mut Scan, typ: &str, file_path: &Path, td0_path: String, container_name: Option<&str>, file_name: &str,
+        provenance: &Provenance, format: ImageFormat, header: Option<ImageHeader>, comment: Option<Comment>, mut disk: Disk, status: ImageStatus) {
+    // look at the disk to see if there are directory structures etc, unless --sector-analysis off.
+    // with only -d asked for, the sectors' data wasn't decoded and there's none to look at
+    let headers_only = args.headers_only();
+    let analyse = args.sector_analysis == SectorAnalysis::On && !headers_only;
+    let mut detectors = args.detectors();
+    let dent_counts = if analyse { analyse_directories(args, &disk, &mut detectors) } else { Vec::new() };
+    let stem = scan.output_stem(&report_file_stem(args.image_id.as_deref().unwrap_or(&td0_path)));
+
+    if !args.quiet {
+        for &(c, h, s) in &args.dump {
+            match disk.sector(c, h, s) {
+                Some(sector) => {
+                    image_println!(args, "Cylinder {} head {} sector {} of '{}' ({} bytes, flags {:02x}):", c, h, s, td0_path, sector.data.len(), sector.header.flags);
+                    print_hex_dump(args, &sector.data);
+                },
+                None => image_println!(args, "No cylinder {} head {} sector {} in '{}'", c, h, s, td0_path),
+            }
+        }
+    }
 
-        let signature = [bytes[0], bytes[1]]; // Extract signature
-        let sequence = bytes[2];
-        let _check_sequence = bytes[3];
-        let version = bytes[4];
-        let data_rate = bytes[5];
-        let drive_type = bytes[6];
-        let stepping = bytes[7];
-        let dos_flag = bytes[8];
-        let sides = bytes[9];
-        let _crc = u16::from_le_bytes([bytes[10], bytes[11]]); // Extract CRC
+    if args.map && args.shows_detail() && !disk.tracks.is_empty() {
+        print_sector_map(args, &disk, &td0_path);
+    }
 
-        ImageHeader {
-            signature,
-            sequence,
-            _check_sequence,
-            version,
-            data_rate,
-            drive_type,
-            stepping,
-            dos_flag,
-            sides,
-            _crc,
+    let mut map_png = None;
+    if let Some(dir) = &args.map_png {
+        if !disk.tracks.is_empty() {
+            let png_path = Path::new(dir).join(format!("{}.png", stem));
+            match write_map_png(&disk, &png_path) {
+                Ok(()) => map_png = Some(png_path),
+                Err(e) => scan.io_error(&format!("Failed to write {}: {}", png_path.to_string_lossy(), e)),
+            }
         }
     }
 
-    // Method to check if a comment header is present
-    fn has_comment_header(&self) -> bool {
-        self.stepping & 0x80 == 0x80
+    if let Some(dir) = &args.export_img {
+        if !disk.tracks.is_empty() {
+            let img_path = Path::new(dir).join(format!("{}.img", stem));
+            if let Err(e) = write_img_export(&disk, header.as_ref(), comment.as_ref(), &td0_path, &img_path) {
+                scan.io_error(&format!("Failed to write {}: {}", img_path.to_string_lossy(), e));
+            }
+        }
     }
 
-    // Optionally, you can add a method to validate the signature
-    fn is_valid(&self) -> bool {
-        self.signature == [0x54, 0x44] // Example signature check
+    if let Some(dir) = &args.export_hfe {
+        if !disk.tracks.is_empty() {
+            let hfe_path = Path::new(dir).join(format!("{}.hfe", stem));
+            if let Err(e) = write_hfe(&disk, header.as_ref(), &hfe_path) {
+                scan.io_error(&format!("Failed to write {}: {}", hfe_path.to_string_lossy(), e));
+            }
+        }
     }
-}
 
-#[derive(Debug)]
-struct CommentHeader {
-    _crc: u16,       // 16-bit CRC of the comment header
-    length: u16,     // Length of the comment
-    year: u8,        // Year of the comment
-    month: u8,       // Month of the comment
-    day: u8,         // Day of the comment
-    hour: u8,        // Hour of the comment
-    minute: u8,      // Minute of the comment
-    second: u8,      // Second of the comment
-}
+    if let Some(dir) = &args.dump_sectors {
+        if !disk.tracks.is_empty() {
+            let dir = Path::new(dir).join(&stem);
+            match write_sector_files(&disk, &dir, args.whole_tracks) {
+                Ok(written) => log::info!("Wrote {} sectors of {} into {}", written, td0_path, dir.to_string_lossy()),
+                Err(e) => scan.io_error(&format!("Failed to write sectors into {}: {}", dir.to_string_lossy(), e)),
+            }
+        }
+    }
 
-impl CommentHeader {
-    fn from_bytes(bytes: &[u8]) -> Self {
-        assert!(bytes.len() == 10, "CommentHeader must be 10 bytes long");
-
-        let _crc = u16::from_le_bytes([bytes[0], bytes[1]]);
-        let length = u16::from_le_bytes([bytes[2], bytes[3]]);
-        let year = bytes[4];
-        let month = bytes[5];
-        let day = bytes[6];
-        let hour = bytes[7];
-        let minute = bytes[8];
-        let second = bytes[9];
-
-        CommentHeader {
-            _crc,
-            length,
-            year,
-            month,
-            day,
-            hour,
-            minute,
-            second,
+    if let Some(dir) = args.extract.as_ref().filter(|_| analyse) {
+        if !disk.fat_volumes().is_empty() {
+            let dir = Path::new(dir).join(&stem);
+            match extract_fat_files(&disk, &dir, args.undelete, args.codepage) {
+                Ok(extracted) => {
+                    log::info!("Extracted {} files and undeleted {} from {} into {}", extracted.files, extracted.undeleted,
+                        td0_path, dir.to_string_lossy());
+                    for name in &extracted.reused {
+                        log::warn!("Undeleted {} from {}, but some of its clusters are now another file's", name, td0_path);
+                    }
+                },
+                Err(e) => scan.io_error(&format!("Failed to extract files into {}: {}", dir.to_string_lossy(), e)),
+            }
         }
     }
-}
 
-#[derive(Debug)]
-struct TrackHeader {
-    number_of_sectors: u8,  // Number of sectors in the track
-    cylinder_number: u8,    // Cylinder number of the track
-    side_number: u8,        // Side number of the track
-}
+    let boot = if analyse { disk.boot_sector() } else { None };
+    let partitions = if analyse { disk.partitions() } else { Vec::new() };
+    let detection = if analyse { detect_filesystem(&disk, &detectors, &dent_counts, args.codepage) } else { None };
+    let filesystem = detection.as_ref().map(|d| d.filesystem);
+    let (label, files) = detection.map_or((None, Vec::new()), |d| (d.label, d.files));
+    let amstrad = if analyse { disk.amstrad_format(filesystem) } else { None };
+    if args.analyse_first_tracks && analyse {
+        if let Some(boot) = &boot {
+            image_println!(args, "    {}", boot.describe(args.language));
+            print_partitions(args, &disk, &partitions, &files);
+        }
+        if let Some(system) = disk.trdos_system(args.codepage) {
+            image_println!(args, "    {}", system.describe(args.language));
+        }
+        if let Some(amstrad) = &amstrad {
+            image_println!(args, "    {}", amstrad.describe(args.language));
+        }
+        if let Some(catalogue) = disk.apple_catalogue() {
+            image_println!(args, "    {}", catalogue.describe(args.language));
+        }
+    }
 
-impl TrackHeader {
-    fn from_bytes(bytes: &[u8]) -> Self {
-        assert!(bytes.len() == 4, "TrackHeader must be 4 bytes long");
+    let geometry = disk.geometry();
+    let sector_counts = disk.sector_counts();
+    let content = if analyse { disk.content_counts() } else { ContentCounts::default() };
+    let health = health_score(&disk, status, header.as_ref(), comment.as_ref(), &geometry, &sector_counts);
+    if args.disk_image_info && !disk.tracks.is_empty() {
+        image_println!(args, "    {}", geometry.describe(args.language));
+        image_println!(args, "    {} {} - {}", tr(args.language, "health"), health, sector_counts.describe(args.language));
+        if analyse {
+            image_println!(args, "    {}", content.describe(args.language));
+        }
+    }
+    let track_repeats = if headers_only { TrackRepeats::default() } else { disk.track_repeats() };
+    let protection = disk.protection(&track_repeats);
+    let interleave = (!matches!(format, ImageFormat::Flat) && !disk.tracks.is_empty()).then(|| disk.interleave());
+    let compression = Compression { stored: disk.stored, decoded: geometry.capacity, encodings: disk.encodings };
+    if args.disk_image_info {
+        for line in track_repeats.describe(args.language) {
+            image_println!(args, "    {}", line);
+        }
+        if let Some(interleave) = &interleave {
+            image_println!(args, "    {}", interleave.describe(args.language));
+        }
+        if !disk.tracks.is_empty() {
+            image_println!(args, "    {}", compression.describe(args.language));
+        }
+        if protection.likelihood > 0 {
+            image_println!(args, "    {}", protection.describe(args.language));
+        }
+    }
 
-        let number_of_sectors = bytes[0];
-        let cylinder_number = bytes[1];
-        let side_number = bytes[2];
+    if let Some(name) = boot.as_ref().and_then(|b| b.loader).filter(|l| l.virus).map(|l| l.name) {
+        if let Some((track, sector)) = disk.logical_track_sectors().first() {
+            let location = Location::Sector { cylinder: track.cylinder, head: track.header.side_number, sector: sector.header.sector_number };
+            disk.findings.push(finding!(Severity::Warning, "boot-virus", location, "the boot sector is the {} virus", name));
+        }
+    }
 
-        TrackHeader {
-            number_of_sectors,
-            cylinder_number,
-            side_number,
+    let fat_usage = if analyse { disk.fat_usage() } else { Vec::new() };
+    for usage in &fat_usage {
+        if args.analyse_first_tracks {
+            image_println!(args, "    {}", usage.describe(args.language));
+        }
+        if usage.lost_chains > 0 {
+            disk.findings.push(finding!(Severity::Warning, "lost-chains", Location::Filesystem { partition: usage.partition },
+                "{} chains of {} clusters in use that no file or directory leads to", usage.lost_chains, usage.lost_clusters));
+        }
+        if usage.bad > 0 {
+            disk.findings.push(finding!(Severity::Info, "bad-clusters", Location::Filesystem { partition: usage.partition },
+                "{} clusters marked bad", usage.bad));
         }
     }
-}
 
-#[derive(Debug)]
-struct SectorHeader {
-    cylinder_number: u8,      // Cylinder number of the sector
-    side_number: u8,          // Side number of the sector
-    sector_number: u8,        // Sector number
-    // raw_sector_size: u8,      // Raw sector size (exponent)
-    sector_size: u16,         // Actual size of the sector (128 << raw_sector_size)
-    flags: u8,                // Flags associated with the sector
-}
+    let irregular = disk.irregular_tracks();
+    disk.findings.extend(irregular);
 
-impl SectorHeader {
-    fn from_bytes(bytes: &[u8]) -> Self {
-        assert!(bytes.len() == 6, "SectorHeader must be 6 bytes long");
+    for finding in &disk.findings {
+        log::info!("{}: {}", td0_path, finding.describe(args.language));
+        if args.shows_detail() {
+            image_println!(args, "    {}", args.paint(FLAG_COLOUR, &finding.describe(args.language)));
+        }
+    }
 
-        let cylinder_number = bytes[0];
-        let side_number = bytes[1];
-        let sector_number = bytes[2];
-        let raw_sector_size = bytes[3];
-        let flags = bytes[4];
-        let sector_size = 128 << raw_sector_size; // Calculate the actual size
+    let sectors = disk.tracks.iter().map(|t| t.sectors.len()).sum();
+    if args.summary_only {
+        let t = |english| tr(args.language, english);
+        image_println!(args, "{}", args.with_path(&format!("{} : {} - {} {} {} {} {} {} - ", typ, t(&status.to_string()), disk.tracks.len(), t("tracks"),
+            sectors, t("sectors"), t("health"), health), &td0_path));
+    } else if status != ImageStatus::Ok && !args.quiet {
+        image_println!(args, "{}", args.paint(ERROR_COLOUR, &args.with_path(&format!("{} : {} - ", typ, tr(args.language, &status.to_string())), &td0_path)));
+    }
 
-        SectorHeader {
-            cylinder_number,
-            side_number,
-            sector_number,
-            // raw_sector_size,
-            sector_size,
-            flags,
+    let grep_matches = grep_disk(args, &disk);
+
+    let checksums = if headers_only { None } else { disk.checksums() };
+    // a member's path is in the archive's own separators, whatever the system's
+    let (member_directory, name) = file_name.rsplit_once('/').unwrap_or(("", file_name));
+    let mut report = ImageReport {
+        path: td0_path,
+        id: args.image_id.clone(),
+        source: typ.to_string(),
+        container: container_name.map(str::to_string),
+        directory: image_path(file_path, container_name, member_directory),
+        name: name.to_string(),
+        format,
+        toolchain: header.as_ref().map(|h| Toolchain::identify(h, comment.as_ref())),
+        header,
+        sort_keys: SortKeys::of(name, label.as_deref(), comment.as_ref()),
+        provenance: provenance.scanned(comment.as_ref()),
+        comment,
+        label,
+        files,
+        filesystem,
+        content_hash: checksums.as_ref().map(|c| c.sha1.clone()),
+        checksums,
+        match_hash: if args.hash_exclude.is_empty() { None } else { disk.content_hash(&args.hash_exclude) },
+        status,
+        track_hashes: if args.find_similar.is_some() { disk.track_hashes() } else { Vec::new() },
+        sector_map: if args.report_html.is_some() {
+            disk.sector_map().into_iter().map(|(track, statuses)| ((track.cylinder, track.header.side_number), statuses)).collect()
+        } else {
+            Vec::new()
+        },
+        map_png,
+        tracks: disk.tracks.len(),
+        sectors,
+        geometry,
+        sector_counts,
+        content,
+        protection,
+        interleave,
+        compression,
+        track_repeats,
+        boot,
+        amstrad,
+        disk_set: None,
+        partitions,
+        fat_usage,
+        health,
+        findings: disk.findings.clone(),
+        shown: false,
+        #[cfg(feature = "browse")]
+        disk: if args.keep_disks { Some(disk) } else { None },
+    };
+    report.shown = args.only.iter().all(|filter| filter.matches(&report));
+    if report.shown {
+        for line in grep_matches {
+            image_println!(args, "{}: {}", report.path, line);
+        }
+    }
+    if args.card && report.shown {
+        let card = report.card(args.language);
+        // the path is the card's first line
+        let (path, rest) = card.split_once('\n').unwrap_or((&card, ""));
+        image_println!(args, "{}", args.paint(HEADER_COLOUR, &args.with_path("", path)));
+        for line in rest.lines() {
+            image_println!(args, "{}", line);
+        }
+    }
+    if let Some(dir) = args.out_dir.as_ref().filter(|_| report.shown) {
+        let (extension, contents) = match args.out_format {
+            OutFormat::Text => ("txt", report.card(args.language)),
+            OutFormat::Json => ("json", serde_json::to_string_pretty(&report).unwrap_or_default() + "\n"),
+            OutFormat::Output => ("txt", IMAGE_OUTPUT.with_borrow_mut(|lines| lines.as_mut().map(std::mem::take)).unwrap_or_default()
+                .iter().map(|line| format!("{}\n", line)).collect()),
+        };
+        let report_path = Path::new(dir).join(format!("{}.{}", stem, extension));
+        if let Err(e) = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&report_path, contents)) {
+            scan.io_error(&format!("Failed to write {}: {}", report_path.to_string_lossy(), e));
+        }
+    }
+    if let Some((dir, comment)) = args.export_comments.as_ref().zip(report.comment.as_ref()).filter(|_| report.shown) {
+        let comment_path = Path::new(dir).join(format!("{}.comment.txt", stem));
+        if let Err(e) = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&comment_path, comment.to_text())) {
+            scan.io_error(&format!("Failed to write {}: {}", comment_path.to_string_lossy(), e));
         }
     }
+    // lines about an image left out by --only go nowhere
+    if args.writes_output() && !report.shown {
+        IMAGE_OUTPUT.with_borrow_mut(|lines| lines.as_mut().map(Vec::clear));
+    }
+    scan.reports.push(report);
 }
 
-fn analyze_teledisk_image_format_from_stream(
-        args : &Args, file: &mut dyn Read,
-        typ: &str, file_path: &str, container_name: Option<&str>, file_name: &str) {
-    let headers = TeleDiskHeaders::from_stream(file);
+// with --out-format output, the lines about a file that weren't about any image in it
+fn write_image_output(scan: &mut Scan, dir: &str, path: &str, lines: &[String]) {
+    let output_path = Path::new(dir).join(format!("{}.txt", scan.output_stem(&report_file_stem(path))));
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    if let Err(e) = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&output_path, text)) {
+        scan.io_error(&format!("Failed to write {}: {}", output_path.to_string_lossy(), e));
+    }
+}
 
-    if headers.image_header.is_valid() {
-        // build the full path from file_path, container name if there's a container, and file_name
-        let mut parts = Vec::new();
-        parts.push(file_path.to_string());
-        if let Some(container) = container_name {
-            parts.push(container.to_string());
+// a line per partition, then for FAT partitions the files in its root directory
+fn print_partitions(args: &Args, disk: &Disk, partitions: &[Partition], files: &[FileEntry]) {
+    let volumes = disk.fat_volumes();
+    for partition in partitions {
+        let volume = volumes.iter().find(|v| v.partition == Some(partition.index));
+        image_println!(args, "    partition {}{} type {:02x} ({}) start {} sectors {} CHS {}/{}/{}-{}/{}/{} - {}", partition.index,
+            if partition.bootable { " bootable" } else { "" }, partition.kind, partition.kind_name, partition.start, partition.sectors,
+            partition.first.0, partition.first.1, partition.first.2, partition.last.0, partition.last.1, partition.last.2,
+            match volume {
+                Some(volume) => format!("FAT{}", disk.fat_volume_label(volume, args.codepage).map(|l| format!(" '{}'", l)).unwrap_or_default()),
+                None if partition.is_extended() => "logical partitions follow".to_string(),
+                None => "no filesystem recognised".to_string(),
+            });
+        for file in files.iter().filter(|f| f.partition == Some(partition.index)) {
+            image_println!(args, "        {}", file.listing(args.language));
         }
-        parts.push(file_name.to_string());
-        let td0_path = parts.join("/");
+    }
+}
 
-        if args.disk_image_info {
-            println!("{} : {}{} seq {:02x} ver {:02x} rate {:02x} type {:02x} oh {} step {:02x} dos {:02x} sides {:02x} - {}",
-                typ, headers.image_header.signature[0] as char, headers.image_header.signature[1] as char,
-                headers.image_header.sequence, headers.image_header.version, headers.image_header.data_rate, headers.image_header.drive_type,
-                if headers.comment_header.is_some() { "O" } else { "-" },
-                headers.image_header.stepping & 0x7f, headers.image_header.dos_flag, headers.image_header.sides, td0_path);
+// an image in an archive is read no further than the size its member declares, so running out of
+// data there means the image's structure doesn't fit its member rather than a short file. what
+// follows the end-of-image marker is read too, and how much there was returned. when it's the
+// header of another image, only the header is read, and given back to read the image from
+fn analyse_track_and_sector_data(args : &Args, file: &mut dyn Read, typ: &str, header: &ImageHeader, td0_path: &str,
+        member_size: Option<u64>) -> (Disk, ImageStatus, usize, Option<Vec<u8>>) {
+    let mut disk = Disk::default();
+    let status = match read_tracks(args, file, typ, header, td0_path, &mut disk) {
+        Ok(()) if disk.tracks.is_empty() => ImageStatus::EmptyDump,
+        Ok(()) => ImageStatus::Ok,
+        // a header with nothing after it is what an aborted dump leaves behind
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof && disk.tracks.is_empty() => ImageStatus::EmptyDump,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => match member_size {
+            Some(size) => {
+                log::error!("{} runs past the end of its {} byte archive member", td0_path, size);
+                ImageStatus::Overrun
+            },
+            None => ImageStatus::Truncated,
+        },
+        Err(e) => {
+            log::error!("Failed to read {}: {}", td0_path, e);
+            ImageStatus::ReadError
+        },
+    };
+
+    if matches!(status, ImageStatus::Truncated | ImageStatus::Overrun) {
+        if let Some(finding) = recovered_finding(&disk) {
+            disk.findings.push(finding);
+        }
+    }
+    if status == ImageStatus::Ok {
+        // see if there are any trailing bytes, and if they're another image that can be read
+        let mut start = Vec::with_capacity(12);
+        let peeked = (&mut *file).take(12).read_to_end(&mut start);
+        let next = start.get(..12).map(ImageHeader::from_bytes).filter(|h| h.crc_ok() && h.is_valid());
+        if peeked.is_ok() && next.is_some() {
+            return (disk, status, start.len(), Some(start));
+        }
+        let more = Trailing::read(&mut io::Cursor::new(start).chain(file)).unwrap_or_else(|(e, more)| {
+            log::debug!("Failed to read past the end of {}: {}", td0_path, e);
+            more
+        });
+        if more.len > 0 {
+            disk.findings.push(trailing_finding(&more));
         }
+        return (disk, status, more.len, None);
+    }
 
-        if let Some(comment_header) = headers.comment_header {
-            let date = NaiveDate::from_ymd_opt((comment_header.year as i32) + 1900, (comment_header.month as u32) + 1, comment_header.day as u32).unwrap();
-            let time = NaiveTime::from_hms_opt(comment_header.hour as u32, comment_header.minute as u32, comment_header.second as u32).unwrap();
-            let datetime = NaiveDateTime::new(date, time);
+    (disk, status, 0, None)
+}
 
-            // now we read 'length' bytes which we will convert to an ascii string (it's padded with zeros)
-            let mut data = vec![0; comment_header.length as usize];
-            file.read_exact(&mut data).expect("Failed to read data");
-            let data = String::from_utf8_lossy(&data).to_string();
-            if args.comment_info {
-                println!("    {} : {}", datetime, data);
-            }
+// how much of an image that stops early was read, and where it stopped
+fn recovered_finding(disk: &Disk) -> Option<Finding> {
+    let last = disk.tracks.last()?;
+    let sectors: usize = disk.tracks.iter().map(|t| t.sectors.len()).sum();
+    let cut_short = last.sectors.len() < last.header.number_of_sectors as usize;
+    // the words counted are filled in like numbers so they're translated along with the template
+    let tracks = if disk.tracks.len() == 1 { "track" } else { "tracks" };
+    let sector_word = if sectors == 1 { "sector" } else { "sectors" };
+    let location = Location::Track { cylinder: last.cylinder, head: last.header.side_number };
+    Some(if cut_short {
+        finding!(Severity::Warning, "truncated", location, "{} {} and {} {} were read before the data ran out, {} of the {} sectors of the last one",
+            disk.tracks.len(), tracks, sectors, sector_word, last.sectors.len(), last.header.number_of_sectors)
+    } else {
+        finding!(Severity::Warning, "truncated", location, "{} {} and {} {} were read before the data ran out, after the last one",
+            disk.tracks.len(), tracks, sectors, sector_word)
+    })
+}
+
+// what follows the end-of-image marker, read through in pieces so a TD0 at the start of a huge
+// file or archive member takes no more memory than any other. only its start is kept
+#[derive(Default)]
+struct Trailing {
+    len: usize,
+    start: Vec<u8>,     // the first TRAILING_KEPT bytes
+    uniform: bool,      // every byte the same as the first
+}
+
+const TRAILING_KEPT: usize = 64;
+
+impl Trailing {
+    // what was read before any error is given back with it
+    fn read(file: &mut dyn Read) -> Result<Self, (io::Error, Self)> {
+        let mut more = Trailing { uniform: true, ..Default::default() };
+        let mut buffer = [0; 8192];
+        loop {
+            let n = match file.read(&mut buffer) {
+                Ok(0) => return Ok(more),
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err((e, more)),
+            };
+            let piece = &buffer[..n];
+            let kept = piece.len().min(TRAILING_KEPT - more.start.len());
+            more.start.extend_from_slice(&piece[..kept]);
+            more.uniform &= piece.iter().all(|&b| b == more.start[0]);
+            more.len += n;
         }
-        analyse_track_and_sector_data(args, file, typ, headers.image_header, td0_path);
     }
 }
 
-fn analyse_track_and_sector_data(args : &Args, file: &mut dyn Read, typ: &str, header: ImageHeader, td0_path: String) {
-    for t in 0.. {
-        let mut track = [0; 4];
-        file.read_exact(&mut track).expect("Failed to read track info");
-        let th = TrackHeader::from_bytes(&track);
+// what follows the end-of-image marker: another image joined on, padding out a block, or anything else
+fn trailing_finding(more: &Trailing) -> Finding {
+    let header = more.start.get(..12).map(ImageHeader::from_bytes);
+    if let Some(header) = header.filter(|h| h.crc_ok() && (h.is_valid() || h.signature == *b"td")) {
+        return if header.is_valid() {
+            finding!(Severity::Warning, "trailing-td0", Location::AfterEnd, "{} bytes that start another TeleDisk image, volume {}",
+                more.len, header.sequence)
+        } else {
+            finding!(Severity::Warning, "trailing-td0", Location::AfterEnd,
+                "{} bytes that start another TeleDisk image with advanced compression, volume {}", more.len, header.sequence)
+        };
+    }
+    if more.uniform {
+        return finding!(Severity::Info, "trailing-padding", Location::AfterEnd, "{} bytes of {} padding", more.len, format!("{:02x}", more.start[0]));
+    }
+    finding!(Severity::Warning, "trailing-bytes", Location::AfterEnd, "{} bytes: {}{}",
+        more.len, more.start.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" "),
+        if more.start.len() < more.len { " ..." } else { "" })
+}
+
+// read tracks up to the end-of-image marker, adding each to the disk as it's read so whatever was
+// read before an error is kept, even of the track it cut short
+fn read_tracks(args : &Args, file: &mut dyn Read, typ: &str, header: &ImageHeader, td0_path: &str, disk: &mut Disk) -> io::Result<()> {
+    // hard disks can have more than 256 cylinders, whose numbers wrap in the track header's byte.
+    // tracks are imaged in order, so a big drop in cylinder number means it has wrapped
+    let mut cylinder_base = 0;
+    let mut last_cylinder_number = 0u8;
+
+    let tracks = if args.headers_only() { td0::Tracks::skipping_data(file) } else { td0::Tracks::new(file) };
+    for (t, track) in tracks.enumerate() {
+        let (track, error) = match track {
+            Ok(track) => (track, None),
+            Err(td0::TrackError { error, partial: Some(track) }) => (track, Some(error)),
+            Err(e) => return Err(e.into()),
+        };
+        let th = &track.header;
 
-        if th.number_of_sectors == 255 { break; }
+        if !track.crc_ok {
+            disk.encodings.track_headers_failed += 1;
+            log::debug!("Track {} of {} fails its header CRC", t, td0_path);
+        }
 
         if args.track_info {
-            println!("{} sectors, cylinder #{}, side/head #{}", th.number_of_sectors, th.cylinder_number, th.side_number);
+            image_println!(args, "{}", args.paint(TRACK_COLOUR, &format!("{} sectors, cylinder #{}, side/head #{}", th.number_of_sectors, th.cylinder_number, th.side_number)));
         }
 
-        for s in 0..th.number_of_sectors {
-            let mut sect = [0; 6];
-            file.read_exact(&mut sect).expect("Failed to read sector info");
-            let sh = SectorHeader::from_bytes(&sect);
+        if th.cylinder_number < last_cylinder_number && last_cylinder_number - th.cylinder_number > 128 {
+            cylinder_base += 256;
+            disk.findings.push(finding!(Severity::Info, "cylinder-wrap",
+                Location::Track { cylinder: cylinder_base + th.cylinder_number as usize, head: th.side_number },
+                "the track header says cylinder {}, taken as past cylinder 255", th.cylinder_number));
+        }
+        last_cylinder_number = th.cylinder_number;
+        let cylinder = cylinder_base + th.cylinder_number as usize;
+
+        let mut sectors = Vec::with_capacity(th.number_of_sectors as usize);
+        for (s, sector) in track.sectors().enumerate() {
+            let sh = &sector.header;
 
             if args.sector_info {
+                let quirk = sh.id_quirk().map_or_else(String::new, |q| format!(" {}", args.paint(FLAG_COLOUR, q.code())));
+                // flags other than none stand out
+                let flags = match sh.flags {
+                    0 => "00".to_string(),
+                    flags => args.paint(FLAG_COLOUR, &format!("{:02x}", flags)),
+                };
                 // new disk image: image info, track info, sector info
                 if t == 0 && s == 0 {
-                    println!("{} : {}{} seq {:02x} ver {:02x} rate {:02x} type {:02x} oh {} step {:02x} dos {:02x} sides {:02x} \
-                                - [n{} c{:3} h{}] [c{:3} h{} s{} z{} f{:02x}] - {}",
+                    image_println!(args, "{}", args.with_path(&format!("{} : {}{} seq {:02x} ver {:02x} rate {:02x} type {:02x} oh {} step {:02x} dos {:02x} sides {:02x} \
+                                - [n{} c{:3} h{}] [c{:3} h{} s{} z{} f{}]{} - ",
                         typ, header.signature[0] as char, header.signature[1] as char,
                         header.sequence, header.version, header.data_rate, header.drive_type,
                         if header.stepping & 0x80 == 0x80 { "O" } else { "-" },
                         header.stepping & 0x7f, header.dos_flag, header.sides,
                         th.number_of_sectors, th.cylinder_number, th.side_number,
-                        sh.cylinder_number, sh.side_number, sh.sector_number, sh.sector_size, sh.flags,
-                        td0_path
-                    );
+                        sh.cylinder_number, sh.side_number, sh.sector_number, sh.sector_size, flags, quirk), td0_path));
                 // sector 0 means new track: track info, sector info
                 } else if s == 0 {
-                    println!("{: ^68}[n{} c{:3} h{}] [c{:3} h{} s{} z{} f{:02x}]",
-                        "", th.number_of_sectors, th.cylinder_number, th.side_number, sh.cylinder_number, sh.side_number, sh.sector_number, sh.sector_size, sh.flags);
+                    image_println!(args, "{: ^68}[n{} c{:3} h{}] [c{:3} h{} s{} z{} f{}]{}",
+                        "", th.number_of_sectors, th.cylinder_number, th.side_number, sh.cylinder_number, sh.side_number, sh.sector_number, sh.sector_size, flags, quirk);
                 // all other sectors
                 } else {
-                    println!("{: ^81}[c{:3} h{} s{} z{} f{:02x}]",
-                        "", sh.cylinder_number, sh.side_number, sh.sector_number, sh.sector_size, sh.flags);
+                    image_println!(args, "{: ^81}[c{:3} h{} s{} z{} f{}]{}",
+                        "", sh.cylinder_number, sh.side_number, sh.sector_number, sh.sector_size, flags, quirk);
                 }
             }
 
-            // data block
-            let mut dblen = [0; 2];
-            file.read_exact(&mut dblen).expect("Failed to read data block length");
-            let dblen = u16::from_le_bytes(dblen);
-            let mut datablock = vec![0; dblen as usize];
-            file.read_exact(&mut datablock).expect("Failed to read data block");
+            if !sh.has_data() {
+                disk.encodings.no_data += 1;
+                sectors.push(vec![0; sh.sector_size as usize]);
+                continue;
+            }
 
-            let should_analyse_sector = true;
+            log::trace!("Track {} Sector {}->{} of '{}'", t, s, sh.sector_number, td0_path);
 
-            if should_analyse_sector  {
-                if !args.verbose {
-                    println!("Track {} Sector {}->{} of '{}'", t, s, sh.sector_number, td0_path);
-                }
-
-                // decode this sector of the td0 image into raw sector data
-                let decoded = decode_td0(datablock[0], &datablock[1..], sh.sector_size);
-                
-                // look at the sector to see if there are directory structures etc
-                analyse_raw_sector(args, &decoded);
+            // decode this sector of the td0 image into raw sector data
+            let location = Location::Sector { cylinder, head: th.side_number, sector: sh.sector_number };
+            if let Some(len) = sector.oversized() {
+                disk.findings.push(finding!(Severity::Warning, "oversized-block", location.clone(),
+                    "the data block says it's {} bytes, more than a sector of {} bytes should take, and only its start is decoded", len, sh.sector_size));
+            }
+            let decoded = match sector.fitted_data() {
+                Ok((decoded, None)) => decoded,
+                // read by its length without being decoded, with only -d asked for
+                Err(DecodeError::Skipped) => Vec::new(),
+                Ok((decoded, Some(len))) => {
+                    disk.findings.push(if len > decoded.len() {
+                        finding!(Severity::Warning, "wrong-size", location,
+                            "the data block decodes to more than the sector's {} bytes, and is cut to fit", decoded.len())
+                    } else {
+                        finding!(Severity::Warning, "wrong-size", location,
+                            "the data block decodes to {} of the sector's {} bytes, and is padded with zeros", len, decoded.len())
+                    });
+                    decoded
+                },
+                Err(e) => {
+                    // a cut block running out partway through a run is the cut, which is a finding already
+                    if sector.oversized().is_none() || !matches!(e, DecodeError::Truncated) {
+                        disk.findings.push(match e {
+                            DecodeError::UnknownEncoding(method) => finding!(Severity::Warning, "unknown-encoding", location,
+                                "encoding method {} isn't raw, repeated or RLE", format!("{:02x}", method)),
+                            DecodeError::WrongSize(len) => finding!(Severity::Warning, "undecodable", location,
+                                "the data block decodes to {} bytes", len),
+                            _ => finding!(Severity::Warning, "undecodable", location, "the data block stops partway through a run"),
+                        });
+                    }
+                    // there's no telling what its data is, so it's left blank
+                    disk.encodings.undecodable += 1;
+                    sectors.push(vec![0; sh.sector_size as usize]);
+                    continue;
+                },
+            };
+            // a skipped block's CRC can't be checked
+            let failed = !decoded.is_empty() && !sector.crc_ok(&decoded);
+            let e = &mut disk.encodings;
+            let (count, failures) = match sector.encoding() {
+                Some(0) => (&mut e.raw, &mut e.raw_failed),
+                Some(1) => (&mut e.repeated, &mut e.repeated_failed),
+                _ => (&mut e.rle, &mut e.rle_failed),
+            };
+            *count += 1;
+            if failed {
+                *failures += 1;
+                log::debug!("Track {} sector {} of {} fails its data CRC", t, sh.sector_number, td0_path);
             }
+            sectors.push(decoded);
+        }
+
+        let (header, read) = track.into_parts();
+        let sectors = read.into_iter().zip(sectors).map(|(sector, data)| Sector { header: sector.header, data }).collect();
+        disk.tracks.push(Track { header, cylinder, sectors });
+        if let Some(error) = error {
+            return Err(error);
         }
     }
 
-    // see if there are any trailing bytes
-    let mut more = [0; 64];
-    let r = file.read(&mut more).expect("Failed to read more");
-    if r != 0 { println!("Read {} more bytes: 0x{:x?}", r, &more[0..r]); }
+    Ok(())
 }
 
-// turn td0 data for one sector into raw sector data
-fn decode_td0(encoding_method: u8, mut input: &[u8], sector_size: u16) -> Vec<u8> {
-    let mut output = vec![0; 0 as usize];
-    match encoding_method {
-        2 => { // RLE encoding
-            while input.len() > 1 {
-                let (a, b) = (input[0] as usize, input[1] as usize);
+// a file name standing for an image's ID with --ids, otherwise its path, for per-image output files
+fn report_file_stem(td0_path: &str) -> String {
+    td0_path.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect()
+}
 
-                let (count, len) = if a == 0 {
-                    (1, b)
-                } else {
-                    (b, a * 2)
-                };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fs::Codepage, import::write_td0};
 
-                for _ in 0..count {
-                    output.extend_from_slice(&input[2..2 + len]);
-                }
-                input = &input[2 + len..]; // Move the input pointer forward
-            }
-        },
-        0 => { // Raw
-            output.extend_from_slice(input);
-        },
-        1 => { // Repeated
-            while input.len() > 1 {
-                let count = u16::from_le_bytes(input[0..2].try_into().unwrap());
-                let pattern = u16::from_le_bytes(input[2..4].try_into().unwrap());
-                for _ in 0..count {
-                    output.extend_from_slice(&pattern.to_le_bytes());
-                }
-                input = &input[4..];
+    // an empty directory for a test's files, named after the test
+    pub(crate) fn temp_dir(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("kc8587-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // a TD0 of one track of sectors, each a sector header, then a data block unless the flags say none
+    fn td0_of(sectors: &[(&[u8; 6], &[u8])]) -> Vec<u8> {
+        let header = ImageHeader::synthetic(0x02, 0x01, 1);
+        let mut td0 = header.crc_bytes().to_vec();
+        td0.extend_from_slice(&crc16(&header.crc_bytes()).to_le_bytes());
+        td0.extend_from_slice(&[sectors.len() as u8, 0, 0, 0]);
+        for (sector, block) in sectors {
+            td0.extend_from_slice(&sector[..]);
+            if sector[4] & 0x30 == 0 {
+                td0.extend_from_slice(&(block.len() as u16).to_le_bytes());
+                td0.extend_from_slice(block);
             }
-        },
-        _ => {
-            panic!("Unknown encoding method: {}", encoding_method);
         }
+        td0.extend_from_slice(&[255, 0, 0, 0]);
+        td0
     }
-    assert!(output.len() == sector_size as usize);
-    output
-}
 
-fn analyse_raw_sector(args: &Args, data: &[u8]) {
-    let mut cpm_dent_count = 0;
-    let mut dos_fat_dent_count = 0;
-    let dent_size = 32;
+    fn scan_td0(td0: &[u8]) -> ImageReport {
+        scan_td0_with(td0, &[])
+    }
 
-    for i in (0..data.len()).step_by(dent_size) {
-        let mut clocked = 0;
-        if let ControlFlow::Continue(_) = isfat(data, i, args, dent_size) {
-            clocked += 1;
-            dos_fat_dent_count += 1;
-        }
+    fn scan_td0_with(td0: &[u8], options: &[&str]) -> ImageReport {
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin"].iter().chain(options));
+        let mut scan = Scan::default();
+        analyze_teledisk_image_format_from_stream(&args, &mut scan, &mut &td0[..], "S", Path::new(""), None, "test.td0", &Provenance::default());
+        scan.reports.pop().unwrap()
+    }
 
-        if let ControlFlow::Continue(_) = iscpm(data, i, args, dent_size) {
-            clocked += 1;
-            cpm_dent_count += 1;
+    #[test]
+    fn lists_only_good_headers() {
+        let args = Args::parse_from(["kc8587", "--list", "-0", "--stdin"]);
+        let mut scan = Scan::default();
+        let td0 = td0_of(&[]);
+        analyse_image(&args, &mut scan, &mut &td0[..], "S", Path::new(""), None, "good.td0", &Provenance::default());
+        let mut bad = td0.clone();
+        bad[9] = 2;
+        analyse_image(&args, &mut scan, &mut &bad[..], "S", Path::new(""), None, "bad.td0", &Provenance::default());
+        analyse_image(&args, &mut scan, &mut &td0[..6], "S", Path::new(""), None, "short.td0", &Provenance::default());
+        analyse_image(&args, &mut scan, &mut &[0; 512][..], "S", Path::new(""), None, "disk.img", &Provenance::default());
+        assert_eq!((scan.listed, scan.invalid_images, scan.reports.len()), (1, 2, 0));
+        assert_eq!(scan.exit_code(), EXIT_INVALID_IMAGES);
+    }
+
+    #[test]
+    fn exit_status_says_how_the_scan_went() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin"]);
+        let mut scan = Scan::default();
+        assert_eq!(scan.exit_code(), crate::cli::EXIT_NO_TD0_FOUND);
+        // --quiet prints nothing about an image
+        IMAGE_OUTPUT.set(Some(Vec::new()));
+        analyse_image(&args, &mut scan, &mut &td0[..], "S", Path::new(""), None, "good.td0", &Provenance::default());
+        assert_eq!(IMAGE_OUTPUT.take().unwrap(), Vec::<String>::new());
+        assert_eq!(scan.exit_code(), 0);
+        let mut bad_crc = td0.clone();
+        bad_crc[10] ^= 1;
+        analyse_image(&args, &mut scan, &mut &bad_crc[..], "S", Path::new(""), None, "bad.td0", &Provenance::default());
+        assert_eq!(scan.exit_code(), EXIT_INVALID_IMAGES);
+        scan.io_error("Failed to read gone.td0");
+        assert_eq!(scan.exit_code(), EXIT_IO_ERROR);
+    }
+
+    #[test]
+    fn catalogue_records_have_the_header_as_the_library_serializes_it() {
+        let report = scan_td0(&td0_of(&[]));
+        let record = serde_json::to_value(&report).unwrap();
+        let mut header = serde_json::to_value(report.header.as_ref().unwrap()).unwrap();
+        header["crc_ok"] = true.into();
+        assert_eq!(record["header"], header);
+        assert_eq!((record["format"].as_str(), record["status"].as_str(), record["toolchain"]["release"].as_str()),
+            (Some("td0"), Some("ok"), Some("TeleDisk 2.1x")));
+        assert_eq!(record["sort_keys"]["name"], "test");
+        // what's only kept for other outputs isn't in it
+        assert!(record.get("files").is_none() && record.get("shown").is_none());
+    }
+
+    #[test]
+    fn sampling_details_every_nth_image_or_the_first_in_each_directory() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let mut scan = Scan::default();
+        let args = Args::parse_from(["kc8587", "--sample-verbose", "2", "--stdin"]);
+        let mut detailed = Vec::new();
+        for _ in 0..4 {
+            detailed.push(args.for_image(&mut scan, Path::new("")).shows_detail());
+            analyse_image(&args, &mut scan, &mut &td0[..], "S", Path::new(""), None, "test.td0", &Provenance::default());
         }
+        assert_eq!(detailed, [true, false, true, false]);
+        let args = Args::parse_from(["kc8587", "--sample-verbose", "2", "--sample-per-dir", "--stdin"]);
+        let detailed: Vec<bool> = ["a", "a", "a", "b"].iter()
+            .map(|dir| args.for_image(&mut scan, Path::new(dir)).shows_detail())
+            .collect();
+        assert_eq!(detailed, [true, true, false, true]);
+    }
+
+    #[test]
+    fn blocks_cut_for_their_length_say_so() {
+        // RLE padded with empty literals, which is legal but longer than a block is taken to need
+        let block = [&[2][..], &[0; 2 * 191], &[0, 128], &[0xe5; 128]].concat();
+        let report = scan_td0(&td0_of(&[(&[0, 0, 1, 0, 0, 0], &block)]));
+        let findings: Vec<_> = report.findings.iter().map(|f| (f.code, f.message.as_str())).collect();
+        assert_eq!(findings, [("oversized-block", "the data block says it's 513 bytes, more than a sector of 128 bytes should take, and only its start is decoded")]);
+        assert_eq!(report.compression.encodings.undecodable, 1);
+    }
 
-        if clocked != 1 {
-            print_hex_and_ascii(args, i/32, &data[i..i+dent_size], clocked != 0);
+    #[test]
+    fn unknown_encodings_are_left_blank() {
+        let report = scan_td0(&td0_of(&[(&[0, 0, 1, 0, 0, 0], &[3, 1, 2, 3]), (&[0, 0, 2, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5]),
+            (&[0, 0, 3, 0, 0, 0], &[1, 64, 0, 0xe5]), (&[0, 0, 4, 0, 0, 0], &[])]));
+        assert_eq!(report.status, ImageStatus::Ok);
+        let codes: Vec<_> = report.findings.iter().map(|f| f.code).collect();
+        assert_eq!(codes, ["unknown-encoding", "undecodable", "undecodable"]);
+        let encodings = report.compression.encodings;
+        assert_eq!((encodings.undecodable, encodings.repeated), (3, 1));
+    }
+
+    #[test]
+    fn wrong_sizes_are_fitted() {
+        let short: Vec<u8> = [0].into_iter().chain(1..=100).collect();
+        let crc = crc16(&[1, 2, 3, 4].repeat(32)) as u8;
+        let report = scan_td0(&td0_of(&[(&[0, 0, 1, 0, 0, 0], &short), (&[0, 0, 2, 0, 0, crc], &[2, 2, 0xff, 1, 2, 3, 4])]));
+        let messages: Vec<_> = report.findings.iter().map(|f| (f.code, f.message.as_str())).collect();
+        assert_eq!(messages, [
+            ("wrong-size", "the data block decodes to 100 of the sector's 128 bytes, and is padded with zeros"),
+            ("wrong-size", "the data block decodes to more than the sector's 128 bytes, and is cut to fit"),
+        ]);
+        // they count as decoded, though the one cut short doesn't pass its CRC
+        let encodings = report.compression.encodings;
+        assert_eq!((encodings.raw, encodings.raw_failed, encodings.rle, encodings.rle_failed, encodings.undecodable), (1, 1, 1, 0, 0));
+    }
+
+    // a 160K DOS floppy with one file on it
+    fn fat_floppy() -> Vec<u8> {
+        let mut img = vec![0xf6; 320 * 512];
+        img[..0x20].copy_from_slice(&[0xeb, 0x3c, 0x90, b'K', b'C', b'8', b'5', b'8', b'7', b' ', b' ',
+            0x00, 0x02, 1, 1, 0, 2, 64, 0, 0x40, 0x01, 0xfe, 1, 0, 8, 0, 1, 0, 0, 0, 0, 0]);
+        img[510..512].copy_from_slice(&[0x55, 0xaa]);
+        for fat in [512, 1024] {
+            img[fat..fat + 512].fill(0);
+            img[fat..fat + 4].copy_from_slice(&[0xfe, 0xff, 0xff, 0xff]);
         }
+        img[3 * 512..7 * 512].fill(0);
+        img[3 * 512..3 * 512 + 32].copy_from_slice(b"HELLO   TXT\x20\0\0\0\0\0\0\0\0\0\0\0\0\x21\x17\x02\0\x05\0\0\0");
+        img[7 * 512..7 * 512 + 5].copy_from_slice(b"hello");
+        img
     }
-}
 
-fn isfat(data: &[u8], i: usize, args: &Args, dent_size: usize) -> ControlFlow<()> {
-    let name_and_ext = &data[i..i+11];
-    let attr = data[i+0x0b];
-    let zeros = &data[i+0x0c..i+0x16]; // zeroes in my CM1910DC.TD0
-    let time = &data[i+0x16..i+0x18]; // time
-    let date = &data[i+0x18..i+0x1a]; // date
-    let cluster1 = &data[i+0x1a..i+0x1c]; // first cluster
-    let file_size = &data[i+0x1c..i+0x20]; // file size
-
-    // filename[0] can also be: 0x00, 0x05, 0x2E, 0xE5
-    let status: Option<&str> = match name_and_ext[0] {
-        0x00 => Some("00"), // Null character
-        0x05 => Some("05"), // Special value
-        0x2E => Some("2E"), // Special value
-        0xE5 => Some("E5"), // Special value
-        b if (0x20..=0x7E).contains(&b) => Some("--"), // Printable characters
-        _ => None, // Any other value indicates an error
-    };
+    fn scan_flat(img: &[u8]) -> ImageReport {
+        scan_flat_with(img, &[])
+    }
 
-    if status.is_none() { return ControlFlow::Break(()); }
-    let status = status.unwrap();
-    
-    for b in name_and_ext {
-        let b = *b & 0x7f;
-        if b < 0x20 || b > 0x7e { return ControlFlow::Break(()); }
+    // a 160K image of forty tracks of eight sectors, as a TD0
+    fn scan_flat_with(img: &[u8], options: &[&str]) -> ImageReport {
+        let mut td0 = Vec::new();
+        write_td0(&mut td0, &ImageHeader::synthetic(0x00, 0x01, 1), None, &flat_disk(img, 40, 1, 8, 512), Codepage::Cp437).unwrap();
+        scan_td0_with(&td0, options)
     }
 
-    // count how many bytes in 'rest1' are non-zero - in my CM1910DC.TD0 they seem to be all zero
-    let nonzero_count = zeros.iter().fold(0, |acc, &b| if b != 0 { acc + 1 } else { acc });
-    if nonzero_count > 2 { return ControlFlow::Break(()); }
+    #[test]
+    fn html_report_has_a_row_and_a_section_per_image() {
+        let dir = temp_dir("report");
+        let path = dir.join("report.html");
+        let mut scan = Scan::default();
+        let mut report = scan_flat_with(&fat_floppy(), &["--report-html", path.to_str().unwrap()]);
+        report.path = "<disks>/a.td0".to_string();
+        report.shown = true;
+        scan.reports.push(report);
+        write_html_report(&scan, path.to_str().unwrap(), crate::report::Language::En).unwrap();
+        let html = std::fs::read_to_string(&path).unwrap();
+        assert!(html.contains("<a href=\"#image-0\">&lt;disks&gt;/a.td0</a>"));
+        assert!(html.contains("<details id=\"image-0\">"));
+        // forty tracks of eight good sectors, and the file on the disk
+        assert_eq!(html.matches("title=\"good\">.</span>").count(), 40 * 8 + 1);
+        assert!(html.contains("HELLO.TXT"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-    let first_letter = match name_and_ext[0] {
-        b if (0x20..=0x7E).contains(&b) => b as char,
-        _ => '?',
-    };
+    #[test]
+    fn html_report_shows_the_map_pngs() {
+        let dir = temp_dir("report-maps");
+        let path = dir.join("report.html");
+        let mut report = scan_flat_with(&fat_floppy(), &["--report-html", path.to_str().unwrap(), "--map-png", dir.to_str().unwrap()]);
+        report.shown = true;
+        let png = std::fs::read(report.map_png.as_ref().unwrap()).unwrap();
+        let mut scan = Scan::default();
+        scan.reports.push(report);
+        write_html_report(&scan, path.to_str().unwrap(), crate::report::Language::En).unwrap();
+        let html = std::fs::read_to_string(&path).unwrap();
+        assert!(html.contains("<img class=\"map\" src=\"data:image/png;base64,iVBORw0KGgo"), "{}", html);
+        assert!(html.contains("width=\"32\" height=\"160\"") && png.starts_with(b"\x89PNG"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-    let att = match attr {
-        b if b & !0x3f != 0 => format!("  {:02x}  ", b),
-        _ => format!("{}{}{}{}{}{}",
-            if attr & 0x20 != 0 { "a" } else { "-" },   // archive
-            if attr & 0x10 != 0 { "d" } else { "-" },   // subdir
-            if attr & 0x08 != 0 { "v" } else { "-" },   // volume
-            if attr & 0x04 != 0 { "s" } else { "-" },   // system
-            if attr & 0x02 != 0 { "h" } else { "-" },   // hidden
-            if attr & 0x01 != 0 { "r" } else { "-" }    // readonly
-        ),
-    };
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn database_has_a_row_per_image_and_its_files() {
+        let dir = temp_dir("db");
+        let path = dir.join("scan.sqlite");
+        let mut scan = Scan::default();
+        let mut report = scan_flat(&fat_floppy());
+        report.shown = true;
+        scan.reports.push(report);
+        // a second scan of the same image replaces its rows rather than adding to them
+        for _ in 0..2 {
+            write_database(&scan, path.to_str().unwrap()).unwrap();
+        }
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let (image_path, health, filesystem): (String, i64, String) = conn
+            .query_row("SELECT path, health, filesystem FROM images", [], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap();
+        assert_eq!((image_path.as_str(), health), ("test.td0", scan.reports[0].health as i64));
+        assert_eq!(Some(filesystem.as_str()), scan.reports[0].filesystem);
+        let files: Vec<(String, i64)> = conn.prepare("SELECT name, size FROM files").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().map(Result::unwrap).collect();
+        assert_eq!(files, [("HELLO.TXT".to_string(), 5)]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-    println!("FAT {:2} St: {} {}{}.{} Attr: {} Rest: {:02x?} {:02x?} {:02x?} {:04x?} {:08x?}",
-        i/32, status,
-        first_letter, String::from_iter(name_and_ext[1..8].iter().map(|&b| b as char)),
-        String::from_iter(name_and_ext[8..11].iter().map(|&b| b as char)),
-        att, zeros,
-        time,
-        date,
-        cluster1.iter().rev().fold(0, |acc, &b| (acc << 8) | b as usize),   // 16 bit little endian
-        file_size.iter().rev().fold(0, |acc, &b| (acc << 8) | b as usize),  // 32 bit little endian
-    );
-
-    ControlFlow::Continue(())
-}
+    #[test]
+    fn boot_sector_viruses_are_findings() {
+        let mut img = fat_floppy();
+        img[0x1a0..0x1b6].copy_from_slice(b"Your PC is now Stoned!");
+        let report = scan_flat(&img);
+        assert_eq!(report.boot.as_ref().and_then(|b| b.loader).map(|l| l.name), Some("Stoned"));
+        let findings: Vec<_> = report.findings.iter().filter(|f| f.code == "boot-virus").map(|f| f.message.as_str()).collect();
+        assert_eq!(findings, ["the boot sector is the Stoned virus"]);
+        assert!(scan_flat(&fat_floppy()).boot.is_some_and(|b| b.loader.is_none()));
+    }
 
-fn iscpm(data: &[u8], i: usize, args: &Args, dent_size: usize) -> ControlFlow<()> {
-    let status = data[i];
-    let cpm_name_and_ext = &data[i + 1..i + 12];
-    let ex = data[i + 12];
-    let s1 = data[i + 13];
-    let s2 = data[i + 14];
-    let rc = data[i + 15];
-    let al = &data[i + 16..i + 32];
-
-    // KC 85 / Robotron allow only 0x00, 0xe5, or 0x80
-    if status != 0x00 && status != 0xe5 && status != 0x80 { return ControlFlow::Break(()); }
-
-    for b in cpm_name_and_ext {
-        let b = *b & 0x7f;
-        if b < 0x20 || b > 0x7e { return ControlFlow::Break(()); }
-    }
-
-    let (name_and_ext, flags): ([char; 11], [bool; 11]) = cpm_name_and_ext.iter().enumerate().fold(
-        ([0 as char; 11], [false; 11]),
-        |(mut n, mut f), (i, b)| {
-            n[i] = (b & 0x7f) as char;
-            f[i] = b & 0x80 != 0;
-            (n, f)
-        }
-    );
-    
-    // check for false positive when status is 0xe5 *and* so is every byte of the filename and extension
-    if status == 0xe5 && name_and_ext.iter().all(|b| *b as u8 == 0xe5) { return ControlFlow::Break(()); }
-
-    // KC 85 / Robotron -specific checks: S1 and S2 must be 0x00, s3 must be <= 128
-    if s1 != 0x00 || s2 != 0x00 || rc > 128 {
-        return ControlFlow::Break(());
-    }
-
-    // AL must match: zero or more nonzero pairs, followed by zero or more zero pairs
-    for window in al.chunks_exact(2).collect::<Vec<_>>().windows(2) {
-        if window[1][0] != 0x00 || window[1][1] != 0x00 {
-            if window[0][0] == 0x00 && window[0][1] == 0x00 {
-                return ControlFlow::Break(());
+    #[test]
+    fn disk_sets_are_grouped() {
+        let mut scan = Scan::default();
+        for path in ["a/Lotus (Disk 1 of 3).td0", "a/LOTUS (DISK 3 OF 3).TD0", "a/GAME1.TD0", "a/GAME2.TD0", "b/GAME3.TD0", "a/OTHER1.TD0"] {
+            let mut report = scan_td0(&td0_of(&[]));
+            (report.directory, report.name) = path.split_once('/').map(|(d, n)| (d.to_string(), n.to_string())).unwrap();
+            report.path = path.to_string();
+            scan.reports.push(report);
+        }
+        group_disk_sets(&mut scan);
+        let sets: Vec<_> = scan.reports.iter().map(|r| r.disk_set.as_ref().map(|s| (s.name.as_str(), s.disk, s.of, s.missing.clone()))).collect();
+        assert_eq!(sets, [
+            Some(("Lotus", 1, 3, vec![2])), Some(("Lotus", 3, 3, vec![2])),
+            Some(("GAME", 1, 2, vec![])), Some(("GAME", 2, 2, vec![])),
+            None, None,
+        ]);
+        let codes: Vec<_> = scan.reports[0].findings.iter().map(|f| f.code).collect();
+        assert_eq!(codes, ["disk-set", "disk-set-missing"]);
+    }
+
+    #[test]
+    fn the_same_trailing_digit_isnt_a_set() {
+        let mut scan = Scan::default();
+        for path in ["a/WP2.TD0", "a/WP2.IMG", "b/joined3.td0 #1", "b/joined3.td0 #2", "c/DISK 2 OF 3.TD0", "c/disk 2 of 3.img"] {
+            let mut report = scan_td0(&td0_of(&[]));
+            (report.directory, report.name) = path.split_once('/').map(|(d, n)| (d.to_string(), n.to_string())).unwrap();
+            report.path = path.to_string();
+            scan.reports.push(report);
+        }
+        group_disk_sets(&mut scan);
+        let sets: Vec<_> = scan.reports.iter().map(|r| r.disk_set.as_ref().map(|s| (s.disk, s.missing.clone()))).collect();
+        // a disk that says which it is is still one of a set, however many copies there are
+        assert_eq!(sets, [None, None, None, None, Some((2, vec![1, 3])), Some((2, vec![1, 3]))]);
+    }
+
+    #[test]
+    fn damaged_filesystems_are_scanned() {
+        let img = fat_floppy();
+        let report = scan_flat(&img);
+        assert_eq!(report.filesystem, Some("FAT"));
+        assert_eq!(report.files.len(), 1);
+        // the BPB's sizes and counts, the start of the FAT, and the file's attributes, cluster and
+        // size, each cleared and set
+        let bpb = [0x0b, 0x0c, 0x0d, 0x0e, 0x10, 0x11, 0x13, 0x14, 0x16, 0x18, 0x1a];
+        let entry = [0x0b, 0x1a, 0x1c, 0x1f].map(|offset| 3 * 512 + offset);
+        for offset in bpb.into_iter().chain([512, 513]).chain(entry) {
+            for value in [0x00, 0xff] {
+                let mut damaged = img.clone();
+                damaged[offset] = value;
+                assert_eq!(scan_flat(&damaged).status, ImageStatus::Ok, "byte {:#x} set to {:02x}", offset, value);
             }
         }
     }
-    
-    let (name, ext) = name_and_ext.split_at(8);
 
-    println!("CPM {:2} St: {:02x} {}.{} {} ExS1S2Rc: {:3?} AL: {:3?}",
-        i/32, status,
-        name.iter().collect::<String>(), ext.iter().collect::<String>(),
-        flags.iter().map(|b| if *b { "1" } else { "0" }).collect::<String>(),
-        (ex, s1, s2, rc), al);
+    #[test]
+    fn sector_analysis_can_be_left_out() {
+        let report = scan_flat_with(&fat_floppy(), &["--sector-analysis", "off"]);
+        assert_eq!((report.status, report.sectors, report.filesystem, report.files.len()), (ImageStatus::Ok, 320, None, 0));
+        assert!(report.boot.is_none());
+    }
 
-    ControlFlow::Continue(())
-}
+    #[test]
+    fn disk_image_info_alone_skips_the_data() {
+        let mut td0 = Vec::new();
+        write_td0(&mut td0, &ImageHeader::synthetic(0x00, 0x01, 1), None, &flat_disk(&fat_floppy(), 40, 1, 8, 512), Codepage::Cp437).unwrap();
+        let report = scan_td0_with(&td0, &["-d"]);
+        assert_eq!((report.status, report.sectors, report.filesystem, report.checksums.is_none()), (ImageStatus::Ok, 320, None, true));
+        assert_eq!((report.compression.encodings.total(), report.compression.encodings.failed()), (320, 0));
+        assert_eq!(report.compression.stored, td0.len() as u64);
+        // anything else that needs the contents has them decoded
+        let report = scan_td0_with(&td0, &["-d", "--find-duplicates"]);
+        assert_eq!((report.filesystem.is_some(), report.checksums.is_some()), (true, true));
+    }
 
-fn print_hex_and_ascii(args: &Args, line_number: usize, data: &[u8], hexonly: bool) {
-    let (grn, blu, off) = if args.colour {
-        ("\x1b[32m", "\x1b[34m", "\x1b[0m")
-    } else {
-        ("", "", "")
-    };
-    let chunklen = 0x1c + 4;
-    // Include additional bytes
-    for i in (0..data.len()).step_by(chunklen) {
-        let end = (i + chunklen).min(data.len()); // Prevent overflow
-        let s: String = data[i..end]
-            .iter()
-            .map(|&b| {
-                if !hexonly && (0x20..=0x7e).contains(&b) {
-                    format!("{} {} {}", grn, b as char, off)
-                } else {
-                    format!("{}{:02x} {}", blu, b, off)
-                }
-            })
-            .collect();
-    
-        println!("--- {:2}     {}", line_number, s);
+    #[test]
+    fn directories_are_looked_for_in_the_first_tracks() {
+        // CP/M directory entries on the second track only
+        let mut img = vec![0xe5; 2 * 8 * 512];
+        for (i, name) in [b"HELLO   TXT", b"WORLD   COM"].into_iter().enumerate() {
+            let at = 8 * 512 + i * 32;
+            img[at..at + 32].fill(0);
+            img[at + 1..at + 12].copy_from_slice(name);
+            img[at + 15] = 8;
+            img[at + 16] = 2 + i as u8;
+        }
+        let disk = flat_disk(&img, 2, 1, 8, 512);
+        let entries = |options: &[&str]| {
+            let args = Args::parse_from(["kc8587", "--quiet", "--stdin"].iter().chain(options));
+            analyse_directories(&args, &disk, &mut args.detectors()).iter().sum::<usize>()
+        };
+        assert!(entries(&[]) > 0);
+        assert_eq!(entries(&["--tracks", "1"]), 0);
+        assert!(entries(&["--tracks", "2"]) > 0);
+    }
+
+    #[test]
+    fn header_filters_pass_images_over() {
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin", "--sides", "2"]);
+        let mut scan = Scan::default();
+        let td0 = td0_of(&[]);
+        analyse_image(&args, &mut scan, &mut &td0[..], "S", Path::new(""), None, "<stdin>", &Provenance::default());
+        analyse_image(&args, &mut scan, &mut &fat_floppy()[..], "F", Path::new(""), None, "disk.img", &Provenance::default());
+        assert_eq!((scan.reports.len(), scan.passed_over, scan.exit_code()), (0, 2, 0));
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin", "--sides", "1"]);
+        analyse_image(&args, &mut scan, &mut &td0[..], "S", Path::new(""), None, "<stdin>", &Provenance::default());
+        assert_eq!(scan.reports.len(), 1);
+    }
+
+    #[test]
+    fn truncated_images_say_what_was_read() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5]), (&[0, 0, 2, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let report = scan_td0(&td0[..td0.len() - 4 - 7 - 6]);
+        assert_eq!(report.status, ImageStatus::Truncated);
+        let messages: Vec<_> = report.findings.iter().map(|f| (f.code, f.message.as_str())).collect();
+        assert_eq!(messages, [("truncated", "1 track and 1 sector were read before the data ran out, 1 of the 2 sectors of the last one")]);
+        let report = scan_td0(&td0[..td0.len() - 4]);
+        assert_eq!(report.findings[0].message, "1 track and 2 sectors were read before the data ran out, after the last one");
+    }
+
+    #[test]
+    fn trailing_bytes_are_classified() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let findings = |trailing: &[u8]| {
+            let report = scan_td0(&[&td0[..], trailing].concat());
+            assert_eq!(report.status, ImageStatus::Ok);
+            report.findings.iter().map(|f| (f.code, f.message.clone())).collect::<Vec<_>>()
+        };
+        assert_eq!(findings(&[0x1a; 100]), [("trailing-padding", "100 bytes of 1a padding".to_string())]);
+        let mut advanced = td0.clone();
+        advanced[..2].copy_from_slice(b"td");
+        let crc = crc16(&advanced[..10]).to_le_bytes();
+        advanced[10..12].copy_from_slice(&crc);
+        assert_eq!(findings(&advanced)[0].1, format!("{} bytes that start another TeleDisk image with advanced compression, volume 0", td0.len()));
+        assert_eq!(findings(b"\r\nend"), [("trailing-bytes", "5 bytes: 0d 0a 65 6e 64".to_string())]);
+        assert!(findings(&[7; 65])[0].1.starts_with("65 bytes of 07"));
+        assert_eq!(findings(&[0; 20000])[0].1, "20000 bytes of 00 padding");
+        assert!(findings(&[&[0; 20000][..], &[1]].concat())[0].1.starts_with("20001 bytes: 00 00"));
+        assert!(findings(&[&[1][..], &[2; 99]].concat())[0].1.ends_with(" 02 ..."));
+    }
+
+    #[test]
+    fn joined_images_are_read_in_turn() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin"]);
+        let mut scan = Scan::default();
+        let joined = [&td0[..], &td0, b"\x1a\x1a"].concat();
+        analyze_teledisk_image_format_from_stream(&args, &mut scan, &mut &joined[..], "S", Path::new(""), None, "test.td0", &Provenance::default());
+        let names: Vec<_> = scan.reports.iter().map(|r| (r.name.as_str(), r.status, r.findings.iter().map(|f| f.code).collect::<Vec<_>>())).collect();
+        assert_eq!(names, [("test.td0 #1", ImageStatus::Ok, vec![]), ("test.td0 #2", ImageStatus::Ok, vec!["trailing-padding"])]);
+        assert!(scan.reports.iter().all(|r| r.compression.stored == td0.len() as u64));
+    }
+
+    // each image is read in the same loop, however many there are
+    #[test]
+    fn many_joined_images_are_read() {
+        let td0 = td0_of(&[]);
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin"]);
+        let mut scan = Scan::default();
+        let joined = td0.repeat(5000);
+        analyze_teledisk_image_format_from_stream(&args, &mut scan, &mut &joined[..], "S", Path::new(""), None, "test.td0", &Provenance::default());
+        assert_eq!(scan.reports.len(), 5000);
+        assert_eq!(scan.reports.last().unwrap().name, "test.td0 #5000");
+    }
+
+    #[test]
+    fn out_dir_takes_what_is_printed_about_each_image() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let dir = temp_dir("output");
+        let args = Args::parse_from(["kc8587".as_ref(), "-d".as_ref(), "--stdin".as_ref(), "--out-format=output".as_ref(), "--out-dir".as_ref(), dir.as_os_str()]);
+        let mut scan = Scan::default();
+        analyse_image(&args, &mut scan, &mut &[&td0[..], &td0].concat()[..], "F", Path::new(""), None, "two.td0", &Provenance::default());
+        analyse_image(&args, &mut scan, &mut &b"not an image"[..], "F", Path::new(""), None, "text.td0", &Provenance::default());
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert!(read("two.td0__1.txt").starts_with("F : TD") && read("two.td0__2.txt").starts_with("F : TD"));
+        // nothing was printed about a file that isn't an image, so it gets no file
+        assert!(!dir.join("text.td0.txt").exists());
+        // unless it's the one image given, when it's told it isn't one
+        let mut args = Args::parse_from(["kc8587".as_ref(), "--stdin".as_ref(), "--out-format=output".as_ref(), "--out-dir".as_ref(), dir.as_os_str()]);
+        args.card = true;
+        analyse_image(&args, &mut scan, &mut &b"not an image"[..], "F", Path::new(""), None, "text.td0", &Provenance::default());
+        assert!(read("text.td0.txt").starts_with("text.td0 is not a TeleDisk image"));
+        // and an image --only leaves out gets none either
+        let args = Args::parse_from(["kc8587".as_ref(), "-d".as_ref(), "--stdin".as_ref(), "--only=health<=50".as_ref(), "--out-format=output".as_ref(), "--out-dir".as_ref(), dir.as_os_str()]);
+        analyse_image(&args, &mut scan, &mut &td0[..], "F", Path::new(""), None, "healthy.td0", &Provenance::default());
+        assert!(!dir.join("healthy.td0.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn images_whose_names_flatten_alike_get_their_own_files() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let dir = temp_dir("stems");
+        let args = Args::parse_from(["kc8587".as_ref(), "--stdin".as_ref(), "--out-dir".as_ref(), dir.as_os_str()]);
+        let mut scan = Scan::default();
+        for name in ["a b.td0", "a_b.td0", "A_B.TD0"] {
+            analyse_image(&args, &mut scan, &mut &td0[..], "F", Path::new(""), None, name, &Provenance::default());
+        }
+        for name in ["a_b.td0.txt", "a_b.td0~2.txt", "A_B.TD0~3.txt"] {
+            assert!(dir.join(name).exists(), "{}", name);
+        }
+        // with --ids the files are named by image ID, and the same image met twice still gets two
+        let args = Args::parse_from(["kc8587".as_ref(), "--stdin".as_ref(), "--ids".as_ref(), "--out-dir".as_ref(), dir.as_os_str()]);
+        let id = image_id(&td0);
+        for name in ["one.td0", "two.td0"] {
+            analyse_image(&args, &mut scan, &mut &td0[..], "F", Path::new(""), None, name, &Provenance::default());
+        }
+        assert!(dir.join(format!("{}.txt", id)).exists() && dir.join(format!("{}~2.txt", id)).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ids_start_every_line_about_an_image() {
+        // the SHA-1 of nothing starts da 39 a3 ee 5e
+        assert_eq!(image_id(b""), "v8wt7vjy");
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let id = image_id(&td0);
+        assert!(id.len() == 8 && id != image_id(&td0[..td0.len() - 1]));
+        let args = Args::parse_from(["kc8587", "-d", "--ids", "--stdin"]);
+        let mut scan = Scan::default();
+        IMAGE_OUTPUT.set(Some(Vec::new()));
+        analyse_image(&args, &mut scan, &mut &td0[..], "S", Path::new(""), None, "test.td0", &Provenance::default());
+        let output = IMAGE_OUTPUT.take().unwrap();
+        assert!(!output.is_empty());
+        assert!(output.iter().all(|line| line.starts_with(&format!("{} ", id))), "{:?}", output);
+        assert_eq!(scan.reports[0].id.as_deref(), Some(id.as_str()));
+    }
+
+    #[test]
+    fn header_and_track_lines_are_coloured() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let mut args = Args::parse_from(["kc8587", "-d", "-t", "--stdin"]);
+        args.colours = true;
+        IMAGE_OUTPUT.set(Some(Vec::new()));
+        analyse_image(&args, &mut Scan::default(), &mut &td0[..], "S", Path::new(""), None, "test.td0", &Provenance::default());
+        let output = IMAGE_OUTPUT.take().unwrap();
+        assert!(output.iter().any(|line| line.starts_with(&format!("{}S : TD", HEADER_COLOUR))), "{:?}", output);
+        assert!(output.iter().any(|line| line.starts_with(TRACK_COLOUR) && line.ends_with("\x1b[0m")), "{:?}", output);
+        // reading each sector is a diagnostic for -vvv, not analysis
+        assert!(!output.iter().any(|line| line.contains("Sector 0->")), "{:?}", output);
+    }
+
+    #[test]
+    fn stats_break_the_images_down() {
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin"]);
+        let mut scan = Scan::default();
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        analyse_image(&args, &mut scan, &mut &td0[..], "F", Path::new(""), None, "a.td0", &Provenance::default());
+        analyse_image(&args, &mut scan, &mut &fat_floppy()[..], "F", Path::new(""), None, "b.img", &Provenance::default());
+        analyse_image(&args, &mut scan, &mut &b"not an image"[..], "F", Path::new(""), None, "c.td0", &Provenance::default());
+        let lines = crate::report::stats_lines(&scan, crate::report::Language::En);
+        assert!(lines[0].starts_with("2 images - "));
+        // only TeleDisk images have a version, and breakdowns with nothing to count aren't shown
+        let breakdowns = lines.iter().skip_while(|line| !line.starts_with("    versions")).map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(breakdowns, ["    versions 2.1 1", "    data rates 500 kbps 1", "    drive types 360K 1", "    sides 1 1",
+            "    formats flat 1, td0 1", "    filesystems FAT 1, none 1", "    status ok 2", "    damaged 0, not images 1, read errors 0"]);
+    }
+
+    #[test]
+    fn checks_multi_volume_sets() {
+        let volume = |sequence: u8, check_sequence: u8| {
+            let header = ImageHeader { sequence, check_sequence, ..ImageHeader::synthetic(0x02, 0x01, 1) };
+            let mut td0 = Vec::new();
+            write_td0(&mut td0, &header, None, &flat_disk(&[0xe5; 512], 1, 1, 1, 512), Codepage::Cp437).unwrap();
+            td0
+        };
+        let dir = temp_dir("volumes");
+        let args = Args::parse_from(["kc8587", "--quiet", "--stdin"]);
+        let mut scan = Scan::default();
+        let images = [("disk1.td0", volume(0, 0x11)), ("disk3.td0", volume(2, 0x11)), ("disk3b.td0", volume(2, 0x11)),
+            ("a.td0", volume(1, 0x22)), ("b.td0", volume(0, 0x22)), ("alone.td0", volume(0, 0x33))];
+        for (name, td0) in &images {
+            analyse_image(&args, &mut scan, &mut &td0[..], "F", &dir, None, name, &Provenance::default());
+        }
+        // a first volume that runs out with the next beside it
+        let first = volume(0, 0x44);
+        std::fs::write(dir.join("part.td1"), b"").unwrap();
+        let mut provenance = Provenance::default();
+        provenance.host_file = Some(dir.join("part.td0"));
+        analyse_image(&args, &mut scan, &mut &first[..first.len() - 4], "F", &dir, None, "part.td0", &provenance);
+        check_volume_sets(&mut scan);
+        let codes = |name: &str| {
+            let report = scan.reports.iter().find(|r| r.name == name).unwrap();
+            report.findings.iter().map(|f| f.code).filter(|code| code.starts_with("volume")).collect::<Vec<_>>()
+        };
+        assert_eq!(codes("disk1.td0"), ["volume-set", "volume-missing"]);
+        assert_eq!(codes("disk3.td0"), ["volume-set", "volume-missing", "volume-repeated"]);
+        assert_eq!(codes("a.td0"), ["volume-set", "volume-order"]);
+        assert_eq!(codes("b.td0"), ["volume-set", "volume-order"]);
+        assert!(codes("alone.td0").is_empty());
+        assert_eq!(codes("part.td0"), ["volume-files"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_first_joined_image_is_labelled_in_its_header_line() {
+        let td0 = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        let args = Args::parse_from(["kc8587", "-d", "--stdin"]);
+        IMAGE_OUTPUT.set(Some(Vec::new()));
+        analyze_teledisk_image_format_from_stream(&args, &mut Scan::default(), &mut &td0.repeat(2)[..], "S", Path::new(""), None, "test.td0", &Provenance::default());
+        let output = IMAGE_OUTPUT.take().unwrap();
+        let headers: Vec<_> = output.iter().filter(|line| line.starts_with("S : TD")).collect();
+        assert_eq!(headers.len(), 2);
+        assert!(headers[0].ends_with("test.td0 #1") && headers[1].ends_with("test.td0 #2"), "{:?}", output);
+    }
+
+    #[test]
+    fn impossible_comment_dates_are_findings() {
+        let header = ImageHeader::synthetic(0x02, 0x01, 1);
+        let timestamp = chrono::NaiveDate::from_ymd_opt(1991, 8, 25).unwrap().and_hms_opt(20, 57, 8);
+        let comment = Comment { timestamp, text: "dumped".to_string(), crc_ok: true, truncated: false, fields: BTreeMap::new() };
+        let mut td0 = Vec::new();
+        write_td0(&mut td0, &header, Some(&comment), &flat_disk(&[0xe5; 512], 1, 1, 1, 512), Codepage::Cp437).unwrap();
+        td0[18] = 0;    // the day of the month
+        let report = scan_td0(&td0);
+        assert_eq!(report.status, ImageStatus::Ok);
+        let comment = report.comment.unwrap();
+        assert_eq!((comment.timestamp, comment.text.as_str()), (None, "dumped"));
+        assert!(report.findings.iter().any(|f| f.code == "comment-date"));
+    }
+
+    #[test]
+    fn exports_comments_beside_and_in_one_report() {
+        let header = ImageHeader::synthetic(0x02, 0x01, 1);
+        let timestamp = chrono::NaiveDate::from_ymd_opt(1991, 8, 25).unwrap().and_hms_opt(20, 57, 8);
+        let comment = Comment { timestamp, text: "Lotus 1-2-3\0disk 1".to_string(), crc_ok: true, truncated: false, fields: BTreeMap::new() };
+        let mut td0 = Vec::new();
+        write_td0(&mut td0, &header, Some(&comment), &flat_disk(&[0xe5; 512], 1, 1, 1, 512), Codepage::Cp437).unwrap();
+        let dir = temp_dir("comments");
+        let report_path = dir.join("comments.json");
+        let args = Args::parse_from(["kc8587".as_ref(), "--quiet".as_ref(), "--stdin".as_ref(), "--export-comments".as_ref(), dir.as_os_str()]);
+        let mut scan = Scan::default();
+        analyse_image(&args, &mut scan, &mut &td0[..], "F", Path::new(""), None, "lotus/disk 1.td0", &Provenance::default());
+        let bare = td0_of(&[(&[0, 0, 1, 0, 0, 0], &[1, 64, 0, 0xe5, 0xe5])]);
+        analyse_image(&args, &mut scan, &mut &bare[..], "F", Path::new(""), None, "bare.td0", &Provenance::default());
+        assert_eq!(std::fs::read_to_string(dir.join("lotus_disk_1.td0.comment.txt")).unwrap(), "1991-08-25 20:57:08\nLotus 1-2-3\ndisk 1\n");
+        assert!(!dir.join("bare.td0.comment.txt").exists());
+        write_comment_report(&scan, report_path.to_str().unwrap()).unwrap();
+        let comments: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+        let comments = comments.as_object().unwrap();
+        assert_eq!(comments.keys().collect::<Vec<_>>(), ["lotus/disk 1.td0"]);
+        assert_eq!(comments["lotus/disk 1.td0"]["text"], "Lotus 1-2-3\0disk 1");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
 
-fn verbose_error(args: &Args, e: &str) {
-    if args.verbose {
-        println!("Error: {}", e);
+    #[test]
+    fn writes_the_picked_fix() {
+        let dir = temp_dir("repair");
+        let image = dir.join("bad.td0");
+        let output = dir.join("fixed.td0");
+        // a 360K header with its CRC, then the sides byte changed
+        let header = ImageHeader::synthetic(0x02, 0x01, 2);
+        let mut bytes = header.crc_bytes().to_vec();
+        bytes.extend_from_slice(&crc16(&header.crc_bytes()).to_le_bytes());
+        bytes[9] = 3;
+        bytes.extend_from_slice(&[255, 0, 0, 0]);
+        std::fs::write(&image, &bytes).unwrap();
+        assert!(repair_header(image.to_str().unwrap(), Some(output.to_str().unwrap()), None).unwrap());
+        let fixed = std::fs::read(&output).unwrap();
+        assert_eq!(fixed[9], 2);
+        assert_eq!(fixed[12..], bytes[12..]);
+        assert!(repair_header(image.to_str().unwrap(), Some(output.to_str().unwrap()), Some(2)).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
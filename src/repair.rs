@@ -0,0 +1,236 @@
+use std::io::{self, ErrorKind, Read};
+use kc8587::td0::{self, TeleDiskHeaders};
+
+use crate::{
+    analysis::{Disk, Sector, Track},
+    comment_end,
+    fs::Codepage,
+    import::write_td0,
+    report::{comment_timestamp, Comment},
+};
+
+// what the sectors of a repaired image hold when their data can't be trusted
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Filler {
+    pub(crate) byte: u8,
+    pub(crate) damaged: bool,  // whether sectors that fail their CRC or decode to the wrong size are filled too
+}
+
+// rewrite a TD0 as one that reads back cleanly, printing each thing that was put right
+pub(crate) fn repair(image: &str, output: &str, filler: Filler) -> io::Result<()> {
+    let bytes = std::fs::read(image)?;
+    let (repaired, fixes) = repair_td0(&bytes, filler)?;
+    std::fs::write(output, repaired)?;
+    if fixes.is_empty() {
+        println!("{}: nothing to repair, rewritten as {}", image, output);
+    } else {
+        println!("{}: {} repair{}:", image, fixes.len(), if fixes.len() == 1 { "" } else { "s" });
+        for fix in &fixes {
+            println!("    {}", fix);
+        }
+        println!("Wrote {}", output);
+    }
+    Ok(())
+}
+
+// the headers, comment and every sector that can be read, written out again with their CRCs and
+// lengths worked out afresh. sectors whose data can't be decoded get the filler, flagged as read
+// with a CRC error so the damage isn't hidden, and a track cut short keeps the sectors it has
+pub(crate) fn repair_td0(bytes: &[u8], filler: Filler) -> io::Result<(Vec<u8>, Vec<String>)> {
+    let mut input = bytes;
+    let headers = TeleDiskHeaders::from_stream(&mut input)?;
+    let header = headers.image_header;
+    match &header.signature {
+        b"TD" => {},
+        b"td" => return Err(io::Error::new(ErrorKind::Unsupported, "images with advanced compression can't be repaired")),
+        _ => return Err(io::Error::new(ErrorKind::InvalidData, "not a TeleDisk image")),
+    }
+    let mut fixes = Vec::new();
+    if !header.crc_ok() {
+        fixes.push("header CRC recomputed".to_string());
+    }
+
+    let mut comment = None;
+    if let Some(comment_header) = headers.comment_header {
+        let length = comment_header.length as usize;
+        let mut raw = input.get(..length).unwrap_or(input).to_vec();
+        input = &input[raw.len()..];
+        if raw.len() < length {
+            // as when scanning, the tracks are taken to start where the text stops
+            let start = comment_end(&raw);
+            input = &bytes[bytes.len() - raw.len() + start..];
+            raw.truncate(start);
+            fixes.push(format!("comment length {} past the end of the file, cut to its {} bytes of text", length, start));
+        } else if !comment_header.crc_ok(&raw) {
+            fixes.push("comment CRC recomputed".to_string());
+        }
+        let timestamp = comment_timestamp(&comment_header);
+        if timestamp.is_none() {
+            fixes.push("comment date impossible, written as 1970-01-01 00:00:00".to_string());
+        }
+        // code page 437 gives every byte a character of its own, so the text is written back as it was
+        let text = Codepage::Cp437.decode(&raw).trim_end_matches('\0').to_string();
+        comment = Some(Comment { timestamp, text, crc_ok: true, truncated: false, fields: Default::default() });
+    }
+
+    let mut disk = Disk::default();
+    let mut tracks = td0::Tracks::new(input);
+    for track in &mut tracks {
+        let (track, error) = match track {
+            Ok(track) => (track, None),
+            Err(td0::TrackError { error, partial }) => match partial {
+                Some(track) => (track, Some(error)),
+                None => {
+                    fixes.push(format!("image ends after {} tracks ({}), end marker added", disk.tracks.len(), error));
+                    break;
+                },
+            },
+        };
+        let (cylinder, head) = (track.header.cylinder_number, track.header.side_number);
+        if !track.crc_ok {
+            fixes.push(format!("track c{} h{}: header CRC recomputed", cylinder, head));
+        }
+        let (header, read) = track.into_parts();
+        let mut sectors = Vec::with_capacity(read.len());
+        for sector in read {
+            let at = format!("sector c{} h{} s{}", cylinder, head, sector.header.sector_number);
+            let (data, problem) = match sector.fitted_data() {
+                _ if !sector.header.has_data() => (vec![0; sector.header.sector_size as usize], None),
+                Ok((data, None)) if sector.crc_ok(&data) => (data, None),
+                Ok((data, None)) => (data, Some("fails its CRC")),
+                Ok((data, Some(_))) => (data, Some("decodes to the wrong size")),
+                Err(_) => (Vec::new(), Some("can't be decoded")),
+            };
+            let mut header = sector.header;
+            let data = match problem {
+                None => data,
+                Some(why) if !data.is_empty() && !filler.damaged => {
+                    fixes.push(format!("{}: {}, data kept and CRC recomputed", at, why));
+                    data
+                },
+                Some(why) => {
+                    fixes.push(format!("{}: {}, filled with {:02x}", at, why, filler.byte));
+                    header.flags |= 0x02;
+                    vec![filler.byte; header.sector_size as usize]
+                },
+            };
+            sectors.push(Sector { header, data });
+        }
+        let complete = error.is_none();
+        if let Some(error) = error {
+            fixes.push(format!("track c{} h{}: ends after {} of {} sectors ({}), end marker added", cylinder, head,
+                sectors.len(), header.number_of_sectors, error));
+        }
+        disk.tracks.push(Track { header, cylinder: cylinder as usize, sectors });
+        if !complete { break; }
+    }
+    let mut rest = Vec::new();
+    tracks.into_inner().read_to_end(&mut rest)?;
+    if !rest.is_empty() {
+        fixes.push(format!("{} bytes after the end marker dropped", rest.len()));
+    }
+
+    let mut out = Vec::new();
+    write_td0(&mut out, &header, comment.as_ref(), &disk, Codepage::Cp437)?;
+    Ok((out, fixes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kc8587::td0::{crc16, ImageHeader, Td0Reader};
+
+    const FILL: Filler = Filler { byte: 0xe5, damaged: false };
+
+    // a one track image with a comment: a good raw sector, one that fails its CRC, and one with
+    // an encoding there is no such thing as
+    fn image() -> Vec<u8> {
+        let header = ImageHeader::synthetic(0x02, 0x01, 1);
+        let mut bytes = header.crc_bytes().to_vec();
+        bytes[7] |= 0x80;
+        bytes.extend_from_slice(&crc16(&bytes).to_le_bytes());
+        let mut comment = vec![5, 0, 94, 2, 5, 12, 34, 56];
+        comment.extend_from_slice(b"disk\0");
+        bytes.extend_from_slice(&crc16(&comment).to_le_bytes());
+        bytes.extend_from_slice(&comment);
+        let track = [3, 0, 0];
+        bytes.extend_from_slice(&track);
+        bytes.push(crc16(&track) as u8);
+        bytes.extend_from_slice(&[0, 0, 1, 0, 0, crc16(&[0x5a; 128]) as u8, 129, 0, 0]);
+        bytes.extend_from_slice(&[0x5a; 128]);
+        bytes.extend_from_slice(&[0, 0, 2, 0, 0, 0, 129, 0, 0]);
+        bytes.extend_from_slice(&[0x11; 128]);
+        bytes.extend_from_slice(&[0, 0, 3, 0, 0, 0, 1, 0, 7]);
+        bytes.extend_from_slice(&[255, 0, 0, 0]);
+        bytes
+    }
+
+    // every sector's flags and data, checking that everything about the image reads back clean
+    fn read_back(bytes: &[u8]) -> (String, Vec<(u8, Vec<u8>)>) {
+        let mut reader = Td0Reader::new(bytes).unwrap();
+        assert!(reader.headers.image_header.crc_ok());
+        let comment_header = reader.headers.comment_header.as_ref().unwrap();
+        let text = reader.comment.clone().unwrap();
+        assert!(comment_header.crc_ok(&text));
+        let mut sectors = Vec::new();
+        for track in reader.tracks() {
+            let track = track.unwrap();
+            assert!(track.crc_ok);
+            for sector in track.sectors() {
+                let data = sector.data().unwrap();
+                assert!(sector.crc_ok(&data));
+                sectors.push((sector.header.flags, data));
+            }
+        }
+        assert!(reader.into_inner().is_empty());
+        (String::from_utf8(text).unwrap(), sectors)
+    }
+
+    #[test]
+    fn keeps_what_it_can_and_fills_the_rest() {
+        let (repaired, fixes) = repair_td0(&image(), FILL).unwrap();
+        assert_eq!(fixes, ["sector c0 h0 s2: fails its CRC, data kept and CRC recomputed", "sector c0 h0 s3: can't be decoded, filled with e5"]);
+        let (text, sectors) = read_back(&repaired);
+        assert_eq!(text, "disk\0");
+        assert_eq!(sectors, [(0, vec![0x5a; 128]), (0, vec![0x11; 128]), (0x02, vec![0xe5; 128])]);
+
+        let (repaired, fixes) = repair_td0(&image(), Filler { byte: 0xf6, damaged: true }).unwrap();
+        assert_eq!(fixes[0], "sector c0 h0 s2: fails its CRC, filled with f6");
+        assert_eq!(read_back(&repaired).1[1], (0x02, vec![0xf6; 128]));
+    }
+
+    #[test]
+    fn recomputes_crcs_and_drops_what_follows() {
+        let mut bytes = image();
+        bytes[9] = 2;       // the sides, so the header CRC fails
+        bytes[12] ^= 1;     // the comment CRC
+        bytes[30] ^= 1;     // the track header CRC
+        bytes.extend_from_slice(b"junk");
+        let (repaired, fixes) = repair_td0(&bytes, FILL).unwrap();
+        assert_eq!(fixes[..3], ["header CRC recomputed", "comment CRC recomputed", "track c0 h0: header CRC recomputed"]);
+        assert_eq!(fixes.last().unwrap(), "4 bytes after the end marker dropped");
+        read_back(&repaired);
+        assert_eq!(repaired[9], 2);
+    }
+
+    #[test]
+    fn cuts_a_comment_length_and_ends_a_truncated_image() {
+        let mut bytes = image();
+        // a comment length running past the end, and the image cut off in the second sector
+        bytes[14..16].copy_from_slice(&5000u16.to_le_bytes());
+        bytes.truncate(31 + 6 + 2 + 129 + 6 + 2 + 50);
+        let (repaired, fixes) = repair_td0(&bytes, FILL).unwrap();
+        assert_eq!(fixes[0], "comment length 5000 past the end of the file, cut to its 5 bytes of text");
+        assert!(fixes[1].starts_with("track c0 h0: ends after 1 of 3 sectors"), "{}", fixes[1]);
+        let (text, sectors) = read_back(&repaired);
+        assert_eq!((text.as_str(), sectors.len()), ("disk\0", 1));
+    }
+
+    #[test]
+    fn refuses_what_it_cant_rewrite() {
+        let mut bytes = image();
+        bytes[..2].copy_from_slice(b"td");
+        assert_eq!(repair_td0(&bytes, FILL).unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!(repair_td0(b"PK\x03\x04 not a TeleDisk image", FILL).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,1610 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+use serde_json::{json, Value};
+use kc8587::td0::{CommentHeader, HEADER_REPAIRS, ImageHeader};
+
+use crate::{
+    analysis::{
+        interleave::Interleave, protection::Protection, Checksums, Compression, ContentCounts, Disk, Geometry, SectorCounts,
+        SectorStatus, TrackRepeats,
+    },
+    cli::{EXIT_INVALID_IMAGES, EXIT_IO_ERROR, EXIT_NO_TD0_FOUND, ReportFormat},
+    containers::Provenance,
+    sets::DiskSet,
+    fs::{cpm::AmstradFormat, fat::{BootSector, FatUsage, Partition}, FileEntry},
+};
+#[cfg(feature = "sqlite")]
+use crate::cli::QueryTable;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Language {
+    En,
+    De,
+}
+
+// the words and phrases of summaries and reports in the other languages, looked up by their
+// English. anything missing is left in English
+const GERMAN: &[(&str, &str)] = &[
+    ("ok", "ok"),
+    ("bad", "fehlerhaft"),
+    ("empty/aborted dump", "leere/abgebrochene Kopie"),
+    ("truncated", "abgeschnitten"),
+    ("runs past its archive member", "reicht über sein Archivmitglied hinaus"),
+    ("read error", "Lesefehler"),
+    ("health", "Zustand"),
+    ("header", "Kopf"),
+    ("flat image, no header", "Rohabbild, kein Kopf"),
+    ("created by", "erstellt von"),
+    ("comment", "Kommentar"),
+    ("comment fields", "Kommentarfelder"),
+    ("geometry", "Geometrie"),
+    ("cylinders", "Zylinder"),
+    ("heads", "Köpfe"),
+    ("sides", "Seiten"),
+    ("tracks", "Spuren"),
+    ("sectors", "Sektoren"),
+    ("sectors/track", "Sektoren/Spur"),
+    ("bytes/sector", "Bytes/Sektor"),
+    ("sectors/cluster", "Sektoren/Cluster"),
+    ("bytes", "Bytes"),
+    ("CRC errors", "CRC-Fehler"),
+    ("deleted", "gelöscht"),
+    ("user", "Benutzer"),
+    ("missing", "fehlend"),
+    ("odd size", "abweichende Größe"),
+    ("no ID", "ohne ID"),
+    ("numbered 100 or more", "mit Nummer ab 100"),
+    ("identical tracks", "gleiche Spuren"),
+    ("all", "alle"),
+    ("near identical tracks", "fast gleiche Spuren"),
+    ("bytes differ, the first at", "Bytes verschieden, das erste bei"),
+    ("boot sector", "Bootsektor"),
+    ("short jump", "kurzer Sprung"),
+    ("near jump", "naher Sprung"),
+    ("media", "Medium"),
+    ("signature", "Signatur"),
+    ("CP/M boot loader", "CP/M-Urlader"),
+    ("serial", "Seriennummer"),
+    ("loader", "Lader"),
+    ("virus", "Virus"),
+    ("partition table with", "Partitionstabelle mit"),
+    ("partitions", "Partitionen"),
+    ("partition", "Partition"),
+    ("bootable", "startfähig"),
+    ("type", "Typ"),
+    ("start", "Beginn"),
+    ("nothing recognised", "nichts erkannt"),
+    ("filesystem", "Dateisystem"),
+    ("hash", "Prüfsumme"),
+    ("volume", "Datenträger"),
+    ("files", "Dateien"),
+    ("images", "Abbilder"),
+    ("clean", "fehlerfrei"),
+    ("directory", "Verzeichnis"),
+    ("format", "Format"),
+    ("with disk specification block", "mit Diskettenbeschreibung"),
+    ("AMSDOS headers", "AMSDOS-Köpfe"),
+    ("+3DOS headers", "+3DOS-Köpfe"),
+    ("sectors free", "Sektoren frei"),
+    ("clusters of", "Cluster zu"),
+    ("used", "belegt"),
+    ("free", "frei"),
+    ("marked bad", "als defekt markiert"),
+    ("lost chains of", "verlorene Ketten mit"),
+    ("clusters", "Clustern"),
+    ("content", "Inhalt"),
+    ("blank", "leer"),
+    ("text", "Text"),
+    ("binary", "binär"),
+    ("high entropy", "hohe Entropie"),
+    ("bits/byte", "Bits/Byte"),
+    ("compression", "Kompression"),
+    ("bytes stored", "Bytes gespeichert"),
+    ("decoded", "entpackt"),
+    ("raw", "roh"),
+    ("repeated", "wiederholt"),
+    ("without data", "ohne Daten"),
+    ("failed validation", "Prüfung nicht bestanden"),
+    ("undecodable", "nicht dekodierbar"),
+    ("versions", "Versionen"),
+    ("data rates", "Datenraten"),
+    ("drive types", "Laufwerkstypen"),
+    ("formats", "Formate"),
+    ("filesystems", "Dateisysteme"),
+    ("status", "Zustand"),
+    ("damaged", "beschädigt"),
+    ("not images", "keine Abbilder"),
+    ("read errors", "Lesefehler"),
+    ("none", "keine"),
+    ("findings", "Befunde"),
+    ("info", "Hinweis"),
+    ("warning", "Warnung"),
+    ("track headers", "Spurköpfe"),
+    ("interleave", "Interleave"),
+    ("tracks differ", "Spuren abweichend"),
+    ("cylinder skew", "Zylinderversatz"),
+    ("head skew", "Kopfversatz"),
+    ("protection", "Kopierschutz"),
+    ("likely", "wahrscheinlich"),
+    ("duplicate sector IDs", "doppelte Sektor-IDs"),
+    ("mixed sector sizes", "gemischte Sektorgrößen"),
+    ("sectors without IDs or numbered 100 or more", "Sektoren ohne ID oder mit Nummer ab 100"),
+    ("cylinders beyond 79", "Zylinder über 79"),
+    ("gaps in sector numbers", "Lücken in den Sektornummern"),
+    ("extra sectors", "zusätzliche Sektoren"),
+    ("deleted data", "gelöschte Daten"),
+    ("toolchain", "Werkzeuge"),
+    ("advanced compression", "erweiterte Kompression"),
+    ("single density", "einfache Dichte"),
+    ("DOS allocated sectors only", "nur belegte DOS-Sektoren"),
+    ("clock not set", "Uhr nicht gestellt"),
+    ("dumped long after the release", "lange nach Erscheinen kopiert"),
+    ("the comment names its dumper", "Kommentar nennt den Kopierer"),
+    ("zip", "ZIP-Archiv"),
+    ("tar.gz", "tar.gz-Archiv"),
+    ("RLE", "RLE"),
+    ("hex", "Hex"),
+    ("image", "Abbild"),
+    ("version", "Version"),
+    ("data rate", "Datenrate"),
+    ("drive type", "Laufwerkstyp"),
+    ("sectors per track", "Sektoren pro Spur"),
+    ("capacity", "Kapazität"),
+    ("label", "Bezeichnung"),
+    ("comment date", "Kommentardatum"),
+    ("sector map", "Sektorkarte"),
+    ("good", "gut"),
+    ("CRC error", "CRC-Fehler"),
+    ("no data", "ohne Daten"),
+    ("numbered 100+", "Nummer ab 100"),
+    ("stepping", "Schrittweite"),
+    ("sequence", "Folge"),
+    ("dos", "DOS"),
+    ("cylinder", "Zylinder"),
+    ("head", "Kopf"),
+    ("track", "Spur"),
+    ("sector", "Sektor"),
+    ("after the end-of-image marker", "hinter der Endmarke"),
+    ("file name", "Dateiname"),
+    ("filesystem of partition", "Dateisystem der Partition"),
+    ("version {}.{} isn't a TeleDisk release known", "Version {}.{} ist keine bekannte TeleDisk-Version"),
+    ("{} {} isn't a value TeleDisk writes", "{} {} ist kein Wert, den TeleDisk schreibt"),
+    ("drive type {} isn't one TeleDisk knows", "Laufwerkstyp {} ist TeleDisk unbekannt"),
+    ("volume {} of a multi-volume set with check byte {}, which can't be restored without the volumes before it",
+        "Datenträger {} eines mehrteiligen Satzes mit Prüfbyte {}, ohne die vorigen Datenträger nicht wiederherstellbar"),
+    ("only sectors DOS had allocated were dumped", "nur von DOS belegte Sektoren wurden kopiert"),
+    ("volume {} of a set with check byte {}, {} of its volumes found", "Datenträger {} eines Satzes mit Prüfbyte {}, {} seiner Datenträger gefunden"),
+    ("the set is missing volume {}", "dem Satz fehlt Datenträger {}"),
+    ("another image in the set is volume {} too", "ein anderes Abbild des Satzes ist ebenfalls Datenträger {}"),
+    ("the set's file names don't sort in volume order", "die Dateinamen des Satzes sind nicht nach Datenträgern geordnet"),
+    ("its data runs out and {} beside it is probably the next volume, which isn't read",
+        "die Daten enden und {} daneben ist wohl der nächste Datenträger, der nicht gelesen wird"),
+    ("disk {} of {} in the set '{}', {} of its images found", "Diskette {} von {} des Satzes '{}', {} seiner Abbilder gefunden"),
+    ("the set is missing disk {}", "dem Satz fehlt Diskette {}"),
+    ("{} sectors where most tracks have {}", "{} Sektoren, wo die meisten Spuren {} haben"),
+    ("sectors of more than one size: {} bytes", "Sektoren verschiedener Größe: {} Bytes"),
+    ("the comment header's date {} can't be right", "das Datum {} im Kommentarkopf kann nicht stimmen"),
+    ("a {} byte comment but only {} bytes after the header, tracks read from byte {} of it",
+        "ein Kommentar von {} Bytes, aber nur {} Bytes nach dem Kopf, Spuren ab seinem Byte {} gelesen"),
+    ("the boot sector is the {} virus", "der Bootsektor ist der Virus {}"),
+    ("{} chains of {} clusters in use that no file or directory leads to",
+        "{} Ketten mit {} belegten Clustern, zu denen keine Datei und kein Verzeichnis führt"),
+    ("{} clusters marked bad", "{} Cluster als defekt markiert"),
+    ("{} {} and {} {} were read before the data ran out, {} of the {} sectors of the last one",
+        "{} {} und {} {} wurden gelesen, bevor die Daten ausgingen, {} der {} Sektoren der letzten"),
+    ("{} {} and {} {} were read before the data ran out, after the last one",
+        "{} {} und {} {} wurden gelesen, bevor die Daten ausgingen, nach der letzten"),
+    ("{} bytes that start another TeleDisk image, volume {}", "{} Bytes, mit denen ein weiteres TeleDisk-Abbild beginnt, Datenträger {}"),
+    ("{} bytes that start another TeleDisk image with advanced compression, volume {}",
+        "{} Bytes, mit denen ein weiteres TeleDisk-Abbild mit erweiterter Kompression beginnt, Datenträger {}"),
+    ("{} bytes of {} padding", "{} Bytes Füllung mit {}"),
+    ("{} bytes: {}{}", "{} Bytes: {}{}"),
+    ("the track header says cylinder {}, taken as past cylinder 255", "der Spurkopf nennt Zylinder {}, als hinter Zylinder 255 genommen"),
+    ("the data block says it's {} bytes, more than a sector of {} bytes should take, and only its start is decoded",
+        "der Datenblock nennt {} Bytes, mehr als ein Sektor von {} Bytes brauchen sollte, und nur sein Anfang wird entpackt"),
+    ("the data block decodes to more than the sector's {} bytes, and is cut to fit",
+        "der Datenblock ergibt entpackt mehr als die {} Bytes des Sektors und wird gekürzt"),
+    ("the data block decodes to {} of the sector's {} bytes, and is padded with zeros",
+        "der Datenblock ergibt entpackt {} der {} Bytes des Sektors und wird mit Nullen aufgefüllt"),
+    ("encoding method {} isn't raw, repeated or RLE", "Kodierung {} ist weder roh noch wiederholt noch RLE"),
+    ("the data block decodes to {} bytes", "der Datenblock ergibt entpackt {} Bytes"),
+    ("the data block stops partway through a run", "der Datenblock endet mitten in einer Folge"),
+];
+
+pub(crate) fn tr(language: Language, english: &str) -> &str {
+    let table = match language {
+        Language::En => return english,
+        Language::De => GERMAN,
+    };
+    table.iter().find(|(key, _)| *key == english).map_or(english, |(_, translation)| translation)
+}
+
+// everything gathered over a whole run, for the reports written once the walk is done
+#[derive(Debug, Default)]
+pub(crate) struct Scan {
+    pub(crate) reports: Vec<ImageReport>,
+    pub(crate) invalid_images: usize,  // .td0 files without a readable TeleDisk header
+    io_errors: usize,
+    images_per_dir: BTreeMap<PathBuf, usize>,  // for --sample-per-dir
+    pub(crate) walk_summaries: Vec<WalkSummary>,  // for --walk-summary, each directory and archive as it was finished
+    pub(crate) listed: usize,  // TD0s with a good header, with --list
+    pub(crate) passed_over: usize,  // images left unread by --drive-type and the other header filters
+    output_stems: BTreeSet<String>,  // the names per-image output files have been given, in lower case
+}
+
+impl Scan {
+    // with --sample-verbose N, whether the next image found in dir gets full detail
+    pub(crate) fn sample_in_detail(&mut self, n: u64, per_dir: bool, dir: &Path) -> bool {
+        if per_dir {
+            let seen = self.images_per_dir.entry(dir.to_path_buf()).or_default();
+            *seen += 1;
+            *seen <= n as usize
+        } else {
+            (self.reports.len() as u64).is_multiple_of(n)
+        }
+    }
+
+    // a name for an image's output files that no other image's have had: paths made into file
+    // names can come out the same, and files can share an ID, so later ones get ~2, ~3 and so on.
+    // names differing only in case are the same file on some systems
+    pub(crate) fn output_stem(&mut self, stem: &str) -> String {
+        let mut unique = stem.to_string();
+        for n in 2.. {
+            if self.output_stems.insert(unique.to_lowercase()) { break; }
+            unique = format!("{}~{}", stem, n);
+        }
+        unique
+    }
+
+    pub(crate) fn exit_code(&self) -> i32 {
+        if self.io_errors > 0 || self.reports.iter().any(|r| r.status == ImageStatus::ReadError) {
+            EXIT_IO_ERROR
+        } else if self.invalid_images > 0 || self.reports.iter().any(|r| r.failed_validation()) {
+            EXIT_INVALID_IMAGES
+        } else if self.reports.is_empty() && self.listed == 0 && self.passed_over == 0 {
+            EXIT_NO_TD0_FOUND
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn io_error(&mut self, e: &str) {
+        self.io_errors += 1;
+        log::error!("{}", e);
+    }
+
+    // how far the scan has got, for telling what a file turned out to hold
+    pub(crate) fn mark(&self) -> (usize, usize) {
+        (self.reports.len(), self.invalid_images)
+    }
+
+    // the images found since mark, and how many of them read without failing validation
+    pub(crate) fn images_since(&self, (reports, invalid_images): (usize, usize)) -> (usize, usize) {
+        let found = &self.reports[reports..];
+        (found.len() + self.invalid_images - invalid_images, found.iter().filter(|r| !r.failed_validation()).count())
+    }
+
+    // the reports --only lets through, for everything written after the walk
+    pub(crate) fn shown(&self) -> impl Iterator<Item = &ImageReport> {
+        self.reports.iter().filter(|r| r.shown)
+    }
+}
+
+// what a directory or archive held: the files in it, how many were images, counting those in
+// archives in a directory, and how many of those read without failing validation
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub(crate) struct WalkSummary {
+    pub(crate) kind: &'static str,  // directory, zip or tar.gz
+    pub(crate) path: String,
+    pub(crate) files: usize,
+    pub(crate) images: usize,
+    pub(crate) clean: usize,
+}
+
+impl WalkSummary {
+    pub(crate) fn add(&mut self, (images, clean): (usize, usize)) {
+        self.files += 1;
+        self.images += images;
+        self.clean += clean;
+    }
+
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        format!("{} {}: {} {}, {} {}, {} {}", t(self.kind), self.path, self.files, t("files"), self.images, t("images"), self.clean, t("clean"))
+    }
+}
+
+// overall outcome of walking an image's track data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ImageStatus {
+    Ok,         // track data read through to the end-of-image marker
+    #[serde(rename = "empty")]
+    EmptyDump,  // valid header but no track data at all (aborted dump)
+    Truncated,  // the data ran out before the end-of-image marker
+    Overrun,    // the image went on past the end of its archive member
+    ReadError,  // reading failed for some reason other than running out of data
+}
+
+impl ImageStatus {
+    // stable short name used in the catalogue
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            ImageStatus::Ok => "ok",
+            ImageStatus::EmptyDump => "empty",
+            ImageStatus::Truncated => "truncated",
+            ImageStatus::Overrun => "overrun",
+            ImageStatus::ReadError => "read-error",
+        }
+    }
+}
+
+impl fmt::Display for ImageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ImageStatus::Ok => "ok",
+            ImageStatus::EmptyDump => "empty/aborted dump",
+            ImageStatus::Truncated => "truncated",
+            ImageStatus::Overrun => "runs past its archive member",
+            ImageStatus::ReadError => "read error",
+        })
+    }
+}
+
+// something unusual about an image that doesn't stop it being read, with a stable code to filter
+// and count by. it's only serialized, as its code and the header field it's in are static strings.
+// its message is kept in English for the catalogue, and its template and what fills it in so it
+// can be described in another language
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Finding {
+    pub(crate) severity: Severity,
+    pub(crate) code: &'static str,
+    pub(crate) message: String,
+    location: Location,
+    #[serde(skip)]
+    template: &'static str,     // the message with {} for each of args
+    #[serde(skip)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Severity {
+    Info,       // worth knowing, like a header recording DOS allocation
+    Warning,    // a value TeleDisk doesn't write, or data it wouldn't leave
+}
+
+// where in the image a finding is
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "in", rename_all = "lowercase")]
+pub(crate) enum Location {
+    Header { field: &'static str },
+    Comment,
+    Track { cylinder: usize, head: u8 },
+    Sector { cylinder: usize, head: u8, sector: u8 },
+    #[serde(rename = "end")]
+    AfterEnd,               // past the end-of-image marker
+    #[serde(rename = "file name")]
+    FileName,               // the image's file name, not anything in it
+    Filesystem { partition: Option<u8> },  // the FAT filesystem, of this partition on a hard disk
+}
+
+impl Finding {
+    // made through finding!, which turns what fills in the template into strings
+    pub(crate) fn new(severity: Severity, code: &'static str, location: Location, template: &'static str, args: Vec<String>) -> Self {
+        Finding { severity, code, message: fill(template, &args), location, template, args }
+    }
+
+    // words among what fills it in, like the header field a value is in, are translated too
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let args: Vec<String> = self.args.iter().map(|arg| tr(language, arg).to_string()).collect();
+        format!("{} {} - {} - {}", tr(language, self.severity.code()), self.code, self.location.describe(language),
+            fill(tr(language, self.template), &args))
+    }
+}
+
+// a template with each {} replaced by the next of args
+fn fill(template: &str, args: &[String]) -> String {
+    let mut pieces = template.split("{}");
+    let mut text = pieces.next().unwrap_or_default().to_string();
+    for (piece, arg) in pieces.zip(args) {
+        text += arg;
+        text += piece;
+    }
+    text
+}
+
+impl Severity {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+impl Location {
+    pub(crate) fn describe(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        match self {
+            Location::Header { field } => format!("{} {}", t("header"), t(field)),
+            Location::Comment => t("comment").to_string(),
+            Location::Track { cylinder, head } => format!("{} {} {} {}", t("cylinder"), cylinder, t("head"), head),
+            Location::Sector { cylinder, head, sector } => format!("{} {} {} {} {} {}", t("cylinder"), cylinder, t("head"), head, t("sector"), sector),
+            Location::AfterEnd => t("after the end-of-image marker").to_string(),
+            Location::FileName => t("file name").to_string(),
+            Location::Filesystem { partition: None } => t("filesystem").to_string(),
+            Location::Filesystem { partition: Some(partition) } => format!("{} {}", t("filesystem of partition"), partition),
+        }
+    }
+}
+
+// TeleDisk's drive types: 5.25" 96 tpi, 360K, 1.2M, 720K, 1.44M, 8" and 3.5"
+const KNOWN_DRIVE_TYPES: std::ops::RangeInclusive<u8> = 0..=6;
+
+// header values TeleDisk doesn't write, or writes rarely enough to be worth a look
+pub(crate) fn header_findings(header: &ImageHeader) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if !TELEDISK_RELEASES.iter().any(|(low, high, ..)| (*low..=*high).contains(&header.version)) {
+        findings.push(finding!(Severity::Warning, "unknown-version", Location::Header { field: "version" },
+            "version {}.{} isn't a TeleDisk release known", header.version / 10, header.version % 10));
+    }
+    let bytes = header.crc_bytes();
+    for &(field, offset, values) in HEADER_REPAIRS {
+        if !values.contains(&bytes[offset]) {
+            let code = match field {
+                "data rate" => "unusual-data-rate",
+                "stepping" => "unusual-stepping",
+                _ => "unusual-sides",
+            };
+            findings.push(finding!(Severity::Warning, code, Location::Header { field },
+                "{} {} isn't a value TeleDisk writes", field, format!("{:02x}", bytes[offset])));
+        }
+    }
+    if !KNOWN_DRIVE_TYPES.contains(&header.drive_type) {
+        findings.push(finding!(Severity::Warning, "unknown-drive-type", Location::Header { field: "drive type" },
+            "drive type {} isn't one TeleDisk knows", format!("{:02x}", header.drive_type)));
+    }
+    if header.sequence != 0 {
+        findings.push(finding!(Severity::Warning, "later-volume", Location::Header { field: "sequence" },
+            "volume {} of a multi-volume set with check byte {}, which can't be restored without the volumes before it",
+            header.sequence as usize + 1, format!("{:02x}", header.check_sequence)));
+    }
+    if header.dos_flag != 0 {
+        findings.push(finding!(Severity::Info, "dos-allocation", Location::Header { field: "dos" },
+            "only sectors DOS had allocated were dumped"));
+    }
+    findings
+}
+
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Comment {
+    pub(crate) timestamp: Option<NaiveDateTime>,   // None when the comment header's date is impossible
+    pub(crate) text: String,
+    pub(crate) crc_ok: bool,
+    pub(crate) truncated: bool,    // its length ran past the end of the file
+    pub(crate) fields: BTreeMap<String, String>,  // what --comment-pattern and the built in patterns pulled out
+}
+
+impl Comment {
+    // its date, then the text with the NULs TeleDisk separates lines with made newlines
+    pub(crate) fn to_text(&self) -> String {
+        format!("{}\n{}\n", self.date(), self.text.replace('\0', "\n"))
+    }
+
+    pub(crate) fn date(&self) -> String {
+        self.timestamp.map_or_else(|| "-".to_string(), |t| t.to_string())
+    }
+}
+
+// when a comment header says the image was made: years are counted from 1900 and months from 0.
+// none when the fields make no date, as a month or day of 0 often does
+pub(crate) fn comment_timestamp(header: &CommentHeader) -> Option<NaiveDateTime> {
+    let date = NaiveDate::from_ymd_opt(header.year as i32 + 1900, header.month as u32 + 1, header.day as u32)?;
+    let time = NaiveTime::from_hms_opt(header.hour as u32, header.minute as u32, header.second as u32)?;
+    Some(NaiveDateTime::new(date, time))
+}
+
+// the comment header's date as its fields have it, for when they make no real one
+pub(crate) fn raw_comment_date(header: &CommentHeader) -> String {
+    format!("{}-{:02}-{:02} {:02}:{:02}:{:02}", header.year as i32 + 1900, header.month as u32 + 1, header.day,
+        header.hour, header.minute, header.second)
+}
+
+// the TeleDisk releases by the version byte they write, which is the version times ten so 21 is
+// 2.1, and the years each was the one in use: lowest and highest version, release, from and to
+const TELEDISK_RELEASES: &[(u8, u8, &str, i32, i32)] = &[
+    (10, 19, "TeleDisk 1.x", 1985, 1987),
+    (20, 20, "TeleDisk 2.0x", 1987, 1988),
+    (21, 21, "TeleDisk 2.1x", 1988, 1991),
+];
+
+// what a data rate, without the FM bit, and drive type pairing says TeleDisk ran on
+const DUMPING_MACHINES: &[(u8, u8, &str)] = &[
+    (0, 1, "PC or XT with a 360K drive"),
+    (1, 1, "AT with a 1.2M drive set up as 360K"),
+    (1, 2, "AT with a 1.2M drive reading a double density disk"),
+    (2, 2, "AT with a 1.2M drive"),
+    (0, 3, "PC with a 720K drive"),
+    (0, 4, "PC with a 1.44M drive reading a 720K disk"),
+    (2, 4, "PC with a 1.44M drive"),
+];
+
+// dates this long after a release ended are archival dumps made with old software
+const ARCHIVAL_YEARS: i32 = 5;
+
+// the probable setup an image was dumped with, from its header and the shape of its comment
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Toolchain {
+    release: String,            // the TeleDisk release, or the version byte if it's none known
+    pub(crate) machine: String,            // what the data rate and drive type suggest
+    era: String,                // the comment's year when it's believable, otherwise the release's years
+    clues: Vec<&'static str>,   // anything else the header or comment gives away
+}
+
+impl Toolchain {
+    pub(crate) fn identify(header: &ImageHeader, comment: Option<&Comment>) -> Self {
+        let mut clues = Vec::new();
+        if header.signature == *b"td" {
+            clues.push("advanced compression");
+        }
+        if header.data_rate & 0x80 != 0 {
+            clues.push("single density");
+        }
+        if header.dos_flag != 0 {
+            clues.push("DOS allocated sectors only");
+        }
+        let release = TELEDISK_RELEASES.iter().find(|(low, high, ..)| (*low..=*high).contains(&header.version));
+        let rate = header.data_rate & 0x7f;
+        let machine = DUMPING_MACHINES.iter().find(|&&(r, drive, _)| r == rate && drive == header.drive_type)
+            .map_or_else(|| format!("drive type {} at {} kbps", header.drive_type, match rate {
+                0 => "250", 1 => "300", 2 => "500", _ => "?",
+            }), |(.., machine)| machine.to_string());
+        let mut era = release.map(|&(.., from, to)| format!("{}-{}", from, to));
+        if let Some(comment) = comment {
+            if let Some(year) = comment.timestamp.map(|t| t.year()) {
+                match release {
+                    Some(&(.., from, _)) if year < from => clues.push("clock not set"),
+                    Some(&(.., to)) if year > to + ARCHIVAL_YEARS => {
+                        clues.push("dumped long after the release");
+                        era = Some(year.to_string());
+                    },
+                    _ => era = Some(year.to_string()),
+                }
+            }
+            if comment.fields.contains_key("dumper") {
+                clues.push("the comment names its dumper");
+            }
+        }
+        Toolchain {
+            release: release.map_or_else(|| format!("TeleDisk version {:02x}", header.version), |(.., name, _, _)| name.to_string()),
+            machine,
+            era: era.unwrap_or_else(|| "?".to_string()),
+            clues,
+        }
+    }
+
+    pub(crate) fn describe(&self, language: Language) -> String {
+        format!("{} {} - {} - {}{}", tr(language, "toolchain"), self.release, self.machine, self.era,
+            self.clues.iter().map(|clue| format!(", {}", tr(language, clue))).collect::<String>())
+    }
+}
+
+// what an image was read from
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+pub(crate) enum ImageFormat {
+    Td0,
+    Flat,
+    Dsk { creator: String },    // CPCEMU
+    Edsk { creator: String },   // extended CPCEMU
+}
+
+impl ImageFormat {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            ImageFormat::Td0 => "td0",
+            ImageFormat::Flat => "flat",
+            ImageFormat::Dsk { .. } => "dsk",
+            ImageFormat::Edsk { .. } => "edsk",
+        }
+    }
+}
+
+// what a scan learned about one image, kept until the end of the run for the catalogue, whose
+// records are what it serializes to
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct ImageReport {
+    pub(crate) path: String,
+    pub(crate) id: Option<String>,         // with --ids, see image_id
+    pub(crate) source: String,             // F/Z/T/S as in the text output
+    pub(crate) container: Option<String>,
+    #[serde(skip)]
+    pub(crate) directory: String,          // the path of the directory, archive or archive directory it's in
+    #[serde(skip)]
+    pub(crate) name: String,               // the image's own file name, without any container path
+    #[serde(flatten)]
+    pub(crate) format: ImageFormat,
+    #[serde(serialize_with = "header_with_crc")]
+    pub(crate) header: Option<ImageHeader>,   // only TD0 images have one
+    pub(crate) comment: Option<Comment>,
+    pub(crate) toolchain: Option<Toolchain>,  // TD0 images only, as it's worked out from the header
+    pub(crate) label: Option<String>,      // volume label, if the filesystem has one
+    pub(crate) filesystem: Option<&'static str>,
+    pub(crate) content_hash: Option<String>,  // SHA-1 of the decoded sectors in logical order
+    pub(crate) match_hash: Option<String>,    // the same, but with any --hash-exclude regions zeroed
+    pub(crate) checksums: Option<Checksums>,  // of the decoded sectors in logical order, as DATs list them
+    #[serde(skip)]
+    pub(crate) track_hashes: Vec<((usize, u8), [u8; 20])>,  // per track, only kept for --find-similar
+    #[serde(skip)]
+    pub(crate) sector_map: Vec<((usize, u8), Vec<SectorStatus>)>,  // per track, only kept for --report-html
+    #[serde(skip)]
+    pub(crate) map_png: Option<PathBuf>,   // the --map-png file written for it, for --report-html to show
+    #[serde(skip)]
+    pub(crate) files: Vec<FileEntry>,      // for the database and --out-dir, too many for the catalogue
+    #[cfg(feature = "browse")]
+    #[serde(skip)]
+    pub(crate) disk: Option<Disk>,         // the sectors themselves, only kept for browse
+    pub(crate) status: ImageStatus,
+    pub(crate) tracks: usize,
+    pub(crate) sectors: usize,
+    pub(crate) geometry: Geometry,
+    pub(crate) sector_counts: SectorCounts,
+    pub(crate) content: ContentCounts,
+    pub(crate) protection: Protection,
+    pub(crate) interleave: Option<Interleave>,    // flat images don't record the order sectors were in
+    pub(crate) compression: Compression,
+    pub(crate) track_repeats: TrackRepeats,
+    pub(crate) boot: Option<BootSector>,
+    pub(crate) amstrad: Option<AmstradFormat>,
+    pub(crate) disk_set: Option<DiskSet>,  // filled in once the scan is done, see group_disk_sets
+    pub(crate) partitions: Vec<Partition>,
+    pub(crate) fat_usage: Vec<FatUsage>,   // a FAT filesystem's, or each FAT partition's
+    pub(crate) health: u8,                 // 0 to 100, see health_score
+    pub(crate) findings: Vec<Finding>,
+    #[serde(skip)]
+    pub(crate) shown: bool,                // matches every --only filter; the rest are kept for the exit status
+    pub(crate) provenance: Provenance,
+    pub(crate) sort_keys: SortKeys,
+}
+
+// the header as the library serializes it, with whether its CRC checks out
+fn header_with_crc<S: serde::Serializer>(header: &Option<ImageHeader>, serializer: S) -> Result<S::Ok, S::Error> {
+    #[derive(serde::Serialize)]
+    struct Checked<'a> {
+        #[serde(flatten)]
+        header: &'a ImageHeader,
+        crc_ok: bool,
+    }
+    serde::Serialize::serialize(&header.as_ref().map(|header| Checked { header, crc_ok: header.crc_ok() }), serializer)
+}
+
+// the image's name without its extension, volume label and comment as sort_key has them, for
+// sorting the database by
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct SortKeys {
+    name: String,
+    label: Option<String>,
+    comment: Option<String>,
+}
+
+impl SortKeys {
+    pub(crate) fn of(name: &str, label: Option<&str>, comment: Option<&Comment>) -> Self {
+        SortKeys {
+            name: sort_key(name.rsplit_once('.').map_or(name, |(stem, _)| stem)),
+            label: label.map(sort_key),
+            comment: comment.map(|c| sort_key(&c.text)),
+        }
+    }
+}
+
+impl ImageReport {
+    fn failed_validation(&self) -> bool {
+        self.status != ImageStatus::Ok
+            || self.header.as_ref().is_some_and(|h| !h.crc_ok())
+            || self.comment.as_ref().is_some_and(|c| !c.crc_ok)
+    }
+
+    // everything about the image in a few lines of text, the files last
+    pub(crate) fn card(&self, language: Language) -> String {
+        let t = |english| tr(language, english);
+        let crc = |ok: bool| t(if ok { "ok" } else { "bad" });
+        let mut lines = vec![
+            self.path.clone(),
+            format!("    {} : {} - {} {}", self.source, tr(language, &self.status.to_string()), t("health"), self.health),
+            match &self.header {
+                Some(h) => format!("    {} {} seq {:02x} ver {:02x} rate {:02x} type {:02x} step {:02x} dos {:02x} sides {:02x} - CRC {}", t("header"),
+                    String::from_utf8_lossy(&h.signature), h.sequence, h.version, h.data_rate, h.drive_type,
+                    h.stepping & 0x7f, h.dos_flag, h.sides, crc(h.crc_ok())),
+                None => match &self.format {
+                    ImageFormat::Dsk { creator } => format!("    MV - CPCEMU {} '{}'", t("created by"), creator),
+                    ImageFormat::Edsk { creator } => format!("    EXTENDED CPC DSK {} '{}'", t("created by"), creator),
+                    _ => format!("    {}", t("flat image, no header")),
+                },
+            },
+        ];
+        if let Some(comment) = &self.comment {
+            lines.push(format!("    {} {} - CRC {}{} : {}", t("comment"), comment.date(), crc(comment.crc_ok),
+                if comment.truncated { format!(", {}", t("truncated")) } else { String::new() }, comment.text));
+            if !comment.fields.is_empty() {
+                lines.push(format!("    {} {}", t("comment fields"), comment.fields.iter()
+                    .map(|(name, value)| format!("{} '{}'", name, value)).collect::<Vec<_>>().join(" ")));
+            }
+        }
+        if let Some(toolchain) = &self.toolchain {
+            lines.push(format!("    {}", toolchain.describe(language)));
+        }
+        if self.tracks > 0 {
+            lines.push(format!("    {}", self.geometry.describe(language)));
+            lines.push(format!("    {} {} {} {} - {}", self.tracks, t("tracks"), self.sectors, t("sectors"), self.sector_counts.describe(language)));
+            lines.push(format!("    {}", self.content.describe(language)));
+            lines.extend(self.track_repeats.describe(language).into_iter().map(|line| format!("    {}", line)));
+            if let Some(interleave) = &self.interleave {
+                lines.push(format!("    {}", interleave.describe(language)));
+            }
+            lines.push(format!("    {}", self.compression.describe(language)));
+            if self.protection.likelihood > 0 {
+                lines.push(format!("    {}", self.protection.describe(language)));
+            }
+        }
+        if let Some(boot) = &self.boot {
+            lines.push(format!("    {}", boot.describe(language)));
+        }
+        if let Some(amstrad) = &self.amstrad {
+            lines.push(format!("    {}", amstrad.describe(language)));
+        }
+        for partition in &self.partitions {
+            lines.push(format!("    {} {}{} {} {:02x} ({}) {} {} {} {}", t("partition"), partition.index,
+                if partition.bootable { format!(" {}", t("bootable")) } else { String::new() }, t("type"), partition.kind,
+                partition.kind_name, t("start"), partition.start, t("sectors"), partition.sectors));
+        }
+        for usage in &self.fat_usage {
+            lines.push(format!("    {}", usage.describe(language)));
+        }
+        if let Some(filesystem) = self.filesystem {
+            lines.push(format!("    {} {}{}", t("filesystem"), filesystem, self.label.as_ref().map(|l| format!(" '{}'", l)).unwrap_or_default()));
+        }
+        if let Some(checksums) = &self.checksums {
+            lines.push(format!("    {} sha1 {} md5 {} crc32 {}", t("hash"), checksums.sha1, checksums.md5, checksums.crc32));
+        }
+        lines.extend(self.findings.iter().map(|finding| format!("    {}", finding.describe(language))));
+        for file in &self.files {
+            lines.push(format!("        {}{}", file.partition.map(|p| format!("{}: ", p)).unwrap_or_default(), file.listing(language)));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+// a rough 0-100 measure for sorting out which images most need attention. anything that couldn't
+// be read costs the most, then sectors without good data, then things that merely look wrong
+pub(crate) fn health_score(disk: &Disk, status: ImageStatus, header: Option<&ImageHeader>, comment: Option<&Comment>,
+        geometry: &Geometry, counts: &SectorCounts) -> u8 {
+    let mut penalty = match status {
+        ImageStatus::Ok => 0,
+        ImageStatus::EmptyDump => return 0,
+        ImageStatus::Truncated | ImageStatus::Overrun => 40,
+        ImageStatus::ReadError => 50,
+    };
+    if header.is_some_and(|h| !h.crc_ok()) { penalty += 10; }
+    if comment.is_some_and(|c| !c.crc_ok) { penalty += 5; }
+
+    penalty += (5 * (counts.crc_errors + counts.missing)).min(50);
+
+    if geometry.min_sectors_per_track != geometry.max_sectors_per_track { penalty += 5; }
+    if counts.odd_size > 0 { penalty += 5; }
+
+    if disk.fat_consistent() == Some(false) { penalty += 10; }
+
+    100u8.saturating_sub(penalty.min(100) as u8)
+}
+
+// a key that sorts text sensibly whatever code page it came from: transliterated to ASCII, case
+// folded, punctuation dropped and runs of digits zero padded so "Disk 2" sorts before "Disk 10"
+fn sort_key(text: &str) -> String {
+    let folded = deunicode::deunicode(text).to_lowercase();
+    let mut key = String::with_capacity(folded.len());
+    let mut digits = String::new();
+    for c in folded.chars().chain(std::iter::once(' ')) {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        if !digits.is_empty() {
+            key.push_str(&format!("{:0>8}", digits));
+            digits.clear();
+        }
+        if c.is_ascii_alphanumeric() {
+            key.push(c);
+        } else if !key.is_empty() && !key.ends_with(' ') {
+            key.push(' ');
+        }
+    }
+    key.trim_end().to_string()
+}
+
+// a quick selection of the images reported, for --only
+#[derive(Debug, Clone)]
+pub(crate) enum OnlyFilter {
+    Filesystem(String, bool),   // the name, and whether it should match rather than not
+    Health(&'static str, u8),   // the comparison and the score
+    Status(String),
+    Format(String),
+    HasComment,
+    HasLabel,
+    HasFiles,
+    Finding(String),
+}
+
+impl OnlyFilter {
+    pub(crate) fn matches(&self, report: &ImageReport) -> bool {
+        match self {
+            OnlyFilter::Filesystem(name, wanted) => report.filesystem.is_some_and(|fs| fs.eq_ignore_ascii_case(name)) == *wanted,
+            OnlyFilter::Health(op, score) => match *op {
+                "<" => report.health < *score,
+                "<=" => report.health <= *score,
+                ">" => report.health > *score,
+                ">=" => report.health >= *score,
+                _ => report.health == *score,
+            },
+            OnlyFilter::Status(code) => report.status.code() == code,
+            OnlyFilter::Format(code) => report.format.code() == code,
+            OnlyFilter::HasComment => report.comment.as_ref().is_some_and(|c| !c.text.trim().is_empty()),
+            OnlyFilter::HasLabel => report.label.is_some(),
+            OnlyFilter::HasFiles => report.files.iter().any(|file| !file.deleted),
+            OnlyFilter::Finding(code) => report.findings.iter().any(|f| f.code == code),
+        }
+    }
+}
+
+// an --only filter: a flag, or a field, comparison and value
+pub(crate) fn parse_only(spec: &str) -> Result<OnlyFilter, String> {
+    match spec.trim() {
+        "has-comment" => return Ok(OnlyFilter::HasComment),
+        "has-label" => return Ok(OnlyFilter::HasLabel),
+        "has-files" => return Ok(OnlyFilter::HasFiles),
+        _ => {},
+    }
+    let op = ["<=", ">=", "!=", "<", ">", "="].into_iter().find(|op| spec.contains(op))
+        .ok_or_else(|| format!("'{}' isn't fs=, health<, status=, format=, finding=, has-comment, has-label or has-files", spec))?;
+    let (field, value) = spec.split_once(op).unwrap();
+    let (field, value) = (field.trim(), value.trim());
+    match (field, op) {
+        ("fs", "=" | "!=") => Ok(OnlyFilter::Filesystem(value.to_string(), op == "=")),
+        ("health", "!=") => Err("health can be compared with <, <=, =, >= or >".to_string()),
+        ("health", _) => value.parse::<u8>().ok().filter(|&n| n <= 100).map(|n| OnlyFilter::Health(op, n))
+            .ok_or_else(|| format!("'{}' isn't a health score from 0 to 100", value)),
+        ("status", "=") => Ok(OnlyFilter::Status(value.to_lowercase())),
+        ("format", "=") => Ok(OnlyFilter::Format(value.to_lowercase())),
+        ("finding", "=") => Ok(OnlyFilter::Finding(value.to_lowercase())),
+        _ => Err(format!("'{}' can't be filtered on with {}", field, op)),
+    }
+}
+
+// the volumes of a multi-volume TD0 share the header's check byte and are numbered from 0 by its
+// sequence byte. images in one directory or archive with the same check byte, any of them past
+// the first volume, are taken as a set and checked for missing, repeated and misnamed volumes.
+// a first volume that runs out of data with a .td1 file beside it is most likely the start of one
+pub(crate) fn check_volume_sets(scan: &mut Scan) {
+    let mut sets: BTreeMap<(String, u8), Vec<(u8, usize)>> = BTreeMap::new();
+    for (i, report) in scan.reports.iter().enumerate() {
+        if let (ImageFormat::Td0, Some(header)) = (&report.format, &report.header) {
+            sets.entry((report.directory.clone(), header.check_sequence)).or_default().push((header.sequence, i));
+        }
+    }
+    for ((_, check), mut members) in sets {
+        if members.iter().all(|&(volume, _)| volume == 0) {
+            if let [(_, first)] = members[..] {
+                next_volume_file(&mut scan.reports[first]);
+            }
+            continue;
+        }
+        let name = |i: usize| scan.reports[i].name.to_lowercase();
+        members.sort_by_key(|&(volume, i)| (volume, name(i)));
+        let named_in_order = members.windows(2).all(|pair| name(pair[0].1) <= name(pair[1].1));
+        let numbers: BTreeSet<u8> = members.iter().map(|&(volume, _)| volume).collect();
+        let last = *numbers.iter().next_back().unwrap();
+        let missing: Vec<String> = (0..=last).filter(|n| !numbers.contains(n)).map(|n| (n as usize + 1).to_string()).collect();
+        let volumes = members.len();
+        for &(volume, i) in &members {
+            let repeated = members.iter().filter(|&&(other, _)| other == volume).count() > 1;
+            let report = &mut scan.reports[i];
+            let mut add = |finding: Finding| {
+                match finding.severity {
+                    Severity::Info => log::info!("{}: {}", report.path, finding.message),
+                    Severity::Warning => log::warn!("{}: {}", report.path, finding.message),
+                }
+                report.findings.push(finding);
+            };
+            let location = || Location::Header { field: "sequence" };
+            add(finding!(Severity::Info, "volume-set", location(), "volume {} of a set with check byte {}, {} of its volumes found",
+                volume as usize + 1, format!("{:02x}", check), volumes));
+            if !missing.is_empty() {
+                add(finding!(Severity::Warning, "volume-missing", location(), "the set is missing volume {}", missing.join(", ")));
+            }
+            if repeated {
+                add(finding!(Severity::Warning, "volume-repeated", location(), "another image in the set is volume {} too", volume as usize + 1));
+            }
+            if !named_in_order {
+                add(finding!(Severity::Warning, "volume-order", location(), "the set's file names don't sort in volume order"));
+            }
+        }
+    }
+}
+
+// TeleDisk names the volumes after the first .td1, .td2 and so on, which aren't read
+fn next_volume_file(report: &mut ImageReport) {
+    if report.container.is_some() || !matches!(report.status, ImageStatus::Truncated) { return; }
+    let Some(path) = report.provenance.host_file.as_deref() else { return };
+    let extension = match path.extension().and_then(|e| e.to_str()) {
+        Some("TD0") => "TD1",
+        Some(_) => "td1",
+        None => return,
+    };
+    let next = path.with_extension(extension);
+    if next.is_file() {
+        report.findings.push(finding!(Severity::Info, "volume-files", Location::AfterEnd,
+            "its data runs out and {} beside it is probably the next volume, which isn't read",
+            next.file_name().unwrap_or_default().to_string_lossy()));
+    }
+}
+
+pub(crate) fn print_duplicates(scan: &Scan) {
+    let mut by_hash: BTreeMap<&str, Vec<&ImageReport>> = BTreeMap::new();
+    for report in scan.shown() {
+        if let Some(hash) = report.match_hash.as_ref().or(report.content_hash.as_ref()) {
+            by_hash.entry(hash).or_default().push(report);
+        }
+    }
+    for (hash, reports) in by_hash.iter().filter(|(_, reports)| reports.len() > 1) {
+        println!("Duplicates {} ({} images):", hash, reports.len());
+        for report in reports {
+            println!("    {}", report.path);
+        }
+    }
+}
+
+// compares every pair of images track by track, so catches re-dumps where a few sectors read
+// differently. identical images are left to --find-duplicates
+pub(crate) fn print_similar(scan: &Scan, percent: u64) {
+    let reports: Vec<&ImageReport> = scan.shown().filter(|r| !r.track_hashes.is_empty()).collect();
+    for (i, a) in reports.iter().enumerate() {
+        for b in &reports[i + 1..] {
+            if a.content_hash == b.content_hash { continue; }
+            let (shared, total) = shared_tracks(&a.track_hashes, &b.track_hashes);
+            if shared * 100 >= total * percent as usize {
+                println!("Similar {}% ({}/{} tracks):", shared * 100 / total, shared, total);
+                println!("    {}", a.path);
+                println!("    {}", b.path);
+            }
+        }
+    }
+}
+
+// how many tracks at the same cylinder and head have the same contents, out of the most either
+// image has. both are in order of cylinder and head, as Disk::track_hashes gives them
+fn shared_tracks(a: &[((usize, u8), [u8; 20])], b: &[((usize, u8), [u8; 20])]) -> (usize, usize) {
+    let (mut shared, mut ai, mut bi) = (0, 0, 0);
+    while ai < a.len() && bi < b.len() {
+        let ((a_pos, a_hash), (b_pos, b_hash)) = (&a[ai], &b[bi]);
+        if a_pos == b_pos && a_hash == b_hash { shared += 1; }
+        if a_pos <= b_pos { ai += 1; }
+        if b_pos <= a_pos { bi += 1; }
+    }
+    (shared, a.len().max(b.len()))
+}
+
+pub(crate) fn write_catalogue(scan: &Scan, path: &str) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for report in scan.shown() {
+        serde_json::to_writer(&mut out, report)?;
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+// how many of the images shown have each value, for the --stats breakdowns
+#[derive(Debug, Default)]
+struct CorpusCounts {
+    versions: BTreeMap<String, usize>,
+    data_rates: BTreeMap<String, usize>,
+    drive_types: BTreeMap<String, usize>,
+    pub(crate) sides: BTreeMap<String, usize>,
+    pub(crate) formats: BTreeMap<String, usize>,
+    pub(crate) filesystems: BTreeMap<String, usize>,
+    statuses: BTreeMap<String, usize>,
+    pub(crate) findings: BTreeMap<String, usize>,  // images with each code, not how many times it turned up
+}
+
+impl CorpusCounts {
+    pub(crate) fn of(scan: &Scan) -> Self {
+        let mut counts = CorpusCounts::default();
+        let count = |map: &mut BTreeMap<String, usize>, key: String| *map.entry(key).or_default() += 1;
+        for report in scan.shown() {
+            if let Some(h) = &report.header {
+                if matches!(report.format, ImageFormat::Td0) {
+                    count(&mut counts.versions, format!("{}.{}", h.version / 10, h.version % 10));
+                }
+                count(&mut counts.data_rates, format!("{} kbps{}", match h.data_rate & 0x7f {
+                    0 => "250", 1 => "300", 2 => "500", _ => "?",
+                }, if h.data_rate & 0x80 != 0 { " FM" } else { "" }));
+                count(&mut counts.drive_types, match h.drive_type {
+                    1 => "360K".to_string(), 2 => "1.2M".to_string(), 3 => "720K".to_string(), 4 => "1.44M".to_string(),
+                    other => format!("type {}", other),
+                });
+                count(&mut counts.sides, h.sides.to_string());
+            }
+            count(&mut counts.formats, report.format.code().to_string());
+            count(&mut counts.filesystems, report.filesystem.unwrap_or("none").to_string());
+            count(&mut counts.statuses, report.status.code().to_string());
+            let codes: BTreeSet<&str> = report.findings.iter().map(|f| f.code).collect();
+            for code in codes {
+                count(&mut counts.findings, code.to_string());
+            }
+        }
+        counts
+    }
+
+    // (label, counts) in the order they're printed
+    fn breakdowns(&self) -> [(&'static str, &BTreeMap<String, usize>); 8] {
+        [("versions", &self.versions), ("data rates", &self.data_rates), ("drive types", &self.drive_types),
+            ("sides", &self.sides), ("formats", &self.formats), ("filesystems", &self.filesystems),
+            ("status", &self.statuses), ("findings", &self.findings)]
+    }
+}
+
+// the compression of every image added up, to estimate what the collection would take decoded,
+// then the breakdowns
+pub(crate) fn stats_lines(scan: &Scan, language: Language) -> Vec<String> {
+    let mut total = Compression::default();
+    for report in scan.shown() {
+        total.add(&report.compression);
+    }
+    let mut lines = vec![format!("{} {} - {}", scan.shown().count(), tr(language, "images"), total.describe(language))];
+    lines.extend(total.encodings.describe_methods(language).iter().map(|line| format!("    {}", line)));
+    for (label, map) in CorpusCounts::of(scan).breakdowns() {
+        if map.is_empty() { continue; }
+        let values: Vec<String> = map.iter().map(|(value, n)| format!("{} {}", tr(language, value), n)).collect();
+        lines.push(format!("    {} {}", tr(language, label), values.join(", ")));
+    }
+    lines.push(format!("    {} {}, {} {}, {} {}", tr(language, "damaged"), scan.shown().filter(|r| r.failed_validation()).count(),
+        tr(language, "not images"), scan.invalid_images, tr(language, "read errors"), scan.io_errors));
+    lines
+}
+
+pub(crate) fn print_stats(scan: &Scan, format: ReportFormat, language: Language) {
+    match format {
+        ReportFormat::Text => {
+            for line in stats_lines(scan, language) {
+                println!("{}", line);
+            }
+        },
+        ReportFormat::Json => {
+            let mut total = Compression::default();
+            for report in scan.shown() {
+                total.add(&report.compression);
+            }
+            let images = scan.shown().count();
+            let failed_validation = scan.shown().filter(|r| r.failed_validation()).count();
+            let counts = CorpusCounts::of(scan);
+            let breakdown = |map: &BTreeMap<String, usize>| json!(map);
+            println!("{:#}", json!({
+                "images": images,
+                "crc_failures": scan.shown().filter(|r| r.compression.encodings.failed() > 0).count(),
+                "compression": total,
+                "versions": breakdown(&counts.versions),
+                "data_rates": breakdown(&counts.data_rates),
+                "drive_types": breakdown(&counts.drive_types),
+                "sides": breakdown(&counts.sides),
+                "formats": breakdown(&counts.formats),
+                "filesystems": breakdown(&counts.filesystems),
+                "status": breakdown(&counts.statuses),
+                "findings": breakdown(&counts.findings),
+                "damaged": failed_validation,
+                "not_images": scan.invalid_images,
+                "io_errors": scan.io_errors,
+                "walk": scan.walk_summaries,
+            }))
+        },
+    }
+}
+
+// every comment in one object, so they can be gathered up without scraping the console
+pub(crate) fn write_comment_report(scan: &Scan, path: &str) -> io::Result<()> {
+    let comments: BTreeMap<&str, &Comment> = scan.shown()
+        .filter_map(|report| report.comment.as_ref().map(|c| (report.path.as_str(), c)))
+        .collect();
+    std::fs::write(path, serde_json::to_string_pretty(&comments)? + "\n")
+}
+
+// catalogue records keyed by image path
+fn read_catalogue(path: &str) -> io::Result<BTreeMap<String, Value>> {
+    let file = File::open(path)?;
+    let mut records = BTreeMap::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() { continue; }
+        match serde_json::from_str::<Value>(&line) {
+            Ok(record) => match record["path"].as_str() {
+                Some(image_path) => { records.insert(image_path.to_string(), record); },
+                None => log::warn!("{}:{}: record has no path", path, n + 1),
+            },
+            Err(e) => log::warn!("{}:{}: {}", path, n + 1, e),
+        }
+    }
+    Ok(records)
+}
+
+// the images table has a column for every field of the catalogue record, nested fields joined
+// with underscores, added as new fields turn up; the whole record is kept alongside as JSON
+#[cfg(feature = "sqlite")]
+pub(crate) fn write_database(scan: &Scan, path: &str) -> rusqlite::Result<()> {
+    use rusqlite::types::Value as SqlValue;
+
+    let mut conn = rusqlite::Connection::open(path)?;
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS images (path TEXT PRIMARY KEY, record TEXT NOT NULL, scanned_at TEXT NOT NULL);
+        CREATE TABLE IF NOT EXISTS files (image_path TEXT NOT NULL, partition INTEGER, name TEXT NOT NULL, size INTEGER NOT NULL, directory INTEGER NOT NULL, deleted INTEGER NOT NULL DEFAULT 0, user_area INTEGER);
+        CREATE INDEX IF NOT EXISTS files_by_image ON files (image_path);")?;
+    // databases from before hard disk support have no partition column
+    if conn.prepare("SELECT partition FROM files").is_err() {
+        conn.execute_batch("ALTER TABLE files ADD COLUMN partition INTEGER")?;
+    }
+    // nor do those from before deleted files were listed
+    if conn.prepare("SELECT deleted FROM files").is_err() {
+        conn.execute_batch("ALTER TABLE files ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0")?;
+    }
+    // or CP/M user areas
+    if conn.prepare("SELECT user_area FROM files").is_err() {
+        conn.execute_batch("ALTER TABLE files ADD COLUMN user_area INTEGER")?;
+    }
+
+    let mut columns = std::collections::HashSet::new();
+    {
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('images')")?;
+        for name in stmt.query_map([], |row| row.get::<_, String>(0))? {
+            columns.insert(name?);
+        }
+    }
+
+    let scanned_at = chrono::Local::now().to_rfc3339();
+    let tx = conn.transaction()?;
+    for report in scan.shown() {
+        let record = serde_json::to_value(report).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        let mut fields = BTreeMap::new();
+        flatten_record("", &record, &mut fields);
+        fields.remove("path");
+
+        let mut names = vec!["path".to_string(), "record".to_string(), "scanned_at".to_string()];
+        let mut values = vec![SqlValue::Text(report.path.clone()), SqlValue::Text(record.to_string()), SqlValue::Text(scanned_at.clone())];
+        for (key, value) in fields {
+            let column = key.replace('.', "_");
+            if columns.insert(column.clone()) {
+                tx.execute(&format!("ALTER TABLE images ADD COLUMN \"{}\"", column), [])?;
+            }
+            names.push(column);
+            values.push(match value {
+                Value::Null => SqlValue::Null,
+                Value::Bool(b) => SqlValue::Integer(b as i64),
+                Value::Number(n) => n.as_i64().map_or_else(|| SqlValue::Real(n.as_f64().unwrap_or(0.0)), SqlValue::Integer),
+                Value::String(s) => SqlValue::Text(s),
+                other => SqlValue::Text(other.to_string()),
+            });
+        }
+
+        // replacing the whole row clears fields an earlier scan recorded but this one didn't
+        let sql = format!("INSERT OR REPLACE INTO images ({}) VALUES ({})",
+            names.iter().map(|n| format!("\"{}\"", n)).collect::<Vec<_>>().join(", "),
+            (1..=names.len()).map(|i| format!("?{}", i)).collect::<Vec<_>>().join(", "));
+        tx.execute(&sql, rusqlite::params_from_iter(values))?;
+
+        tx.execute("DELETE FROM files WHERE image_path = ?1", [&report.path])?;
+        for file in &report.files {
+            tx.execute("INSERT INTO files (image_path, partition, name, size, directory, deleted, user_area) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                rusqlite::params![report.path, file.partition, file.name, file.size as i64, file.directory, file.deleted, file.user])?;
+        }
+    }
+    tx.commit()
+}
+
+// conditions are passed on to SQLite as they are, it's the user's own database
+#[cfg(feature = "sqlite")]
+pub(crate) fn query_database(path: &str, table: &QueryTable) -> rusqlite::Result<()> {
+    for line in query_lines(path, table)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+// a line for each image or file the query finds
+#[cfg(feature = "sqlite")]
+fn query_lines(path: &str, table: &QueryTable) -> rusqlite::Result<Vec<String>> {
+    let conn = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut lines = Vec::new();
+    match table {
+        QueryTable::Images { condition, sort } => {
+            let sql = format!("SELECT path, status, filesystem, label, health FROM images WHERE {} ORDER BY {}",
+                condition.as_deref().unwrap_or("1"), sort.as_deref().unwrap_or("path"));
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let filesystem: Option<String> = row.get(2)?;
+                let label: Option<String> = row.get(3)?;
+                let health: Option<i64> = row.get(4)?;
+                lines.push(format!("{} : {} health {} {}{}", row.get::<_, String>(0)?, row.get::<_, String>(1)?,
+                    health.map_or("-".to_string(), |h| h.to_string()),
+                    filesystem.as_deref().unwrap_or("-"), label.map(|l| format!(" '{}'", l)).unwrap_or_default()));
+            }
+        },
+        QueryTable::Files { name, condition } => {
+            // GLOB is case sensitive but DOS and CP/M names are upper case, so match the pattern in upper case
+            let sql = format!("SELECT image_path, name, size, directory, partition, deleted, user_area FROM files WHERE name GLOB ?1 AND ({}) ORDER BY image_path, partition, user_area, name",
+                condition.as_deref().unwrap_or("1"));
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query([name.as_deref().unwrap_or("*").to_uppercase()])?;
+            while let Some(row) = rows.next()? {
+                let directory: bool = row.get(3)?;
+                let partition: Option<i64> = row.get(4)?;
+                let deleted: bool = row.get(5)?;
+                let user: Option<i64> = row.get(6)?;
+                lines.push(format!("{}{} : {:12} {:>8}{}{}", row.get::<_, String>(0)?, partition.map(|p| format!(" partition {}", p)).unwrap_or_default(),
+                    row.get::<_, String>(1)?,
+                    if directory { "<DIR>".to_string() } else { row.get::<_, i64>(2)?.to_string() },
+                    if deleted { " deleted" } else { "" },
+                    user.filter(|&u| u != 0).map(|u| format!(" user {}", u)).unwrap_or_default()));
+            }
+        },
+    }
+    Ok(lines)
+}
+
+// nested objects become dotted keys so a change is reported against the field that changed
+fn flatten_record(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match value.as_object() {
+        Some(fields) => for (key, field) in fields {
+            let key = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+            flatten_record(&key, field, out);
+        },
+        None => { out.insert(prefix.to_string(), value.clone()); },
+    }
+}
+
+// these change every run, or from one copy of a collection to the next, without the image changing
+const UNCOMPARED_FIELDS: [&str; 4] = ["provenance.scanned_at", "provenance.tool_version", "provenance.host_file", "provenance.host_modified"];
+
+// the fields that differ between two records of the same image, with what they were and are
+fn record_changes(old_record: &Value, new_record: &Value) -> Vec<(String, Value, Value)> {
+    let (mut was, mut now) = (BTreeMap::new(), BTreeMap::new());
+    flatten_record("", old_record, &mut was);
+    flatten_record("", new_record, &mut now);
+    let mut keys: Vec<&String> = was.keys().chain(now.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|k| !UNCOMPARED_FIELDS.contains(&k.as_str()))
+        .map(|key| (key.clone(), was.get(key).cloned().unwrap_or(Value::Null), now.get(key).cloned().unwrap_or(Value::Null)))
+        .filter(|(_, before, after)| before != after)
+        .collect()
+}
+
+// a line for each image added, changed or gone, by path: + for new, ~ for each field that changed
+// and - for gone
+fn run_changes(old: &BTreeMap<String, Value>, new: &BTreeMap<String, Value>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (image_path, new_record) in new {
+        match old.get(image_path) {
+            None => lines.push(format!("+ {}", image_path)),
+            Some(old_record) => {
+                for (key, before, after) in record_changes(old_record, new_record) {
+                    lines.push(format!("~ {} : {} {} -> {}", image_path, key, before, after));
+                }
+            }
+        }
+    }
+    for image_path in old.keys().filter(|p| !new.contains_key(*p)) {
+        lines.push(format!("- {}", image_path));
+    }
+    lines
+}
+
+// fails if either catalogue can't be read, naming which
+pub(crate) fn compare_runs(old_path: &str, new_path: &str) -> Result<(), String> {
+    let read = |path| read_catalogue(path).map_err(|e| format!("Failed to read catalogue {}: {}", path, e));
+    let (old, new) = (read(old_path)?, read(new_path)?);
+    for line in run_changes(&old, &new) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalogue_io_errors_are_returned() {
+        let dir = crate::tests::temp_dir("catalogue");
+        let missing = dir.join("missing").join("catalogue.jsonl");
+        assert!(write_catalogue(&Scan::default(), missing.to_str().unwrap()).is_err());
+        assert!(read_catalogue(missing.to_str().unwrap()).is_err());
+        assert!(compare_runs(missing.to_str().unwrap(), missing.to_str().unwrap()).unwrap_err().contains("missing"));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("catalogue.jsonl");
+        std::fs::write(&path, "{\"path\": \"a.td0\"}\nnot json\n").unwrap();
+        assert_eq!(read_catalogue(path.to_str().unwrap()).unwrap().keys().collect::<Vec<_>>(), ["a.td0"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compares_two_runs_by_path() {
+        let dir = crate::tests::temp_dir("compare");
+        let (before, after) = (dir.join("before.jsonl"), dir.join("after.jsonl"));
+        std::fs::write(&before, "{\"path\": \"a.td0\", \"health\": 100}\n{\"path\": \"gone.td0\"}\n").unwrap();
+        std::fs::write(&after, "{\"path\": \"new.td0\"}\n{\"path\": \"a.td0\", \"health\": 90, \"label\": \"DOS\"}\n").unwrap();
+        let read = |path: &Path| read_catalogue(path.to_str().unwrap()).unwrap();
+        assert_eq!(run_changes(&read(&before), &read(&after)),
+            ["~ a.td0 : health 100 -> 90", "~ a.td0 : label null -> \"DOS\"", "+ new.td0", "- gone.td0"]);
+        assert!(run_changes(&read(&after), &read(&after)).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn where_a_copy_is_and_when_it_was_scanned_arent_changes() {
+        let old = json!({"path": "a.td0", "health": 100, "provenance": {"host_file": "/mnt/a/a.td0", "host_modified": "2024-01-01T00:00:00+00:00", "scanned_at": "2024-01-02T00:00:00+00:00", "tool_version": "0.1.0"}});
+        let new = json!({"path": "a.td0", "health": 90, "provenance": {"host_file": "/home/b/a.td0", "host_modified": "2025-01-01T00:00:00+00:00", "scanned_at": "2025-01-02T00:00:00+00:00", "tool_version": "0.2.0"}});
+        assert_eq!(record_changes(&old, &new), [("health".to_string(), json!(100), json!(90))]);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn queries_images_and_files() {
+        let dir = crate::tests::temp_dir("query");
+        let path = dir.join("scan.sqlite");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch("CREATE TABLE images (path TEXT, status TEXT, filesystem TEXT, label TEXT, health INTEGER);
+            INSERT INTO images VALUES ('b.td0', 'ok', 'CP/M', NULL, 100), ('a.td0', 'truncated', 'FAT12', 'GAMES', 40);
+            CREATE TABLE files (image_path TEXT, partition INTEGER, name TEXT, size INTEGER, directory INTEGER, deleted INTEGER, user_area INTEGER);
+            INSERT INTO files VALUES ('a.td0', NULL, 'GAME.EXE', 1234, 0, 0, NULL), ('a.td0', NULL, 'SAVES', 0, 1, 0, NULL),
+                ('b.td0', NULL, 'PIP.COM', 8, 0, 1, 3);").unwrap();
+        let query = |table| query_lines(path.to_str().unwrap(), &table).unwrap();
+        assert_eq!(query(QueryTable::Images { condition: None, sort: None }),
+            ["a.td0 : truncated health 40 FAT12 'GAMES'", "b.td0 : ok health 100 CP/M"]);
+        assert_eq!(query(QueryTable::Images { condition: Some("health > 50".to_string()), sort: None }), ["b.td0 : ok health 100 CP/M"]);
+        assert_eq!(query(QueryTable::Images { condition: None, sort: Some("health DESC".to_string()) })[0], "b.td0 : ok health 100 CP/M");
+        // names are matched in upper case, as DOS and CP/M keep them
+        assert_eq!(query(QueryTable::Files { name: Some("*.exe".to_string()), condition: None }), ["a.td0 : GAME.EXE         1234"]);
+        assert_eq!(query(QueryTable::Files { name: None, condition: Some("directory".to_string()) }), ["a.td0 : SAVES           <DIR>"]);
+        assert_eq!(query(QueryTable::Files { name: Some("pip*".to_string()), condition: None }), ["b.td0 : PIP.COM             8 deleted user 3"]);
+        assert!(query_lines(path.to_str().unwrap(), &QueryTable::Images { condition: Some("no_such_column".to_string()), sort: None }).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shares_tracks_at_the_same_place() {
+        let track = |cylinder, head, byte| ((cylinder, head), [byte; 20]);
+        let a = [track(0, 0, 1), track(0, 1, 2), track(1, 0, 3), track(1, 1, 4)];
+        assert_eq!(shared_tracks(&a, &a), (4, 4));
+        // a track read differently, one missing and the same contents somewhere else don't count
+        let b = [track(0, 0, 1), track(0, 1, 9), track(1, 1, 4), track(2, 0, 3)];
+        assert_eq!(shared_tracks(&a, &b), (2, 4));
+        assert_eq!(shared_tracks(&a[..1], &b), (1, 4));
+        assert_eq!(shared_tracks(&[], &b), (0, 4));
+    }
+
+    #[test]
+    fn health_costs_most_for_what_couldnt_be_read() {
+        let disk = crate::formats::flat_disk(&[0; 4 * 512], 2, 1, 2, 512);
+        let geometry = disk.geometry();
+        let mut header = ImageHeader::synthetic(0x02, 0x01, 1);
+        header.crc = kc8587::td0::crc16(&header.crc_bytes());
+        let score = |status, header: Option<&ImageHeader>, counts: &SectorCounts| health_score(&disk, status, header, None, &geometry, counts);
+        let clean = SectorCounts::default();
+        assert_eq!(score(ImageStatus::Ok, Some(&header), &clean), 100);
+        assert_eq!(score(ImageStatus::Truncated, Some(&header), &clean), 60);
+        assert_eq!(score(ImageStatus::EmptyDump, Some(&header), &clean), 0);
+        let mut bad_crc = header.clone();
+        bad_crc.crc ^= 1;
+        assert_eq!(score(ImageStatus::Ok, Some(&bad_crc), &clean), 90);
+        // bad sectors cost five each, up to half the score
+        assert_eq!(score(ImageStatus::Ok, None, &SectorCounts { crc_errors: 2, missing: 1, ..clean.clone() }), 85);
+        assert_eq!(score(ImageStatus::Ok, None, &SectorCounts { crc_errors: 40, ..clean.clone() }), 50);
+        assert_eq!(score(ImageStatus::ReadError, None, &SectorCounts { crc_errors: 40, odd_size: 1, ..clean }), 0);
+    }
+
+    #[test]
+    fn german_falls_back_to_english() {
+        assert_eq!(tr(Language::De, "bad"), "fehlerhaft");
+        assert_eq!(tr(Language::En, "bad"), "bad");
+        assert_eq!(tr(Language::De, "not a phrase"), "not a phrase");
+        let keys: BTreeSet<&str> = GERMAN.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys.len(), GERMAN.len(), "a phrase is translated twice");
+        assert!(GERMAN.iter().all(|(_, translation)| !translation.is_empty()));
+        let geometry = crate::formats::flat_disk(&[0; 4 * 512], 2, 1, 2, 512).geometry();
+        assert!(geometry.describe(Language::De).starts_with("Geometrie 2 Zylinder"));
+        let finding = finding!(Severity::Warning, "unusual-data-rate", Location::Header { field: "data rate" },
+            "{} {} isn't a value TeleDisk writes", "data rate", "05");
+        assert_eq!(finding.message, "data rate 05 isn't a value TeleDisk writes");
+        assert_eq!(finding.describe(Language::De), "Warnung unusual-data-rate - Kopf Datenrate - Datenrate 05 ist kein Wert, den TeleDisk schreibt");
+    }
+
+    // each call of name in the source, as its arguments split at the commas between them
+    fn calls<'a>(source: &'a str, name: &str) -> Vec<Vec<&'a str>> {
+        let mut calls = Vec::new();
+        for (at, _) in source.match_indices(name) {
+            // t( is a call of t only when it isn't the end of split(
+            if source[..at].ends_with(|c: char| c.is_alphanumeric() || c == '_') { continue; }
+            let start = at + name.len();
+            let (mut args, mut from, mut depth, mut quoted, mut escaped) = (Vec::new(), start, 0, false, false);
+            for (i, c) in source[start..].char_indices().map(|(i, c)| (start + i, c)) {
+                if quoted {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => quoted = false,
+                        _ => {},
+                    }
+                    continue;
+                }
+                match c {
+                    '"' => quoted = true,
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' if depth > 0 => depth -= 1,
+                    ',' if depth == 0 => {
+                        args.push(source[from..i].trim());
+                        from = i + 1;
+                    },
+                    ')' => {
+                        args.push(source[from..i].trim());
+                        break;
+                    },
+                    _ => {},
+                }
+            }
+            calls.push(args);
+        }
+        calls
+    }
+
+    // the string literals in some code
+    fn literals(code: &str) -> Vec<String> {
+        let mut literals = Vec::new();
+        let mut chars = code.chars();
+        while let Some(c) = chars.next() {
+            if c != '"' { continue; }
+            let mut literal = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => literal.extend(chars.next()),
+                    c => literal.push(c),
+                }
+            }
+            literals.push(literal);
+        }
+        literals
+    }
+
+    // every phrase looked up has its German: those written where they're looked up, the finding
+    // templates with the header fields and words that fill them in, and those kept in tables
+    #[test]
+    fn every_phrase_looked_up_has_german() {
+        let sources = [include_str!("main.rs"), include_str!("report.rs"), include_str!("sets.rs"), include_str!("html.rs"),
+            include_str!("tui.rs"), include_str!("containers.rs"), include_str!("fs/mod.rs"), include_str!("fs/fat.rs"),
+            include_str!("fs/cpm.rs"), include_str!("analysis/mod.rs"), include_str!("analysis/interleave.rs"),
+            include_str!("analysis/protection.rs")];
+        let phrase = |literal: &String| !literal.contains('{');
+        let words = |literal: &String| !literal.trim().is_empty() && literal.chars().all(|c| c.is_alphabetic() || c == ' ');
+        let mut phrases: Vec<String> = Vec::new();
+        for source in sources {
+            // what the tests themselves look up isn't shown to anyone
+            let source = source.split("#[cfg(test)]\nmod tests").next().unwrap();
+            for args in calls(source, "t(").into_iter().chain(calls(source, "clues.push(")) {
+                phrases.extend(args.iter().flat_map(|arg| literals(arg)).filter(phrase));
+            }
+            for args in calls(source, "tr(") {
+                phrases.extend(args.iter().skip(1).flat_map(|arg| literals(arg)).filter(phrase));
+            }
+            for args in calls(source, "finding!(") {
+                phrases.extend(literals(args[2]).into_iter().chain(literals(args[3])));
+                phrases.extend(args[4..].iter().flat_map(|arg| literals(arg)).filter(words));
+            }
+            for (at, _) in source.match_indices("WalkSummary { kind: ") {
+                phrases.extend(literals(&source[at..]).into_iter().take(1));
+            }
+        }
+        assert!(phrases.iter().any(|p| p == "the boot sector is the {} virus"), "the finding templates weren't found");
+        phrases.extend([Severity::Info, Severity::Warning].iter().map(|s| s.code().to_string()));
+        phrases.extend([ImageStatus::Ok, ImageStatus::EmptyDump, ImageStatus::Truncated, ImageStatus::Overrun, ImageStatus::ReadError]
+            .iter().map(ImageStatus::to_string));
+        phrases.extend(SectorStatus::ALL.iter().map(|s| s.name().to_string()));
+        phrases.extend(crate::analysis::protection::PROTECTION_SIGNS.iter().map(|(_, description, _)| description.to_string()));
+        phrases.extend(CorpusCounts::default().breakdowns().iter().map(|(label, _)| label.to_string()));
+        phrases.extend(HEADER_REPAIRS.iter().map(|(field, ..)| field.to_string()));
+        #[cfg(feature = "browse")]
+        phrases.extend(crate::tui::BROWSE_TABS.iter().map(|tab| tab.to_string()));
+        let missing: BTreeSet<&String> = phrases.iter().filter(|p| !GERMAN.iter().any(|(key, _)| key == p)).collect();
+        assert!(missing.is_empty(), "no German for {:?}", missing);
+    }
+
+    #[test]
+    fn sort_keys() {
+        assert!(sort_key("Disk 2") < sort_key("Disk 10"));
+        assert_eq!(sort_key("  Über-Disk!"), "uber disk");
+    }
+
+    #[test]
+    fn only_filters() {
+        assert!(matches!(parse_only("fs!=FAT12"), Ok(OnlyFilter::Filesystem(name, false)) if name == "FAT12"));
+        assert!(matches!(parse_only("health<=50"), Ok(OnlyFilter::Health("<=", 50))));
+        assert!(matches!(parse_only("finding=Trailing-Bytes"), Ok(OnlyFilter::Finding(code)) if code == "trailing-bytes"));
+        assert!(matches!(parse_only(" has-label "), Ok(OnlyFilter::HasLabel)));
+        assert!(parse_only("health=101").is_err());
+        assert!(parse_only("status<ok").is_err());
+        assert!(parse_only("colour").is_err());
+    }
+
+    #[test]
+    fn header_oddities() {
+        let mut header = ImageHeader::synthetic(0x02, 0x01, 2);
+        assert!(header_findings(&header).is_empty());
+        header.stepping = 0x05;
+        header.sequence = 1;
+        let codes: Vec<_> = header_findings(&header).iter().map(|f| f.code).collect();
+        assert_eq!(codes, ["unusual-stepping", "later-volume"]);
+    }
+
+    #[test]
+    fn toolchain_from_the_header_and_comment() {
+        let comment = |year: i32, dumper: bool| Comment {
+            timestamp: NaiveDate::from_ymd_opt(year, 6, 1).and_then(|d| d.and_hms_opt(12, 0, 0)),
+            text: String::new(),
+            crc_ok: true,
+            truncated: false,
+            fields: if dumper { [("dumper".to_string(), "Ann".to_string())].into() } else { BTreeMap::new() },
+        };
+        let header = ImageHeader::synthetic(0x02, 0x02, 2);
+        let toolchain = Toolchain::identify(&header, None);
+        assert_eq!((toolchain.release.as_str(), toolchain.machine.as_str(), toolchain.era.as_str()),
+            ("TeleDisk 2.1x", "AT with a 1.2M drive", "1988-1991"));
+        assert!(toolchain.clues.is_empty());
+        assert_eq!(Toolchain::identify(&header, Some(&comment(1989, false))).era, "1989");
+        let toolchain = Toolchain::identify(&header, Some(&comment(1980, true)));
+        assert_eq!((toolchain.era.as_str(), toolchain.clues.as_slice()), ("1988-1991", &["clock not set", "the comment names its dumper"][..]));
+        let toolchain = Toolchain::identify(&header, Some(&comment(2003, false)));
+        assert_eq!((toolchain.era.as_str(), toolchain.clues.as_slice()), ("2003", &["dumped long after the release"][..]));
+
+        let mut header = ImageHeader::synthetic(0x81, 0x07, 1);
+        header.signature = *b"td";
+        header.version = 0x30;
+        header.dos_flag = 1;
+        let toolchain = Toolchain::identify(&header, None);
+        assert_eq!((toolchain.release.as_str(), toolchain.machine.as_str(), toolchain.era.as_str()),
+            ("TeleDisk version 30", "drive type 7 at 300 kbps", "?"));
+        assert_eq!(toolchain.clues, ["advanced compression", "single density", "DOS allocated sectors only"]);
+        assert_eq!(toolchain.describe(Language::En),
+            "toolchain TeleDisk version 30 - drive type 7 at 300 kbps - ?, advanced compression, single density, DOS allocated sectors only");
+    }
+
+    #[test]
+    fn findings_serialize_with_their_location() {
+        let mut header = ImageHeader::synthetic(0x02, 0x01, 2);
+        header.stepping = 0x05;
+        let findings = header_findings(&header);
+        assert_eq!(findings.iter().map(|f| f.code).collect::<Vec<_>>(), ["unusual-stepping"]);
+        assert_eq!(serde_json::to_value(&findings[0]).unwrap(), json!({
+            "severity": "warning",
+            "code": "unusual-stepping",
+            "message": "stepping 05 isn't a value TeleDisk writes",
+            "location": { "in": "header", "field": "stepping" },
+        }));
+        let location = Location::Filesystem { partition: Some(1) };
+        assert_eq!(serde_json::to_value(location).unwrap(), json!({ "in": "filesystem", "partition": 1 }));
+        assert_eq!(serde_json::to_value(Location::AfterEnd).unwrap(), json!({ "in": "end" }));
+        assert_eq!(serde_json::to_value(ImageStatus::EmptyDump).unwrap(), ImageStatus::EmptyDump.code());
+        assert_eq!(serde_json::to_value(ImageStatus::ReadError).unwrap(), ImageStatus::ReadError.code());
+    }
+}
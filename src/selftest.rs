@@ -0,0 +1,171 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+};
+use chrono::NaiveDate;
+use clap::Parser;
+use kc8587::td0::{decode_td0, ImageHeader};
+
+use crate::{
+    analysis::Disk,
+    cli::{value_name, Args, Profile},
+    containers::Provenance,
+    formats::{flat_disk, flat_geometry, FLAT_FORMATS},
+    fs::Codepage,
+    import::{read_sector_map, write_img_export, write_td0},
+    report::{Comment, ImageStatus, Scan},
+    analyze_teledisk_image_format_from_stream,
+};
+
+// synthetic images of each format round tripped through writing, reading and converting, a line
+// per check. true if they all pass
+pub(crate) fn selftest() -> bool {
+    let mut checks: Vec<(String, Result<(), String>)> = Vec::new();
+    let args = Args::parse_from(["kc8587", "--quiet", "--stdin"]);
+    let timestamp = NaiveDate::from_ymd_opt(1991, 8, 25).unwrap().and_hms_opt(20, 57, 8);
+
+    let mut shapes: Vec<(String, usize, u8, u8, u16)> = <Profile as clap::ValueEnum>::value_variants().iter().map(|profile| {
+        let (cylinders, heads, sectors, _, _) = profile.shape();
+        (value_name(profile), cylinders, heads, sectors, 512)
+    }).collect();
+    shapes.extend(FLAT_FORMATS.iter().map(|&(size, cylinders, heads, sectors, sector_size)| {
+        (format!("{}k", size / 1024), cylinders, heads, sectors, sector_size)
+    }));
+
+    for (name, cylinders, heads, sectors, sector_size) in &shapes {
+        let img = selftest_image(cylinders * *heads as usize * *sectors as usize, *sector_size);
+        let disk = flat_disk(&img, *cylinders, *heads, *sectors, *sector_size);
+        let header = ImageHeader::synthetic(0, 1, *heads);
+        let comment = Comment { timestamp, text: "Self test\r\nDumped by kc8587".to_string(), crc_ok: true, truncated: false, fields: BTreeMap::new() };
+
+        checks.push((format!("flat geometry {}", name), match flat_geometry(&img) {
+            Some(found) if found == (*cylinders, *heads, *sectors, *sector_size) => Ok(()),
+            found => Err(format!("found {:?}", found)),
+        }));
+
+        checks.push((format!("td0 round trip {}", name), selftest_check(|| selftest_td0(&args, name, &header, &comment, &disk))));
+
+        let dir = std::env::temp_dir().join(format!("kc8587-selftest-{}", std::process::id()));
+        let img_path = dir.join(format!("{}.img", name));
+        checks.push((format!("img export {}", name), selftest_check(|| selftest_export(&img_path, &header, &comment, &img, &disk))));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // nothing here writes RLE, so its blocks are made by hand: runs of a repeated pair, and literals
+    for (name, data) in [
+        ("rle formatted and noise", selftest_image(4, 128)),
+        ("rle runs", [vec![0xe5; 300], [0x4e, 0x00].repeat(100), vec![0xf6; 12]].concat()),
+    ] {
+        let mut block = Vec::new();
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let run = rest.chunks_exact(2).take(255).take_while(|pair| *pair == &rest[..2]).count();
+            if run > 1 {
+                block.extend_from_slice(&[1, run as u8, rest[0], rest[1]]);
+                rest = &rest[run * 2..];
+            } else {
+                let len = rest.len().min(255);
+                block.extend_from_slice(&[0, len as u8]);
+                block.extend_from_slice(&rest[..len]);
+                rest = &rest[len..];
+            }
+        }
+        checks.push((name.to_string(), selftest_check(|| {
+            let mut decoded = Vec::new();
+            match decode_td0(2, &block, data.len() as u16, &mut decoded) {
+                Ok(()) if decoded == data => Ok(()),
+                _ => Err("decoded differently".to_string()),
+            }
+        })));
+    }
+
+    for (codepage, text) in [
+        (Codepage::Cp437, "Grüße, ½ café"),
+        (Codepage::Cp850, "Ærøskøbing ÀÉÎ"),
+        (Codepage::Cp852, "Łódź, Příbram"),
+        (Codepage::Cp866, "Привет, мир"),
+        (Codepage::Koi8R, "Привет, мир"),
+        (Codepage::Koi8U, "Привіт, ґанок"),
+    ] {
+        let decoded = codepage.decode(&codepage.encode(text));
+        checks.push((format!("codepage {}", value_name(&codepage)),
+            if decoded == text { Ok(()) } else { Err(format!("'{}' came back as '{}'", text, decoded)) }));
+    }
+
+    let failed = checks.iter().filter(|(_, result)| result.is_err()).count();
+    for (name, result) in &checks {
+        match result {
+            Ok(()) => println!("ok   {}", name),
+            Err(e) => println!("FAIL {}: {}", name, e),
+        }
+    }
+    println!("{} passed, {} failed", checks.len() - failed, failed);
+    failed == 0
+}
+
+// a check that panicked has failed, and the rest should still be run
+pub(crate) fn selftest_check(check: impl FnOnce() -> Result<(), String>) -> Result<(), String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(check)).unwrap_or_else(|_| Err("panicked".to_string()))
+}
+
+// the disk written as a TD0 and read back as any other image would be
+pub(crate) fn selftest_td0(args: &Args, name: &str, header: &ImageHeader, comment: &Comment, disk: &Disk) -> Result<(), String> {
+    let mut td0 = Vec::new();
+    write_td0(&mut td0, header, Some(comment), disk, Codepage::Cp437).map_err(|e| e.to_string())?;
+    let mut scan = Scan::default();
+    analyze_teledisk_image_format_from_stream(args, &mut scan, &mut &td0[..], "S", Path::new(""), None, name, &Provenance::default());
+    let report = scan.reports.pop().ok_or("not read as an image")?;
+    if report.status != ImageStatus::Ok { return Err(format!("status {}", report.status)); }
+    if !report.header.as_ref().is_some_and(ImageHeader::crc_ok) { return Err("header CRC bad".to_string()); }
+    match &report.comment {
+        Some(c) if c.crc_ok && c.text == comment.text && c.timestamp == comment.timestamp => {},
+        c => return Err(format!("comment {:?}", c)),
+    }
+    if report.content_hash != disk.content_hash(&[]) { return Err("sectors differ".to_string()); }
+    Ok(())
+}
+
+// the disk exported as by --export-img, and its .map read back as import does
+pub(crate) fn selftest_export(img_path: &Path, header: &ImageHeader, comment: &Comment, img: &[u8], disk: &Disk) -> Result<(), String> {
+    write_img_export(disk, Some(header), Some(comment), "selftest", img_path).map_err(|e| e.to_string())?;
+    let exported = std::fs::read(img_path).map_err(|e| e.to_string())?;
+    let map = std::fs::read_to_string(img_path.with_extension("map")).map_err(|e| e.to_string())?;
+    if exported != img { return Err("image differs".to_string()); }
+    let (_, read_comment, read_disk) = read_sector_map(&map, &exported)?;
+    if read_comment.is_none_or(|c| c.text != comment.text) { return Err("comment differs".to_string()); }
+    if read_disk.content_hash(&[]) != disk.content_hash(&[]) { return Err("sectors differ".to_string()); }
+    Ok(())
+}
+
+// sectors alternately of one repeated pair of bytes, as freshly formatted, and of noise, so both
+// ways write_td0 stores sectors are used
+pub(crate) fn selftest_image(sectors: usize, sector_size: u16) -> Vec<u8> {
+    let mut state = 0x2545f491u32;
+    (0..sectors).flat_map(|n| {
+        (0..sector_size).map(|_| if n % 2 == 0 {
+            0xe5
+        } else {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect::<Vec<_>>()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_check_passes() {
+        assert!(selftest());
+    }
+
+    #[test]
+    fn a_panicking_check_fails() {
+        assert_eq!(selftest_check(|| panic!("checking")), Err("panicked".to_string()));
+        assert_eq!(selftest_check(|| Err("differs".to_string())), Err("differs".to_string()));
+        assert_eq!(selftest_check(|| Ok(())), Ok(()));
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use regex::Regex;
+
+use crate::report::{Finding, ImageReport, Location, Scan, Severity};
+
+// "Disk 1 of 3", "(Disk 2)", "disk_2", "DISK#1", "Vol 2/3": a word for a disk, then its number and
+// maybe how many there are. it has to start a word, or follow an underscore as file names have it
+const MARKER: &str = r"(?i)(?:^|[^a-z])(?P<marker>(?:dis[kc]|vol(?:ume)?)[\s_#.-]*(?P<number>\d{1,2})(?:[\s_]*(?:of|/)[\s_]*(?P<of>\d{1,2}))?)(?:$|[^a-z0-9])";
+
+// a name ending in a single digit after a letter, as in WP1.TD0 and WP2.TD0. it's only taken for
+// a set's number when another image beside it has the same name with another digit
+const TRAILING_DIGIT: &str = r"(?i)^(?P<base>.*[a-z_ -])(?P<number>\d)$";
+
+// which set of disks an image is one of, and what's known of the rest
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct DiskSet {
+    pub(crate) name: String,
+    pub(crate) disk: u32,
+    pub(crate) of: u32,               // the most any of its images claims, or the highest number found
+    pub(crate) found: usize,          // images in the set, counting any that are the same disk
+    pub(crate) missing: Vec<u32>,
+}
+
+// what one image's name or comment says about the set it's in
+struct Clue {
+    base: String,       // the name or comment line with the disk's number taken out
+    number: u32,
+    of: Option<u32>,
+    location: Location,
+    weak: bool,         // only a trailing digit
+}
+
+// the name or line with the marker taken out, tidied of what was around it
+fn without(text: &str, start: usize, end: usize) -> String {
+    let joined = format!("{}{}", &text[..start], &text[end..]).replace("()", " ").replace("[]", " ").replace('_', " ");
+    let words: Vec<&str> = joined.split_whitespace().collect();
+    words.join(" ").trim_matches(|c: char| !c.is_alphanumeric() && c != ')' && c != ']').to_string()
+}
+
+// how sets are told apart: letters and digits only, in lower case
+fn set_key(base: &str) -> String {
+    base.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect()
+}
+
+fn clue(marker: &Regex, trailing: &Regex, name: &str, comment: Option<&str>) -> Option<Clue> {
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    let found = |text: &str| marker.captures(text).map(|c| {
+        let m = c.name("marker").unwrap();
+        (without(text, m.start(), m.end()), c["number"].parse::<u32>().unwrap(), c.name("of").map(|of| of.as_str().parse::<u32>().unwrap()))
+    });
+    // a comment is looked at a line at a time, TeleDisk separating them with NULs
+    let in_comment = comment.and_then(|text| text.split(['\0', '\n']).find_map(found));
+    if let Some((base, number, of)) = found(stem) {
+        return Some(Clue { base, number, of: of.or(in_comment.and_then(|(_, _, of)| of)), location: Location::FileName, weak: false });
+    }
+    if let Some((line, number, of)) = in_comment {
+        let base = if set_key(&line).is_empty() { stem.to_string() } else { line };
+        return Some(Clue { base, number, of, location: Location::Comment, weak: false });
+    }
+    trailing.captures(stem).map(|c| Clue {
+        base: without(stem, c.name("base").unwrap().end(), stem.len()),
+        number: c["number"].parse().unwrap(),
+        of: None,
+        location: Location::FileName,
+        weak: true,
+    })
+}
+
+// gathers images in the same directory or archive that say they're disks of the same set, by
+// their names or comments, and records the set on each with findings for any disks missing
+pub(crate) fn group_disk_sets(scan: &mut Scan) {
+    let marker = Regex::new(MARKER).unwrap();
+    let trailing = Regex::new(TRAILING_DIGIT).unwrap();
+    let mut sets: BTreeMap<(String, String), Vec<(usize, Clue)>> = BTreeMap::new();
+    for (i, report) in scan.reports.iter().enumerate() {
+        if let Some(mut clue) = clue(&marker, &trailing, &report.name, report.comment.as_ref().map(|c| c.text.as_str())) {
+            // DISK1.TD0 and DISK2.TD0 are named after the directory or archive they're in
+            if set_key(&clue.base).is_empty() {
+                clue.base = Path::new(&report.directory).file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+            }
+            let key = set_key(&clue.base);
+            sets.entry((report.directory.clone(), key)).or_default().push((i, clue));
+        }
+    }
+    for (_, members) in sets {
+        let of = members.iter().filter_map(|(_, clue)| clue.of).max();
+        let numbers: BTreeSet<u32> = members.iter().map(|(_, clue)| clue.number).collect();
+        // a lone image is a set only when it says there are more, and trailing digits only make
+        // one when they differ: GAME2.TD0 beside GAME2.IMG is the same disk twice
+        let lone = members.len() == 1;
+        if lone && (members[0].1.weak || of.unwrap_or(0) < 2) { continue; }
+        if members.iter().all(|(_, clue)| clue.weak) && numbers.len() < 2 { continue; }
+        let last = of.unwrap_or(0).max(*numbers.iter().next_back().unwrap());
+        let first = if numbers.contains(&0) { 0 } else { 1 };
+        let missing: Vec<u32> = (first..=last).filter(|n| !numbers.contains(n)).collect();
+        let name = members[0].1.base.clone();
+        for (i, clue) in &members {
+            let report = &mut scan.reports[*i];
+            let set = DiskSet { name: name.clone(), disk: clue.number, of: last, found: members.len(), missing: missing.clone() };
+            let mut add = |finding: Finding| {
+                match finding.severity {
+                    Severity::Info => log::info!("{}: {}", report.path, finding.message),
+                    Severity::Warning => log::warn!("{}: {}", report.path, finding.message),
+                }
+                report.findings.push(finding);
+            };
+            add(finding!(Severity::Info, "disk-set", clue.location.clone(), "disk {} of {} in the set '{}', {} of its images found",
+                set.disk, set.of, set.name, set.found));
+            if !missing.is_empty() {
+                add(finding!(Severity::Warning, "disk-set-missing", clue.location.clone(), "the set is missing disk {}",
+                    missing.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")));
+            }
+            report.disk_set = Some(set);
+        }
+    }
+}
+
+// the sets found among the images shown, each with its disks in order
+pub(crate) fn print_disk_sets(scan: &Scan) {
+    let mut sets: BTreeMap<(&str, &str), Vec<&ImageReport>> = BTreeMap::new();
+    for report in scan.shown() {
+        if let Some(set) = &report.disk_set {
+            sets.entry((report.directory.as_str(), &set.name)).or_default().push(report);
+        }
+    }
+    for ((_, name), mut reports) in sets {
+        reports.sort_by_key(|r| (r.disk_set.as_ref().map(|s| s.disk), &r.path));
+        let set = reports[0].disk_set.as_ref().unwrap();
+        let missing = if set.missing.is_empty() {
+            String::new()
+        } else {
+            format!(", missing {}", set.missing.iter().map(u32::to_string).collect::<Vec<_>>().join(", "))
+        };
+        println!("Set '{}' ({} of {} disks{}):", name, reports.len(), set.of, missing);
+        for report in reports {
+            println!("    {:2} {}", report.disk_set.as_ref().unwrap().disk, report.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clue_of(name: &str, comment: Option<&str>) -> Option<(String, u32, Option<u32>, bool)> {
+        let (marker, trailing) = (Regex::new(MARKER).unwrap(), Regex::new(TRAILING_DIGIT).unwrap());
+        clue(&marker, &trailing, name, comment).map(|c| (c.base, c.number, c.of, c.weak))
+    }
+
+    #[test]
+    fn reads_disk_numbers() {
+        assert_eq!(clue_of("Lotus 123 (Disk 1 of 3).td0", None), Some(("Lotus 123".to_string(), 1, Some(3), false)));
+        assert_eq!(clue_of("WORDSTAR_DISK2.TD0", None), Some(("WORDSTAR".to_string(), 2, None, false)));
+        assert_eq!(clue_of("dBase III [disk 2].td0", Some("Disk 2 of 4")), Some(("dBase III".to_string(), 2, Some(4), false)));
+        assert_eq!(clue_of("A.TD0", Some("Turbo Pascal 3.0\0Disk 2/2")), Some(("A".to_string(), 2, Some(2), false)));
+        assert_eq!(clue_of("a.td0", Some("Turbo Pascal 3.0 Disk 1 of 2")), Some(("Turbo Pascal 3.0".to_string(), 1, Some(2), false)));
+        assert_eq!(clue_of("GAME2.TD0", None), Some(("GAME".to_string(), 2, None, true)));
+        // a version or a year isn't a disk number
+        assert_eq!(clue_of("LOTUS123.TD0", None), None);
+        assert_eq!(clue_of("Diskmaster.td0", None), None);
+    }
+}
@@ -0,0 +1,21 @@
+// the CRC-16 TeleDisk uses throughout: polynomial 0xa097, initial value 0, msb first
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, &b| {
+        (0..8).fold(crc ^ ((b as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0xa097 } else { crc << 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        assert_eq!(crc16(&[]), 0);
+        assert_eq!(crc16(b"TD"), 0x206f);
+        // the first ten bytes of a 360K image's header, whose CRC follows them
+        assert_eq!(crc16(&[0x54, 0x44, 0x00, 0x00, 0x15, 0x02, 0x01, 0x00, 0x00, 0x02]), 0x032f);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{error, fmt};
+
+// why a sector's data block couldn't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownEncoding(u8),    // not raw, repeated or RLE
+    Truncated,              // a run or block running past the end of the data, or no data at all
+    WrongSize(usize),       // decoding to other than the sector's size: at least this many bytes
+    Skipped,                // read past by Tracks::skipping_data without being kept
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownEncoding(method) => write!(f, "encoding method {:02x} isn't raw, repeated or RLE", method),
+            DecodeError::Truncated => write!(f, "the data block stops partway through a run"),
+            DecodeError::WrongSize(len) => write!(f, "the data block decodes to {} bytes", len),
+            DecodeError::Skipped => write!(f, "the data block was skipped without being decoded"),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+// turn td0 data for one sector into raw sector data, decoded into output in place of whatever it
+// held. a buffer kept from one sector to the next is only allocated once
+pub fn decode_td0(encoding_method: u8, input: &[u8], sector_size: u16, output: &mut Vec<u8>) -> Result<(), DecodeError> {
+    decode_runs(encoding_method, input, sector_size as usize, output)?;
+    if output.len() != sector_size as usize {
+        return Err(DecodeError::WrongSize(output.len()));
+    }
+    Ok(())
+}
+
+// decode_td0, but data decoding to the wrong size is cut or padded with zeros to the sector's size,
+// giving how long it came to: at least that, when it's longer
+pub fn decode_td0_fitted(encoding_method: u8, input: &[u8], sector_size: u16, output: &mut Vec<u8>) -> Result<Option<usize>, DecodeError> {
+    decode_runs(encoding_method, input, sector_size as usize, output)?;
+    let len = output.len();
+    output.resize(sector_size as usize, 0);
+    Ok((len != sector_size as usize).then_some(len))
+}
+
+// the runs of a data block one after another, stopping as soon as they're longer than the sector.
+// each run is copied whole rather than a repeat at a time
+fn decode_runs(encoding_method: u8, mut input: &[u8], sector_size: usize, output: &mut Vec<u8>) -> Result<(), DecodeError> {
+    output.clear();
+    output.reserve(sector_size);
+    // how many more copies of a block of len bytes take the output past the sector, which a
+    // crafted count mustn't be able to run us out of memory beyond
+    let room = |output: &Vec<u8>, len: usize| (sector_size - output.len()) / len + 1;
+    match encoding_method {
+        2 => { // RLE encoding
+            while input.len() > 1 {
+                let (a, b) = (input[0] as usize, input[1] as usize);
+
+                let (count, len) = if a == 0 {
+                    (1, b)
+                } else {
+                    (b, a * 2)
+                };
+
+                let block = input.get(2..2 + len).ok_or(DecodeError::Truncated)?;
+                if !block.is_empty() {
+                    repeat(output, block, count.min(room(output, len)));
+                    if output.len() > sector_size { return Ok(()); }
+                }
+                input = &input[2 + len..]; // Move the input pointer forward
+            }
+        },
+        0 => { // Raw
+            output.extend_from_slice(input);
+        },
+        1 => { // Repeated
+            while input.len() > 1 {
+                let block = input.get(..4).ok_or(DecodeError::Truncated)?;
+                let count = u16::from_le_bytes([block[0], block[1]]) as usize;
+                repeat(output, &block[2..], count.min(room(output, 2)));
+                if output.len() > sector_size { return Ok(()); }
+                input = &input[4..];
+            }
+        },
+        _ => return Err(DecodeError::UnknownEncoding(encoding_method)),
+    }
+    Ok(())
+}
+
+// count copies of block on the end of output: one copied in, then doubled up from there
+fn repeat(output: &mut Vec<u8>, block: &[u8], count: usize) {
+    if count == 0 { return; }
+    let start = output.len();
+    let end = start + block.len() * count;
+    output.extend_from_slice(block);
+    while output.len() < end {
+        let len = (output.len() - start).min(end - output.len());
+        output.extend_from_within(start..start + len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(method: u8, input: &[u8], sector_size: u16) -> Result<Vec<u8>, DecodeError> {
+        let mut output = Vec::new();
+        decode_td0(method, input, sector_size, &mut output).map(|()| output)
+    }
+
+    fn decode_fitted(method: u8, input: &[u8], sector_size: u16) -> Result<(Vec<u8>, Option<usize>), DecodeError> {
+        let mut output = Vec::new();
+        decode_td0_fitted(method, input, sector_size, &mut output).map(|len| (output, len))
+    }
+
+    #[test]
+    fn raw() {
+        assert_eq!(decode(0, &[1, 2, 3, 4], 4), Ok(vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn repeated() {
+        // two runs of a two byte pattern
+        assert_eq!(decode(1, &[2, 0, 0xe5, 0xf6, 1, 0, 0x12, 0x34], 6), Ok(vec![0xe5, 0xf6, 0xe5, 0xf6, 0x12, 0x34]));
+    }
+
+    #[test]
+    fn rle() {
+        // three copies of a two byte block, then three bytes as they are
+        assert_eq!(decode(2, &[1, 3, 0xaa, 0x55, 0, 3, 7, 8, 9], 9), Ok(vec![0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 7, 8, 9]));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(decode(3, &[0; 4], 4), Err(DecodeError::UnknownEncoding(3)));
+        assert_eq!(decode(1, &[2, 0, 0xe5], 4), Err(DecodeError::Truncated));
+        assert_eq!(decode(2, &[0, 5, 1, 2], 5), Err(DecodeError::Truncated));
+        assert_eq!(decode(2, &[2, 3, 1, 2, 3], 12), Err(DecodeError::Truncated));
+        assert_eq!(decode(0, &[1, 2, 3], 4), Err(DecodeError::WrongSize(3)));
+        // a run far longer than the sector stops as soon as it's past it
+        assert_eq!(decode(1, &[0xff, 0xff, 0, 0], 128), Err(DecodeError::WrongSize(130)));
+        assert_eq!(decode(2, &[0xff, 0xff, 0, 0], 128), Err(DecodeError::Truncated));
+        assert_eq!(decode(2, &[1, 0xff, 0, 0], 128), Err(DecodeError::WrongSize(130)));
+    }
+
+    #[test]
+    fn fitted() {
+        assert_eq!(decode_fitted(0, &[1, 2, 3, 4], 4), Ok((vec![1, 2, 3, 4], None)));
+        assert_eq!(decode_fitted(0, &[1, 2, 3], 4), Ok((vec![1, 2, 3, 0], Some(3))));
+        assert_eq!(decode_fitted(1, &[3, 0, 0xe5, 0xf6], 4), Ok((vec![0xe5, 0xf6, 0xe5, 0xf6], Some(6))));
+        assert_eq!(decode_fitted(2, &[1, 0xff, 7, 8], 5), Ok((vec![7, 8, 7, 8, 7], Some(6))));
+        // what can't be decoded at all still can't
+        assert_eq!(decode_fitted(2, &[0, 5, 1, 2], 5), Err(DecodeError::Truncated));
+        assert_eq!(decode_fitted(4, &[], 5), Err(DecodeError::UnknownEncoding(4)));
+    }
+
+    #[test]
+    fn reuses_the_buffer() {
+        let mut output = vec![0xff; 1000];
+        decode_td0(1, &[4, 0, 0xe5, 0xf6], 8, &mut output).unwrap();
+        assert_eq!(output, [0xe5, 0xf6].repeat(4));
+        assert_eq!(decode_td0_fitted(2, &[0, 2, 7, 8], 4, &mut output), Ok(Some(2)));
+        assert_eq!(output, [7, 8, 0, 0]);
+        assert!(output.capacity() >= 1000);
+    }
+}
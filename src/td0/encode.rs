@@ -0,0 +1,97 @@
+// a sector's data as the shortest of the three ways a data block can hold it, the encoding
+// method first: raw, runs of a two byte pattern, or RLE. ties go to the simpler encoding
+pub fn encode_td0(data: &[u8]) -> Vec<u8> {
+    let raw = [&[0][..], data].concat();
+    [Some(raw), repeated(data), Some(rle(data))].into_iter().flatten()
+        .min_by_key(Vec::len)
+        .unwrap()
+}
+
+// a count then the pattern, for each run of the same two bytes. only odd sized data can't be
+// stored this way
+fn repeated(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) { return None; }
+    let mut out = vec![1];
+    let mut pairs = data.chunks_exact(2).peekable();
+    while let Some(pair) = pairs.next() {
+        let mut count = 1u16;
+        while count < u16::MAX && pairs.next_if_eq(&pair).is_some() {
+            count += 1;
+        }
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(pair);
+    }
+    Some(out)
+}
+
+// literal stretches of up to 255 bytes, 0 then the length then the bytes, between runs of a two
+// byte pattern, 1 then the count then the pattern. a run is only worth it from three repeats on
+fn rle(data: &[u8]) -> Vec<u8> {
+    fn literal(out: &mut Vec<u8>, bytes: &[u8]) {
+        for chunk in bytes.chunks(255) {
+            out.extend_from_slice(&[0, chunk.len() as u8]);
+            out.extend_from_slice(chunk);
+        }
+    }
+    let mut out = vec![2];
+    let (mut start, mut i) = (0, 0);
+    while i + 2 <= data.len() {
+        let pair = &data[i..i + 2];
+        let count = data[i..].chunks_exact(2).take(255).take_while(|&p| p == pair).count();
+        if count >= 3 {
+            literal(&mut out, &data[start..i]);
+            out.extend_from_slice(&[1, count as u8, pair[0], pair[1]]);
+            i += count * 2;
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    literal(&mut out, &data[start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::td0::decode_td0;
+
+    fn round_trip(data: &[u8]) -> Vec<u8> {
+        let block = encode_td0(data);
+        let mut decoded = Vec::new();
+        decode_td0(block[0], &block[1..], data.len() as u16, &mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        block
+    }
+
+    #[test]
+    fn picks_the_shortest() {
+        // freshly formatted, a pattern and nothing else
+        assert_eq!(round_trip(&[0xe5; 512]), [1, 0, 1, 0xe5, 0xe5]);
+        // nothing repeats
+        let counting: Vec<u8> = (0..=255).collect();
+        assert_eq!(round_trip(&counting), [&[0][..], &counting].concat());
+        // a few runs, some of them starting on odd bytes
+        let mut mixed = vec![0xf6; 300];
+        mixed.extend(0..=40u8);
+        mixed.extend([0x4e; 171]);
+        let block = round_trip(&mixed);
+        assert_eq!(block[0], 2);
+        assert!(block.len() < 60, "{}", block.len());
+    }
+
+    #[test]
+    fn reads_back_whatever_it_writes() {
+        // long runs, literals longer than a block holds, and everything in between
+        let mut state = 1u32;
+        for len in [128, 256, 512, 1024, 8192] {
+            let data: Vec<u8> = (0..len).map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                if (i / 64) % 3 == 0 { (state >> 24) as u8 } else { (i / 200) as u8 }
+            }).collect();
+            round_trip(&data);
+        }
+        round_trip(&[7; 3]);
+        round_trip(&[]);
+    }
+}
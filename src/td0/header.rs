@@ -0,0 +1,264 @@
+use std::io::{self, Read};
+
+use super::crc16;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ImageHeader {
+    pub signature: [u8; 2], // Signature to identify the file format
+    pub sequence: u8,       // Volume sequence number, 0 for the first
+    pub check_sequence: u8, // the same in every volume of a multi-volume set
+    pub version: u8,        // Version of the disk image format
+    pub data_rate: u8,      // Data rate of the disk image
+    pub drive_type: u8,     // Type of the drive
+    pub stepping: u8,       // Stepping field
+    pub dos_flag: u8,       // DOS allocation flag
+    pub sides: u8,          // Number of sides
+    pub crc: u16,           // CRC of the header
+}
+
+impl ImageHeader {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() == 12, "ImageHeader must be 12 bytes long");
+
+        let signature = [bytes[0], bytes[1]]; // Extract signature
+        let sequence = bytes[2];
+        let check_sequence = bytes[3];
+        let version = bytes[4];
+        let data_rate = bytes[5];
+        let drive_type = bytes[6];
+        let stepping = bytes[7];
+        let dos_flag = bytes[8];
+        let sides = bytes[9];
+        let crc = u16::from_le_bytes([bytes[10], bytes[11]]); // Extract CRC
+
+        ImageHeader {
+            signature,
+            sequence,
+            check_sequence,
+            version,
+            data_rate,
+            drive_type,
+            stepping,
+            dos_flag,
+            sides,
+            crc,
+        }
+    }
+
+    // a header for an image that didn't come with one
+    pub fn synthetic(data_rate: u8, drive_type: u8, sides: u8) -> Self {
+        ImageHeader {
+            signature: *b"TD", sequence: 0, check_sequence: 0, version: 0x15, data_rate, drive_type,
+            stepping: 0, dos_flag: 0, sides, crc: 0,
+        }
+    }
+
+    // Method to check if a comment header is present
+    pub fn has_comment_header(&self) -> bool {
+        self.stepping & 0x80 == 0x80
+    }
+
+    // Optionally, you can add a method to validate the signature
+    pub fn is_valid(&self) -> bool {
+        self.signature == [0x54, 0x44] // Example signature check
+    }
+
+    // the ten bytes covered by the header CRC, as they appear in the file
+    pub fn crc_bytes(&self) -> [u8; 10] {
+        [self.signature[0], self.signature[1], self.sequence, self.check_sequence, self.version,
+            self.data_rate, self.drive_type, self.stepping, self.dos_flag, self.sides]
+    }
+
+    pub fn crc_ok(&self) -> bool {
+        crc16(&self.crc_bytes()) == self.crc
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommentHeader {
+    pub crc: u16,        // 16-bit CRC of the comment header and comment text
+    pub length: u16,     // Length of the comment
+    pub year: u8,        // Year of the comment
+    pub month: u8,       // Month of the comment
+    pub day: u8,         // Day of the comment
+    pub hour: u8,        // Hour of the comment
+    pub minute: u8,      // Minute of the comment
+    pub second: u8,      // Second of the comment
+}
+
+impl CommentHeader {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() == 10, "CommentHeader must be 10 bytes long");
+
+        let crc = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let length = u16::from_le_bytes([bytes[2], bytes[3]]);
+        let year = bytes[4];
+        let month = bytes[5];
+        let day = bytes[6];
+        let hour = bytes[7];
+        let minute = bytes[8];
+        let second = bytes[9];
+
+        CommentHeader {
+            crc,
+            length,
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    // the CRC covers the rest of the comment header followed by the comment text itself
+    pub fn crc_ok(&self, text: &[u8]) -> bool {
+        let mut bytes = self.length.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&[self.year, self.month, self.day, self.hour, self.minute, self.second]);
+        bytes.extend_from_slice(text);
+        crc16(&bytes) == self.crc
+    }
+}
+
+#[derive(Debug)]
+pub struct TeleDiskHeaders {
+    pub image_header: ImageHeader,              // Standard header
+    pub comment_header: Option<CommentHeader>,  // Optional comment header
+}
+
+impl TeleDiskHeaders {
+    pub fn from_stream(file: &mut dyn Read) -> io::Result<Self> {
+        let mut header_bytes = [0; 12];
+        file.read_exact(&mut header_bytes)?;
+        let image_header = ImageHeader::from_bytes(&header_bytes);
+
+        let mut comment_header = None;
+
+        if image_header.is_valid() && image_header.has_comment_header() {
+            let mut comment_bytes = [0; 10];
+            file.read_exact(&mut comment_bytes)?;
+            comment_header = Some(CommentHeader::from_bytes(&comment_bytes));
+        }
+
+        Ok(TeleDiskHeaders {
+            image_header,
+            comment_header,
+        })
+    }
+}
+
+// the header fields repair-header tries other values for: name, offset in the header, and the
+// values each can plausibly have. stepping's top bit says a comment follows
+pub const HEADER_REPAIRS: &[(&str, usize, &[u8])] = &[
+    ("data rate", 5, &[0x00, 0x01, 0x02, 0x80, 0x81, 0x82]),
+    ("stepping", 7, &[0x00, 0x01, 0x02, 0x80, 0x81, 0x82]),
+    ("sides", 9, &[1, 2]),
+];
+
+// one byte of a TD0 header changed to make its CRC match
+#[derive(Debug, PartialEq)]
+pub struct HeaderRepair {
+    pub field: &'static str,
+    pub offset: usize,
+    pub from: u8,
+    pub to: u8,
+}
+
+impl HeaderRepair {
+    pub fn apply(&self, bytes: &mut [u8]) {
+        bytes[self.offset] = self.to;
+    }
+}
+
+// every change of one of HEADER_REPAIRS' fields to another of its values that makes the header's
+// CRC match. none for a header whose CRC already does
+pub fn header_repairs(header: &[u8; 12]) -> Vec<HeaderRepair> {
+    let crc = u16::from_le_bytes([header[10], header[11]]);
+    if crc16(&header[..10]) == crc { return Vec::new(); }
+    let mut repairs = Vec::new();
+    for &(field, offset, values) in HEADER_REPAIRS {
+        for &value in values.iter().filter(|&&v| v != header[offset]) {
+            let mut fixed = *header;
+            fixed[offset] = value;
+            if crc16(&fixed[..10]) == crc {
+                repairs.push(HeaderRepair { field, offset, from: header[offset], to: value });
+            }
+        }
+    }
+    repairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 360K header with its CRC, then one byte of it changed
+    fn damaged(offset: usize, value: u8) -> [u8; 12] {
+        let header = ImageHeader::synthetic(0x02, 0x01, 2);
+        let mut bytes = [0; 12];
+        bytes[..10].copy_from_slice(&header.crc_bytes());
+        bytes[10..].copy_from_slice(&crc16(&header.crc_bytes()).to_le_bytes());
+        bytes[offset] = value;
+        bytes
+    }
+
+    #[test]
+    fn good_header_needs_no_repair() {
+        let header = damaged(9, 2);
+        assert!(ImageHeader::from_bytes(&header).crc_ok());
+        assert!(header_repairs(&header).is_empty());
+    }
+
+    #[test]
+    fn repairs_each_field() {
+        for (field, offset, good, bad) in [("sides", 9, 2, 3), ("data rate", 5, 0x02, 0x06), ("stepping", 7, 0x00, 0x40)] {
+            let header = damaged(offset, bad);
+            let repairs = header_repairs(&header);
+            assert_eq!(repairs, vec![HeaderRepair { field, offset, from: bad, to: good }], "{}", field);
+            let mut fixed = header;
+            repairs[0].apply(&mut fixed);
+            assert!(ImageHeader::from_bytes(&fixed).crc_ok(), "{}", field);
+        }
+    }
+
+    #[test]
+    fn leaves_other_fields_alone() {
+        // a damaged version byte can't be put right by changing the fields repairs are tried on
+        let header = damaged(4, 0x14);
+        assert!(!ImageHeader::from_bytes(&header).crc_ok());
+        assert!(header_repairs(&header).is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn header_round_trips_through_serde() {
+        let header = ImageHeader::from_bytes(&damaged(9, 2));
+        let value = serde_json::to_value(&header).unwrap();
+        assert_eq!(value["check_sequence"], header.check_sequence);
+        let back: ImageHeader = serde_json::from_value(value).unwrap();
+        assert_eq!(back.crc_bytes(), header.crc_bytes());
+        assert_eq!(back.crc, header.crc);
+    }
+
+    #[test]
+    fn reads_the_comment_header_when_stepping_says_so() {
+        let mut bytes = damaged(7, 0x80).to_vec();
+        bytes.extend_from_slice(&[0x34, 0x12, 5, 0, 89, 11, 9, 12, 30, 0]);
+        let headers = TeleDiskHeaders::from_stream(&mut &bytes[..]).unwrap();
+        let comment = headers.comment_header.unwrap();
+        assert_eq!((comment.crc, comment.length, comment.year, comment.minute), (0x1234, 5, 89, 30));
+        assert!(TeleDiskHeaders::from_stream(&mut &damaged(7, 0x00)[..]).unwrap().comment_header.is_none());
+    }
+
+    #[test]
+    fn comment_crc_covers_the_text() {
+        let mut comment = CommentHeader::from_bytes(&[0, 0, 5, 0, 89, 11, 9, 12, 30, 0]);
+        let mut covered = [5, 0, 89, 11, 9, 12, 30, 0].to_vec();
+        covered.extend_from_slice(b"hello");
+        comment.crc = crc16(&covered);
+        assert!(comment.crc_ok(b"hello"));
+        assert!(!comment.crc_ok(b"hellp"));
+    }
+}
@@ -0,0 +1,20 @@
+// a TD0 image is a header, an optional comment, then tracks of sectors each with its data encoded
+// one of three ways, up to a track of 255 sectors marking the end. everything has a CRC-16
+
+pub mod crc;
+pub mod decode;
+pub mod encode;
+pub mod header;
+pub mod reader;
+pub mod sector;
+pub mod summary;
+pub mod track;
+
+pub use crc::crc16;
+pub use decode::{decode_td0, decode_td0_fitted, DecodeError};
+pub use encode::encode_td0;
+pub use header::{header_repairs, CommentHeader, HeaderRepair, ImageHeader, TeleDiskHeaders, HEADER_REPAIRS};
+pub use reader::{Sector, Td0Reader, Track, TrackError, Tracks};
+pub use sector::{IdQuirk, SectorHeader, MAX_SIZE_CODE};
+pub use summary::{summarise, ImageSummary, SectorSummary, TrackSummary};
+pub use track::TrackHeader;
@@ -0,0 +1,377 @@
+use std::{
+    error, fmt,
+    io::{self, Read},
+};
+
+use super::{crc16, decode_td0, decode_td0_fitted, DecodeError, SectorHeader, TeleDiskHeaders, TrackHeader};
+
+// a TD0 image read a track at a time: new reads the headers and comment, then tracks goes on from
+// there to the end-of-image marker
+pub struct Td0Reader<R> {
+    input: R,
+    pub headers: TeleDiskHeaders,
+    pub comment: Option<Vec<u8>>,   // the comment text as stored, padded with nuls
+}
+
+impl<R: Read> Td0Reader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let headers = TeleDiskHeaders::from_stream(&mut input)?;
+        let comment = match &headers.comment_header {
+            Some(comment_header) => {
+                // read rather than allocated ahead, as the length may be bogus
+                let mut text = Vec::new();
+                (&mut input).take(comment_header.length as u64).read_to_end(&mut text)?;
+                if text.len() < comment_header.length as usize {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Some(text)
+            },
+            None => None,
+        };
+        Ok(Td0Reader { input, headers, comment })
+    }
+
+    pub fn tracks(&mut self) -> Tracks<&mut R> {
+        Tracks::new(&mut self.input)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+// the tracks of a TD0 from where they start, each read whole but with its sectors still encoded
+pub struct Tracks<R> {
+    input: R,
+    done: bool,
+    skip_data: bool,
+}
+
+impl<R: Read> Tracks<R> {
+    pub fn new(input: R) -> Self {
+        Tracks { input, done: false, skip_data: false }
+    }
+
+    // tracks with only the encoding method of each data block kept, the rest read past by its
+    // stored length, for when the headers are all that's wanted. the input may be a stream out of
+    // an archive, so it's read through rather than seeked
+    pub fn skipping_data(input: R) -> Self {
+        Tracks { input, done: false, skip_data: true }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    fn read_track(&mut self) -> Result<Option<Track>, TrackError> {
+        let mut bytes = [0; 4];
+        self.input.read_exact(&mut bytes).map_err(|error| TrackError { error, partial: None })?;
+        let header = TrackHeader::from_bytes(&bytes);
+        if header.number_of_sectors == 255 { return Ok(None); }
+
+        let sectors = Vec::with_capacity(header.number_of_sectors as usize);
+        let mut track = Track { crc_ok: crc16(&bytes[..3]) as u8 == bytes[3], header, sectors };
+        for _ in 0..track.header.number_of_sectors {
+            match self.read_sector() {
+                Ok(sector) => track.sectors.push(sector),
+                Err(error) => return Err(TrackError { error, partial: Some(track) }),
+            }
+        }
+        Ok(Some(track))
+    }
+
+    fn read_sector(&mut self) -> io::Result<Sector> {
+        let mut bytes = [0; 6];
+        self.input.read_exact(&mut bytes)?;
+        let header = SectorHeader::from_bytes(&bytes)?;
+        // sectors skipped by DOS allocation or with no data field don't have a data block
+        let mut oversized = None;
+        let block = if header.has_data() {
+            let mut len = [0; 2];
+            self.input.read_exact(&mut len)?;
+            let len = u16::from_le_bytes(len) as usize;
+            // no more is kept than a block for a sector of its size is likely to need, so a bogus
+            // length doesn't cost memory. the rest is read past, as the next sector starts after it
+            let kept = if self.skip_data { len.min(1) } else { len.min(max_block_len(header.sector_size)) };
+            oversized = (!self.skip_data && len > kept).then_some(len);
+            let mut block = vec![0; kept];
+            self.input.read_exact(&mut block)?;
+            let rest = (len - kept) as u64;
+            if io::copy(&mut (&mut self.input).take(rest), &mut io::sink())? < rest {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Some(block)
+        } else {
+            None
+        };
+        Ok(Sector { header, crc: bytes[5], block, skipped: self.skip_data, oversized })
+    }
+}
+
+// a heuristic for the longest a data block for a sector of this many bytes should be: RLE with a
+// literal run of one byte after another takes three bytes for each, and there's the method byte.
+// empty literals and repeats of nothing are legal, so a padded block can be longer and still
+// valid, which is why one that's cut is told apart, see Sector::oversized
+fn max_block_len(sector_size: u16) -> usize {
+    1 + 3 * sector_size as usize
+}
+
+impl<R: Read> Iterator for Tracks<R> {
+    type Item = Result<Track, TrackError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done { return None; }
+        let track = self.read_track().transpose();
+        // nothing can be read after an error or the end-of-image marker
+        self.done = !matches!(track, Some(Ok(_)));
+        track
+    }
+}
+
+// a track and its sectors as they're recorded in the image
+#[derive(Debug)]
+pub struct Track {
+    pub header: TrackHeader,
+    pub crc_ok: bool,   // whether the low byte of the CRC of the rest of the track header matches
+    sectors: Vec<Sector>,
+}
+
+impl Track {
+    pub fn sectors(&self) -> impl ExactSizeIterator<Item = &Sector> {
+        self.sectors.iter()
+    }
+
+    pub fn into_parts(self) -> (TrackHeader, Vec<Sector>) {
+        (self.header, self.sectors)
+    }
+}
+
+// a sector header and its data block, decoded when it's asked for
+#[derive(Debug)]
+pub struct Sector {
+    pub header: SectorHeader,
+    pub crc: u8,                // low byte of the CRC of the decoded data
+    block: Option<Vec<u8>>,     // the encoding method then the encoded data
+    skipped: bool,              // read by Tracks::skipping_data, with only the encoding method kept
+    oversized: Option<usize>,   // the length a block longer than max_block_len said it was
+}
+
+impl Sector {
+    // the length the data block declared, when it was longer than max_block_len and only the
+    // start of it was kept
+    pub fn oversized(&self) -> Option<usize> {
+        self.oversized
+    }
+
+    // 0 raw, 1 a two byte pattern repeated, 2 RLE. none for a sector without a data block, or
+    // with one too short to say
+    pub fn encoding(&self) -> Option<u8> {
+        self.block.as_ref().and_then(|block| block.first().copied())
+    }
+
+    // the sector's contents, all zero for one without a data block
+    pub fn data(&self) -> Result<Vec<u8>, DecodeError> {
+        let mut data = Vec::new();
+        self.data_into(&mut data).map(|()| data)
+    }
+
+    // data, decoded into a buffer that can be used again for the next sector
+    pub fn data_into(&self, output: &mut Vec<u8>) -> Result<(), DecodeError> {
+        match self.block()? {
+            Some([method, data @ ..]) => decode_td0(*method, data, self.header.sector_size, output),
+            Some([]) => Err(DecodeError::Truncated),
+            None => {
+                self.blank(output);
+                Ok(())
+            },
+        }
+    }
+
+    // data, but a block decoding to the wrong size is made to fit, along with how long it came to
+    pub fn fitted_data(&self) -> Result<(Vec<u8>, Option<usize>), DecodeError> {
+        let mut data = Vec::new();
+        self.fitted_data_into(&mut data).map(|len| (data, len))
+    }
+
+    pub fn fitted_data_into(&self, output: &mut Vec<u8>) -> Result<Option<usize>, DecodeError> {
+        match self.block()? {
+            Some([method, data @ ..]) => decode_td0_fitted(*method, data, self.header.sector_size, output),
+            Some([]) => Err(DecodeError::Truncated),
+            None => {
+                self.blank(output);
+                Ok(None)
+            },
+        }
+    }
+
+    fn block(&self) -> Result<Option<&[u8]>, DecodeError> {
+        match self.block.as_deref() {
+            Some(block) if self.skipped && !block.is_empty() => Err(DecodeError::Skipped),
+            block => Ok(block),
+        }
+    }
+
+    fn blank(&self, output: &mut Vec<u8>) {
+        output.clear();
+        output.resize(self.header.sector_size as usize, 0);
+    }
+
+    pub fn crc_ok(&self, data: &[u8]) -> bool {
+        crc16(data) as u8 == self.crc
+    }
+}
+
+// a read error with the sectors of the track it cut short read so far
+#[derive(Debug)]
+pub struct TrackError {
+    pub error: io::Error,
+    pub partial: Option<Track>,
+}
+
+impl fmt::Display for TrackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.partial {
+            Some(track) => write!(f, "{} after {} sectors of a track", self.error, track.sectors.len()),
+            None => self.error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for TrackError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<TrackError> for io::Error {
+    fn from(e: TrackError) -> Self {
+        e.error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::td0::ImageHeader;
+
+    // a one track image: a raw sector, one repeated, and one with no data block
+    fn image() -> Vec<u8> {
+        let header = ImageHeader::synthetic(0x02, 0x01, 1);
+        let mut bytes = header.crc_bytes().to_vec();
+        bytes.extend_from_slice(&crc16(&header.crc_bytes()).to_le_bytes());
+        let track = [3, 0, 0];
+        bytes.extend_from_slice(&track);
+        bytes.push(crc16(&track) as u8);
+        bytes.extend_from_slice(&[0, 0, 1, 0, 0, crc16(&[0x5a; 128]) as u8, 129, 0, 0]);
+        bytes.extend_from_slice(&[0x5a; 128]);
+        bytes.extend_from_slice(&[0, 0, 2, 0, 0, 0, 5, 0, 1, 64, 0, 0xe5, 0xe5]);
+        bytes.extend_from_slice(&[0, 0, 3, 0, 0x10, 0]);
+        bytes.extend_from_slice(&[255, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn reads_tracks_and_sectors() {
+        let bytes = image();
+        let mut reader = Td0Reader::new(&bytes[..]).unwrap();
+        assert!(reader.comment.is_none());
+        let tracks: Vec<Track> = reader.tracks().collect::<Result<_, _>>().unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert!(tracks[0].crc_ok);
+        let sectors: Vec<&Sector> = tracks[0].sectors().collect();
+        assert_eq!(sectors.iter().map(|s| s.encoding()).collect::<Vec<_>>(), [Some(0), Some(1), None]);
+        assert!(sectors[0].crc_ok(&sectors[0].data().unwrap()));
+        assert_eq!(sectors[1].data(), Ok(vec![0xe5; 128]));
+        assert!(!sectors[1].crc_ok(&sectors[1].data().unwrap()));
+        assert_eq!(sectors[2].data(), Ok(vec![0; 128]));
+        assert!(reader.into_inner().is_empty());
+    }
+
+    #[test]
+    fn keeps_the_sectors_of_a_track_cut_short() {
+        let bytes = image();
+        let mut tracks = Tracks::new(&bytes[12..12 + 4 + 6 + 2 + 129 + 6]);
+        let error = tracks.next().unwrap().unwrap_err();
+        assert_eq!(error.error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(error.partial.unwrap().sectors().len(), 1);
+        assert!(tracks.next().is_none());
+    }
+
+    #[test]
+    fn skips_data_blocks() {
+        let bytes = image();
+        let mut tracks = Tracks::skipping_data(&bytes[12..]);
+        let track = tracks.next().unwrap().unwrap();
+        let sectors: Vec<&Sector> = track.sectors().collect();
+        assert_eq!(sectors.iter().map(|s| s.encoding()).collect::<Vec<_>>(), [Some(0), Some(1), None]);
+        assert_eq!((sectors[0].data(), sectors[1].fitted_data()), (Err(DecodeError::Skipped), Err(DecodeError::Skipped)));
+        assert_eq!(sectors[2].data(), Ok(vec![0; 128]));
+        assert!(tracks.next().is_none());
+        assert!(tracks.into_inner().is_empty());
+
+        // a data block running past the end is still noticed
+        let error = Tracks::skipping_data(&bytes[12..12 + 4 + 6 + 2 + 100]).next().unwrap().unwrap_err();
+        assert_eq!(error.error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn keeps_no_more_of_a_block_than_its_sector_needs() {
+        let mut bytes = image();
+        // the first sector's raw block made 60000 bytes long
+        let at = 12 + 4 + 6;
+        bytes.splice(at..at + 2 + 129, [&60001u16.to_le_bytes()[..], &[0], &[0x5a; 60000]].concat());
+        let track = Tracks::new(&bytes[12..]).next().unwrap().unwrap();
+        let sectors: Vec<&Sector> = track.sectors().collect();
+        assert_eq!(sectors[0].block.as_ref().map(Vec::len), Some(max_block_len(128)));
+        assert_eq!((sectors[0].oversized(), sectors[1].oversized()), (Some(60001), None));
+        assert_eq!(sectors[0].fitted_data(), Ok((vec![0x5a; 128], Some(3 * 128))));
+        assert_eq!(sectors[1].data(), Ok(vec![0xe5; 128]));
+    }
+
+    #[test]
+    fn reports_bad_sectors_without_panicking() {
+        let mut bytes = image();
+        // an empty data block for the first sector
+        bytes.splice(12 + 4 + 6..12 + 4 + 6 + 2 + 129, [0, 0]);
+        let track = Tracks::new(&bytes[12..]).next().unwrap().unwrap();
+        let sector = track.sectors().next().unwrap();
+        assert_eq!((sector.encoding(), sector.data()), (None, Err(DecodeError::Truncated)));
+
+        // a size code too big for a sector
+        let mut bytes = image();
+        bytes[12 + 4 + 6 + 2 + 129 + 3] = 9;
+        let error = Tracks::new(&bytes[12..]).next().unwrap().unwrap_err();
+        assert_eq!(error.error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.partial.unwrap().sectors().len(), 1);
+    }
+
+    // every sector of every track, decoded or not, ending at the first read error
+    fn read_all(bytes: &[u8]) {
+        let Ok(mut reader) = Td0Reader::new(bytes) else { return };
+        for track in reader.tracks() {
+            let track = match track {
+                Ok(track) => track,
+                Err(TrackError { partial: Some(track), .. }) => track,
+                Err(_) => return,
+            };
+            for sector in track.sectors() {
+                let _ = (sector.encoding(), sector.data(), sector.fitted_data());
+            }
+        }
+    }
+
+    #[test]
+    fn survives_damaged_images() {
+        let bytes = image();
+        for len in 0..bytes.len() {
+            read_all(&bytes[..len]);
+        }
+        for i in 0..bytes.len() {
+            for value in [0x00, 0x01, 0x02, 0x7f, 0x80, 0xff] {
+                let mut damaged = bytes.clone();
+                damaged[i] = value;
+                read_all(&damaged);
+            }
+        }
+    }
+}
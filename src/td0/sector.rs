@@ -0,0 +1,112 @@
+use std::io;
+
+// the largest size code, for 8K sectors. TeleDisk only writes those an FDC can format
+pub const MAX_SIZE_CODE: u8 = 6;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SectorHeader {
+    pub cylinder_number: u8,      // Cylinder number of the sector
+    pub side_number: u8,          // Side number of the sector
+    pub sector_number: u8,        // Sector number
+    // pub raw_sector_size: u8,   // Raw sector size (exponent)
+    pub sector_size: u16,         // Actual size of the sector (128 << raw_sector_size)
+    pub flags: u8,                // Flags associated with the sector
+}
+
+impl SectorHeader {
+    // fails on a size code past MAX_SIZE_CODE
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        assert!(bytes.len() == 6, "SectorHeader must be 6 bytes long");
+
+        let cylinder_number = bytes[0];
+        let side_number = bytes[1];
+        let sector_number = bytes[2];
+        let raw_sector_size = bytes[3];
+        let flags = bytes[4];
+        if raw_sector_size > MAX_SIZE_CODE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("sector size code {} isn't one of 0 to {}", raw_sector_size, MAX_SIZE_CODE)));
+        }
+        let sector_size = 128 << raw_sector_size; // Calculate the actual size
+
+        Ok(SectorHeader {
+            cylinder_number,
+            side_number,
+            sector_number,
+            // raw_sector_size,
+            sector_size,
+            flags,
+        })
+    }
+
+    // flags 0x10 (skipped by DOS allocation) and 0x20 (ID found but no data) mean no data block follows
+    pub fn has_data(&self) -> bool {
+        self.flags & 0x30 == 0
+    }
+
+    // IDs no format uses: protection schemes record sectors whose ID field can't be read as sector
+    // 0xff, and number others past anything a format would, from 100 up but for the c1 to c9 of
+    // Amstrad's data format. a cylinder of 0xff isn't one, hard disks wrap round to it
+    pub fn id_quirk(&self) -> Option<IdQuirk> {
+        if self.sector_number == 0xff {
+            Some(IdQuirk::NoId)
+        } else if self.sector_number >= 100 && !(0xc1..=0xc9).contains(&self.sector_number) {
+            Some(IdQuirk::HighNumber)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdQuirk {
+    NoId,           // 0xff standing in for an ID that wasn't there
+    HighNumber,     // sector numbered 100 or more
+}
+
+impl IdQuirk {
+    pub fn code(&self) -> &'static str {
+        match self {
+            IdQuirk::NoId => "no-id",
+            IdQuirk::HighNumber => "high-id",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sector(number: u8, flags: u8) -> SectorHeader {
+        SectorHeader::from_bytes(&[0, 0, number, 2, flags, 0]).unwrap()
+    }
+
+    #[test]
+    fn from_bytes() {
+        let header = SectorHeader::from_bytes(&[79, 1, 9, 3, 0x02, 0x77]).unwrap();
+        assert_eq!((header.cylinder_number, header.side_number, header.sector_number), (79, 1, 9));
+        assert_eq!((header.sector_size, header.flags), (1024, 0x02));
+        assert_eq!(SectorHeader::from_bytes(&[0, 0, 1, 6, 0, 0]).unwrap().sector_size, 8192);
+        for code in [7, 9, 0xff] {
+            assert_eq!(SectorHeader::from_bytes(&[0, 0, 1, code, 0, 0]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn data_block_flags() {
+        assert!(sector(1, 0x00).has_data());
+        assert!(sector(1, 0x04).has_data());
+        assert!(!sector(1, 0x10).has_data());
+        assert!(!sector(1, 0x20).has_data());
+    }
+
+    #[test]
+    fn id_quirks() {
+        assert_eq!(sector(9, 0).id_quirk(), None);
+        assert_eq!(sector(0xc1, 0).id_quirk(), None);
+        assert_eq!(sector(0xff, 0).id_quirk(), Some(IdQuirk::NoId));
+        assert_eq!(sector(100, 0).id_quirk(), Some(IdQuirk::HighNumber));
+        assert_eq!(sector(0xca, 0).id_quirk().map(|q| q.code()), Some("high-id"));
+    }
+}
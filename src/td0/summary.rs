@@ -0,0 +1,132 @@
+use std::io;
+
+use super::{CommentHeader, ImageHeader, SectorHeader, Td0Reader, TrackError};
+
+// what an image in memory holds, read through to the end or the first thing that stops it, for
+// front ends that want the structure of an image without the analyser: a web page, say
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ImageSummary {
+    pub header: ImageHeader,
+    pub header_crc_ok: bool,
+    pub comment_header: Option<CommentHeader>,
+    pub comment: Option<Vec<u8>>,           // as stored, without its padding. the code page is the reader's to pick
+    pub comment_crc_ok: Option<bool>,
+    pub tracks: Vec<TrackSummary>,
+    pub error: Option<String>,              // why reading stopped before the end-of-image marker
+    pub trailing_bytes: usize,              // after the end-of-image marker
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TrackSummary {
+    pub cylinder: u8,
+    pub side: u8,
+    pub crc_ok: bool,
+    pub sectors: Vec<SectorSummary>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SectorSummary {
+    pub header: SectorHeader,
+    pub encoding: Option<u8>,               // none without a data block
+    pub crc_ok: Option<bool>,               // none when it couldn't be decoded
+    pub decode_error: Option<String>,
+}
+
+impl ImageSummary {
+    pub fn sectors(&self) -> impl Iterator<Item = &SectorSummary> {
+        self.tracks.iter().flat_map(|track| &track.sectors)
+    }
+}
+
+// fails only when there's no header and comment to read. a track cut short keeps the sectors read
+pub fn summarise(bytes: &[u8]) -> io::Result<ImageSummary> {
+    let mut reader = Td0Reader::new(bytes)?;
+    let mut tracks = Vec::new();
+    let mut error = None;
+    let mut rest = reader.tracks();
+    // each sector is decoded only to check its CRC, so one buffer does for them all
+    let mut data = Vec::new();
+    for track in rest.by_ref() {
+        let track = match track {
+            Ok(track) => track,
+            Err(TrackError { error: e, partial }) => {
+                error = Some(e.to_string());
+                match partial {
+                    Some(track) => track,
+                    None => break,
+                }
+            },
+        };
+        let sectors = track.sectors().map(|sector| {
+            let (crc_ok, decode_error) = match sector.data_into(&mut data) {
+                Ok(()) => (Some(sector.crc_ok(&data)), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            SectorSummary { header: sector.header.clone(), encoding: sector.encoding(), crc_ok, decode_error }
+        }).collect();
+        tracks.push(TrackSummary { cylinder: track.header.cylinder_number, side: track.header.side_number, crc_ok: track.crc_ok, sectors });
+    }
+    let trailing_bytes = rest.into_inner().len();
+
+    let headers = reader.headers;
+    let comment_crc_ok = headers.comment_header.as_ref().zip(reader.comment.as_ref()).map(|(header, text)| header.crc_ok(text));
+    let comment = reader.comment.map(|text| {
+        let end = text.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        text[..end].to_vec()
+    });
+    Ok(ImageSummary {
+        header_crc_ok: headers.image_header.crc_ok(),
+        header: headers.image_header,
+        comment_header: headers.comment_header,
+        comment,
+        comment_crc_ok,
+        tracks,
+        error,
+        trailing_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::td0::crc16;
+
+    // a one track image of a raw sector and one with a data block that can't be decoded
+    fn image() -> Vec<u8> {
+        let header = ImageHeader::synthetic(0x02, 0x01, 1);
+        let mut bytes = header.crc_bytes().to_vec();
+        bytes.extend_from_slice(&crc16(&header.crc_bytes()).to_le_bytes());
+        let track = [2, 0, 0];
+        bytes.extend_from_slice(&track);
+        bytes.push(crc16(&track) as u8);
+        bytes.extend_from_slice(&[0, 0, 1, 0, 0, crc16(&[0x5a; 128]) as u8, 129, 0, 0]);
+        bytes.extend_from_slice(&[0x5a; 128]);
+        bytes.extend_from_slice(&[0, 0, 2, 0, 0, 0, 2, 0, 7, 0]);
+        bytes.extend_from_slice(&[255, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn summarises_tracks_and_sectors() {
+        let mut bytes = image();
+        bytes.extend_from_slice(b"junk");
+        let summary = summarise(&bytes).unwrap();
+        assert!(summary.header_crc_ok && summary.comment.is_none() && summary.error.is_none());
+        assert_eq!(summary.trailing_bytes, 4);
+        assert_eq!(summary.tracks.len(), 1);
+        let sectors: Vec<_> = summary.sectors().map(|s| (s.encoding, s.crc_ok, s.decode_error.is_some())).collect();
+        assert_eq!(sectors, [(Some(0), Some(true), false), (Some(7), None, true)]);
+    }
+
+    #[test]
+    fn keeps_what_was_read_of_a_short_image() {
+        let bytes = image();
+        let summary = summarise(&bytes[..12 + 4 + 6 + 2 + 129 + 3]).unwrap();
+        assert!(summary.error.is_some());
+        assert_eq!(summary.sectors().count(), 1);
+        assert!(summarise(&bytes[..6]).is_err());
+    }
+}
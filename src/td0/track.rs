@@ -0,0 +1,34 @@
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackHeader {
+    pub number_of_sectors: u8,  // Number of sectors in the track
+    pub cylinder_number: u8,    // Cylinder number of the track
+    pub side_number: u8,        // Side number of the track
+}
+
+impl TrackHeader {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() == 4, "TrackHeader must be 4 bytes long");
+
+        let number_of_sectors = bytes[0];
+        let cylinder_number = bytes[1];
+        let side_number = bytes[2];
+
+        TrackHeader {
+            number_of_sectors,
+            cylinder_number,
+            side_number,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes() {
+        let track = TrackHeader::from_bytes(&[9, 39, 1, 0x5a]);
+        assert_eq!((track.number_of_sectors, track.cylinder_number, track.side_number), (9, 39, 1));
+    }
+}
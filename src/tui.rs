@@ -0,0 +1,248 @@
+use std::{io::{self, ErrorKind}, path::Path};
+use clap::Parser;
+
+use crate::{
+    analysis::{Disk, Sector, SectorStatus, Track},
+    cli::Args,
+    containers::walk_path,
+    dump::sector_dump,
+    fs::Codepage,
+    report::{tr, ImageReport, Language, Scan},
+    COMMENT_PATTERNS,
+};
+
+// scan quietly, keeping the sectors, then browse what was found until q is pressed
+pub(crate) fn browse(path: &str, codepage: Codepage, language: Language) -> io::Result<()> {
+    let mut args = Args::parse_from(["kc8587", "--quiet", path]);
+    args.codepage = codepage;
+    args.language = language;
+    args.keep_disks = true;
+    args.comment_pattern.extend(COMMENT_PATTERNS.iter().map(|p| regex::Regex::new(p).unwrap()));
+    let mut scan = Scan::default();
+    walk_path(&args, &mut scan, Path::new(path));
+    if scan.reports.is_empty() {
+        return Err(io::Error::new(ErrorKind::NotFound, "no images found"));
+    }
+
+    let mut browser = Browser::new(&scan.reports, language);
+    let mut terminal = ratatui::init();
+    let result = browser.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+pub(crate) const BROWSE_TABS: [&str; 4] = ["header", "sectors", "hex", "files"];
+
+pub(crate) struct Browser<'a> {
+    pub(crate) reports: &'a [ImageReport],
+    pub(crate) language: Language,
+    pub(crate) rows: Vec<(String, Option<usize>)>,     // the tree: a line per directory or container, its images under it
+    pub(crate) images: ratatui::widgets::ListState,    // the selected row, always an image's
+    pub(crate) tab: usize,
+    pub(crate) sectors: ratatui::widgets::TableState,  // the selected sector, tracks in logical order and sectors as recorded
+    pub(crate) scroll: u16,                            // of the header, hex and files tabs
+    pub(crate) in_tab: bool,                           // whether the arrow keys move within the tab or between images
+}
+
+impl<'a> Browser<'a> {
+    pub(crate) fn new(reports: &'a [ImageReport], language: Language) -> Self {
+        // directories and containers in the order they were first seen
+        let mut parents: Vec<(&str, Vec<(&str, usize)>)> = Vec::new();
+        for (i, report) in reports.iter().enumerate() {
+            let dir = if report.directory.is_empty() { "." } else { report.directory.as_str() };
+            let name = report.name.as_str();
+            match parents.iter_mut().find(|(parent, _)| *parent == dir) {
+                Some((_, images)) => images.push((name, i)),
+                None => parents.push((dir, vec![(name, i)])),
+            }
+        }
+        let mut rows = Vec::new();
+        for (dir, images) in parents {
+            rows.push((dir.to_string(), None));
+            rows.extend(images.into_iter().map(|(name, i)| (format!("  {}", name), Some(i))));
+        }
+        let mut images = ratatui::widgets::ListState::default();
+        images.select(rows.iter().position(|(_, image)| image.is_some()));
+        let sectors = ratatui::widgets::TableState::default().with_selected(Some(0));
+        Browser { reports, language, rows, images, tab: 0, sectors, scroll: 0, in_tab: false }
+    }
+
+    pub(crate) fn report(&self) -> &'a ImageReport {
+        let reports = self.reports;
+        &reports[self.images.selected().and_then(|row| self.rows[row].1).unwrap_or(0)]
+    }
+
+    pub(crate) fn sector_list(&self) -> Vec<&'a Sector> {
+        let Some(disk) = &self.report().disk else { return Vec::new() };
+        let mut tracks: Vec<&Track> = disk.tracks.iter().collect();
+        tracks.sort_by_key(|t| (t.cylinder, t.header.side_number));
+        tracks.into_iter().flat_map(|t| &t.sectors).collect()
+    }
+
+    pub(crate) fn run(&mut self, terminal: &mut ratatui::DefaultTerminal) -> io::Result<()> {
+        use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press { continue; }
+            match key.code {
+                KeyCode::Char('q') => return Ok(()),
+                KeyCode::Esc if !self.in_tab => return Ok(()),
+                KeyCode::Esc => self.in_tab = false,
+                KeyCode::Enter => self.in_tab = !self.in_tab,
+                KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => self.switch_tab(1),
+                KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => self.switch_tab(BROWSE_TABS.len() - 1),
+                KeyCode::Down | KeyCode::Char('j') => self.step(1),
+                KeyCode::Up | KeyCode::Char('k') => self.step(-1),
+                KeyCode::PageDown => self.step(20),
+                KeyCode::PageUp => self.step(-20),
+                _ => {},
+            }
+        }
+    }
+
+    pub(crate) fn switch_tab(&mut self, by: usize) {
+        self.tab = (self.tab + by) % BROWSE_TABS.len();
+        self.scroll = 0;
+    }
+
+    // down the tree to the next image, or within the tab: the sector table moves its selection,
+    // which the hex tab shows, and the others scroll
+    pub(crate) fn step(&mut self, by: isize) {
+        if !self.in_tab {
+            let images: Vec<usize> = (0..self.rows.len()).filter(|&row| self.rows[row].1.is_some()).collect();
+            let current = images.iter().position(|&row| Some(row) == self.images.selected()).unwrap_or(0);
+            let next = (current as isize + by).clamp(0, images.len() as isize - 1) as usize;
+            self.images.select(Some(images[next]));
+            self.sectors.select(Some(0));
+            self.scroll = 0;
+        } else if BROWSE_TABS[self.tab] == "sectors" {
+            let count = self.sector_list().len();
+            if count > 0 {
+                let current = self.sectors.selected().unwrap_or(0) as isize;
+                self.sectors.select(Some((current + by).clamp(0, count as isize - 1) as usize));
+            }
+        } else {
+            self.scroll = (self.scroll as isize + by).max(0) as u16;
+        }
+    }
+
+    pub(crate) fn draw(&mut self, frame: &mut ratatui::Frame) {
+        use ratatui::{layout::{Constraint, Layout}, style::{Modifier, Style}, text::Line,
+            widgets::{Block, List, ListItem, Paragraph, Row, Table, Tabs}};
+        let language = self.language;
+        let t = |english| tr(language, english);
+        let [main, help] = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let [tree, right] = Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(main);
+        let [tabs, body] = Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(right);
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+
+        let items: Vec<ListItem> = self.rows.iter().map(|(label, image)| match image {
+            Some(_) => ListItem::new(label.as_str()),
+            None => ListItem::new(label.as_str()).style(Style::default().add_modifier(Modifier::BOLD)),
+        }).collect();
+        let list = List::new(items).block(Block::bordered().title(format!("{} {}", self.reports.len(), t("images"))))
+            .highlight_style(if self.in_tab { Style::default().add_modifier(Modifier::BOLD) } else { highlight });
+        frame.render_stateful_widget(list, tree, &mut self.images);
+
+        let report = self.report();
+        let titles = BROWSE_TABS.iter().map(|&tab| t(tab));
+        frame.render_widget(Tabs::new(titles).select(self.tab).highlight_style(highlight)
+            .block(Block::bordered().title(report.name.as_str())), tabs);
+
+        let sectors = self.sector_list();
+        let selected = sectors.get(self.sectors.selected().unwrap_or(0)).copied();
+        let block = Block::bordered();
+        match BROWSE_TABS[self.tab] {
+            "header" => {
+                // the card without its files, which have their own tab
+                let card = report.card(language);
+                let lines = card.lines().count() - report.files.len();
+                let text: Vec<Line> = card.lines().take(lines).map(Line::from).collect();
+                frame.render_widget(Paragraph::new(text).block(block).scroll((self.scroll, 0)), body);
+            },
+            "sectors" => {
+                let usual_size = report.disk.as_ref().map_or(512, Disk::usual_sector_size);
+                let rows = sectors.iter().map(|sector| {
+                    let h = &sector.header;
+                    Row::new(vec![format!("{:3}", h.cylinder_number), h.side_number.to_string(), h.sector_number.to_string(),
+                        h.sector_size.to_string(), format!("{:02x}", h.flags), SectorStatus::of(h, usual_size).name().to_string()])
+                });
+                let widths = [Constraint::Length(4), Constraint::Length(2), Constraint::Length(4), Constraint::Length(5),
+                    Constraint::Length(5), Constraint::Fill(1)];
+                let table = Table::new(rows, widths)
+                    .header(Row::new(["C", "H", "S", "size", "flags", "status"]).style(Style::default().add_modifier(Modifier::BOLD)))
+                    .row_highlight_style(highlight).block(block);
+                frame.render_stateful_widget(table, body, &mut self.sectors);
+            },
+            "hex" => {
+                let (title, text) = match selected {
+                    Some(sector) => {
+                        let h = &sector.header;
+                        (format!("C {} H {} S {}", h.cylinder_number, h.side_number, h.sector_number),
+                            sector_dump(&sector.data, ("", "", ""), 16).into_iter().map(Line::from).collect())
+                    },
+                    None => (String::new(), Vec::new()),
+                };
+                frame.render_widget(Paragraph::new(text).block(block.title(title)).scroll((self.scroll, 0)), body);
+            },
+            _ => {
+                let text: Vec<Line> = report.files.iter().map(|file| Line::from(format!("{}{}",
+                    file.partition.map(|p| format!("{}: ", p)).unwrap_or_default(), file.listing(self.language)))).collect();
+                frame.render_widget(Paragraph::new(text).block(block).scroll((self.scroll, 0)), body);
+            },
+        }
+
+        let keys = if self.in_tab { "up/down move in the tab  enter/esc back to images" } else { "up/down images  enter into the tab" };
+        frame.render_widget(Paragraph::new(format!(" {}  left/right tabs  q quit", keys)), help);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_between_images_and_within_tabs() {
+        let dir = crate::tests::temp_dir("browse");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let mut td0 = Vec::new();
+        let disk = crate::formats::flat_disk(&[0xe5; 4 * 512], 2, 1, 2, 512);
+        crate::import::write_td0(&mut td0, &kc8587::td0::ImageHeader::synthetic(0x02, 0x01, 1), None, &disk, Codepage::Cp437).unwrap();
+        for name in ["a.td0", "b.td0", "sub/c.td0"] {
+            std::fs::write(dir.join(name), &td0).unwrap();
+        }
+        let mut args = Args::parse_from(["kc8587".as_ref(), "--quiet".as_ref(), "--sort".as_ref(), dir.as_os_str()]);
+        args.keep_disks = true;
+        let mut scan = Scan::default();
+        walk_path(&args, &mut scan, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut browser = Browser::new(&scan.reports, Language::En);
+        let rows: Vec<_> = browser.rows.iter().map(|(label, image)| (label.trim_start().rsplit(std::path::MAIN_SEPARATOR).next().unwrap(), *image)).collect();
+        assert_eq!(rows[1..], [("a.td0", Some(0)), ("b.td0", Some(1)), ("sub", None), ("c.td0", Some(2))]);
+        // the directory rows are stepped over, and the ends held to
+        assert_eq!(browser.report().name, "a.td0");
+        browser.step(2);
+        assert_eq!(browser.report().name, "c.td0");
+        browser.step(20);
+        assert_eq!(browser.images.selected(), Some(4));
+        browser.step(-20);
+        assert_eq!(browser.report().name, "a.td0");
+
+        browser.switch_tab(1);
+        browser.in_tab = true;
+        browser.step(3);
+        assert_eq!((BROWSE_TABS[browser.tab], browser.sectors.selected()), ("sectors", Some(3)));
+        browser.step(1);
+        assert_eq!(browser.sectors.selected(), Some(3));
+        browser.switch_tab(BROWSE_TABS.len() - 2);
+        assert_eq!(BROWSE_TABS[browser.tab], "files");
+
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(100, 20)).unwrap();
+        browser.switch_tab(BROWSE_TABS.len() - 1);
+        terminal.draw(|frame| browser.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("3 images") && screen.contains("C 1 H 0 S 2") && screen.contains("blank, all e5"), "{}", screen);
+    }
+}
@@ -0,0 +1,66 @@
+// td0::summarise for a web page, built for wasm32-unknown-unknown with the wasm feature and without
+// the analyser's. javascript copies an image into memory from td0_alloc, gets its summary as a
+// nul-terminated JSON string from td0_summarise, and hands that back to td0_free
+
+use std::ffi::{c_char, CString};
+
+use serde_json::json;
+
+use crate::td0::summarise;
+
+// room for an image of len bytes, for td0_summarise to take back
+#[no_mangle]
+pub extern "C" fn td0_alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(len);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+/// # Safety
+///
+/// `ptr` must come from `td0_alloc(len)` with the image written into it. It's freed here.
+#[no_mangle]
+pub unsafe extern "C" fn td0_summarise(ptr: *mut u8, len: usize) -> *mut c_char {
+    let bytes = Vec::from_raw_parts(ptr, len, len);
+    let summary = match summarise(&bytes) {
+        Ok(summary) => serde_json::to_value(summary).unwrap_or_else(|e| json!({ "error": e.to_string() })),
+        Err(e) => json!({ "error": e.to_string() }),
+    };
+    // JSON escapes any nul in a string, so the text never has one to cut it short
+    CString::new(summary.to_string()).unwrap_or_default().into_raw()
+}
+
+/// # Safety
+///
+/// `summary` must come from `td0_summarise`, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn td0_free(summary: *mut c_char) {
+    drop(CString::from_raw(summary));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn summarises_through_the_exports() {
+        let mut image = crate::td0::ImageHeader::synthetic(0x02, 0x01, 1).crc_bytes().to_vec();
+        image.extend_from_slice(&[0, 0, 255, 0, 0, 0]);
+        let ptr = td0_alloc(image.len());
+        unsafe {
+            std::ptr::copy_nonoverlapping(image.as_ptr(), ptr, image.len());
+            let summary = td0_summarise(ptr, image.len());
+            let value: serde_json::Value = serde_json::from_str(CStr::from_ptr(summary).to_str().unwrap()).unwrap();
+            assert_eq!((value["header"]["sides"].as_u64(), value["tracks"].as_array().map(Vec::len)), (Some(1), Some(0)));
+            td0_free(summary);
+
+            let ptr = td0_alloc(3);
+            std::ptr::copy_nonoverlapping(b"TDx".as_ptr(), ptr, 3);
+            let summary = td0_summarise(ptr, 3);
+            assert!(CStr::from_ptr(summary).to_str().unwrap().contains("\"error\""));
+            td0_free(summary);
+        }
+    }
+}